@@ -0,0 +1,321 @@
+use std::ffi::{c_char, c_long, c_ulong, c_void, CString};
+use std::fs;
+use std::io;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{fence, Ordering};
+use crate::{create_and_return_ether, PcapBlock};
+use crate::bpf::{self, Instruction};
+use crate::cli_options::CliOptions;
+use crate::listener::{catch_interrupts, interrupted, POLL_INTERVAL};
+use crate::pipeline::Pipeline;
+
+//From linux/socket.h, linux/if_ether.h and linux/if_packet.h
+const AF_PACKET: i32 = 17;
+const SOCK_RAW: i32 = 3;
+const ETH_P_ALL: u16 = 0x0003;
+const SOL_SOCKET: i32 = 1;
+const SO_ATTACH_FILTER: i32 = 26;
+const SOL_PACKET: i32 = 263;
+const PACKET_ADD_MEMBERSHIP: i32 = 1;
+const PACKET_RX_RING: i32 = 5;
+const PACKET_STATISTICS: i32 = 6;
+const PACKET_VERSION: i32 = 10;
+const TPACKET_V3: i32 = 2;
+const PACKET_MR_PROMISC: u16 = 1;
+const PACKET_OUTGOING: u8 = 4;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_VLAN_VALID: u32 = 0x10;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 0x40;
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+const POLLIN: i16 = 1;
+const POLLERR: i16 = 8;
+
+//The hardware types of /sys/class/net/<interface>/type whose frames start with an Ethernet header
+const ARPHRD_ETHER: u32 = 1;
+const ARPHRD_LOOPBACK: u32 = 772;
+
+//The ring is 64 blocks of 1 MiB. The kernel hands a block over once it is full or after the
+//timeout, so a quiet interface still delivers its packets quickly
+const BLOCK_SIZE: u32 = 1 << 20;
+const BLOCK_COUNT: u32 = 64;
+const FRAME_SIZE: u32 = 2048; //Only used to check the ring, TPACKET_V3 frames take the room they need
+const BLOCK_TIMEOUT_MS: u32 = 50;
+
+/// Frames are captured whole, as far as the ring allows.
+pub(crate) const SNAP_LENGTH: u32 = 262144;
+
+#[repr(C)]
+struct TpacketReq3 {
+    block_size: u32,
+    block_count: u32,
+    frame_size: u32,
+    frame_count: u32,
+    retire_block_timeout: u32,
+    private_size: u32,
+    feature_request: u32,
+}
+
+#[repr(C)]
+struct SockaddrLl {
+    family: u16,
+    protocol: u16,
+    interface: i32,
+    hardware_type: u16,
+    packet_type: u8,
+    address_length: u8,
+    address: [u8; 8],
+}
+
+#[repr(C)]
+struct PacketMreq {
+    interface: i32,
+    kind: u16,
+    address_length: u16,
+    address: [u8; 8],
+}
+
+#[repr(C)]
+struct SockFprog {
+    length: u16,
+    filter: *const Instruction,
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    returned_events: i16,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStatsV3 {
+    packets: u32,
+    drops: u32,
+    freeze_count: u32,
+}
+
+extern "C" {
+    fn socket(domain: i32, kind: i32, protocol: i32) -> i32;
+    fn setsockopt(fd: i32, level: i32, name: i32, value: *const c_void, length: u32) -> i32;
+    fn getsockopt(fd: i32, level: i32, name: i32, value: *mut c_void, length: *mut u32) -> i32;
+    fn bind(fd: i32, address: *const SockaddrLl, length: u32) -> i32;
+    fn mmap(address: *mut c_void, length: usize, protection: i32, flags: i32, fd: i32, offset: c_long) -> *mut c_void;
+    fn munmap(address: *mut c_void, length: usize) -> i32;
+    fn poll(fds: *mut PollFd, count: c_ulong, timeout: i32) -> i32;
+    fn close(fd: i32) -> i32;
+    fn if_nametoindex(name: *const c_char) -> u32;
+}
+
+fn check(result: i32, action: &str) -> Result<(), String> {
+    if result < 0 {
+        return Err(format!("could not {}: {}", action, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn set_option<T>(fd: i32, level: i32, name: i32, value: &T, action: &str) -> Result<(), String> {
+    let result = unsafe { setsockopt(fd, level, name, value as *const T as *const c_void, size_of::<T>() as u32) };
+    check(result, action)
+}
+
+/// A packet socket bound to one interface, with a TPACKET_V3 ring shared with the kernel. The
+/// kernel fills the blocks of the ring with frames and hands each over by marking it as the
+/// user's, until it is handed back.
+struct PacketRing {
+    fd: i32,
+    ring: *mut u8,
+    block: u32, //The block to be read next
+    loopback: bool, //Every packet is seen twice on loopback, once going out and once coming in
+}
+
+impl PacketRing {
+    fn open(interface: &str, promiscuous: bool, filter: Option<&[Instruction]>) -> Result<PacketRing, String> {
+        let name = CString::new(interface).map_err(|_| format!("no interface named {}", interface))?;
+        let index = unsafe { if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(format!("no interface named {}", interface));
+        }
+        let hardware_type = fs::read_to_string(format!("/sys/class/net/{}/type", interface)).ok()
+            .and_then(|text| text.trim().parse::<u32>().ok());
+        if let Some(kind) = hardware_type.filter(|kind| *kind != ARPHRD_ETHER && *kind != ARPHRD_LOOPBACK) {
+            return Err(format!("only Ethernet interfaces can be captured, {} has hardware type {}", interface, kind));
+        }
+
+        //No protocol yet, so nothing is received before the filter is attached and the socket bound
+        let fd = unsafe { socket(AF_PACKET, SOCK_RAW, 0) };
+        check(fd, "open a packet socket, which needs root or CAP_NET_RAW")?;
+        let mut ring = PacketRing { fd, ring: ptr::null_mut(), block: 0, loopback: hardware_type == Some(ARPHRD_LOOPBACK) };
+
+        set_option(fd, SOL_PACKET, PACKET_VERSION, &TPACKET_V3, "use TPACKET_V3")?;
+        if let Some(filter) = filter {
+            let program = SockFprog { length: filter.len() as u16, filter: filter.as_ptr() };
+            set_option(fd, SOL_SOCKET, SO_ATTACH_FILTER, &program, "attach the filter")?;
+        }
+        let request = TpacketReq3 {
+            block_size: BLOCK_SIZE,
+            block_count: BLOCK_COUNT,
+            frame_size: FRAME_SIZE,
+            frame_count: BLOCK_SIZE / FRAME_SIZE * BLOCK_COUNT,
+            retire_block_timeout: BLOCK_TIMEOUT_MS,
+            private_size: 0,
+            feature_request: 0,
+        };
+        set_option(fd, SOL_PACKET, PACKET_RX_RING, &request, "set up the ring")?;
+        let memory = unsafe { mmap(ptr::null_mut(), ring_size(), PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0) };
+        if memory as isize == -1 {
+            return Err(format!("could not map the ring: {}", io::Error::last_os_error()));
+        }
+        ring.ring = memory as *mut u8;
+
+        if promiscuous {
+            let membership = PacketMreq { interface: index as i32, kind: PACKET_MR_PROMISC, address_length: 0, address: [0; 8] };
+            set_option(fd, SOL_PACKET, PACKET_ADD_MEMBERSHIP, &membership, "enter promiscuous mode")?;
+        }
+        let address = SockaddrLl {
+            family: AF_PACKET as u16,
+            protocol: ETH_P_ALL.to_be(),
+            interface: index as i32,
+            hardware_type: 0,
+            packet_type: 0,
+            address_length: 0,
+            address: [0; 8],
+        };
+        check(unsafe { bind(fd, &address, size_of::<SockaddrLl>() as u32) }, &format!("bind to {}", interface))?;
+        Ok(ring)
+    }
+
+    fn block_status(&self) -> *mut u32 {
+        //The status is the first field of the block header, after its version and private offset
+        unsafe { self.ring.add((self.block * BLOCK_SIZE) as usize + 8) as *mut u32 }
+    }
+
+    /// Waits up to `timeout` milliseconds for the next block, and returns its bytes once the
+    /// kernel handed it over.
+    fn next_block(&mut self, timeout: i32) -> Option<&[u8]> {
+        let status = self.block_status();
+        if unsafe { ptr::read_volatile(status) } & TP_STATUS_USER == 0 {
+            let mut poll_fd = PollFd { fd: self.fd, events: POLLIN | POLLERR, returned_events: 0 };
+            unsafe { poll(&mut poll_fd, 1, timeout) };
+            if unsafe { ptr::read_volatile(status) } & TP_STATUS_USER == 0 {
+                return None;
+            }
+        }
+        //The frames are read only after the status that says they are complete
+        fence(Ordering::Acquire);
+        Some(unsafe { slice::from_raw_parts(self.ring.add((self.block * BLOCK_SIZE) as usize), BLOCK_SIZE as usize) })
+    }
+
+    /// Hands the block that was read back to the kernel.
+    fn release_block(&mut self) {
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.block_status(), TP_STATUS_KERNEL) };
+        self.block = (self.block + 1) % BLOCK_COUNT;
+    }
+
+    /// The packets the filter accepted and those the ring had no room for, since the socket
+    /// was opened.
+    fn statistics(&self) -> TpacketStatsV3 {
+        let mut statistics = TpacketStatsV3::default();
+        let mut length = size_of::<TpacketStatsV3>() as u32;
+        unsafe {
+            getsockopt(self.fd, SOL_PACKET, PACKET_STATISTICS, &mut statistics as *mut TpacketStatsV3 as *mut c_void, &mut length);
+        }
+        statistics
+    }
+}
+
+impl Drop for PacketRing {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                munmap(self.ring as *mut c_void, ring_size());
+            }
+            close(self.fd);
+        }
+    }
+}
+
+fn ring_size() -> usize {
+    BLOCK_SIZE as usize * BLOCK_COUNT as usize
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The frames of a block. Each starts with a `tpacket3_hdr` giving the offset of the next one,
+/// the timestamp, the lengths and where the frame starts, followed by a `sockaddr_ll` saying
+/// which way the frame went.
+fn read_block(block: &[u8], skip_outgoing: bool) -> Vec<PcapBlock> {
+    let count = read_u32(block, 12);
+    let mut offset = read_u32(block, 16) as usize;
+    let mut blocks = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let header = &block[offset..];
+        offset += read_u32(header, 0) as usize;
+        if skip_outgoing && header[58] == PACKET_OUTGOING {
+            continue;
+        }
+        let captured_length = read_u32(header, 12) as usize;
+        let mut original_length = read_u32(header, 16);
+        let status = read_u32(header, 20);
+        let start = read_u16(header, 24) as usize;
+        let mut data = header[start..start + captured_length].to_vec();
+        //The kernel takes the VLAN tag out of the frame, and it goes back in as it was on the wire
+        if status & TP_STATUS_VLAN_VALID != 0 && data.len() >= 12 {
+            let tag_protocol = if status & TP_STATUS_VLAN_TPID_VALID != 0 { read_u16(header, 36) } else { 0x8100 };
+            let tag_control = read_u32(header, 32) as u16;
+            let tag = [tag_protocol.to_be_bytes(), tag_control.to_be_bytes()].concat();
+            data.splice(12..12, tag);
+            original_length += 4;
+        }
+
+        let mut pcap_block = PcapBlock::new();
+        pcap_block.timestamp_seconds = read_u32(header, 4).to_ne_bytes();
+        pcap_block.timestamp_microseconds = (read_u32(header, 8) / 1000).to_ne_bytes();
+        pcap_block.captured_length = (data.len() as u32).to_ne_bytes();
+        pcap_block.original_length = original_length.max(data.len() as u32).to_ne_bytes();
+        pcap_block.ether_frame = create_and_return_ether(&data);
+        pcap_block.data = data;
+        blocks.push(pcap_block);
+    }
+    blocks
+}
+
+/// Captures the frames of an interface until interrupted or until `-c` packets were seen. The
+/// capture filter runs in the kernel, and the frames go through the same filters and outputs
+/// as the packets of a capture file.
+pub(crate) fn capture(interface: &str, options: &CliOptions) -> Result<(), String> {
+    let program = bpf::compile(&options.filter, SNAP_LENGTH);
+    let mut ring = PacketRing::open(interface, options.promiscuous, program.as_deref())?;
+    eprintln!("Capturing on {}", interface);
+    let mut pipeline = Pipeline::new(options, SNAP_LENGTH, 1, false)?;
+    catch_interrupts();
+
+    let skip_outgoing = ring.loopback;
+    let mut number = 0;
+    while !interrupted() && !pipeline.done() {
+        let Some(block) = ring.next_block(POLL_INTERVAL.as_millis() as i32) else { continue };
+        let blocks = read_block(block, skip_outgoing);
+        ring.release_block();
+        for mut pcap_block in blocks {
+            number += 1;
+            pcap_block.number = number;
+            pipeline.process(pcap_block)?;
+        }
+    }
+
+    let statistics = ring.statistics();
+    eprintln!("{} packets captured by the kernel, {} dropped", statistics.packets, statistics.drops);
+    pipeline.finish(|count| format!("Captured {} packets on {}", count, interface))?;
+    Ok(())
+}
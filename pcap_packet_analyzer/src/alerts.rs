@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::cli_options::OutputFormat;
+use crate::internet_packet::format_ipv4;
+use crate::io_graph::parse_interval;
+use crate::json::JsonValue;
+use crate::pcap_block::format_utc;
+use crate::statistics::Statistics;
+use crate::view::{Packet, Transport};
+
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+const ECHO_REQUEST: u8 = 8;
+
+//UDP has no handshake, so a flow is remembered for this long after its last packet, or for
+//the window when that is longer
+const UDP_FLOW_TIMEOUT: f64 = 60.0;
+
+type Address = [u8; 4];
+type Service = ([u8; 4], u16);
+
+/// How much of something one source may do within one window before it raises an alert. Set
+/// with `--threshold <name>=<value>`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct AlertThresholds {
+    pub(crate) ports: usize, //Ports of one host probed by one source
+    pub(crate) hosts: usize, //Hosts probed on the same port by one source
+    pub(crate) half_open: usize, //Handshakes to one service left without their final ACK
+    pub(crate) sweep: usize, //Hosts sent an echo request by one source
+    pub(crate) pings: usize, //Echo requests from one source to one host
+    pub(crate) window: f64, //Seconds every count is kept for
+}
+
+impl AlertThresholds {
+    pub(crate) fn new() -> AlertThresholds {
+        AlertThresholds {
+            ports: 20,
+            hosts: 20,
+            half_open: 100,
+            sweep: 20,
+            pings: 100,
+            window: 10.0,
+        }
+    }
+
+    /// Changes one threshold from `name=value`, where the window takes an interval like `500ms`.
+    pub(crate) fn set(&mut self, setting: &str) -> Result<(), String> {
+        let (name, value) = setting.split_once('=')
+            .ok_or_else(|| format!("'{}' is not a threshold, expected <name>=<value>", setting))?;
+        if name == "window" {
+            self.window = parse_interval(value)? as f64 / 1_000_000.0;
+            return Ok(());
+        }
+        let count = match value.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => return Err(format!("threshold '{}' needs a count above zero", name)),
+        };
+        match name {
+            "ports" => self.ports = count,
+            "hosts" => self.hosts = count,
+            "half-open" => self.half_open = count,
+            "sweep" => self.sweep = count,
+            "pings" => self.pings = count,
+            _ => return Err(format!("unknown threshold '{}', expected ports, hosts, half-open, sweep, pings or window", name)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum AlertKind {
+    VerticalScan,
+    HorizontalScan,
+    SynFlood,
+    IcmpSweep,
+    PingFlood,
+}
+
+impl AlertKind {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            AlertKind::VerticalScan => "Vertical port scan",
+            AlertKind::HorizontalScan => "Horizontal port scan",
+            AlertKind::SynFlood => "SYN flood",
+            AlertKind::IcmpSweep => "ICMP sweep",
+            AlertKind::PingFlood => "Ping flood",
+        }
+    }
+
+    fn id(&self) -> &'static str {
+        match self {
+            AlertKind::VerticalScan => "vertical_scan",
+            AlertKind::HorizontalScan => "horizontal_scan",
+            AlertKind::SynFlood => "syn_flood",
+            AlertKind::IcmpSweep => "icmp_sweep",
+            AlertKind::PingFlood => "ping_flood",
+        }
+    }
+
+    //What the count of the alert counts
+    fn unit(&self) -> &'static str {
+        match self {
+            AlertKind::VerticalScan => "ports",
+            AlertKind::HorizontalScan | AlertKind::IcmpSweep => "hosts",
+            AlertKind::SynFlood => "half-open",
+            AlertKind::PingFlood => "requests",
+        }
+    }
+}
+
+struct Alert {
+    kind: AlertKind,
+    protocol: &'static str,
+    source: String,
+    target: String,
+    count: usize,
+    start: f64, //The start of the window the threshold was crossed in
+    end: f64, //The last packet counted towards the alert
+}
+
+//What one key has been seen doing in its current window
+struct Window<T> {
+    start: f64,
+    seen: HashSet<T>,
+    count: usize,
+    alert: Option<usize>, //The alert already raised in this window
+}
+
+impl<T: Eq + std::hash::Hash> Window<T> {
+    fn new(start: f64) -> Window<T> {
+        Window { start, seen: HashSet::new(), count: 0, alert: None }
+    }
+}
+
+/// Looks for port scans, SYN floods, ICMP sweeps and ping floods. Every count is kept per source
+/// in windows of a fixed length that start with the first packet counted, and each threshold
+/// crossed raises one alert per window that grows while the window lasts.
+pub(crate) struct Alerts {
+    thresholds: AlertThresholds,
+    format: OutputFormat,
+    alerts: Vec<Alert>,
+    vertical: HashMap<(&'static str, Address, Address), Window<u16>>,
+    horizontal: HashMap<(&'static str, Address, u16), Window<Address>>,
+    sweeps: HashMap<Address, Window<Address>>,
+    pings: HashMap<(Address, Address), Window<()>>,
+    //Handshakes waiting for their final ACK per service, with the time of their SYN
+    half_open: HashMap<Service, (HashMap<Service, f64>, Option<usize>)>,
+    //UDP flows by source and destination, with the time of their last packet
+    udp_flows: HashMap<(Service, Service), f64>,
+    last_expiry: f64,
+}
+
+impl Alerts {
+    pub(crate) fn new(thresholds: AlertThresholds, format: OutputFormat) -> Alerts {
+        Alerts {
+            thresholds,
+            format,
+            alerts: Vec::new(),
+            vertical: HashMap::new(),
+            horizontal: HashMap::new(),
+            sweeps: HashMap::new(),
+            pings: HashMap::new(),
+            half_open: HashMap::new(),
+            udp_flows: HashMap::new(),
+            last_expiry: 0.0,
+        }
+    }
+
+    /// Forgets the windows that are over and the flows that went quiet, so long and live
+    /// captures do not keep every source they ever saw.
+    fn expire(&mut self, time: f64) {
+        let window = self.thresholds.window;
+        if time - self.last_expiry < window {
+            return;
+        }
+        self.last_expiry = time;
+        self.vertical.retain(|_, state| time - state.start <= window);
+        self.horizontal.retain(|_, state| time - state.start <= window);
+        self.sweeps.retain(|_, state| time - state.start <= window);
+        self.pings.retain(|_, state| time - state.start <= window);
+        self.half_open.retain(|_, (pending, _)| {
+            pending.retain(|_, sent| time - *sent <= window);
+            !pending.is_empty()
+        });
+        let timeout = window.max(UDP_FLOW_TIMEOUT);
+        self.udp_flows.retain(|_, last| time - *last <= timeout);
+    }
+
+    //Counts `item` in the window of a key and returns the alert to raise or grow, if the threshold is crossed
+    fn count<K, T>(windows: &mut HashMap<K, Window<T>>, key: K, item: T, time: f64, window: f64, threshold: usize,
+                   distinct: bool) -> Option<(usize, Option<usize>)>
+        where K: Eq + std::hash::Hash, T: Eq + std::hash::Hash {
+        let state = windows.entry(key).or_insert_with(|| Window::new(time));
+        if time - state.start > window {
+            *state = Window::new(time);
+        }
+        state.seen.insert(item);
+        state.count += 1;
+        let count = if distinct { state.seen.len() } else { state.count };
+        (count >= threshold).then_some((count, state.alert))
+    }
+
+    //Raises a new alert or brings the one of the window up to date, and returns its index
+    fn raise(&mut self, existing: Option<usize>, alert: Alert) -> usize {
+        match existing {
+            Some(index) => {
+                self.alerts[index].source = alert.source;
+                self.alerts[index].count = alert.count;
+                self.alerts[index].end = alert.end;
+                index
+            }
+            None => {
+                self.alerts.push(alert);
+                self.alerts.len() - 1
+            }
+        }
+    }
+
+    fn add_probe(&mut self, protocol: &'static str, source: [u8; 4], target: ([u8; 4], u16), time: f64) {
+        let thresholds = self.thresholds;
+        let found = Alerts::count(&mut self.vertical, (protocol, source, target.0), target.1, time, thresholds.window,
+                                  thresholds.ports, true);
+        if let Some((count, existing)) = found {
+            let start = self.vertical[&(protocol, source, target.0)].start;
+            let index = self.raise(existing, Alert {
+                kind: AlertKind::VerticalScan,
+                protocol,
+                source: format_ipv4(&source),
+                target: format_ipv4(&target.0),
+                count,
+                start,
+                end: time,
+            });
+            self.vertical.get_mut(&(protocol, source, target.0)).unwrap().alert = Some(index);
+        }
+        let found = Alerts::count(&mut self.horizontal, (protocol, source, target.1), target.0, time, thresholds.window,
+                                  thresholds.hosts, true);
+        if let Some((count, existing)) = found {
+            let start = self.horizontal[&(protocol, source, target.1)].start;
+            let index = self.raise(existing, Alert {
+                kind: AlertKind::HorizontalScan,
+                protocol,
+                source: format_ipv4(&source),
+                target: format!("port {}", target.1),
+                count,
+                start,
+                end: time,
+            });
+            self.horizontal.get_mut(&(protocol, source, target.1)).unwrap().alert = Some(index);
+        }
+    }
+
+    fn add_tcp(&mut self, source: ([u8; 4], u16), target: ([u8; 4], u16), flags: u8, time: f64) {
+        //Handshakes are tracked from the side of the service they were sent to
+        if flags & (SYN | ACK) == SYN {
+            let window = self.thresholds.window;
+            let (pending, alert) = self.half_open.entry(target).or_insert_with(|| (HashMap::new(), None));
+            pending.retain(|_, sent| time - *sent <= window);
+            if pending.is_empty() {
+                *alert = None;
+            }
+            pending.insert(source, time);
+            if pending.len() >= self.thresholds.half_open {
+                let sources: HashSet<[u8; 4]> = pending.keys().map(|(address, _)| *address).collect();
+                let start = pending.values().copied().fold(time, f64::min);
+                let (count, existing) = (pending.len(), *alert);
+                let index = self.raise(existing, Alert {
+                    kind: AlertKind::SynFlood,
+                    protocol: "tcp",
+                    //Floods usually come from spoofed addresses, so only a single one is named
+                    source: match sources.len() {
+                        1 => format_ipv4(&source.0),
+                        sources => format!("{} sources", sources),
+                    },
+                    target: format!("{}:{}", format_ipv4(&target.0), target.1),
+                    count,
+                    start,
+                    end: time,
+                });
+                self.half_open.get_mut(&target).unwrap().1 = Some(index);
+            }
+        } else if flags & (SYN | ACK) == ACK || flags & RST != 0 {
+            //The final ACK of the handshake, or a reset from either side, closes it
+            for (service, client) in [(target, source), (source, target)] {
+                if let Some((pending, _)) = self.half_open.get_mut(&service) {
+                    pending.remove(&client);
+                }
+            }
+        }
+        //Segments without an ACK are what SYN, FIN, NULL and Xmas scans send
+        if flags & (ACK | RST) == 0 {
+            self.add_probe("tcp", source.0, target, time);
+        }
+    }
+
+    /// Counts a UDP packet as a probe when it opens a flow. Replies are not probes: neither those
+    /// of a flow seen the other way nor those sent from a well known port to an ephemeral one,
+    /// such as a resolver answering queries from fresh client ports.
+    fn add_udp(&mut self, source: Service, target: Service, time: f64) {
+        let timeout = self.thresholds.window.max(UDP_FLOW_TIMEOUT);
+        let opens = match self.udp_flows.insert((source, target), time) {
+            Some(last) => time - last > timeout,
+            None => true,
+        };
+        let reply = self.udp_flows.get(&(target, source)).is_some_and(|last| time - *last <= timeout)
+            || (source.1 < 1024 && target.1 >= 1024);
+        if opens && !reply {
+            self.add_probe("udp", source.0, target, time);
+        }
+    }
+
+    fn add_echo_request(&mut self, source: [u8; 4], target: [u8; 4], time: f64) {
+        let thresholds = self.thresholds;
+        let found = Alerts::count(&mut self.sweeps, source, target, time, thresholds.window, thresholds.sweep, true);
+        if let Some((count, existing)) = found {
+            let start = self.sweeps[&source].start;
+            let index = self.raise(existing, Alert {
+                kind: AlertKind::IcmpSweep,
+                protocol: "icmp",
+                source: format_ipv4(&source),
+                target: "echo request".to_string(),
+                count,
+                start,
+                end: time,
+            });
+            self.sweeps.get_mut(&source).unwrap().alert = Some(index);
+        }
+        let found = Alerts::count(&mut self.pings, (source, target), (), time, thresholds.window, thresholds.pings, false);
+        if let Some((count, existing)) = found {
+            let start = self.pings[&(source, target)].start;
+            let index = self.raise(existing, Alert {
+                kind: AlertKind::PingFlood,
+                protocol: "icmp",
+                source: format_ipv4(&source),
+                target: format_ipv4(&target),
+                count,
+                start,
+                end: time,
+            });
+            self.pings.get_mut(&(source, target)).unwrap().alert = Some(index);
+        }
+    }
+
+    fn json(&self, alert: &Alert) -> JsonValue {
+        JsonValue::object(vec![
+            ("alert", JsonValue::string(alert.kind.id())),
+            ("protocol", JsonValue::string(alert.protocol)),
+            ("source", JsonValue::string(&alert.source)),
+            ("target", JsonValue::string(&alert.target)),
+            ("count", (alert.count as u64).into()),
+            ("unit", JsonValue::string(alert.kind.unit())),
+            ("start", JsonValue::Float(alert.start)),
+            ("end", JsonValue::Float(alert.end)),
+        ])
+    }
+}
+
+impl Statistics for Alerts {
+    fn add(&mut self, packet: &Packet) {
+        let Some(ip) = packet.ipv4() else { return };
+        let (source, destination) = (ip.source().octets(), ip.destination().octets());
+        let time = packet.timestamp();
+        self.expire(time);
+        match ip.transport() {
+            Transport::Tcp(tcp) => self.add_tcp((source, tcp.source_port()), (destination, tcp.destination_port()), tcp.flags(), time),
+            Transport::Udp(udp) => self.add_udp((source, udp.source_port()), (destination, udp.destination_port()), time),
+            Transport::Icmp(icmp) if icmp.message_type() == ECHO_REQUEST => self.add_echo_request(source, destination, time),
+            _ => {}
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut alerts: Vec<&Alert> = self.alerts.iter().collect();
+        alerts.sort_by(|a, b| a.start.total_cmp(&b.start));
+        match self.format {
+            OutputFormat::Json => return alerts.iter().map(|alert| self.json(alert).to_compact()).collect::<Vec<_>>().join("\n"),
+            OutputFormat::JsonPretty => return alerts.iter().map(|alert| self.json(alert).to_pretty()).collect::<Vec<_>>().join("\n"),
+            _ => {}
+        }
+        let mut out = String::new();
+        writeln!(out, "Alerts").unwrap();
+        if alerts.is_empty() {
+            write!(out, "  No attacks found").unwrap();
+            return out;
+        }
+        writeln!(out, "{:<26}  {:>10}  {:<20}  {:<5}  {:<15}  {:<21}  {:>6}", "Start", "Duration", "Alert", "Proto",
+                 "Source", "Target", "Count").unwrap();
+        for alert in alerts {
+            let seconds = alert.start.floor();
+            let micros = ((alert.start - seconds) * 1_000_000.0).round().min(999_999.0);
+            writeln!(out, "{:<26}  {:>10.6}  {:<20}  {:<5}  {:<15}  {:<21}  {:>6} {}", format_utc(seconds as u32, micros as u32),
+                     alert.end - alert.start, alert.kind.name(), alert.protocol, alert.source, alert.target, alert.count,
+                     alert.kind.unit()).unwrap();
+        }
+        out.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PcapBlock;
+
+    fn udp(source: Service, destination: Service, time: u32) -> PcapBlock {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&[source.0, destination.0].concat());
+        frame.extend_from_slice(&[source.1.to_be_bytes(), destination.1.to_be_bytes(), [0, 8], [0, 0]].concat());
+        PcapBlock::from_frame(frame, time, 0)
+    }
+
+    fn alerts<'a>(blocks: impl IntoIterator<Item = &'a PcapBlock>) -> Alerts {
+        let mut alerts = Alerts::new(AlertThresholds::new(), OutputFormat::Text);
+        blocks.into_iter().for_each(|block| alerts.add(&block.view()));
+        alerts
+    }
+
+    #[test]
+    fn does_not_count_udp_replies_as_probes() {
+        let (client, resolver) = ([192, 168, 1, 10], [192, 168, 1, 1]);
+        let dns: Vec<PcapBlock> = (0..30)
+            .flat_map(|i| [udp((client, 50000 + i), (resolver, 53), 1), udp((resolver, 53), (client, 50000 + i), 1)])
+            .collect();
+        assert!(alerts(&dns).alerts.is_empty());
+        //Nor when only the answers were captured
+        assert!(alerts(dns.iter().skip(1).step_by(2)).alerts.is_empty());
+        //Replies from high ports are known by the request they answer
+        let replies: Vec<PcapBlock> = (0..30)
+            .flat_map(|i| [udp((client, 3000 + i), (resolver, 5000), 1), udp((resolver, 5000), (client, 3000 + i), 1)])
+            .collect();
+        assert!(alerts(&replies).alerts.is_empty());
+
+        let scan: Vec<PcapBlock> = (0..30).map(|port| udp((client, 40000), (resolver, port + 1), 1)).collect();
+        let found = alerts(&scan);
+        assert_eq!(found.alerts.len(), 1);
+        assert_eq!((found.alerts[0].kind, found.alerts[0].count), (AlertKind::VerticalScan, 30));
+    }
+
+    #[test]
+    fn forgets_windows_that_are_over() {
+        let blocks: Vec<PcapBlock> = (0..1000_u32)
+            .map(|i| udp(([10, 0, (i / 250) as u8, i as u8], 40000), ([10, 1, 0, 1], 53), i))
+            .collect();
+        let found = alerts(&blocks);
+        assert!(found.alerts.is_empty());
+        assert!(found.vertical.len() <= 21 && found.horizontal.len() <= 21, "{} {}", found.vertical.len(), found.horizontal.len());
+        assert!(found.udp_flows.len() <= 71, "{}", found.udp_flows.len());
+    }
+}
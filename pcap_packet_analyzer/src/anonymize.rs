@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use crate::PcapBlock;
+use crate::aes::Aes128;
+use crate::frame_layout::{internet_checksum, recompute_checksums, FrameLayout};
+use crate::pcap_writer::PcapWriter;
+
+/// What happens to the bytes after the transport header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum PayloadPolicy {
+    Keep,
+    Zero,
+    Truncate(usize), //Keeps this many bytes and shortens the packet to end after them
+}
+
+impl PayloadPolicy {
+    pub(crate) fn from_str(text: &str) -> Result<PayloadPolicy, String> {
+        let (policy, argument) = text.split_once(',').unwrap_or((text, ""));
+        match (policy, argument) {
+            ("keep", "") => Ok(PayloadPolicy::Keep),
+            ("zero", "") => Ok(PayloadPolicy::Zero),
+            ("truncate", "") => Ok(PayloadPolicy::Truncate(0)),
+            ("truncate", bytes) => bytes.parse().map(PayloadPolicy::Truncate)
+                .map_err(|_| format!("'{}' is not a number of bytes", bytes)),
+            _ => Err(format!("unknown payload handling '{}', expected keep, zero, truncate or truncate,<bytes>", text)),
+        }
+    }
+}
+
+/// What `--anonymize` was asked to do.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AnonymizeOptions {
+    pub(crate) output: String,
+    pub(crate) key: Option<[u8; 32]>, //A random key is used when none was given
+    pub(crate) macs: bool,
+    pub(crate) payload: PayloadPolicy,
+}
+
+impl AnonymizeOptions {
+    pub(crate) fn new(output: String) -> AnonymizeOptions {
+        AnonymizeOptions {
+            output,
+            key: None,
+            macs: false,
+            payload: PayloadPolicy::Keep,
+        }
+    }
+}
+
+/// Reads a key written as 64 hexadecimal digits.
+pub(crate) fn parse_key(text: &str) -> Result<[u8; 32], String> {
+    let error = || format!("the anonymization key must be 64 hexadecimal digits, got '{}'", text);
+    if text.len() != 64 || !text.is_ascii() {
+        return Err(error());
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| error())?;
+    }
+    Ok(key)
+}
+
+fn format_key(key: &[u8; 32]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The prefix-preserving anonymization of Xu, Fan, Ammar and Moon: two addresses sharing an
+/// n-bit prefix still share an n-bit prefix afterwards. The first half of the key is the AES
+/// key, the second half is encrypted into the pad.
+pub(crate) struct CryptoPan {
+    aes: Aes128,
+    pad: u128,
+}
+
+impl CryptoPan {
+    pub(crate) fn new(key: &[u8; 32]) -> CryptoPan {
+        let aes = Aes128::new(key[..16].try_into().unwrap());
+        let pad = u128::from_be_bytes(aes.encrypt(key[16..].try_into().unwrap()));
+        CryptoPan { aes, pad }
+    }
+
+    pub(crate) fn anonymize_ipv4(&self, address: [u8; 4]) -> [u8; 4] {
+        let anonymized = self.anonymize_bits((u32::from_be_bytes(address) as u128) << 96, 32);
+        ((anonymized >> 96) as u32).to_be_bytes()
+    }
+
+    pub(crate) fn anonymize_ipv6(&self, address: [u8; 16]) -> [u8; 16] {
+        self.anonymize_bits(u128::from_be_bytes(address), 128).to_be_bytes()
+    }
+
+    //Every bit is flipped by a function of the bits before it, the address being left aligned
+    fn anonymize_bits(&self, address: u128, bits: u32) -> u128 {
+        let mut flips = 0;
+        for position in 0..bits {
+            let prefix_mask = if position == 0 { 0 } else { u128::MAX << (128 - position) };
+            let input = (address & prefix_mask) | (self.pad & !prefix_mask);
+            let output = self.aes.encrypt(&input.to_be_bytes());
+            flips |= ((output[0] >> 7) as u128) << (127 - position);
+        }
+        address ^ flips
+    }
+}
+
+/// Writes the packets to a new capture with their addresses anonymized, the payloads handled
+/// as asked and every checksum fixed up.
+pub(crate) struct Anonymizer {
+    crypto_pan: CryptoPan,
+    options: AnonymizeOptions,
+    writer: PcapWriter<BufWriter<File>>,
+    ipv4: HashMap<[u8; 4], [u8; 4]>,
+    ipv6: HashMap<[u8; 16], [u8; 16]>,
+    pub(crate) zeroed: u64, //Packets whose contents could not be parsed and were zeroed
+}
+
+impl Anonymizer {
+    pub(crate) fn new(options: &AnonymizeOptions, snap_length: u32, link_type: u32) -> Result<Anonymizer, String> {
+        if link_type != 1 {
+            return Err(format!("only Ethernet captures can be anonymized, this one has link type {}", link_type));
+        }
+        let key = match options.key {
+            Some(key) => key,
+            None => {
+                //Printed so the same mapping can be used again on another capture
+                let key = random_key()?;
+                eprintln!("Anonymizing with the key {}", format_key(&key));
+                key
+            }
+        };
+        let file = File::create(&options.output).map_err(|error| format!("could not create {}: {}", options.output, error))?;
+        let writer = PcapWriter::new(BufWriter::new(file), snap_length, link_type)
+            .map_err(|error| format!("could not write {}: {}", options.output, error))?;
+        Ok(Anonymizer {
+            crypto_pan: CryptoPan::new(&key),
+            options: options.clone(),
+            writer,
+            ipv4: HashMap::new(),
+            ipv6: HashMap::new(),
+            zeroed: 0,
+        })
+    }
+
+    pub(crate) fn add(&mut self, block: &PcapBlock) -> Result<(), String> {
+        let mut data = block.data.clone();
+        let mut original_length = u32::from_ne_bytes(block.original_length);
+        let layout = FrameLayout::parse(&data);
+        if self.options.macs && data.len() >= 12 {
+            self.anonymize_mac(&mut data, 0);
+            self.anonymize_mac(&mut data, 6);
+        }
+        let network = layout.network;
+        let arp = layout.ether_type == 0x0806 && data.len() >= network + 28 && data[network + 4] == 6 && data[network + 5] == 4;
+        //Whatever is not parsed could hold addresses, so it is zeroed rather than passed on: frames
+        //cut short in their headers, MPLS, PPPoE and every other EtherType
+        if !arp && layout.ip.is_none() {
+            let start = if data.len() < 14 { 0 } else { network };
+            if data[start..].iter().any(|&byte| byte != 0) {
+                data[start..].fill(0);
+                self.zeroed += 1;
+            }
+        }
+        //ARP for IPv4 over Ethernet carries both addresses of both sides
+        if arp {
+            self.anonymize_ipv4(&mut data, network + 14);
+            self.anonymize_ipv4(&mut data, network + 24);
+            if self.options.macs {
+                self.anonymize_mac(&mut data, network + 8);
+                self.anonymize_mac(&mut data, network + 18);
+            }
+        }
+
+        if let Some(ip) = layout.ip {
+            self.anonymize_ip_addresses(&mut data, ip.version, ip.start);
+            let first_fragment = match ip.version {
+                4 => u16::from_be_bytes([data[ip.start + 6], data[ip.start + 7]]) & 0x1fff == 0,
+                _ => !ip.fragment,
+            };
+            let end = ip.end.min(data.len());
+            let icmp_error = match (ip.version, ip.protocol) {
+                (4, 1) => data.get(ip.transport).is_some_and(|kind| matches!(kind, 3 | 4 | 5 | 11 | 12)),
+                (6, 58) => data.get(ip.transport).is_some_and(|kind| (1..=4).contains(kind)),
+                _ => false,
+            };
+            let header_length = match ip.protocol {
+                _ if !first_fragment => 0,
+                _ if icmp_error => end.saturating_sub(ip.transport), //The quoted packet is header, not payload
+                6 => data.get(ip.transport + 12).map_or(20, |offset| (offset >> 4) as usize * 4),
+                17 | 1 | 58 => 8,
+                _ => 0,
+            };
+            if icmp_error && end > ip.transport + 8 {
+                self.anonymize_quoted_packet(&mut data, ip.transport + 8, end);
+            }
+            //Tunnels carry whole packets with addresses of their own, and an IPv6 extension header
+            //is only left over when the packet was cut short inside it
+            if matches!(ip.protocol, 4 | 41 | 47) || (ip.version == 6 && matches!(ip.protocol, 0 | 43 | 44 | 60)) {
+                data[ip.transport.min(end)..end].fill(0);
+            }
+            let payload_start = (ip.transport + header_length).min(end);
+            match self.options.payload {
+                PayloadPolicy::Keep => {}
+                PayloadPolicy::Zero => data[payload_start..end].fill(0),
+                PayloadPolicy::Truncate(keep) if ip.end > payload_start + keep => {
+                    //Everything after the kept bytes goes, including what the capture had already cut off
+                    let new_end = (payload_start + keep).min(end);
+                    let removed = ip.end - new_end;
+                    data.truncate(new_end);
+                    original_length = data.len() as u32;
+                    let shorten = |data: &mut Vec<u8>, offset: usize| {
+                        let length = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+                        data[offset..offset + 2].copy_from_slice(&(length.saturating_sub(removed) as u16).to_be_bytes());
+                    };
+                    shorten(&mut data, if ip.version == 4 { ip.start + 2 } else { ip.start + 4 });
+                    if ip.protocol == 17 && first_fragment && !ip.fragment && data.len() >= ip.transport + 8 {
+                        shorten(&mut data, ip.transport + 4);
+                    }
+                }
+                PayloadPolicy::Truncate(_) => {}
+            }
+        }
+
+        recompute_checksums(&mut data);
+        self.writer.write_packet(u32::from_ne_bytes(block.timestamp_seconds), u32::from_ne_bytes(block.timestamp_microseconds),
+                                 original_length, &data)
+            .map_err(|error| format!("could not write {}: {}", self.options.output, error))
+    }
+
+    /// Finishes the file and returns the number of packets written.
+    pub(crate) fn finish(&mut self) -> Result<u64, String> {
+        self.writer.flush().map_err(|error| format!("could not write {}: {}", self.options.output, error))?;
+        Ok(self.writer.packet_count)
+    }
+
+    fn anonymize_ip_addresses(&mut self, data: &mut [u8], version: u8, start: usize) {
+        if version == 4 {
+            self.anonymize_ipv4(data, start + 12);
+            self.anonymize_ipv4(data, start + 16);
+        } else {
+            self.anonymize_ipv6(data, start + 8);
+            self.anonymize_ipv6(data, start + 24);
+        }
+    }
+
+    fn anonymize_ipv4(&mut self, data: &mut [u8], offset: usize) {
+        let Some(bytes) = data.get_mut(offset..offset + 4) else { return };
+        let address: [u8; 4] = (*bytes).try_into().unwrap();
+        let crypto_pan = &self.crypto_pan;
+        bytes.copy_from_slice(self.ipv4.entry(address).or_insert_with(|| crypto_pan.anonymize_ipv4(address)));
+    }
+
+    fn anonymize_ipv6(&mut self, data: &mut [u8], offset: usize) {
+        let Some(bytes) = data.get_mut(offset..offset + 16) else { return };
+        let address: [u8; 16] = (*bytes).try_into().unwrap();
+        let crypto_pan = &self.crypto_pan;
+        bytes.copy_from_slice(self.ipv6.entry(address).or_insert_with(|| crypto_pan.anonymize_ipv6(address)));
+    }
+
+    /// Replaces a MAC address by a keyed pseudonym marked as locally administered. Broadcast and
+    /// multicast addresses say nothing about a host and are kept.
+    fn anonymize_mac(&self, data: &mut [u8], offset: usize) {
+        let Some(bytes) = data.get_mut(offset..offset + 6) else { return };
+        if bytes[0] & 0x01 != 0 {
+            return;
+        }
+        let mut input = [0_u8; 16];
+        input[0] = b'M'; //Keeps the blocks apart from those of the addresses
+        input[1..7].copy_from_slice(bytes);
+        let output = self.crypto_pan.aes.encrypt(&input);
+        bytes.copy_from_slice(&output[..6]);
+        bytes[0] = (bytes[0] & 0xfc) | 0x02;
+    }
+
+    //The packet an ICMP error quotes has the same addresses, which would give the originals away
+    fn anonymize_quoted_packet(&mut self, data: &mut [u8], start: usize, end: usize) {
+        match data[start] >> 4 {
+            4 if end >= start + 20 => {
+                self.anonymize_ip_addresses(data, 4, start);
+                let header_end = (start + (data[start] & 0x0f) as usize * 4).min(end);
+                data[start + 10..start + 12].fill(0);
+                let checksum = internet_checksum(&data[start..header_end]);
+                data[start + 10..start + 12].copy_from_slice(&checksum.to_be_bytes());
+            }
+            6 if end >= start + 40 => self.anonymize_ip_addresses(data, 6, start),
+            _ => data[start..end].fill(0),
+        }
+    }
+}
+
+fn random_key() -> Result<[u8; 32], String> {
+    let mut key = [0; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut key))
+        .map_err(|error| format!("could not make a random key, give one with --anon-key: {}", error))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::PcapReader;
+
+    const KEY: [u8; 32] = [0xab; 32];
+
+    fn ethernet(ether_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2];
+        frame.extend_from_slice(&ether_type.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn ipv4(protocol: u8, source: [u8; 4], destination: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0];
+        packet[2..4].copy_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&destination);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// The frames as they are written by an anonymizer with `KEY`, and how many were zeroed.
+    fn anonymize(name: &str, options: &[fn(&mut AnonymizeOptions)], frames: &[Vec<u8>]) -> (Vec<Vec<u8>>, u64) {
+        let path = std::env::temp_dir().join(format!("packet_analyzer-{}-{}", std::process::id(), name));
+        let mut anonymize_options = AnonymizeOptions::new(path.display().to_string());
+        anonymize_options.key = Some(KEY);
+        options.iter().for_each(|option| option(&mut anonymize_options));
+        let mut anonymizer = Anonymizer::new(&anonymize_options, 65535, 1).unwrap();
+        for frame in frames {
+            anonymizer.add(&PcapBlock::from_frame(frame.clone(), 0, 0)).unwrap();
+        }
+        anonymizer.finish().unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        (PcapReader::new(&written[..]).unwrap().map(|block| block.data).collect(), anonymizer.zeroed)
+    }
+
+    #[test]
+    fn zeroes_what_it_cannot_parse() {
+        let private = ipv4(17, [10, 0, 0, 1], [10, 0, 0, 2], &[0; 8]);
+        let frames = [
+            ethernet(0x8847, &[[0, 1, 0x01, 64].as_slice(), &private].concat()), //MPLS
+            ethernet(0x8864, &[[0x11, 0, 0, 1, 0, 30, 0, 0x21].as_slice(), &private].concat()), //PPPoE
+            ethernet(0x0800, &private[..16]), //Cut short inside the source address
+            ethernet(0x86dd, &[0x60; 30]),
+            ethernet(0x8100, &[0, 1]), //A VLAN tag cut short
+            vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0],
+        ];
+        let (written, zeroed) = anonymize("zeroes.pcap", &[], &frames);
+        assert_eq!(zeroed, frames.len() as u64);
+        for (frame, written) in frames.iter().zip(&written) {
+            assert_eq!(written.len(), frame.len());
+            let header = if frame.len() < 14 { 0 } else { 14 };
+            assert_eq!(written[..header], frame[..header]);
+            assert!(written[header..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn zeroes_the_packets_inside_tunnels_and_partial_quotes() {
+        let inner = ipv4(17, [10, 0, 0, 1], [10, 0, 0, 2], &[0; 8]);
+        let tunnel = ethernet(0x0800, &ipv4(4, [192, 0, 2, 1], [192, 0, 2, 2], &inner));
+        //A port unreachable quoting only the first 16 bytes of the packet, its source address among them
+        let unreachable = [[3, 3, 0, 0, 0, 0, 0, 0].as_slice(), &inner[..16]].concat();
+        let quote = ethernet(0x0800, &ipv4(1, [192, 0, 2, 1], [192, 0, 2, 2], &unreachable));
+        let (written, zeroed) = anonymize("tunnels.pcap", &[], &[tunnel.clone(), quote.clone()]);
+        assert_eq!(zeroed, 0);
+        assert_ne!(written[0][26..34], tunnel[26..34]);
+        assert!(written[0][34..].iter().all(|&byte| byte == 0));
+        assert_ne!(written[1][26..34], quote[26..34]);
+        assert!(written[1][42..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn maps_addresses_like_the_reference_implementation() {
+        //The key and the first addresses of the sample trace that comes with Crypto-PAn
+        let key = [21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16,
+                   216, 152, 143, 131, 121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2];
+        let crypto_pan = CryptoPan::new(&key);
+        for (address, anonymized) in [
+            ([128, 11, 68, 132], [135, 242, 180, 132]),
+            ([129, 118, 74, 4], [134, 136, 186, 123]),
+            ([130, 132, 252, 244], [133, 68, 164, 234]),
+            ([141, 223, 7, 43], [141, 167, 8, 160]),
+            ([141, 233, 145, 108], [141, 129, 237, 235]),
+        ] {
+            assert_eq!(crypto_pan.anonymize_ipv4(address), anonymized);
+        }
+    }
+
+    #[test]
+    fn keeps_prefixes_shared() {
+        let crypto_pan = CryptoPan::new(&KEY);
+        let first = u128::from_be_bytes(crypto_pan.anonymize_ipv6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]));
+        let second = u128::from_be_bytes(crypto_pan.anonymize_ipv6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 1]));
+        assert_eq!((first ^ second).leading_zeros(), 96);
+    }
+
+    //The checksum over the pseudo-header and the segment, its own checksum included
+    fn transport_checksum(frame: &[u8], protocol: u8) -> u16 {
+        let segment = &frame[34..];
+        let mut covered = frame[26..34].to_vec();
+        covered.extend_from_slice(&[0, protocol]);
+        covered.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        covered.extend_from_slice(segment);
+        internet_checksum(&covered)
+    }
+
+    #[test]
+    fn writes_valid_checksums() {
+        let tcp = [[0x30, 0x39, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0].as_slice(), b"payload"].concat();
+        let udp = [[0x30, 0x39, 0, 53, 0, 15, 0x12, 0x34].as_slice(), b"payload"].concat();
+        let frames = [
+            ethernet(0x0800, &ipv4(6, [10, 1, 2, 3], [172, 16, 0, 9], &tcp)),
+            ethernet(0x0800, &ipv4(17, [10, 1, 2, 3], [172, 16, 0, 9], &udp)),
+        ];
+        let (written, _) = anonymize("checksums.pcap", &[|options| options.payload = PayloadPolicy::Truncate(3)], &frames);
+        for ((frame, written), protocol) in frames.iter().zip(&written).zip([6, 17]) {
+            assert_ne!(written[26..34], frame[26..34]);
+            assert_eq!(internet_checksum(&written[14..34]), 0);
+            assert_eq!(transport_checksum(written, protocol), 0);
+            assert_eq!(&written[written.len() - 3..], b"pay");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::Filter;
+
+//Instruction classes and modes of classic BPF, from linux/filter.h
+const LD_W_ABS: u16 = 0x20;
+const LD_H_ABS: u16 = 0x28;
+const LD_B_ABS: u16 = 0x30;
+const LD_H_IND: u16 = 0x48;
+const LDX_B_MSH: u16 = 0xb1; //X = 4 * (byte & 0x0f), the length of the IPv4 header
+const JEQ_K: u16 = 0x15;
+const RET_K: u16 = 0x06;
+
+const ETHER_TYPE: u32 = 12;
+const IPV4_PROTOCOL: u32 = 23;
+const IPV4_SOURCE: u32 = 26;
+const IPV4_DESTINATION: u32 = 30;
+const IPV4_HEADER: u32 = 14;
+
+/// One instruction of a classic BPF program, laid out as the kernel's `struct sock_filter`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Instruction {
+    code: u16,
+    jump_true: u8, //Instructions skipped when the comparison holds
+    jump_false: u8,
+    k: u32,
+}
+
+fn statement(code: u16, k: u32) -> Instruction {
+    Instruction { code, jump_true: 0, jump_false: 0, k }
+}
+
+fn jump(k: u32, jump_true: u8, jump_false: u8) -> Instruction {
+    Instruction { code: JEQ_K, jump_true, jump_false, k }
+}
+
+/// Compiles the capture filter into a program the kernel runs on every frame, so the frames
+/// it rejects are never copied to the capture. It accepts exactly the frames the filter
+/// matches, keeping up to `snap_length` bytes of them. None when there is nothing to compile,
+/// for no filter or `-c`.
+pub(crate) fn compile(filter: &Filter, snap_length: u32) -> Option<Vec<Instruction>> {
+    let accept = statement(RET_K, snap_length);
+    let reject = statement(RET_K, 0);
+    //Every program starts by checking for IPv4, jumping to the reject at its end otherwise
+    let ipv4 = |rest: &[Instruction]| {
+        let mut program = vec![statement(LD_H_ABS, ETHER_TYPE), jump(0x0800, 0, rest.len() as u8 + 1)];
+        program.extend_from_slice(rest);
+        program.push(accept);
+        program.push(reject);
+        program
+    };
+    let program = match *filter {
+        Filter::Ip => ipv4(&[]),
+        Filter::Tcp => ipv4(&[statement(LD_B_ABS, IPV4_PROTOCOL), jump(6, 0, 1)]),
+        Filter::Udp => ipv4(&[statement(LD_B_ABS, IPV4_PROTOCOL), jump(17, 0, 1)]),
+        Filter::Icmp => ipv4(&[statement(LD_B_ABS, IPV4_PROTOCOL), jump(1, 0, 1)]),
+        Filter::Host(address) | Filter::Net(address) => {
+            let address = u32::from_be_bytes(address);
+            ipv4(&[
+                statement(LD_W_ABS, IPV4_SOURCE),
+                jump(address, 2, 0),
+                statement(LD_W_ABS, IPV4_DESTINATION),
+                jump(address, 0, 1),
+            ])
+        }
+        Filter::Port(port) => {
+            let port = u16::from_be_bytes(port) as u32;
+            ipv4(&[
+                statement(LD_B_ABS, IPV4_PROTOCOL),
+                jump(6, 1, 0),
+                jump(17, 0, 6),
+                statement(LDX_B_MSH, IPV4_HEADER),
+                statement(LD_H_IND, IPV4_HEADER), //The source port, right after the IPv4 header
+                jump(port, 2, 0),
+                statement(LD_H_IND, IPV4_HEADER + 2),
+                jump(port, 0, 1),
+            ])
+        }
+        Filter::Count(_) | Filter::Default(_) => return None,
+    };
+    Some(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Row = (u16, u8, u8, u32); //Code, jumps and constant of an instruction
+
+    /// Runs the program over the frame as the kernel would, returning the bytes kept.
+    fn run(program: &[Instruction], frame: &[u8]) -> u32 {
+        let load = |offset: usize, size: usize| {
+            frame[offset..offset + size].iter().fold(0, |value, &byte| value << 8 | byte as u32)
+        };
+        let (mut a, mut x, mut pc) = (0, 0, 0);
+        loop {
+            let instruction = program[pc];
+            let k = instruction.k;
+            match instruction.code {
+                LD_W_ABS => a = load(k as usize, 4),
+                LD_H_ABS => a = load(k as usize, 2),
+                LD_B_ABS => a = load(k as usize, 1),
+                LD_H_IND => a = load((x + k) as usize, 2),
+                LDX_B_MSH => x = 4 * (load(k as usize, 1) & 0x0f),
+                JEQ_K => pc += if a == k { instruction.jump_true } else { instruction.jump_false } as usize,
+                RET_K => return k,
+                code => panic!("unknown instruction {:#x}", code),
+            }
+            pc += 1;
+        }
+    }
+
+    fn ipv4(protocol: u8, options: usize, transport: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00, 0x45 + options as u8, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend(std::iter::repeat_n(1, options * 4));
+        frame.extend_from_slice(transport);
+        frame
+    }
+
+    #[test]
+    fn compiles_every_filter() {
+        let header = [(LD_H_ABS, 0, 0, 12)];
+        let cases: [(Filter, &[Row]); 7] = [
+            (Filter::Ip, &[(JEQ_K, 0, 1, 0x0800)]),
+            (Filter::Tcp, &[(JEQ_K, 0, 3, 0x0800), (LD_B_ABS, 0, 0, 23), (JEQ_K, 0, 1, 6)]),
+            (Filter::Udp, &[(JEQ_K, 0, 3, 0x0800), (LD_B_ABS, 0, 0, 23), (JEQ_K, 0, 1, 17)]),
+            (Filter::Icmp, &[(JEQ_K, 0, 3, 0x0800), (LD_B_ABS, 0, 0, 23), (JEQ_K, 0, 1, 1)]),
+            (Filter::Host([10, 0, 0, 2]), &[
+                (JEQ_K, 0, 5, 0x0800),
+                (LD_W_ABS, 0, 0, 26), (JEQ_K, 2, 0, 0x0a000002),
+                (LD_W_ABS, 0, 0, 30), (JEQ_K, 0, 1, 0x0a000002),
+            ]),
+            (Filter::Net([192, 168, 1, 0]), &[
+                (JEQ_K, 0, 5, 0x0800),
+                (LD_W_ABS, 0, 0, 26), (JEQ_K, 2, 0, 0xc0a80100),
+                (LD_W_ABS, 0, 0, 30), (JEQ_K, 0, 1, 0xc0a80100),
+            ]),
+            (Filter::Port([0, 53]), &[
+                (JEQ_K, 0, 9, 0x0800),
+                (LD_B_ABS, 0, 0, 23), (JEQ_K, 1, 0, 6), (JEQ_K, 0, 6, 17),
+                (LDX_B_MSH, 0, 0, 14), (LD_H_IND, 0, 0, 14), (JEQ_K, 2, 0, 53),
+                (LD_H_IND, 0, 0, 16), (JEQ_K, 0, 1, 53),
+            ]),
+        ];
+        for (filter, body) in cases {
+            let expected: Vec<Instruction> = header.iter().chain(body).chain(&[(RET_K, 0, 0, 96), (RET_K, 0, 0, 0)])
+                .map(|&(code, jump_true, jump_false, k)| Instruction { code, jump_true, jump_false, k })
+                .collect();
+            assert_eq!(compile(&filter, 96), Some(expected), "{:?}", filter);
+        }
+        assert_eq!(compile(&Filter::Count(10), 96), None);
+        assert_eq!(compile(&Filter::Default("Default".to_string()), 96), None);
+    }
+
+    #[test]
+    fn accepts_what_the_filter_matches() {
+        let tcp = ipv4(6, 0, &[0x04, 0xd2, 0, 80]);
+        let udp = ipv4(17, 2, &[0, 53, 0x04, 0xd2]); //The ports follow the options
+        let icmp = ipv4(1, 0, &[8, 0, 0, 0]);
+        let mut arp = ipv4(6, 0, &[0, 53, 0, 53]);
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+        let cases = [
+            (Filter::Ip, [true, true, true, false]),
+            (Filter::Tcp, [true, false, false, false]),
+            (Filter::Udp, [false, true, false, false]),
+            (Filter::Icmp, [false, false, true, false]),
+            (Filter::Host([10, 0, 0, 1]), [true, true, true, false]),
+            (Filter::Host([10, 0, 0, 3]), [false, false, false, false]),
+            (Filter::Port([0, 80]), [true, false, false, false]),
+            (Filter::Port([0, 53]), [false, true, false, false]),
+            (Filter::Port([0x04, 0xd2]), [true, true, false, false]),
+        ];
+        for (filter, expected) in cases {
+            let program = compile(&filter, 65535).unwrap();
+            for (frame, accepted) in [&tcp, &udp, &icmp, &arp].into_iter().zip(expected) {
+                assert_eq!(run(&program, frame), if accepted { 65535 } else { 0 }, "{:?} on {:?}", filter, frame);
+            }
+        }
+    }
+}
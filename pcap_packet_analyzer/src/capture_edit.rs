@@ -0,0 +1,383 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Read};
+use crate::{PcapBlock, PcapReader, PcapWriter};
+use crate::cli_options::{parse_count, parse_megabytes, parse_u32};
+use crate::compressed;
+use crate::pcap_writer::{RotatingPcapWriter, WriteOptions};
+
+//The commands that work on whole capture files, in the way of mergecap and editcap
+const COMMANDS: [&str; 5] = ["merge", "split", "slice", "truncate", "dedup"];
+
+//How far apart in time two copies of a packet can be for dedup, in microseconds
+const DEFAULT_WINDOW: u64 = 1_000_000;
+
+pub(crate) fn is_command(word: &str) -> bool {
+    COMMANDS.contains(&word)
+}
+
+/// What a command does to the packets it reads.
+enum Edit {
+    Merge,
+    Split,
+    Slice(Vec<(u32, u32)>), //The first and last packet number of every range, from 1
+    Truncate(u32), //The bytes kept of every packet
+    Dedup(u64), //The window in microseconds
+}
+
+struct EditOptions {
+    edit: Edit,
+    inputs: Vec<String>,
+    output: WriteOptions, //Only split has more than one file to write
+}
+
+impl EditOptions {
+    fn from_args(command: &str, args: Vec<String>) -> Result<EditOptions, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+        let mut split = (None, None, None);
+        let mut ranges = Vec::new();
+        let mut snap_length = None;
+        let mut window = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match (command, arg.as_str()) {
+                (_, "-w") => output = Some(args.next().ok_or("-w needs an output file")?),
+                ("split", "-C") => split.0 = Some(parse_megabytes("-C", args.next())?),
+                ("split", "-G") => split.1 = Some(parse_u32("-G", "seconds", args.next())?),
+                ("split", "-c") => split.2 = Some(parse_count("-c", "packets", args.next())?),
+                ("slice", "-r") => ranges.extend(parse_ranges(&args.next().ok_or("-r needs packet ranges such as 1-100,250,300-")?)?),
+                ("truncate", "-s") => {
+                    snap_length = Some(u32::try_from(parse_count("-s", "bytes", args.next())?).unwrap_or(u32::MAX));
+                }
+                ("dedup", "--window") => window = Some(parse_window(args.next())?),
+                //A lone - is stdin
+                (_, option) if option.starts_with('-') && option != "-" => {
+                    return Err(format!("{} is not an option of {}", option, command));
+                }
+                _ => inputs.push(arg),
+            }
+        }
+
+        let output = output.ok_or(format!("{} needs -w <output file>", command))?;
+        if inputs.is_empty() {
+            return Err(format!("{} needs a capture file to read", command));
+        }
+        if command != "merge" && inputs.len() > 1 {
+            return Err(format!("{} reads a single capture file, merge them first", command));
+        }
+        if inputs.iter().filter(|input| *input == "-").count() > 1 {
+            return Err("stdin can only be read once".to_string());
+        }
+        if inputs.contains(&output) {
+            return Err(format!("{} would be written over while it is read", output));
+        }
+        let edit = match command {
+            "merge" => Edit::Merge,
+            "split" if split == (None, None, None) => return Err("split needs -c <packets>, -C <megabytes> or -G <seconds>".to_string()),
+            "split" => Edit::Split,
+            "slice" if ranges.is_empty() => return Err("slice needs -r <ranges>".to_string()),
+            "slice" => Edit::Slice(ranges),
+            "truncate" => Edit::Truncate(snap_length.ok_or("truncate needs -s <bytes>")?),
+            _ => Edit::Dedup(window.unwrap_or(DEFAULT_WINDOW)),
+        };
+        let mut output = WriteOptions::new(output);
+        (output.file_size, output.seconds, output.packets) = split;
+        Ok(EditOptions { edit, inputs, output })
+    }
+}
+
+/// Ranges of packet numbers like `1-100,250,300-`, the last one going on to the end.
+fn parse_ranges(text: &str) -> Result<Vec<(u32, u32)>, String> {
+    let number = |text: &str| match text.parse() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("'{}' is not a packet number", text)),
+    };
+    text.split(',').map(|range| {
+        let (first, last) = match range.split_once('-') {
+            Some((first, "")) => (number(first)?, u32::MAX),
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(format!("the range {} ends before it starts", range));
+        }
+        Ok((first, last))
+    }).collect()
+}
+
+fn parse_window(value: Option<String>) -> Result<u64, String> {
+    let value = value.ok_or("--window needs a number of seconds")?;
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok((seconds * 1_000_000.0).round() as u64),
+        _ => Err(format!("'{}' is not a number of seconds", value)),
+    }
+}
+
+/// Runs a command on capture files, with the arguments that follow its name.
+pub(crate) fn run(command: &str, args: Vec<String>) -> Result<(), String> {
+    let options = EditOptions::from_args(command, args)?;
+    match &options.edit {
+        Edit::Merge => merge(&options),
+        Edit::Split => split(&options),
+        Edit::Slice(ranges) => slice(&options, ranges),
+        Edit::Truncate(snap_length) => truncate(&options, *snap_length),
+        Edit::Dedup(window) => dedup(&options, *window),
+    }
+}
+
+/// Writes the packets of every file in the order of their timestamps, those of the file given
+/// first going first when they are the same.
+fn merge(options: &EditOptions) -> Result<(), String> {
+    let mut readers = Vec::new();
+    for input in &options.inputs {
+        readers.push(open(input)?);
+    }
+    let link_type = readers[0].link_type();
+    for (reader, input) in readers.iter().zip(&options.inputs) {
+        if reader.link_type() != link_type {
+            return Err(format!("{} has link type {} and {} has {}, they cannot be merged",
+                               options.inputs[0], link_type, input, reader.link_type()));
+        }
+    }
+    let snap_length = readers.iter().map(|reader| reader.snap_length()).max().unwrap_or(0);
+    //With one file in nanoseconds they all are, the microseconds of the others scaled up
+    let nanosecond = readers.iter().any(|reader| reader.nanosecond());
+    let mut writer = create(&options.output.path, snap_length, link_type, nanosecond)?;
+
+    //The next packet of every file, queued by its timestamp and then the position of its file
+    let mut next: Vec<Option<PcapBlock>> = readers.iter_mut().map(|reader| reader.next()).collect();
+    let mut queue: BinaryHeap<_> = next.iter().enumerate()
+        .filter_map(|(index, block)| block.as_ref().map(|block| Reverse((timestamp(block), index))))
+        .collect();
+    while let Some(Reverse((_, index))) = queue.pop() {
+        let block = next[index].take().unwrap();
+        write(&mut writer, &block, &options.output.path)?;
+        next[index] = readers[index].next();
+        if let Some(block) = &next[index] {
+            queue.push(Reverse((timestamp(block), index)));
+        }
+    }
+    for (reader, input) in readers.iter_mut().zip(&options.inputs) {
+        check(reader, input)?;
+    }
+    finish(&mut writer, &options.output.path)?;
+    println!("Merged {} packets from {} files into {}", writer.packet_count, readers.len(), options.output.path);
+    Ok(())
+}
+
+/// Writes the packets to numbered files, a new one starting after a number of packets, a size
+/// or a time span.
+fn split(options: &EditOptions) -> Result<(), String> {
+    let input = &options.inputs[0];
+    let mut reader = open(input)?;
+    let mut writer = RotatingPcapWriter::new(&options.output, reader.snap_length(), reader.link_type(), reader.nanosecond())?;
+    for block in &mut reader {
+        writer.add(&block)?;
+    }
+    check(&mut reader, input)?;
+    writer.finish()?;
+    println!("Wrote {} packets to {} files", writer.packet_count, writer.file_count);
+    Ok(())
+}
+
+fn slice(options: &EditOptions, ranges: &[(u32, u32)]) -> Result<(), String> {
+    let input = &options.inputs[0];
+    let mut reader = open(input)?;
+    let mut writer = create(&options.output.path, reader.snap_length(), reader.link_type(), reader.nanosecond())?;
+    let end = ranges.iter().map(|(_, last)| *last).max().unwrap_or(0);
+    for block in &mut reader {
+        if ranges.iter().any(|(first, last)| (*first..=*last).contains(&block.number)) {
+            write(&mut writer, &block, &options.output.path)?;
+        }
+        //Nothing after the last range is written, so the rest of the file is not read
+        if block.number >= end {
+            break;
+        }
+    }
+    check(&mut reader, input)?;
+    finish(&mut writer, &options.output.path)?;
+    println!("Wrote {} packets to {}", writer.packet_count, options.output.path);
+    Ok(())
+}
+
+/// Cuts every packet down to a snap length. The original length of each is kept, as it was
+/// on the wire.
+fn truncate(options: &EditOptions, snap_length: u32) -> Result<(), String> {
+    let input = &options.inputs[0];
+    let mut reader = open(input)?;
+    let mut writer = create(&options.output.path, snap_length, reader.link_type(), reader.nanosecond())?;
+    let mut truncated = 0;
+    for mut block in &mut reader {
+        if block.data.len() > snap_length as usize {
+            block.data.truncate(snap_length as usize);
+            truncated += 1;
+        }
+        write(&mut writer, &block, &options.output.path)?;
+    }
+    check(&mut reader, input)?;
+    finish(&mut writer, &options.output.path)?;
+    println!("Wrote {} packets to {}, {} of them truncated to {} bytes", writer.packet_count, options.output.path,
+             truncated, snap_length);
+    Ok(())
+}
+
+fn dedup(options: &EditOptions, window: u64) -> Result<(), String> {
+    let input = &options.inputs[0];
+    let mut reader = open(input)?;
+    let mut writer = create(&options.output.path, reader.snap_length(), reader.link_type(), reader.nanosecond())?;
+    let mut recent = RecentPackets::new(window);
+    let mut duplicates = 0;
+    for block in &mut reader {
+        if recent.is_duplicate(&block) {
+            duplicates += 1;
+        } else {
+            write(&mut writer, &block, &options.output.path)?;
+        }
+    }
+    check(&mut reader, input)?;
+    finish(&mut writer, &options.output.path)?;
+    println!("Wrote {} packets to {}, {} duplicates removed", writer.packet_count, options.output.path, duplicates);
+    Ok(())
+}
+
+/// The packets kept over the last window of time, to tell when one is captured again. A copy
+/// has the same bytes, so the packets are looked up by a hash of them first.
+struct RecentPackets {
+    window: u64, //Microseconds
+    packets: VecDeque<(u64, u64, Vec<u8>)>, //The time, hash and bytes of each packet, oldest first
+    hashes: HashMap<u64, usize>, //The number of packets kept with each hash
+}
+
+impl RecentPackets {
+    fn new(window: u64) -> RecentPackets {
+        RecentPackets { window, packets: VecDeque::new(), hashes: HashMap::new() }
+    }
+
+    /// Whether the packet is a copy of one kept within the window. When it is not it is kept.
+    fn is_duplicate(&mut self, block: &PcapBlock) -> bool {
+        let time = u32::from_ne_bytes(block.timestamp_seconds) as u64 * 1_000_000 + u32::from_ne_bytes(block.timestamp_microseconds) as u64;
+        while self.packets.front().is_some_and(|(kept, _, _)| kept.saturating_add(self.window) < time) {
+            let (_, hash, _) = self.packets.pop_front().unwrap();
+            let count = self.hashes.get_mut(&hash).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.hashes.remove(&hash);
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        block.data.hash(&mut hasher);
+        let hash = hasher.finish();
+        //Packets out of time order keep older ones from being dropped, so the time is checked again
+        if self.hashes.contains_key(&hash) && self.packets.iter().any(|(kept_time, kept_hash, data)| {
+            *kept_hash == hash && kept_time.abs_diff(time) <= self.window && *data == block.data
+        }) {
+            return true;
+        }
+        *self.hashes.entry(hash).or_insert(0) += 1;
+        self.packets.push_back((time, hash, block.data.clone()));
+        false
+    }
+}
+
+/// The timestamp of a block as precise as its capture had it, to put packets in order.
+fn timestamp(block: &PcapBlock) -> (u32, u32) {
+    (u32::from_ne_bytes(block.timestamp_seconds), block.nanoseconds())
+}
+
+fn open(file_name: &str) -> Result<PcapReader<Box<dyn Read>>, String> {
+    let stream = compressed::open_stream(file_name).map_err(|error| format!("could not open {}: {}", file_name, error))?;
+    PcapReader::new(stream).map_err(|error| format!("could not read {}: {}", file_name, error))
+}
+
+/// Fails when the reader stopped before the end of its capture.
+fn check(reader: &mut PcapReader<Box<dyn Read>>, file_name: &str) -> Result<(), String> {
+    match reader.take_error() {
+        Some(error) => Err(format!("could not read {}: {}", file_name, error)),
+        None => Ok(()),
+    }
+}
+
+fn create(file_name: &str, snap_length: u32, link_type: u32, nanosecond: bool) -> Result<PcapWriter<BufWriter<File>>, String> {
+    let file = File::create(file_name).map_err(|error| format!("could not create {}: {}", file_name, error))?;
+    PcapWriter::with_resolution(BufWriter::new(file), snap_length, link_type, nanosecond)
+        .map_err(|error| format!("could not write {}: {}", file_name, error))
+}
+
+fn write(writer: &mut PcapWriter<BufWriter<File>>, block: &PcapBlock, file_name: &str) -> Result<(), String> {
+    writer.write_block(block).map_err(|error| format!("could not write {}: {}", file_name, error))
+}
+
+fn finish(writer: &mut PcapWriter<BufWriter<File>>, file_name: &str) -> Result<(), String> {
+    writer.flush().map_err(|error| format!("could not write {}: {}", file_name, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::*;
+    use crate::pcap_writer::tests::{capture, MICROSECOND, NANOSECOND};
+
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("packet_analyzer-{}-{}", std::process::id(), name))
+    }
+
+    fn edit(command: &str, inputs: &[(&str, Vec<u8>)], options: &[&str]) -> Vec<u8> {
+        let output = temporary(&format!("{}.pcap", command));
+        let mut args: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+        args.extend(["-w".to_string(), output.display().to_string()]);
+        for (name, data) in inputs {
+            fs::write(temporary(name), data).unwrap();
+            args.push(temporary(name).display().to_string());
+        }
+        run(command, args).unwrap();
+        for (name, _) in inputs {
+            fs::remove_file(temporary(name)).unwrap();
+        }
+        let written = fs::read(&output).unwrap();
+        fs::remove_file(output).unwrap();
+        written
+    }
+
+    #[test]
+    fn merges_in_nanoseconds_when_a_file_has_them() {
+        let nanoseconds = capture(NANOSECOND, &[(10, 500), (10, 2_000_001)]);
+        let microseconds = capture(MICROSECOND, &[(10, 0), (10, 1), (10, 2_000)]);
+        let merged = edit("merge", &[("a.pcap", nanoseconds), ("b.pcap", microseconds)], &[]);
+        //The data of each record is its number in its own file
+        let expected = capture(NANOSECOND, &[(10, 0), (10, 500), (10, 1_000), (10, 2_000_000), (10, 2_000_001)]);
+        let numbers: Vec<u8> = merged[24..].chunks(17).map(|record| record[16]).collect();
+        assert_eq!(numbers, [0, 0, 1, 2, 1]);
+        assert_eq!(merged[..24], expected[..24]);
+        let timestamps: Vec<&[u8]> = merged[24..].chunks(17).map(|record| &record[..8]).collect();
+        assert_eq!(timestamps, expected[24..].chunks(17).map(|record| &record[..8]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn slices_without_losing_nanoseconds() {
+        let nanoseconds = capture(NANOSECOND, &[(10, 1), (10, 10_000_123), (11, 5)]);
+        let sliced = edit("slice", &[("c.pcap", nanoseconds)], &["-r", "2-3"]);
+        let expected = capture(NANOSECOND, &[(10, 10_000_123), (11, 5)]);
+        assert_eq!(sliced[..24], expected[..24]);
+        assert_eq!(sliced[24..].chunks(17).map(|record| &record[..16]).collect::<Vec<_>>(),
+                   expected[24..].chunks(17).map(|record| &record[..16]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn refuses_split_sizes_that_do_not_fit() {
+        let split = |option: &str, value: &str| {
+            EditOptions::from_args("split", vec![option.to_string(), value.to_string(), "-w".to_string(), "out.pcap".to_string(),
+                                                 "in.pcap".to_string()]).err()
+        };
+        assert_eq!(split("-C", "18446744073710"), Some("18446744073710 megabytes is too large for -C".to_string()));
+        assert_eq!(split("-G", "4294967296"), Some("4294967296 seconds is too large for -G".to_string()));
+        assert_eq!(split("-C", "18446744073709"), None);
+        assert_eq!(split("-G", "4294967295"), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use crate::{capture_edit, listener, parallel, pcap_over_ip, tui, PcapBlock, PcapReader};
+#[cfg(target_os = "linux")]
+use crate::af_packet;
+use crate::compressed::{self, CaptureInput};
+use crate::cli_options::{CliOptions, OutputFormat};
+use crate::hex_dump::dump_block;
+use crate::pipeline::Pipeline;
+use crate::summary::summary_line;
+use crate::view::Records;
+
+pub(crate) fn print_pcap(out: &mut impl Write, block: &PcapBlock, options: &CliOptions) -> io::Result<()> {
+    match options.output_format {
+        OutputFormat::Text => {
+            match options.verbosity {
+                0 => writeln!(out, "{}", summary_line(block))?,
+                1 => {
+                    writeln!(out, "{}", block.ether_frame)?;
+                    if block.ether_frame.has_ipv4() {
+                        writeln!(out, "{}", block.ether_frame.packet)?;
+                    }
+                    writeln!(out, "\n")?;
+                }
+                _ => {
+                    writeln!(out, "{}", summary_line(block))?;
+                    writeln!(out, "{:#}", block.ether_frame)?;
+                    if block.ether_frame.has_ipv4() {
+                        writeln!(out, "{:#}", block.ether_frame.packet)?;
+                    }
+                    writeln!(out, "\n")?;
+                }
+            }
+            if let Some(hex_options) = &options.hex_dump {
+                write!(out, "{}", dump_block(block, hex_options))?;
+            }
+        }
+        OutputFormat::Json => writeln!(out, "{}", block.to_json().to_compact())?,
+        OutputFormat::JsonPretty => writeln!(out, "{}", block.to_json().to_pretty())?,
+        OutputFormat::Fields => {
+            if let Some(exporter) = &options.field_exporter {
+                writeln!(out, "{}", exporter.row(block))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn read_pcap_file(file_name: &str, options: &CliOptions) -> Result<(), String> {
+    let input = compressed::open(file_name).map_err(|error| format!("could not open {}: {}", file_name, error))?;
+    let could_not_read = |error| format!("could not read {}: {}", file_name, error);
+    let mut stopped = None;
+    let mut pipeline = match input {
+        CaptureInput::Mapped(capture) => {
+            let mut records = Records::new(&capture).map_err(could_not_read)?;
+            let mut pipeline = Pipeline::new(options, records.snap_length(), records.link_type(), records.nanosecond())?;
+            if options.threads > 1 {
+                parallel::process(&mut records, options, &mut pipeline)?;
+            } else {
+                for record in &mut records {
+                    pipeline.process_record(record)?;
+                    if pipeline.done() {
+                        break;
+                    }
+                }
+            }
+            stopped = records.take_error();
+            pipeline
+        }
+        //Stdin and compressed files are decoded as they are read
+        CaptureInput::Stream(stream) => {
+            if options.threads > 1 {
+                return Err("--threads needs a capture file that is neither compressed nor stdin".to_string());
+            }
+            let mut pcap_reader = PcapReader::new(stream).map_err(could_not_read)?;
+            let mut pipeline = Pipeline::new(options, pcap_reader.snap_length(), pcap_reader.link_type(), pcap_reader.nanosecond())?;
+            for pcap_block in &mut pcap_reader {
+                pipeline.process(pcap_block)?;
+                if pipeline.done() {
+                    break;
+                }
+            }
+            stopped = pcap_reader.take_error();
+            pipeline
+        }
+    };
+    pipeline.finish(|count| format!("Total number of packets in the file(Without Filter): {}", count))?;
+    //What was read is still reported, the damage after it is the error
+    match stopped {
+        Some(error) => Err(could_not_read(error)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn capture_interface(options: &CliOptions) -> Result<(), String> {
+    af_packet::capture(options.interface.as_deref().unwrap_or_default(), options)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn capture_interface(options: &CliOptions) -> Result<(), String> {
+    Err("capturing from an interface is only supported on Linux".to_string())
+}
+
+/// Runs the command line program with its arguments, without the program name.
+pub fn run_cli(args: Vec<String>) -> Result<(), String> {
+    //The commands that edit whole capture files come first and have options of their own
+    if let Some(command) = args.first().filter(|command| capture_edit::is_command(command)) {
+        return capture_edit::run(command, args[1..].to_vec());
+    }
+    let options = CliOptions::from_args(args)?;
+
+    //Without a file to read we connect to a pcap stream, or receive frames on a UDP socket
+    match (&options.file_name, &options.connect) {
+        (Some(file_name), _) if options.tui => tui::run(file_name, &options),
+        (Some(file_name), _) => read_pcap_file(file_name, &options),
+        (None, _) if options.tui => Err("--tui needs a capture file".to_string()),
+        (None, Some(address)) => pcap_over_ip::connect(address, &options),
+        (None, None) if options.interface.is_some() => capture_interface(&options),
+        (None, None) => listener::listen(options.listen.as_deref().unwrap_or(listener::DEFAULT_ADDRESS), &options),
+    }
+}
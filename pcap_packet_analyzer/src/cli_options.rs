@@ -0,0 +1,308 @@
+use crate::{DisplayFilter, Filter};
+use crate::alerts::AlertThresholds;
+use crate::anonymize::{parse_key, AnonymizeOptions, PayloadPolicy};
+use crate::field_export::FieldExporter;
+use crate::flow_export::{FlowDestination, FlowExportOptions, FlowFormat};
+use crate::hex_dump::HexDumpOptions;
+use crate::rewrite::{RewriteOptions, RewriteRule};
+use crate::listener::bind_address;
+use crate::encapsulation::Encapsulation;
+use crate::pcap_writer::WriteOptions;
+use crate::statistics::StatisticsKind;
+use crate::zeek::ZeekOptions;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+    JsonPretty,
+    Fields,
+}
+
+/// Everything given on the command line. Words that are not options are the file name followed
+/// by the capture filter, as before.
+pub(crate) struct CliOptions {
+    pub(crate) file_name: Option<String>,
+    pub(crate) listen: Option<String>, //The address frames are received on instead of reading a file
+    pub(crate) encapsulation: Encapsulation,
+    pub(crate) connect: Option<String>, //A pcap over IP server to read from instead of a file
+    pub(crate) serve: Option<String>, //The address the packets are streamed from as pcap over IP
+    pub(crate) interface: Option<String>, //A network interface to capture from instead of reading a file
+    pub(crate) promiscuous: bool,
+    pub(crate) write: Option<WriteOptions>,
+    pub(crate) filter: Filter,
+    pub(crate) display_filter: Option<DisplayFilter>,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) field_exporter: Option<FieldExporter>,
+    pub(crate) verbosity: u8, //0 prints a summary line, 1 the layered headers and 2 every decoded detail
+    pub(crate) hex_dump: Option<HexDumpOptions>,
+    pub(crate) statistics: Vec<StatisticsKind>, //When any are asked for they replace the packet output
+    pub(crate) top: Option<usize>,
+    pub(crate) io_filters: Vec<(String, DisplayFilter)>, //Extra series of the I/O graph, with the expression as the name
+    pub(crate) thresholds: AlertThresholds,
+    pub(crate) flow_export: Option<FlowExportOptions>,
+    pub(crate) zeek: Option<ZeekOptions>, //Writing the logs replaces the packet output
+    pub(crate) tui: bool,
+    pub(crate) anonymize: Option<AnonymizeOptions>,
+    pub(crate) rewrite: Option<RewriteOptions>,
+    pub(crate) threads: usize, //Workers decoding a capture file, 1 decodes it on the main thread
+}
+
+impl CliOptions {
+    pub(crate) fn new() -> CliOptions {
+        CliOptions {
+            file_name: None,
+            listen: None,
+            encapsulation: Encapsulation::Auto,
+            connect: None,
+            serve: None,
+            interface: None,
+            promiscuous: true,
+            write: None,
+            filter: Filter::Default("default".to_owned()),
+            display_filter: None,
+            output_format: OutputFormat::Text,
+            field_exporter: None,
+            verbosity: 0,
+            hex_dump: None,
+            statistics: Vec::new(),
+            top: None,
+            io_filters: Vec::new(),
+            thresholds: AlertThresholds::new(),
+            flow_export: None,
+            zeek: None,
+            tui: false,
+            anonymize: None,
+            rewrite: None,
+            threads: 1,
+        }
+    }
+
+    pub(crate) fn from_args(args: Vec<String>) -> Result<CliOptions, String> {
+        let mut options = CliOptions::new();
+        let mut positional = Vec::new();
+        let mut separator = None;
+        let mut fields = Vec::new();
+        let mut flow_format = None;
+        let mut flow_destination = None;
+        let mut timeouts = (None, None);
+        let mut zeek_json = false;
+        let mut anon_key = None;
+        let mut anon_macs = false;
+        let mut anon_payload = None;
+        let mut rules = Vec::new();
+        let mut no_promiscuous = false;
+        let mut rotation = (None, None, None);
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-Y" => {
+                    let expression = args.next().ok_or("-Y needs a display filter expression")?;
+                    options.display_filter = Some(DisplayFilter::parse(&expression)
+                        .map_err(|error| format!("Invalid display filter: {}", error))?);
+                }
+                "--json" => options.output_format = OutputFormat::Json,
+                "--json-pretty" => options.output_format = OutputFormat::JsonPretty,
+                "-T" => match args.next().as_deref() {
+                    Some("csv") => separator = Some(','),
+                    Some("tsv") => separator = Some('\t'),
+                    _ => return Err("-T needs csv or tsv".to_string()),
+                },
+                "-v" => options.verbosity = 1,
+                "-vv" => options.verbosity = 2,
+                "-x" => { options.hex_dump.get_or_insert(HexDumpOptions::new()); }
+                "-X" => options.hex_dump.get_or_insert(HexDumpOptions::new()).ascii = true,
+                "--payload" => options.hex_dump.get_or_insert(HexDumpOptions::new()).payload_only = true,
+                "--annotate" => options.hex_dump.get_or_insert(HexDumpOptions::new()).annotate = true,
+                "--stats" => {
+                    let name = args.next().ok_or("--stats needs the name of the statistics")?;
+                    options.statistics.push(StatisticsKind::from_str(&name)?);
+                }
+                "--top" => {
+                    let count = args.next().ok_or("--top needs a number of rows")?;
+                    options.top = Some(count.parse().map_err(|_| format!("'{}' is not a number of rows", count))?);
+                }
+                "--io-filter" => {
+                    let expression = args.next().ok_or("--io-filter needs a display filter expression")?;
+                    let filter = DisplayFilter::parse(&expression)
+                        .map_err(|error| format!("Invalid display filter: {}", error))?;
+                    options.io_filters.push((expression, filter));
+                }
+                "--threshold" => options.thresholds.set(&args.next().ok_or("--threshold needs <name>=<value>")?)?,
+                "--flow-export" => {
+                    flow_format = Some(FlowFormat::from_str(&args.next().ok_or("--flow-export needs v5, v9 or ipfix")?)?);
+                }
+                "--flow-output" => {
+                    flow_destination = Some(FlowDestination::File(args.next().ok_or("--flow-output needs a file name")?));
+                }
+                "--flow-collector" => {
+                    flow_destination = Some(FlowDestination::Collector(args.next().ok_or("--flow-collector needs host:port")?));
+                }
+                "--active-timeout" => timeouts.0 = Some(parse_seconds(&arg, args.next())?),
+                "--inactive-timeout" => timeouts.1 = Some(parse_seconds(&arg, args.next())?),
+                "--zeek" => options.zeek = Some(ZeekOptions::new(args.next().ok_or("--zeek needs a directory for the logs")?)),
+                "--zeek-json" => zeek_json = true,
+                "--tui" => options.tui = true,
+                "--anonymize" => options.anonymize = Some(AnonymizeOptions::new(args.next().ok_or("--anonymize needs an output file")?)),
+                "--anon-key" => anon_key = Some(parse_key(&args.next().ok_or("--anon-key needs a key")?)?),
+                "--anon-macs" => anon_macs = true,
+                "--anon-payload" => {
+                    anon_payload = Some(PayloadPolicy::from_str(&args.next().ok_or("--anon-payload needs keep, zero or truncate")?)?);
+                }
+                "--listen" => options.listen = Some(bind_address(&args.next().ok_or("--listen needs an address or a port")?)),
+                "-i" => options.interface = Some(args.next().ok_or("-i needs the name of an interface")?),
+                "-p" => no_promiscuous = true,
+                "-w" => options.write = Some(WriteOptions::new(args.next().ok_or("-w needs an output file")?)),
+                "-C" => rotation.0 = Some(parse_megabytes("-C", args.next())?),
+                "-G" => rotation.1 = Some(parse_u32("-G", "seconds", args.next())?),
+                "-W" => rotation.2 = Some(parse_u32("-W", "files", args.next())?),
+                "--connect" => options.connect = Some(args.next().ok_or("--connect needs <host>:<port>")?),
+                "--serve" => options.serve = Some(bind_address(&args.next().ok_or("--serve needs an address or a port")?)),
+                "--encapsulation" => options.encapsulation = Encapsulation::from_str(&args.next()
+                    .ok_or("--encapsulation needs auto, ethernet, tzsp, pcap or erspan")?)?,
+                "--rewrite" => options.rewrite = Some(RewriteOptions::new(args.next().ok_or("--rewrite needs an output file")?)),
+                "--rule" => rules.push(RewriteRule::from_str(&args.next().ok_or("--rule needs <name>=<value>")?)?),
+                "--threads" => options.threads = parse_count("--threads", "threads", args.next())? as usize,
+                "-e" => fields.push(args.next().ok_or("-e needs a field name")?),
+                _ => positional.push(arg),
+            }
+        }
+
+        if let Some(separator) = separator {
+            options.output_format = OutputFormat::Fields;
+            options.field_exporter = Some(FieldExporter::new(fields, separator)?);
+        } else if !fields.is_empty() {
+            return Err("-e needs -T csv or -T tsv".to_string());
+        }
+
+        match flow_format {
+            Some(format) => {
+                let destination = flow_destination
+                    .ok_or("--flow-export needs --flow-output <file> or --flow-collector <host:port>")?;
+                let mut flow_export = FlowExportOptions::new(format, destination);
+                flow_export.active_timeout = timeouts.0.unwrap_or(flow_export.active_timeout);
+                flow_export.inactive_timeout = timeouts.1.unwrap_or(flow_export.inactive_timeout);
+                options.flow_export = Some(flow_export);
+            }
+            None if flow_destination.is_some() || timeouts != (None, None) => {
+                return Err("the flow options need --flow-export v5, v9 or ipfix".to_string());
+            }
+            None => {}
+        }
+
+        match &mut options.zeek {
+            Some(zeek) => zeek.json = zeek_json,
+            None if zeek_json => return Err("--zeek-json needs --zeek <directory>".to_string()),
+            None => {}
+        }
+
+        match &mut options.anonymize {
+            Some(anonymize) => {
+                anonymize.key = anon_key;
+                anonymize.macs = anon_macs;
+                anonymize.payload = anon_payload.unwrap_or(anonymize.payload);
+            }
+            None if anon_key.is_some() || anon_macs || anon_payload.is_some() => {
+                return Err("the anonymization options need --anonymize <file>".to_string());
+            }
+            None => {}
+        }
+
+        match &mut options.rewrite {
+            Some(_) if rules.is_empty() => return Err("--rewrite needs at least one --rule".to_string()),
+            Some(rewrite) => rewrite.rules = rules,
+            None if !rules.is_empty() => return Err("--rule needs --rewrite <file>".to_string()),
+            None => {}
+        }
+
+        match &mut options.write {
+            Some(_) if rotation.2.is_some() && rotation.0.is_none() && rotation.1.is_none() => {
+                return Err("-W needs -C or -G".to_string());
+            }
+            Some(write) => (write.file_size, write.seconds, write.files) = rotation,
+            None if rotation != (None, None, None) => return Err("-C, -G and -W need -w <file>".to_string()),
+            None => {}
+        }
+        match &options.interface {
+            Some(_) => options.promiscuous = !no_promiscuous,
+            None if no_promiscuous => return Err("-p needs -i <interface>".to_string()),
+            None => {}
+        }
+
+        let sources = [options.listen.is_some(), options.connect.is_some(), options.interface.is_some()];
+        if sources.iter().filter(|source| **source).count() > 1 {
+            return Err("only one of --listen, --connect and -i can be used".to_string());
+        }
+        //While capturing, listening or connected every positional argument is part of the filter
+        if !positional.is_empty() && !sources.contains(&true) {
+            options.file_name = Some(positional.remove(0));
+        }
+        if options.file_name.is_some() && options.encapsulation != Encapsulation::Auto {
+            return Err("--encapsulation only applies to the frames received when listening".to_string());
+        }
+        if options.threads > 1 && (options.file_name.is_none() || options.tui) {
+            return Err("--threads only applies to reading a capture file".to_string());
+        }
+        if options.listen.is_some() && options.tui {
+            return Err("--tui needs a capture file, not --listen".to_string());
+        }
+        if !positional.is_empty() {
+            options.filter = Filter::from_str(positional)?;
+        }
+        Ok(options)
+    }
+}
+
+pub(crate) fn parse_count(option: &str, unit: &str, value: Option<String>) -> Result<u64, String> {
+    let value = value.ok_or(format!("{} needs a number of {}", option, unit))?;
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("'{}' is not a number of {}", value, unit)),
+    }
+}
+
+/// A file size given in megabytes, in bytes.
+pub(crate) fn parse_megabytes(option: &str, value: Option<String>) -> Result<u64, String> {
+    let megabytes = parse_count(option, "megabytes", value)?;
+    megabytes.checked_mul(1_000_000).ok_or(format!("{} megabytes is too large for {}", megabytes, option))
+}
+
+/// A count that has to fit the 32 bits it is kept in.
+pub(crate) fn parse_u32(option: &str, unit: &str, value: Option<String>) -> Result<u32, String> {
+    let count = parse_count(option, unit, value)?;
+    u32::try_from(count).map_err(|_| format!("{} {} is too large for {}", count, unit, option))
+}
+
+fn parse_seconds(option: &str, value: Option<String>) -> Result<u64, String> {
+    let value = value.ok_or(format!("{} needs a number of seconds", option))?;
+    value.parse().map_err(|_| format!("'{}' is not a number of seconds", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliOptions, String> {
+        CliOptions::from_args(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn refuses_malformed_capture_filters() {
+        let cases = [
+            (&["cap.pcap", "host", "10.0.0"][..], "'10.0.0' is not an IPv4 address"),
+            (&["cap.pcap", "host", "10.0.0.1.2"], "'10.0.0.1.2' is not an IPv4 address"),
+            (&["cap.pcap", "net", "10.0.0.256"], "'10.0.0.256' is not an IPv4 address"),
+            (&["cap.pcap", "port", "http"], "'http' is not a port number"),
+            (&["cap.pcap", "port", "65536"], "'65536' is not a port number"),
+            (&["cap.pcap", "-c", "x"], "'x' is not a number of packets"),
+            (&["cap.pcap", "-c", "0"], "'0' is not a number of packets"),
+        ];
+        for (args, error) in cases {
+            assert_eq!(parse(args).err().as_deref(), Some(error), "{:?}", args);
+        }
+        assert!(matches!(parse(&["cap.pcap", "host", "10.0.0.1"]).unwrap().filter, Filter::Host([10, 0, 0, 1])));
+        assert!(matches!(parse(&["cap.pcap", "port", "443"]).unwrap().filter, Filter::Port([1, 187])));
+        assert!(matches!(parse(&["cap.pcap", "-c", "5"]).unwrap().filter, Filter::Count(5)));
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use crate::MappedFile;
+use crate::gzip::GzipReader;
+use crate::zstd::ZstdReader;
+
+/// A capture file as it can be read: mapped into memory when it is a plain file, or as a
+/// stream when it comes from stdin or has to be decompressed on the way.
+pub(crate) enum CaptureInput {
+    Mapped(MappedFile),
+    Stream(Box<dyn Read>),
+}
+
+/// Opens a capture file, `-` being stdin. Gzip and Zstandard files are recognized by their
+/// magic number, whatever their name, and decompressed as they are read.
+pub(crate) fn open(file_name: &str) -> io::Result<CaptureInput> {
+    if file_name == "-" {
+        return Ok(CaptureInput::Stream(open_stream(file_name)?));
+    }
+    let mut file = BufReader::new(File::open(file_name)?);
+    let compressed = !matches!(Compression::detect(file.fill_buf()?), Compression::None);
+    if compressed {
+        Ok(CaptureInput::Stream(decompress(file)?))
+    } else {
+        Ok(CaptureInput::Mapped(MappedFile::open(file_name)?))
+    }
+}
+
+/// Opens a capture file like `open`, always as a stream, for the readers that go through the
+/// packets once and have no use for the whole file in memory.
+pub(crate) fn open_stream(file_name: &str) -> io::Result<Box<dyn Read>> {
+    if file_name == "-" {
+        return decompress(BufReader::new(io::stdin()));
+    }
+    decompress(BufReader::new(File::open(file_name)?))
+}
+
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(start: &[u8]) -> Compression {
+        match start {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+fn decompress<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(GzipReader::new(reader)),
+        Compression::Zstd => Box::new(ZstdReader::new(reader)),
+    })
+}
+
+/// The decompressed bytes not read yet, after as much of the bytes before them as later
+/// back references may copy from.
+pub(crate) struct History {
+    pub(crate) buffer: Vec<u8>,
+    position: usize, //The first byte not read yet
+    window: usize,
+}
+
+impl History {
+    pub(crate) fn new(window: usize) -> History {
+        History { buffer: Vec::new(), position: 0, window }
+    }
+
+    /// A new frame may go back further than the last one, or not at all.
+    pub(crate) fn set_window(&mut self, window: usize) {
+        self.window = window;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.buffer.len()
+    }
+
+    pub(crate) fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let length = buffer.len().min(self.buffer.len() - self.position);
+        buffer[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+        length
+    }
+
+    /// Drops what was read and is out of the window. It waits until there is as much to drop as
+    /// the window holds, so the bytes kept are moved once for every window of output at most.
+    pub(crate) fn trim(&mut self) {
+        let droppable = self.position.min(self.buffer.len().saturating_sub(self.window));
+        if droppable >= self.window.max(1 << 16) {
+            self.buffer.drain(..droppable);
+            self.position -= droppable;
+        }
+    }
+
+    /// Copies `length` bytes from `distance` bytes back, the copy overlapping what it writes
+    /// when the distance is the shorter.
+    pub(crate) fn copy_match(&mut self, distance: usize, length: usize) -> io::Result<()> {
+        if distance == 0 || distance > self.buffer.len() {
+            return Err(corrupt("a match goes back before the start of the data"));
+        }
+        let start = self.buffer.len() - distance;
+        if distance >= length {
+            self.buffer.extend_from_within(start..start + length);
+        } else {
+            for i in 0..length {
+                self.buffer.push(self.buffer[start + i]);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// The error of a compressed stream that stops inside a block or a frame. It is not an end of
+/// file, which a pcap reader takes for a capture that is still being written.
+pub(crate) fn ends_early() -> io::Error {
+    corrupt("the compressed stream ends early")
+}
+
+/// Fills `buffer` from the input of a decompressor, where running out of input means the
+/// stream was cut short.
+pub(crate) fn read_input(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buffer).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => ends_early(),
+        _ => error,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn next(state: &mut u64) -> u64 {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        *state
+    }
+
+    /// Text with words that repeat, for matches, between letters that do not, for the Huffman
+    /// codes. The files in testdata were compressed from these samples.
+    pub(crate) fn sample(length: usize) -> Vec<u8> {
+        let words = ["packet ", "capture ", "stream ", "frame ", "header ", "the ", "of ", "and "];
+        let mut state = 1;
+        let mut text = Vec::new();
+        while text.len() < length {
+            let value = (next(&mut state) >> 33) as usize;
+            if value.is_multiple_of(4) {
+                text.extend_from_slice(words[value / 4 % words.len()].as_bytes());
+            } else {
+                text.push(b"eeeettaoinshrdlu"[value / 4 % 16]);
+            }
+        }
+        text.truncate(length);
+        text
+    }
+
+    /// Bytes that do not compress.
+    pub(crate) fn noise(length: usize) -> Vec<u8> {
+        let mut state = 7;
+        (0..length).map(|_| (next(&mut state) >> 56) as u8).collect()
+    }
+
+    fn read_all(mut reader: Box<dyn Read>) -> Vec<u8> {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn decompresses_by_the_magic_number() {
+        assert_eq!(read_all(decompress(&include_bytes!("../testdata/dynamic.gz")[..]).unwrap()), sample(4000));
+        assert_eq!(read_all(decompress(&include_bytes!("../testdata/compressed.zst")[..]).unwrap()), sample(4000));
+        assert_eq!(read_all(decompress(&b"\xd4\xc3\xb2\xa1 a pcap"[..]).unwrap()), b"\xd4\xc3\xb2\xa1 a pcap");
+        assert_eq!(read_all(decompress(&b""[..]).unwrap()), b"");
+    }
+
+    #[test]
+    fn copies_matches_that_overlap_what_they_write() {
+        let mut history = History::new(32);
+        history.buffer.extend_from_slice(b"abc");
+        history.copy_match(3, 2).unwrap();
+        history.copy_match(1, 4).unwrap();
+        history.copy_match(2, 5).unwrap();
+        assert_eq!(history.buffer, b"abcabbbbbbbbbb");
+        assert_eq!(history.copy_match(15, 1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(history.copy_match(0, 1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn keeps_the_window_once_read() {
+        let mut history = History::new(1 << 16);
+        history.buffer = (0..200_000).map(|i| i as u8).collect();
+        let mut buffer = vec![0; 150_000];
+        assert_eq!(history.read(&mut buffer), 150_000);
+        history.trim();
+        //A window back from the last byte is still there to copy from, the rest was dropped
+        assert_eq!(history.buffer.len(), 1 << 16);
+        history.copy_match(1 << 16, 1).unwrap();
+        assert_eq!(history.buffer.last(), Some(&((200_000 - (1 << 16)) as u8)));
+        assert_eq!(history.read(&mut buffer), 50_001);
+    }
+
+    #[test]
+    fn running_out_of_input_is_damage() {
+        let mut buffer = [0; 4];
+        let error = read_input(&mut &b"ab"[..], &mut buffer).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "the compressed stream ends early");
+        read_input(&mut &b"abcd"[..], &mut buffer).unwrap();
+        assert_eq!(&buffer, b"abcd");
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use crate::ethernet_frame::format_mac;
+use crate::internet_packet::format_ipv4;
+use crate::statistics::{same_kind, Statistics};
+use crate::view::{Packet, Transport};
+
+/// The layer conversations and endpoints are grouped at.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum ConversationLevel {
+    Ethernet,
+    Ip,
+    Tcp,
+    Udp,
+}
+
+impl ConversationLevel {
+    pub(crate) fn from_str(name: &str) -> Result<ConversationLevel, String> {
+        match name {
+            "eth" => Ok(ConversationLevel::Ethernet),
+            "ip" => Ok(ConversationLevel::Ip),
+            "tcp" => Ok(ConversationLevel::Tcp),
+            "udp" => Ok(ConversationLevel::Udp),
+            _ => Err(format!("unknown conversation level '{}', expected eth, ip, tcp or udp", name)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ConversationLevel::Ethernet => "Ethernet",
+            ConversationLevel::Ip => "IPv4",
+            ConversationLevel::Tcp => "TCP",
+            ConversationLevel::Udp => "UDP",
+        }
+    }
+
+    /// The source and destination of the packet at this level, if it has that layer.
+    fn endpoints(&self, packet: &Packet) -> Option<(Endpoint, Endpoint)> {
+        match self {
+            ConversationLevel::Ethernet => packet.frame()
+                .map(|frame| (Endpoint::Ether(frame.source().octets()), Endpoint::Ether(frame.destination().octets()))),
+            ConversationLevel::Ip => packet.ipv4()
+                .map(|ip| (Endpoint::Ipv4(ip.source().octets()), Endpoint::Ipv4(ip.destination().octets()))),
+            ConversationLevel::Tcp | ConversationLevel::Udp => {
+                let ip = packet.ipv4()?;
+                let (source_port, destination_port) = match (self, ip.transport()) {
+                    (ConversationLevel::Tcp, Transport::Tcp(tcp)) => (tcp.source_port(), tcp.destination_port()),
+                    (ConversationLevel::Udp, Transport::Udp(udp)) => (udp.source_port(), udp.destination_port()),
+                    _ => return None,
+                };
+                Some((Endpoint::Transport(ip.source().octets(), source_port),
+                      Endpoint::Transport(ip.destination().octets(), destination_port)))
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Endpoint {
+    Ether([u8; 6]),
+    Ipv4([u8; 4]),
+    Transport([u8; 4], u16),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Ether(address) => write!(f, "{}", format_mac(address)),
+            Endpoint::Ipv4(address) => write!(f, "{}", format_ipv4(address)),
+            Endpoint::Transport(address, port) => write!(f, "{}:{}", format_ipv4(address), port),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Traffic {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+
+    fn merge(&mut self, other: Traffic) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+    }
+}
+
+struct Conversation {
+    a_to_b: Traffic,
+    b_to_a: Traffic,
+    first_seen: f64,
+    last_seen: f64,
+}
+
+/// Packets and bytes in each direction between every pair of endpoints, busiest first.
+pub(crate) struct Conversations {
+    level: ConversationLevel,
+    top: Option<usize>,
+    capture_start: Option<(u32, f64)>, //The number and time of the first packet
+    conversations: HashMap<(Endpoint, Endpoint), Conversation>,
+}
+
+impl Conversations {
+    pub(crate) fn new(level: ConversationLevel, top: Option<usize>) -> Conversations {
+        Conversations {
+            level,
+            top,
+            capture_start: None,
+            conversations: HashMap::new(),
+        }
+    }
+}
+
+impl Statistics for Conversations {
+    fn add(&mut self, packet: &Packet) {
+        let time = packet.timestamp();
+        self.capture_start.get_or_insert((packet.record.number(), time));
+        let Some((source, destination)) = self.level.endpoints(packet) else { return };
+        let bytes = packet.data().len() as u64;
+
+        //Both directions share one entry, keyed with the lower endpoint first
+        let (key, forward) = if source <= destination {
+            ((source, destination), true)
+        } else {
+            ((destination, source), false)
+        };
+        let conversation = self.conversations.entry(key).or_insert(Conversation {
+            a_to_b: Traffic::default(),
+            b_to_a: Traffic::default(),
+            first_seen: time,
+            last_seen: time,
+        });
+        if forward {
+            conversation.a_to_b.add(bytes);
+        } else {
+            conversation.b_to_a.add(bytes);
+        }
+        conversation.first_seen = conversation.first_seen.min(time);
+        conversation.last_seen = conversation.last_seen.max(time);
+    }
+
+    fn merge(&mut self, other: Box<dyn Statistics>) {
+        let other = same_kind::<Conversations>(other);
+        self.capture_start = self.capture_start.into_iter().chain(other.capture_start).min_by_key(|(number, _)| *number);
+        for (key, other) in other.conversations {
+            match self.conversations.get_mut(&key) {
+                Some(conversation) => {
+                    conversation.a_to_b.merge(other.a_to_b);
+                    conversation.b_to_a.merge(other.b_to_a);
+                    conversation.first_seen = conversation.first_seen.min(other.first_seen);
+                    conversation.last_seen = conversation.last_seen.max(other.last_seen);
+                }
+                None => {
+                    self.conversations.insert(key, other);
+                }
+            }
+        }
+    }
+
+    fn report(&self) -> String {
+        let start = self.capture_start.map_or(0.0, |(_, time)| time);
+        let mut rows: Vec<(&(Endpoint, Endpoint), &Conversation)> = self.conversations.iter().collect();
+        rows.sort_by(|a, b| {
+            let total = |c: &Conversation| c.a_to_b.bytes + c.b_to_a.bytes;
+            total(b.1).cmp(&total(a.1)).then(a.0.cmp(b.0))
+        });
+
+        let mut out = String::new();
+        writeln!(out, "{} Conversations", self.level.name()).unwrap();
+        writeln!(out, "{:<23} {:<23} {:>9} {:>11} {:>9} {:>11} {:>9} {:>11} {:>11} {:>10}", "Address A", "Address B",
+                 "Pkts A>B", "Bytes A>B", "Pkts B>A", "Bytes B>A", "Packets", "Bytes", "Rel Start", "Duration").unwrap();
+        for ((a, b), conversation) in rows.into_iter().take(self.top.unwrap_or(usize::MAX)) {
+            writeln!(out, "{:<23} {:<23} {:>9} {:>11} {:>9} {:>11} {:>9} {:>11} {:>11.6} {:>10.6}",
+                     a.to_string(), b.to_string(),
+                     conversation.a_to_b.packets, conversation.a_to_b.bytes,
+                     conversation.b_to_a.packets, conversation.b_to_a.bytes,
+                     conversation.a_to_b.packets + conversation.b_to_a.packets,
+                     conversation.a_to_b.bytes + conversation.b_to_a.bytes,
+                     conversation.first_seen - start, conversation.last_seen - conversation.first_seen).unwrap();
+        }
+        out
+    }
+}
+
+/// Totals sent and received by every address, or address and port, sorted by bytes so the
+/// top talkers come first.
+pub(crate) struct Endpoints {
+    level: ConversationLevel,
+    top: Option<usize>,
+    endpoints: HashMap<Endpoint, (Traffic, Traffic)>,
+}
+
+impl Endpoints {
+    pub(crate) fn new(level: ConversationLevel, top: Option<usize>) -> Endpoints {
+        Endpoints {
+            level,
+            top,
+            endpoints: HashMap::new(),
+        }
+    }
+}
+
+impl Statistics for Endpoints {
+    fn add(&mut self, packet: &Packet) {
+        let Some((source, destination)) = self.level.endpoints(packet) else { return };
+        let bytes = packet.data().len() as u64;
+        self.endpoints.entry(source).or_default().0.add(bytes);
+        self.endpoints.entry(destination).or_default().1.add(bytes);
+    }
+
+    fn merge(&mut self, other: Box<dyn Statistics>) {
+        for (endpoint, (sent, received)) in same_kind::<Endpoints>(other).endpoints {
+            let traffic = self.endpoints.entry(endpoint).or_default();
+            traffic.0.merge(sent);
+            traffic.1.merge(received);
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut rows: Vec<(&Endpoint, &(Traffic, Traffic))> = self.endpoints.iter().collect();
+        rows.sort_by(|a, b| {
+            let total = |(sent, received): &(Traffic, Traffic)| sent.bytes + received.bytes;
+            total(b.1).cmp(&total(a.1)).then(a.0.cmp(b.0))
+        });
+
+        let mut out = String::new();
+        writeln!(out, "{} Endpoints", self.level.name()).unwrap();
+        writeln!(out, "{:<23} {:>9} {:>11} {:>9} {:>11} {:>9} {:>11}", "Address", "Packets", "Bytes",
+                 "Tx Pkts", "Tx Bytes", "Rx Pkts", "Rx Bytes").unwrap();
+        for (endpoint, (sent, received)) in rows.into_iter().take(self.top.unwrap_or(usize::MAX)) {
+            writeln!(out, "{:<23} {:>9} {:>11} {:>9} {:>11} {:>9} {:>11}", endpoint.to_string(),
+                     sent.packets + received.packets, sent.bytes + received.bytes,
+                     sent.packets, sent.bytes, received.packets, received.bytes).unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,465 @@
+use std::str::FromStr;
+use crate::PcapBlock;
+use crate::packet_fields::{field_kind, field_values, FieldKind, FieldValue};
+use crate::view::Packet;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Compare(CompareOp),
+    And,
+    Or,
+    Not,
+    Contains,
+    In,
+    OpenParen,
+    CloseParen,
+    OpenBrace,
+    CloseBrace,
+    Comma,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Unsigned(u64),
+    Bytes(Vec<u8>),
+    Subnet([u8; 4], u8),
+    Range(u64, u64),
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Exists(String),
+    Compare(String, CompareOp, Literal),
+    Contains(String, Vec<u8>),
+    In(String, Vec<Literal>),
+}
+
+/// A Wireshark style display filter such as `ip.ttl < 5 && tcp.flags.syn == 1`, evaluated
+/// against the decoded fields of a block.
+#[derive(Debug, Clone)]
+pub struct DisplayFilter {
+    expression: Expression,
+}
+
+impl DisplayFilter {
+    pub fn parse(text: &str) -> Result<DisplayFilter, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(DisplayFilter { expression }),
+            Some(token) => Err(format!("unexpected {:?} in display filter", token)),
+        }
+    }
+
+    pub fn matches(&self, block: &PcapBlock) -> bool {
+        evaluate(&self.expression, &block.view())
+    }
+
+    pub(crate) fn matches_packet(&self, packet: &Packet) -> bool {
+        evaluate(&self.expression, packet)
+    }
+
+    /// Whether the expression looks at the field `name` anywhere.
+    pub(crate) fn uses_field(&self, name: &str) -> bool {
+        uses_field(&self.expression, name)
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' | '\n' => { i += 1; }
+            '(' => { tokens.push(Token::OpenParen); i += 1; }
+            ')' => { tokens.push(Token::CloseParen); i += 1; }
+            '{' => { tokens.push(Token::OpenBrace); i += 1; }
+            '}' => { tokens.push(Token::CloseBrace); i += 1; }
+            ',' => { tokens.push(Token::Comma); i += 1; }
+            '&' if next == Some('&') => { tokens.push(Token::And); i += 2; }
+            '|' if next == Some('|') => { tokens.push(Token::Or); i += 2; }
+            '=' if next == Some('=') => { tokens.push(Token::Compare(CompareOp::Eq)); i += 2; }
+            '!' if next == Some('=') => { tokens.push(Token::Compare(CompareOp::Ne)); i += 2; }
+            '!' => { tokens.push(Token::Not); i += 1; }
+            '<' if next == Some('=') => { tokens.push(Token::Compare(CompareOp::Le)); i += 2; }
+            '<' => { tokens.push(Token::Compare(CompareOp::Lt)); i += 1; }
+            '>' if next == Some('=') => { tokens.push(Token::Compare(CompareOp::Ge)); i += 2; }
+            '>' => { tokens.push(Token::Compare(CompareOp::Gt)); i += 1; }
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string in display filter".to_string()),
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(other) => {
+                            value.push(*other);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+                i += 1;
+            }
+            c if c.is_ascii_alphanumeric() || "._:/-".contains(c) => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "._:/-".contains(chars[i])) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "contains" => Token::Contains,
+                    "in" => Token::In,
+                    "eq" => Token::Compare(CompareOp::Eq),
+                    "ne" => Token::Compare(CompareOp::Ne),
+                    "lt" => Token::Compare(CompareOp::Lt),
+                    "le" => Token::Compare(CompareOp::Le),
+                    "gt" => Token::Compare(CompareOp::Gt),
+                    "ge" => Token::Compare(CompareOp::Ge),
+                    _ => Token::Word(word),
+                });
+            }
+            _ => return Err(format!("unexpected character '{}' in display filter", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            let right = self.parse_and()?;
+            left = Expression::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            let right = self.parse_unary()?;
+            left = Expression::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Expression::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, String> {
+        let field = match self.advance() {
+            Some(Token::OpenParen) => {
+                let expression = self.parse_or()?;
+                return match self.advance() {
+                    Some(Token::CloseParen) => Ok(expression),
+                    _ => Err("missing ')' in display filter".to_string()),
+                };
+            }
+            Some(Token::Word(field)) => field,
+            Some(token) => return Err(format!("expected a field name but found {:?}", token)),
+            None => return Err("display filter ended unexpectedly".to_string()),
+        };
+        let kind = field_kind(&field).ok_or(format!("unknown field '{}'", field))?;
+
+        match self.peek().cloned() {
+            Some(Token::Compare(op)) => {
+                self.advance();
+                if kind == FieldKind::Protocol {
+                    return Err(format!("'{}' is a protocol and can not be compared", field));
+                }
+                let literal = parse_literal(kind, self.advance())?;
+                Ok(Expression::Compare(field, op, literal))
+            }
+            Some(Token::Contains) => {
+                self.advance();
+                if kind == FieldKind::Protocol || kind == FieldKind::Unsigned {
+                    return Err(format!("'contains' can not be used on '{}'", field));
+                }
+                match parse_literal(FieldKind::Bytes, self.advance())? {
+                    Literal::Bytes(value) => Ok(Expression::Contains(field, value)),
+                    _ => Err("'contains' needs a string or byte sequence".to_string()),
+                }
+            }
+            Some(Token::In) => {
+                self.advance();
+                if self.advance() != Some(Token::OpenBrace) {
+                    return Err("expected '{' after 'in'".to_string());
+                }
+                let mut members = Vec::new();
+                loop {
+                    match self.advance() {
+                        Some(Token::CloseBrace) => break,
+                        Some(Token::Comma) => {}
+                        Some(Token::Word(word)) if kind == FieldKind::Unsigned && word.contains("..") => {
+                            let (low, high) = word.split_once("..").unwrap();
+                            members.push(Literal::Range(parse_unsigned(low)?, parse_unsigned(high)?));
+                        }
+                        token => members.push(parse_literal(kind, token)?),
+                    }
+                }
+                Ok(Expression::In(field, members))
+            }
+            _ => Ok(Expression::Exists(field)),
+        }
+    }
+}
+
+fn parse_unsigned(word: &str) -> Result<u64, String> {
+    let parsed = match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => u64::from_str(word),
+    };
+    parsed.map_err(|_| format!("'{}' is not a number", word))
+}
+
+fn parse_hex_bytes(word: &str) -> Result<Vec<u8>, String> {
+    word.split([':', '-', '.'])
+        .map(|part| u8::from_str_radix(part, 16).map_err(|_| format!("'{}' is not a byte sequence", word)))
+        .collect()
+}
+
+fn parse_literal(kind: FieldKind, token: Option<Token>) -> Result<Literal, String> {
+    let word = match token {
+        Some(Token::Quoted(text)) if kind == FieldKind::Bytes || kind == FieldKind::Text => {
+            return Ok(Literal::Bytes(text.into_bytes()));
+        }
+        Some(Token::Word(word)) | Some(Token::Quoted(word)) => word,
+        Some(token) => return Err(format!("expected a value but found {:?}", token)),
+        None => return Err("display filter ended where a value was expected".to_string()),
+    };
+    match kind {
+        FieldKind::Unsigned => Ok(Literal::Unsigned(parse_unsigned(&word)?)),
+        FieldKind::Ipv4 => {
+            let (address, prefix) = match word.split_once('/') {
+                Some((address, prefix)) => (address, u8::from_str(prefix).map_err(|_| format!("bad prefix in '{}'", word))?),
+                None => (word.as_str(), 32),
+            };
+            let octets: Vec<u8> = address.split('.').map(u8::from_str).collect::<Result<_, _>>()
+                .map_err(|_| format!("'{}' is not an IPv4 address", word))?;
+            if octets.len() != 4 || prefix > 32 {
+                return Err(format!("'{}' is not an IPv4 address", word));
+            }
+            Ok(Literal::Subnet(octets.try_into().unwrap(), prefix))
+        }
+        FieldKind::Ether => {
+            let octets = parse_hex_bytes(&word)?;
+            if octets.len() != 6 {
+                return Err(format!("'{}' is not a MAC address", word));
+            }
+            Ok(Literal::Bytes(octets))
+        }
+        FieldKind::Bytes => Ok(Literal::Bytes(parse_hex_bytes(&word)?)),
+        FieldKind::Text => Ok(Literal::Bytes(word.into_bytes())),
+        FieldKind::Protocol => Err("protocols do not have values".to_string()),
+    }
+}
+
+fn literal_matches(value: &FieldValue, op: CompareOp, literal: &Literal) -> bool {
+    match (value, literal) {
+        (FieldValue::Bytes(address), Literal::Subnet(network, prefix)) if op == CompareOp::Eq || op == CompareOp::Ne => {
+            let mask = if *prefix == 0 { 0 } else { u32::MAX << (32 - *prefix as u32) };
+            let address = u32::from_be_bytes(address.as_slice().try_into().unwrap_or([0; 4]));
+            let same_network = address & mask == u32::from_be_bytes(*network) & mask;
+            if op == CompareOp::Eq { same_network } else { !same_network }
+        }
+        (FieldValue::Bytes(value), Literal::Subnet(network, _)) => compare(value.as_slice().cmp(network.as_slice()), op),
+        (FieldValue::Bytes(value), Literal::Bytes(bytes)) => compare(value.cmp(bytes), op),
+        (FieldValue::Unsigned(value), Literal::Unsigned(number)) => compare(value.cmp(number), op),
+        (FieldValue::Unsigned(value), Literal::Range(low, high)) => {
+            let inside = low <= value && value <= high;
+            if op == CompareOp::Ne { !inside } else { inside }
+        }
+        _ => false,
+    }
+}
+
+fn compare(ordering: std::cmp::Ordering, op: CompareOp) -> bool {
+    match op {
+        CompareOp::Eq => ordering.is_eq(),
+        CompareOp::Ne => ordering.is_ne(),
+        CompareOp::Lt => ordering.is_lt(),
+        CompareOp::Le => ordering.is_le(),
+        CompareOp::Gt => ordering.is_gt(),
+        CompareOp::Ge => ordering.is_ge(),
+    }
+}
+
+fn uses_field(expression: &Expression, name: &str) -> bool {
+    match expression {
+        Expression::And(left, right) | Expression::Or(left, right) => uses_field(left, name) || uses_field(right, name),
+        Expression::Not(inner) => uses_field(inner, name),
+        Expression::Exists(field) | Expression::Compare(field, _, _) | Expression::Contains(field, _)
+        | Expression::In(field, _) => field == name,
+    }
+}
+
+fn evaluate(expression: &Expression, packet: &Packet) -> bool {
+    match expression {
+        Expression::And(left, right) => evaluate(left, packet) && evaluate(right, packet),
+        Expression::Or(left, right) => evaluate(left, packet) || evaluate(right, packet),
+        Expression::Not(inner) => !evaluate(inner, packet),
+        Expression::Exists(field) => !field_values(packet, field).is_empty(),
+        Expression::Compare(field, op, literal) => {
+            let values = field_values(packet, field);
+            //As in Wireshark, "!=" only holds when none of the values are equal
+            if *op == CompareOp::Ne {
+                !values.is_empty() && values.iter().all(|value| literal_matches(value, *op, literal))
+            } else {
+                values.iter().any(|value| literal_matches(value, *op, literal))
+            }
+        }
+        Expression::Contains(field, needle) => field_values(packet, field).iter().any(|value| match value {
+            FieldValue::Bytes(haystack) => needle.is_empty() || haystack.windows(needle.len()).any(|window| window == needle.as_slice()),
+            FieldValue::Unsigned(_) => false,
+        }),
+        Expression::In(field, members) => field_values(packet, field).iter()
+            .any(|value| members.iter().any(|member| literal_matches(value, CompareOp::Eq, member))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A SYN from 10.0.0.1:1234 to 10.0.0.2:80 with a time to live of 64, carrying "GET /"
+    fn syn() -> PcapBlock {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 45, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&[0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(b"GET /");
+        PcapBlock::from_frame(frame, 0, 0)
+    }
+
+    fn matches(text: &str) -> bool {
+        DisplayFilter::parse(text).unwrap().matches(&syn())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expression = DisplayFilter::parse("tcp || udp && ip.ttl < 5").unwrap().expression;
+        assert!(matches!(expression, Expression::Or(_, right) if matches!(*right, Expression::And(..))));
+        let expression = DisplayFilter::parse("not tcp and udp").unwrap().expression;
+        assert!(matches!(expression, Expression::And(left, _) if matches!(*left, Expression::Not(..))));
+        let expression = DisplayFilter::parse("!(tcp or udp)").unwrap().expression;
+        assert!(matches!(expression, Expression::Not(inner) if matches!(*inner, Expression::Or(..))));
+
+        assert!(matches("tcp || udp && ip.ttl < 5"));
+        assert!(!matches("(tcp || udp) && ip.ttl < 5"));
+        assert!(matches("!udp && tcp.flags.syn == 1"));
+        assert!(!matches("!(udp || tcp.flags.syn == 1)"));
+    }
+
+    #[test]
+    fn compares_sets_ranges_and_subnets() {
+        assert!(matches("tcp.port in {22, 80}"));
+        assert!(matches("tcp.port in {22 443 1234}"));
+        assert!(!matches("tcp.dstport in {1..79, 81..1000}"));
+        assert!(matches("tcp.dstport in {79..81}"));
+        assert!(matches("tcp.flags in {0x02}"));
+        assert!(matches("ip.src == 10.0.0.0/8"));
+        assert!(!matches("ip.dst == 10.0.0.0/31"));
+        assert!(matches("ip.addr == 0.0.0.0/0"));
+        assert!(matches("ip.src in {192.168.0.0/16, 10.0.0.0/24}"));
+        assert!(!matches("ip.dst in {192.168.0.0/16, 10.0.0.1}"));
+        assert!(matches("ip.ttl ge 64 and ip.ttl le 64 and ip.ttl > 63 and ip.ttl lt 65"));
+        assert!(matches("eth.src == 02-00-00-00-00-01"));
+        assert!(matches("tcp.payload contains \"GET\" && tcp.payload contains 20:2f"));
+        assert!(!matches("tcp.payload contains \"POST\""));
+    }
+
+    #[test]
+    fn not_equal_holds_when_no_value_is_equal() {
+        assert!(!matches("ip.addr != 10.0.0.1"));
+        assert!(!matches("ip.addr != 10.0.0.2"));
+        assert!(matches("ip.addr != 10.0.0.3"));
+        assert!(!matches("tcp.port != 80"));
+        assert!(matches("tcp.port != 443"));
+        assert!(!matches("ip.addr != 10.0.0.0/30"));
+        assert!(matches("ip.addr != 192.168.0.0/16"));
+        //Neither holds for a field the packet does not have
+        assert!(!matches("udp.port != 53"));
+        assert!(!matches("udp.port == 53"));
+        //Which is not the negation of "==" on the fields with more than one value
+        assert!(matches("ip.addr == 10.0.0.1 && ip.addr ne 10.0.0.3"));
+        assert!(matches("!(ip.addr == 10.0.0.3) && !(ip.addr != 10.0.0.1)"));
+    }
+
+    #[test]
+    fn explains_what_it_can_not_parse() {
+        let cases = [
+            ("", "display filter ended unexpectedly"),
+            ("tcp &&", "display filter ended unexpectedly"),
+            ("ip.ttl ==", "display filter ended where a value was expected"),
+            ("ip.ttl = 5", "unexpected character '=' in display filter"),
+            ("(tcp || udp", "missing ')' in display filter"),
+            ("tcp udp", "unexpected Word(\"udp\") in display filter"),
+            ("== 5", "expected a field name but found Compare(Eq)"),
+            ("ip.ttl == ==", "expected a value but found Compare(Eq)"),
+            ("foo.bar", "unknown field 'foo.bar'"),
+            ("tcp == 1", "'tcp' is a protocol and can not be compared"),
+            ("ip.ttl contains 5", "'contains' can not be used on 'ip.ttl'"),
+            ("tcp.port in 80", "expected '{' after 'in'"),
+            ("tcp.port in {1..x}", "'x' is not a number"),
+            ("ip.ttl == x5", "'x5' is not a number"),
+            ("ip.src == 10.0.0.256", "'10.0.0.256' is not an IPv4 address"),
+            ("ip.src == 10.0.0", "'10.0.0' is not an IPv4 address"),
+            ("ip.src == 10.0.0.0/33", "'10.0.0.0/33' is not an IPv4 address"),
+            ("ip.src == 10.0.0.0/x", "bad prefix in '10.0.0.0/x'"),
+            ("eth.src == 02:00:00", "'02:00:00' is not a MAC address"),
+            ("tcp.payload contains 4g", "'4g' is not a byte sequence"),
+            ("tcp.payload contains \"GET", "unterminated string in display filter"),
+        ];
+        for (text, error) in cases {
+            assert_eq!(DisplayFilter::parse(text).unwrap_err(), error, "{}", text);
+        }
+    }
+}
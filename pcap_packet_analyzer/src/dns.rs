@@ -0,0 +1,151 @@
+use std::net::Ipv6Addr;
+use crate::internet_packet::format_ipv4;
+
+//Compression pointers may only point backwards, but a broken message could still loop
+const MAX_POINTERS: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DnsQuestion {
+    pub(crate) name: String,
+    pub(crate) qtype: u16,
+    pub(crate) qclass: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DnsRecord {
+    pub(crate) name: String,
+    pub(crate) rtype: u16,
+    pub(crate) class: u16,
+    pub(crate) ttl: u32,
+    pub(crate) data: String, //The data written the way dig shows it, or the length for types we do not decode
+}
+
+/// A DNS message from a UDP payload, or from a TCP payload after its two byte length.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DnsMessage {
+    pub(crate) id: u16,
+    pub(crate) flags: u16,
+    pub(crate) questions: Vec<DnsQuestion>,
+    pub(crate) answers: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    pub(crate) fn parse(data: &[u8]) -> Result<DnsMessage, String> {
+        if data.len() < 12 {
+            return Err("DNS message shorter than its header".to_string());
+        }
+        let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let (question_count, answer_count) = (word(4), word(6));
+        let mut position = 12;
+        let mut questions = Vec::new();
+        for _ in 0..question_count {
+            let name = read_name(data, &mut position)?;
+            let fixed = data.get(position..position + 4).ok_or("DNS question cut short")?;
+            questions.push(DnsQuestion {
+                name,
+                qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            position += 4;
+        }
+        let mut answers = Vec::new();
+        for _ in 0..answer_count {
+            let name = read_name(data, &mut position)?;
+            let fixed = data.get(position..position + 10).ok_or("DNS record cut short")?;
+            let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            let start = position + 10;
+            let rdata = data.get(start..start + length).ok_or("DNS record data cut short")?;
+            let name_at = |mut offset: usize| read_name(data, &mut offset);
+            let text = match (rtype, length) {
+                (1, 4) => format_ipv4(&[rdata[0], rdata[1], rdata[2], rdata[3]]),
+                (28, 16) => Ipv6Addr::from(<[u8; 16]>::try_from(rdata).unwrap()).to_string(),
+                (2 | 5 | 12, _) => name_at(start)?,
+                (15, 3..) => name_at(start + 2)?, //MX names follow the preference
+                _ => format!("{} bytes", length),
+            };
+            answers.push(DnsRecord {
+                name,
+                rtype,
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+                ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+                data: text,
+            });
+            position = start + length;
+        }
+        Ok(DnsMessage { id: word(0), flags: word(2), questions, answers })
+    }
+
+    pub(crate) fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
+    }
+
+    pub(crate) fn rcode(&self) -> u16 {
+        self.flags & 0x000f
+    }
+}
+
+/// Reads a name at `position`, following compression pointers, and moves `position` past it.
+fn read_name(data: &[u8], position: &mut usize) -> Result<String, String> {
+    let mut labels = Vec::new();
+    let mut cursor = *position;
+    let mut pointers = 0;
+    loop {
+        let length = *data.get(cursor).ok_or("DNS name cut short")? as usize;
+        match length {
+            0 => {
+                if pointers == 0 {
+                    *position = cursor + 1;
+                }
+                break;
+            }
+            0xc0.. => {
+                let low = *data.get(cursor + 1).ok_or("DNS name pointer cut short")? as usize;
+                if pointers == 0 {
+                    *position = cursor + 2;
+                }
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err("DNS name pointers loop".to_string());
+                }
+                cursor = (length & 0x3f) << 8 | low;
+            }
+            0x40.. => return Err(format!("unknown DNS label type 0x{:02x}", length)),
+            _ => {
+                let label = data.get(cursor + 1..cursor + 1 + length).ok_or("DNS label cut short")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                cursor += 1 + length;
+            }
+        }
+    }
+    Ok(if labels.is_empty() { ".".to_string() } else { labels.join(".") })
+}
+
+pub(crate) fn type_name(rtype: u16) -> Option<&'static str> {
+    match rtype {
+        1 => Some("A"),
+        2 => Some("NS"),
+        5 => Some("CNAME"),
+        6 => Some("SOA"),
+        12 => Some("PTR"),
+        15 => Some("MX"),
+        16 => Some("TXT"),
+        28 => Some("AAAA"),
+        33 => Some("SRV"),
+        65 => Some("HTTPS"),
+        255 => Some("*"),
+        _ => None,
+    }
+}
+
+pub(crate) fn rcode_name(rcode: u16) -> Option<&'static str> {
+    match rcode {
+        0 => Some("NOERROR"),
+        1 => Some("FORMERR"),
+        2 => Some("SERVFAIL"),
+        3 => Some("NXDOMAIN"),
+        4 => Some("NOTIMP"),
+        5 => Some("REFUSED"),
+        _ => None,
+    }
+}
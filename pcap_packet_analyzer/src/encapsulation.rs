@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use crate::frame_layout::FrameLayout;
+
+/// How the frames sent to the listener are wrapped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Encapsulation {
+    Auto, //Recognises each of the others, and takes anything else for a bare frame
+    Ethernet,
+    Tzsp,
+    Pcap,
+    Erspan,
+}
+
+impl Encapsulation {
+    pub(crate) fn from_str(text: &str) -> Result<Encapsulation, String> {
+        match text {
+            "auto" => Ok(Encapsulation::Auto),
+            "ethernet" => Ok(Encapsulation::Ethernet),
+            "tzsp" => Ok(Encapsulation::Tzsp),
+            "pcap" => Ok(Encapsulation::Pcap),
+            "erspan" => Ok(Encapsulation::Erspan),
+            _ => Err(format!("unknown encapsulation '{}', expected auto, ethernet, tzsp, pcap or erspan", text)),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Encapsulation::Auto => "auto",
+            Encapsulation::Ethernet => "ethernet",
+            Encapsulation::Tzsp => "tzsp",
+            Encapsulation::Pcap => "pcap",
+            Encapsulation::Erspan => "erspan",
+        }
+    }
+}
+
+/// What the envelope a frame arrived in said about it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Envelope {
+    pub(crate) kind: Encapsulation,
+    pub(crate) session: Option<u16>, //The ERSPAN session
+    pub(crate) interface: Option<u32>, //The port the frame was mirrored from
+    pub(crate) vlan: Option<u16>, //The VLAN the frame was seen on, when the tag is no longer in it
+}
+
+impl Envelope {
+    fn new(kind: Encapsulation) -> Envelope {
+        Envelope { kind, session: None, interface: None, vlan: None }
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind.name())?;
+        if let Some(session) = self.session {
+            write!(f, " session {}", session)?;
+        }
+        if let Some(interface) = self.interface {
+            write!(f, " interface {}", interface)?;
+        }
+        if let Some(vlan) = self.vlan {
+            write!(f, " vlan {}", vlan)?;
+        }
+        Ok(())
+    }
+}
+
+/// A frame taken out of its envelope, with what the envelope said about it.
+pub(crate) struct Unwrapped {
+    pub(crate) data: Vec<u8>,
+    pub(crate) timestamp: Option<(u32, u32)>, //When the frame was captured, in seconds and microseconds
+    pub(crate) original_length: Option<u32>, //The length on the wire, when the frame was cut short
+    pub(crate) envelope: Option<Envelope>,
+}
+
+impl Unwrapped {
+    fn bare(data: &[u8]) -> Unwrapped {
+        Unwrapped { data: data.to_vec(), timestamp: None, original_length: None, envelope: None }
+    }
+}
+
+//What the pcap file header a sender sent first said about the records after it
+#[derive(Copy, Clone)]
+struct PcapStream {
+    big_endian: bool,
+    nanosecond: bool,
+    link_type: u32,
+}
+
+const ETHERNET: u32 = 1;
+const GRE: u8 = 47;
+const ERSPAN_II: u16 = 0x88be; //Also type I, which has no sequence number and no ERSPAN header
+const ERSPAN_III: u16 = 0x22eb;
+
+/// Takes the frames out of the datagrams sent to the listener.
+pub(crate) struct Decapsulator {
+    encapsulation: Encapsulation,
+    pcap_streams: HashMap<SocketAddr, PcapStream>,
+    pub(crate) dropped: u64, //Datagrams that held no Ethernet frame in the expected envelope
+}
+
+impl Decapsulator {
+    pub(crate) fn new(encapsulation: Encapsulation) -> Decapsulator {
+        Decapsulator { encapsulation, pcap_streams: HashMap::new(), dropped: 0 }
+    }
+
+    /// The frame a datagram holds, or None when it held none, such as a pcap file header.
+    pub(crate) fn unwrap(&mut self, datagram: &[u8], sender: SocketAddr) -> Option<Unwrapped> {
+        if let Some(stream) = parse_pcap_header(datagram) {
+            if matches!(self.encapsulation, Encapsulation::Auto | Encapsulation::Pcap) {
+                self.pcap_streams.insert(sender, stream);
+                return None;
+            }
+        }
+        let stream = self.pcap_streams.get(&sender).copied();
+        let unwrapped = match self.encapsulation {
+            Encapsulation::Auto => unwrap_tzsp(datagram)
+                .or_else(|| unwrap_pcap_record(datagram, stream))
+                .or_else(|| unwrap_gre(datagram))
+                .map(|unwrapped| unwrap_erspan_frame(&unwrapped.data).unwrap_or(unwrapped))
+                .or_else(|| unwrap_erspan_frame(datagram))
+                .or_else(|| Some(Unwrapped::bare(datagram))),
+            Encapsulation::Ethernet => Some(Unwrapped::bare(datagram)),
+            Encapsulation::Tzsp => unwrap_tzsp(datagram),
+            Encapsulation::Pcap => unwrap_pcap_record(datagram, stream),
+            Encapsulation::Erspan => unwrap_gre(datagram).or_else(|| unwrap_erspan_frame(datagram)),
+        };
+        if unwrapped.is_none() {
+            self.dropped += 1;
+        }
+        unwrapped
+    }
+}
+
+/// TZSP as sent by MikroTik and other probes: version 1, a type, the encapsulated protocol and
+/// a list of tags ending with the end tag, then the frame.
+fn unwrap_tzsp(datagram: &[u8]) -> Option<Unwrapped> {
+    //Only received and transmitted packets carry a frame, the other types are for the sensor
+    if datagram.len() < 5 || datagram[0] != 1 || datagram[1] > 1 || u16::from_be_bytes([datagram[2], datagram[3]]) != ETHERNET as u16 {
+        return None;
+    }
+    let mut original_length = None;
+    let mut offset = 4;
+    loop {
+        match *datagram.get(offset)? {
+            0 => offset += 1, //Padding
+            1 => {
+                offset += 1;
+                break;
+            }
+            tag => {
+                let length = *datagram.get(offset + 1)? as usize;
+                let value = datagram.get(offset + 2..offset + 2 + length)?;
+                //The length of the frame as it was received
+                if tag == 0x29 && length == 2 {
+                    original_length = Some(u16::from_be_bytes([value[0], value[1]]) as u32);
+                }
+                offset += 2 + length;
+            }
+        }
+    }
+    Some(Unwrapped {
+        data: datagram[offset..].to_vec(),
+        timestamp: None,
+        original_length,
+        envelope: Some(Envelope::new(Encapsulation::Tzsp)),
+    })
+}
+
+fn parse_pcap_header(datagram: &[u8]) -> Option<PcapStream> {
+    if datagram.len() != 24 {
+        return None;
+    }
+    let (big_endian, nanosecond) = match datagram[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        _ => return None,
+    };
+    let link_type = read_u32(&datagram[20..24], big_endian);
+    Some(PcapStream { big_endian, nanosecond, link_type })
+}
+
+/// One pcap record per datagram, its 16 byte header followed by the frame. The byte order and
+/// resolution come from a file header the sender sent first, or else are those of a little
+/// endian microsecond file, the byte order being guessed from the captured length.
+fn unwrap_pcap_record(datagram: &[u8], stream: Option<PcapStream>) -> Option<Unwrapped> {
+    if datagram.len() < 16 {
+        return None;
+    }
+    let data_length = datagram.len() as u32 - 16;
+    let stream = match stream {
+        Some(stream) if stream.link_type != ETHERNET => return None,
+        Some(stream) => stream,
+        None => {
+            let big_endian = read_u32(&datagram[8..12], true) == data_length && read_u32(&datagram[8..12], false) != data_length;
+            PcapStream { big_endian, nanosecond: false, link_type: ETHERNET }
+        }
+    };
+    let field = |offset: usize| read_u32(&datagram[offset..offset + 4], stream.big_endian);
+    let (seconds, fraction, captured_length, original_length) = (field(0), field(4), field(8), field(12));
+    let fraction_limit = if stream.nanosecond { 1_000_000_000 } else { 1_000_000 };
+    if captured_length != data_length || original_length < captured_length || fraction >= fraction_limit {
+        return None;
+    }
+    let microseconds = if stream.nanosecond { fraction / 1000 } else { fraction };
+    Some(Unwrapped {
+        data: datagram[16..].to_vec(),
+        timestamp: Some((seconds, microseconds)),
+        original_length: Some(original_length),
+        envelope: Some(Envelope::new(Encapsulation::Pcap)),
+    })
+}
+
+/// ERSPAN in a GRE packet that starts the datagram, as GRE in UDP sends it.
+fn unwrap_gre(gre: &[u8]) -> Option<Unwrapped> {
+    if gre.len() < 4 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([gre[0], gre[1]]);
+    let protocol = u16::from_be_bytes([gre[2], gre[3]]);
+    //Routing present, strict source route, recursion or a version other than 0 is not ERSPAN
+    if flags & 0x4fff != 0 || !matches!(protocol, ERSPAN_II | ERSPAN_III) {
+        return None;
+    }
+    let sequenced = flags & 0x1000 != 0;
+    let header_length = 4 + [0x8000, 0x2000, 0x1000].iter().filter(|bit| flags & **bit != 0).count() * 4;
+    let erspan = gre.get(header_length..)?;
+    let word = |offset: usize| u16::from_be_bytes([erspan[offset], erspan[offset + 1]]);
+    let long = |offset: usize| read_u32(&erspan[offset..offset + 4], true);
+
+    let mut envelope = Envelope::new(Encapsulation::Erspan);
+    let mut timestamp = None;
+    let frame = match protocol {
+        ERSPAN_II if !sequenced => erspan,
+        ERSPAN_II if erspan.len() >= 8 && erspan[0] >> 4 == 1 => {
+            envelope.session = Some(word(2) & 0x03ff);
+            envelope.interface = Some(long(4) & 0x000f_ffff);
+            envelope.vlan = Some(word(0) & 0x0fff).filter(|vlan| *vlan != 0);
+            &erspan[8..]
+        }
+        ERSPAN_III if erspan.len() >= 12 && erspan[0] >> 4 == 2 => {
+            let flags = word(10);
+            //Only Ethernet frames are decoded, not the IP packets of frame type 2
+            if (flags >> 10) & 0x1f != 0 {
+                return None;
+            }
+            envelope.session = Some(word(2) & 0x03ff);
+            envelope.vlan = Some(word(0) & 0x0fff).filter(|vlan| *vlan != 0);
+            let mut header_length = 12;
+            //The optional platform specific subheader
+            if flags & 1 != 0 {
+                let platform = erspan.get(12..20)?;
+                let first = read_u32(&platform[..4], true);
+                //Platform 3 has the port and the seconds of an IEEE 1588 timestamp, whose nanoseconds are in the header
+                if first >> 26 == 3 {
+                    envelope.interface = Some(first & 0x3fff);
+                    if (flags >> 1) & 0x3 == 2 {
+                        timestamp = Some((read_u32(&platform[4..8], true), long(4) / 1000));
+                    }
+                }
+                header_length = 20;
+            }
+            &erspan[header_length..]
+        }
+        _ => return None,
+    };
+    Some(Unwrapped { data: frame.to_vec(), timestamp, original_length: None, envelope: Some(envelope) })
+}
+
+/// ERSPAN in a GRE packet carried by IPv4 or IPv6 in an Ethernet frame, as a mirror session
+/// sends it to its destination.
+fn unwrap_erspan_frame(frame: &[u8]) -> Option<Unwrapped> {
+    let ip = FrameLayout::parse(frame).ip?;
+    if ip.protocol != GRE || ip.fragment || ip.transport > ip.end {
+        return None;
+    }
+    unwrap_gre(&frame[ip.transport..ip.end.min(frame.len())])
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes: [u8; 4] = bytes.try_into().unwrap();
+    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+}
@@ -1,7 +1,30 @@
 use std::fmt;
-use crate::{IPacket, IPVersion};
+use crate::{create_and_return_ether, IPacket, IPVersion};
+use crate::json::{hex_word, JsonValue};
 
-pub(crate) struct EthernetFrame {
+/// A hardware address, printed as `00:1a:2b:3c:4d:5e`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> MacAddr {
+        MacAddr(octets)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_mac(&self.0))
+    }
+}
+
+pub struct EthernetFrame {
     pub(crate) packet_size: u32,
     pub(crate) destination_address: [u8; 6],
     pub(crate) source_address: [u8; 6],
@@ -21,6 +44,55 @@ impl EthernetFrame {
             packet: IPacket::new(),
         }
     }
+
+    /// Decodes a captured frame. A frame that is not IPv4 keeps nothing but its Ethernet header.
+    pub fn parse(data: &[u8]) -> EthernetFrame {
+        create_and_return_ether(data)
+    }
+
+    pub fn destination(&self) -> MacAddr {
+        MacAddr(self.destination_address)
+    }
+
+    pub fn source(&self) -> MacAddr {
+        MacAddr(self.source_address)
+    }
+
+    pub fn ether_type(&self) -> u16 {
+        u16::from_be_bytes(self.ether_type)
+    }
+
+    /// The number of bytes the frame was decoded from.
+    pub fn length(&self) -> u32 {
+        self.packet_size
+    }
+
+    /// The IPv4 packet the frame carried, if it carried one.
+    pub fn ipv4(&self) -> Option<&IPacket> {
+        self.has_ipv4().then_some(&self.packet)
+    }
+
+    /// True when the frame carried an IPv4 packet that was decoded into `packet`.
+    pub(crate) fn has_ipv4(&self) -> bool {
+        self.ether_type == [0x08, 0x00] && self.packet.ihl >= 5
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let mut fields = vec![
+            ("packet_size", JsonValue::from(self.packet_size)),
+            ("destination", JsonValue::String(format_mac(&self.destination_address))),
+            ("source", JsonValue::String(format_mac(&self.source_address))),
+            ("ethertype", hex_word(self.ether_type)),
+        ];
+        if self.has_ipv4() {
+            fields.push(("ip", self.packet.to_json()));
+        }
+        JsonValue::object(fields)
+    }
+}
+
+pub(crate) fn format_mac(address: &[u8; 6]) -> String {
+    address.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(":")
 }
 
 impl fmt::Display for EthernetFrame {
@@ -0,0 +1,57 @@
+use crate::PcapBlock;
+use crate::packet_fields::{field_kind, field_values, format_value, FieldKind};
+
+/// Writes the chosen fields of every packet as one CSV or TSV row, like `tshark -T fields`.
+/// Fields missing from a packet are left empty and fields with several values, such as
+/// `ip.addr`, have their values joined with commas.
+pub(crate) struct FieldExporter {
+    fields: Vec<(String, FieldKind)>,
+    separator: char,
+}
+
+impl FieldExporter {
+    pub(crate) fn new(fields: Vec<String>, separator: char) -> Result<FieldExporter, String> {
+        if fields.is_empty() {
+            return Err("choose the fields to export with -e".to_string());
+        }
+        let fields = fields.into_iter()
+            .map(|field| match field_kind(&field) {
+                Some(kind) => Ok((field, kind)),
+                None => Err(format!("unknown field '{}'", field)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(FieldExporter { fields, separator })
+    }
+
+    pub(crate) fn header(&self) -> String {
+        self.join(self.fields.iter().map(|(field, _)| field.clone()).collect())
+    }
+
+    pub(crate) fn row(&self, block: &PcapBlock) -> String {
+        let packet = block.view();
+        self.join(self.fields.iter()
+            .map(|(field, kind)| field_values(&packet, field).iter()
+                .map(|value| format_value(*kind, value))
+                .collect::<Vec<String>>()
+                .join(","))
+            .collect())
+    }
+
+    fn join(&self, cells: Vec<String>) -> String {
+        cells.into_iter()
+            .map(|cell| self.escape(cell))
+            .collect::<Vec<String>>()
+            .join(&self.separator.to_string())
+    }
+
+    //CSV cells are quoted when needed, TSV has no quoting so tabs and newlines become spaces
+    fn escape(&self, cell: String) -> String {
+        if self.separator == '\t' {
+            cell.replace(['\t', '\n', '\r'], " ")
+        } else if cell.contains([self.separator, '"', '\n', '\r']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell
+        }
+    }
+}
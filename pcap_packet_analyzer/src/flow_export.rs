@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use crate::view::{Packet, Transport};
+
+//Flows are checked for timeouts once in every interval of this many microseconds of capture time
+const EXPIRY_INTERVAL: u64 = 1_000_000;
+const TEMPLATE_ID: u16 = 256;
+
+//Information elements of the v9 and IPFIX template, with their lengths in each format
+const TEMPLATE_V9: [(u16, u16); 11] = [(8, 4), (12, 4), (7, 2), (11, 2), (4, 1), (5, 1), (6, 1), (2, 4), (1, 4),
+    (22, 4), (21, 4)];
+const TEMPLATE_IPFIX: [(u16, u16); 11] = [(8, 4), (12, 4), (7, 2), (11, 2), (4, 1), (5, 1), (6, 2), (2, 8), (1, 8),
+    (152, 8), (153, 8)];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum FlowFormat {
+    NetflowV5,
+    NetflowV9,
+    Ipfix,
+}
+
+impl FlowFormat {
+    pub(crate) fn from_str(name: &str) -> Result<FlowFormat, String> {
+        match name {
+            "v5" => Ok(FlowFormat::NetflowV5),
+            "v9" => Ok(FlowFormat::NetflowV9),
+            "ipfix" => Ok(FlowFormat::Ipfix),
+            _ => Err(format!("unknown flow format '{}', expected v5, v9 or ipfix", name)),
+        }
+    }
+
+    /// Records per export datagram. Version 5 allows no more than 30, the template based formats
+    /// stop where the datagram would no longer fit a 1500 byte MTU.
+    fn records_per_datagram(&self) -> usize {
+        match self {
+            FlowFormat::NetflowV5 => 30,
+            FlowFormat::NetflowV9 | FlowFormat::Ipfix => 24,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FlowDestination {
+    File(String),
+    Collector(String), //host:port of a collector listening on UDP
+}
+
+/// What `--flow-export` and the options around it asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FlowExportOptions {
+    pub(crate) format: FlowFormat,
+    pub(crate) destination: FlowDestination,
+    pub(crate) active_timeout: u64, //Seconds before a long running flow is exported and started again
+    pub(crate) inactive_timeout: u64, //Seconds without packets before a flow is exported
+}
+
+impl FlowExportOptions {
+    pub(crate) fn new(format: FlowFormat, destination: FlowDestination) -> FlowExportOptions {
+        FlowExportOptions {
+            format,
+            destination,
+            active_timeout: 1800,
+            inactive_timeout: 15,
+        }
+    }
+}
+
+/// Flows are unidirectional, as NetFlow counts them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct FlowKey {
+    source: [u8; 4],
+    destination: [u8; 4],
+    source_port: u16,
+    destination_port: u16, //ICMP keeps its type and code here, as type * 256 + code
+    protocol: u8,
+}
+
+pub(crate) struct Flow {
+    key: FlowKey,
+    tos: u8,
+    tcp_flags: u8, //Every flag seen on the flow ORed together
+    packets: u64,
+    bytes: u64, //IP bytes, headers included
+    first: u64, //Microseconds since the epoch
+    last: u64,
+}
+
+/// The flows still open, until they time out or their connection ends. Each worker thread of
+/// `--threads` keeps a table of the flows it owns.
+pub(crate) struct FlowTable {
+    active_timeout: u64, //In microseconds
+    inactive_timeout: u64,
+    flows: HashMap<FlowKey, Flow>,
+    now: u64,
+    checked: u64, //The last EXPIRY_INTERVAL of capture time the timeouts were checked in
+    finished: Vec<Flow>, //Flows that timed out or ended, oldest first, until they are exported
+}
+
+impl FlowTable {
+    pub(crate) fn new(options: &FlowExportOptions) -> FlowTable {
+        FlowTable {
+            active_timeout: options.active_timeout * 1_000_000,
+            inactive_timeout: options.inactive_timeout * 1_000_000,
+            flows: HashMap::new(),
+            now: 0,
+            checked: 0,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Finishes the flows that timed out by the start of the interval `time` is in, when the
+    /// timeouts were not checked in that interval yet. Returns whether they were. The timeouts
+    /// are measured from the start of the interval, not the packet, so a table with any share of
+    /// the packets finishes the same flows.
+    pub(crate) fn expire(&mut self, time: u64) -> bool {
+        self.now = self.now.max(time);
+        if self.now / EXPIRY_INTERVAL <= self.checked {
+            return false;
+        }
+        self.checked = self.now / EXPIRY_INTERVAL;
+        let start = self.checked * EXPIRY_INTERVAL;
+        let (active, inactive) = (self.active_timeout, self.inactive_timeout);
+        self.finish(|flow| start >= flow.last + inactive || start >= flow.first + active);
+        true
+    }
+
+    pub(crate) fn add(&mut self, packet: &Packet) {
+        let Some(ip) = packet.ipv4() else { return };
+        let time = packet.microseconds();
+        let (source_port, destination_port, tcp_flags) = match ip.transport() {
+            Transport::Tcp(tcp) => (tcp.source_port(), tcp.destination_port(), tcp.flags()),
+            Transport::Udp(udp) => (udp.source_port(), udp.destination_port(), 0),
+            Transport::Icmp(icmp) => (0, u16::from_be_bytes([icmp.message_type(), icmp.code()]), 0),
+            Transport::Other => (0, 0, 0),
+        };
+        let key = FlowKey {
+            source: ip.source().octets(),
+            destination: ip.destination().octets(),
+            source_port,
+            destination_port,
+            protocol: ip.protocol(),
+        };
+        let flow = self.flows.entry(key).or_insert(Flow {
+            key,
+            tos: ip.type_of_service(),
+            tcp_flags: 0,
+            packets: 0,
+            bytes: 0,
+            first: time,
+            last: time,
+        });
+        flow.tcp_flags |= tcp_flags;
+        flow.packets += 1;
+        flow.bytes += ip.total_length() as u64;
+        flow.first = flow.first.min(time);
+        flow.last = flow.last.max(time);
+
+        //A FIN or RST ends the connection, so its flow is not kept waiting for the timeout
+        if tcp_flags & 0x05 != 0 {
+            let flow = self.flows.remove(&key).unwrap();
+            self.finished.push(flow);
+        }
+    }
+
+    /// Takes the flows that were finished since the last time.
+    pub(crate) fn take_finished(&mut self) -> Vec<Flow> {
+        std::mem::take(&mut self.finished)
+    }
+
+    fn finish(&mut self, expired: impl Fn(&Flow) -> bool) {
+        let mut keys: Vec<FlowKey> = self.flows.values()
+            .filter(|flow| expired(flow))
+            .map(|flow| flow.key)
+            .collect();
+        //Oldest first so the records come out in a stable order
+        keys.sort_by_key(|key| self.flows[key].first);
+        for key in keys {
+            let flow = self.flows.remove(&key).unwrap();
+            self.finished.push(flow);
+        }
+    }
+}
+
+enum Output {
+    File(BufWriter<File>),
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// Aggregates IPv4 packets into flow records by 5-tuple and writes them as NetFlow v5, NetFlow v9
+/// or IPFIX export datagrams. In a file the datagrams follow each other with nothing in between,
+/// which for IPFIX is the file format of RFC 5655.
+pub(crate) struct FlowExporter {
+    options: FlowExportOptions,
+    output: Output,
+    table: FlowTable,
+    pending: Vec<Flow>, //Expired flows waiting to fill an export datagram
+    boot_time: Option<u64>, //The first packet stands for the moment the exporter started
+    now: u64,
+    flows_exported: u64,
+    datagrams_sent: u32,
+}
+
+impl FlowExporter {
+    pub(crate) fn new(options: &FlowExportOptions) -> Result<FlowExporter, String> {
+        let output = match &options.destination {
+            FlowDestination::File(path) => Output::File(BufWriter::new(
+                File::create(path).map_err(|error| format!("could not create {}: {}", path, error))?)),
+            FlowDestination::Collector(address) => {
+                let collector = address.to_socket_addrs()
+                    .map_err(|error| format!("could not resolve the collector {}: {}", address, error))?
+                    .next()
+                    .ok_or(format!("could not resolve the collector {}", address))?;
+                let bind = if collector.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind).map_err(|error| format!("could not open a UDP socket: {}", error))?;
+                Output::Udp(socket, collector)
+            }
+        };
+        Ok(FlowExporter {
+            options: options.clone(),
+            output,
+            table: FlowTable::new(options),
+            pending: Vec::new(),
+            boot_time: None,
+            now: 0,
+            flows_exported: 0,
+            datagrams_sent: 0,
+        })
+    }
+
+    pub(crate) fn add(&mut self, packet: &Packet) -> Result<(), String> {
+        let time = packet.microseconds();
+        self.note(time);
+        if self.table.expire(time) {
+            self.export_finished()?;
+            //Flows that timed out go to the collector now instead of waiting for a full datagram
+            self.send_pending()?;
+        }
+        self.table.add(packet);
+        self.export_finished()
+    }
+
+    /// Notes the time of a packet whose flow a worker thread keeps, for the clock of the export
+    /// datagrams.
+    pub(crate) fn note(&mut self, time: u64) {
+        self.boot_time.get_or_insert(time);
+        self.now = self.now.max(time);
+    }
+
+    /// Exports the flows a worker thread finished.
+    pub(crate) fn export(&mut self, flows: Vec<Flow>) -> Result<(), String> {
+        for flow in flows {
+            self.queue(flow)?;
+        }
+        Ok(())
+    }
+
+    /// Takes over the flows a worker thread still has open, to be exported with the others.
+    /// They never share a 5-tuple with the flows already here, every flow is kept by one worker.
+    pub(crate) fn merge(&mut self, mut table: FlowTable) -> Result<(), String> {
+        self.export(table.take_finished())?;
+        self.table.flows.extend(table.flows);
+        Ok(())
+    }
+
+    /// Exports every flow still open and flushes the output. Returns the number of flow records
+    /// and of export datagrams written.
+    pub(crate) fn finish(&mut self) -> Result<(u64, u32), String> {
+        self.table.finish(|_| true);
+        self.export_finished()?;
+        self.send_pending()?;
+        if let Output::File(file) = &mut self.output {
+            file.flush().map_err(|error| format!("could not write the flow records: {}", error))?;
+        }
+        Ok((self.flows_exported, self.datagrams_sent))
+    }
+
+    fn export_finished(&mut self) -> Result<(), String> {
+        let mut flows = self.table.take_finished();
+        for flow in flows.drain(..) {
+            self.queue(flow)?;
+        }
+        //The vector is kept for the next flows
+        self.table.finished = flows;
+        Ok(())
+    }
+
+    fn queue(&mut self, flow: Flow) -> Result<(), String> {
+        self.pending.push(flow);
+        if self.pending.len() == self.options.format.records_per_datagram() {
+            self.send_pending()?;
+        }
+        Ok(())
+    }
+
+    fn send_pending(&mut self) -> Result<(), String> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let flows = std::mem::take(&mut self.pending);
+        let datagram = match self.options.format {
+            FlowFormat::NetflowV5 => self.netflow_v5(&flows),
+            FlowFormat::NetflowV9 => self.netflow_v9(&flows),
+            FlowFormat::Ipfix => self.ipfix(&flows),
+        };
+        match &mut self.output {
+            Output::File(file) => file.write_all(&datagram).map_err(|error| format!("could not write the flow records: {}", error))?,
+            Output::Udp(socket, collector) => {
+                socket.send_to(&datagram, *collector).map_err(|error| format!("could not send to {}: {}", collector, error))?;
+            }
+        }
+        self.flows_exported += flows.len() as u64;
+        self.datagrams_sent += 1;
+        Ok(())
+    }
+
+    //Milliseconds since the exporter started, the clock NetFlow v5 and v9 records use
+    fn uptime(&self, time: u64) -> u32 {
+        ((time - self.boot_time.unwrap_or(time)) / 1000) as u32
+    }
+
+    fn netflow_v5(&self, flows: &[Flow]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + flows.len() * 48);
+        out.extend_from_slice(&5u16.to_be_bytes());
+        out.extend_from_slice(&(flows.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.uptime(self.now).to_be_bytes());
+        out.extend_from_slice(&((self.now / 1_000_000) as u32).to_be_bytes());
+        out.extend_from_slice(&((self.now % 1_000_000 * 1000) as u32).to_be_bytes());
+        out.extend_from_slice(&(self.flows_exported as u32).to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]); //Engine type and id, sampling interval
+        for flow in flows {
+            out.extend_from_slice(&flow.key.source);
+            out.extend_from_slice(&flow.key.destination);
+            out.extend_from_slice(&[0; 8]); //Next hop, input and output interface
+            out.extend_from_slice(&(flow.packets as u32).to_be_bytes());
+            out.extend_from_slice(&(flow.bytes as u32).to_be_bytes());
+            out.extend_from_slice(&self.uptime(flow.first).to_be_bytes());
+            out.extend_from_slice(&self.uptime(flow.last).to_be_bytes());
+            out.extend_from_slice(&flow.key.source_port.to_be_bytes());
+            out.extend_from_slice(&flow.key.destination_port.to_be_bytes());
+            out.extend_from_slice(&[0, flow.tcp_flags, flow.key.protocol, flow.tos]);
+            out.extend_from_slice(&[0; 8]); //Source and destination AS and masks, padding
+        }
+        out
+    }
+
+    fn netflow_v9(&self, flows: &[Flow]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&9u16.to_be_bytes());
+        //The template is a record as well, and is sent with every datagram
+        out.extend_from_slice(&(flows.len() as u16 + 1).to_be_bytes());
+        out.extend_from_slice(&self.uptime(self.now).to_be_bytes());
+        out.extend_from_slice(&((self.now / 1_000_000) as u32).to_be_bytes());
+        out.extend_from_slice(&self.datagrams_sent.to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes()); //Source id
+        write_template(&mut out, 0, &TEMPLATE_V9);
+
+        let start = out.len();
+        out.extend_from_slice(&TEMPLATE_ID.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        for flow in flows {
+            write_key(&mut out, flow);
+            out.push(flow.tcp_flags);
+            out.extend_from_slice(&(flow.packets as u32).to_be_bytes());
+            out.extend_from_slice(&(flow.bytes as u32).to_be_bytes());
+            out.extend_from_slice(&self.uptime(flow.first).to_be_bytes());
+            out.extend_from_slice(&self.uptime(flow.last).to_be_bytes());
+        }
+        //Version 9 FlowSets are padded to a multiple of four bytes
+        while (out.len() - start) % 4 != 0 {
+            out.push(0);
+        }
+        set_length(&mut out, start);
+        out
+    }
+
+    fn ipfix(&self, flows: &[Flow]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&10u16.to_be_bytes());
+        out.extend_from_slice(&[0, 0]); //Message length, filled in below
+        out.extend_from_slice(&((self.now / 1_000_000) as u32).to_be_bytes());
+        out.extend_from_slice(&(self.flows_exported as u32).to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes()); //Observation domain
+        write_template(&mut out, 2, &TEMPLATE_IPFIX);
+
+        let start = out.len();
+        out.extend_from_slice(&TEMPLATE_ID.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        for flow in flows {
+            write_key(&mut out, flow);
+            out.extend_from_slice(&(flow.tcp_flags as u16).to_be_bytes());
+            out.extend_from_slice(&flow.packets.to_be_bytes());
+            out.extend_from_slice(&flow.bytes.to_be_bytes());
+            out.extend_from_slice(&(flow.first / 1000).to_be_bytes());
+            out.extend_from_slice(&(flow.last / 1000).to_be_bytes());
+        }
+        set_length(&mut out, start);
+        let length = out.len() as u16;
+        out[2..4].copy_from_slice(&length.to_be_bytes());
+        out
+    }
+}
+
+fn write_template(out: &mut Vec<u8>, set_id: u16, fields: &[(u16, u16)]) {
+    let start = out.len();
+    out.extend_from_slice(&set_id.to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&TEMPLATE_ID.to_be_bytes());
+    out.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+    for (field, length) in fields {
+        out.extend_from_slice(&field.to_be_bytes());
+        out.extend_from_slice(&length.to_be_bytes());
+    }
+    set_length(out, start);
+}
+
+//The fields both templates start with: addresses, ports, protocol and type of service
+fn write_key(out: &mut Vec<u8>, flow: &Flow) {
+    out.extend_from_slice(&flow.key.source);
+    out.extend_from_slice(&flow.key.destination);
+    out.extend_from_slice(&flow.key.source_port.to_be_bytes());
+    out.extend_from_slice(&flow.key.destination_port.to_be_bytes());
+    out.push(flow.key.protocol);
+    out.push(flow.tos);
+}
+
+fn set_length(out: &mut [u8], start: usize) {
+    let length = (out.len() - start) as u16;
+    out[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::PcapBlock;
+    use super::*;
+
+    fn ipv4(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 20 + transport.len() as u8, 0, 0, 0, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(transport);
+        frame
+    }
+
+    /// Two UDP packets from port 1024 to 53 and a TCP FIN from 40000 to 80, half a second apart
+    /// at most, as one export datagram in the format.
+    fn export(name: &str, format: FlowFormat) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("packet_analyzer-{}-{}", std::process::id(), name));
+        let options = FlowExportOptions::new(format, FlowDestination::File(path.display().to_string()));
+        let mut exporter = FlowExporter::new(&options).unwrap();
+        let udp = ipv4(17, &[0x04, 0x00, 0x00, 0x35, 0, 8, 0, 0]);
+        let tcp = ipv4(6, &[0x9c, 0x40, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x11, 0xff, 0xff, 0, 0, 0, 0]);
+        exporter.add(&PcapBlock::from_frame(udp.clone(), 1000, 0).view()).unwrap();
+        exporter.add(&PcapBlock::from_frame(udp, 1000, 250_000).view()).unwrap();
+        exporter.add(&PcapBlock::from_frame(tcp, 1000, 500_000).view()).unwrap();
+        assert_eq!(exporter.finish().unwrap(), (2, 1));
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        written
+    }
+
+    #[test]
+    fn writes_netflow_v5() {
+        let expected: Vec<u8> = [
+            &[0, 5, 0, 2][..], //Version, count
+            &[0, 0, 0x01, 0xf4], //Uptime, 500 ms
+            &[0, 0, 0x03, 0xe8, 0x1d, 0xcd, 0x65, 0], //1000 s and 500000000 ns
+            &[0, 0, 0, 0, 0, 0, 0, 0], //Sequence, engine and sampling
+            //The TCP flow closed first
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 1, 0, 0, 0, 40, 0, 0, 0x01, 0xf4, 0, 0, 0x01, 0xf4],
+            &[0x9c, 0x40, 0, 80, 0, 0x11, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 2, 0, 0, 0, 56, 0, 0, 0, 0, 0, 0, 0, 0xfa],
+            &[0x04, 0, 0, 53, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ].concat();
+        assert_eq!(export("v5.flows", FlowFormat::NetflowV5), expected);
+    }
+
+    #[test]
+    fn writes_netflow_v9() {
+        let expected: Vec<u8> = [
+            &[0, 9, 0, 3][..], //Version, the template and two records
+            &[0, 0, 0x01, 0xf4, 0, 0, 0x03, 0xe8], //Uptime, seconds
+            &[0, 0, 0, 0, 0, 0, 0, 0], //Sequence, source id
+            &[0, 0, 0, 52, 1, 0, 0, 11], //Template FlowSet of template 256 with 11 fields
+            &[0, 8, 0, 4, 0, 12, 0, 4, 0, 7, 0, 2, 0, 11, 0, 2, 0, 4, 0, 1, 0, 5, 0, 1],
+            &[0, 6, 0, 1, 0, 2, 0, 4, 0, 1, 0, 4, 0, 22, 0, 4, 0, 21, 0, 4],
+            &[1, 0, 0, 68], //Data FlowSet, two bytes of padding at its end
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0x9c, 0x40, 0, 80, 6, 0, 0x11],
+            &[0, 0, 0, 1, 0, 0, 0, 40, 0, 0, 0x01, 0xf4, 0, 0, 0x01, 0xf4],
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0, 0, 53, 17, 0, 0],
+            &[0, 0, 0, 2, 0, 0, 0, 56, 0, 0, 0, 0, 0, 0, 0, 0xfa],
+            &[0, 0],
+        ].concat();
+        assert_eq!(export("v9.flows", FlowFormat::NetflowV9), expected);
+    }
+
+    #[test]
+    fn writes_ipfix() {
+        let expected: Vec<u8> = [
+            &[0, 10, 0, 168][..], //Version, message length
+            &[0, 0, 0x03, 0xe8, 0, 0, 0, 0, 0, 0, 0, 0], //Export time, sequence, domain
+            &[0, 2, 0, 52, 1, 0, 0, 11], //Template Set of template 256 with 11 fields
+            &[0, 8, 0, 4, 0, 12, 0, 4, 0, 7, 0, 2, 0, 11, 0, 2, 0, 4, 0, 1, 0, 5, 0, 1],
+            &[0, 6, 0, 2, 0, 2, 0, 8, 0, 1, 0, 8, 0, 152, 0, 8, 0, 153, 0, 8],
+            &[1, 0, 0, 100],
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0x9c, 0x40, 0, 80, 6, 0, 0, 0x11],
+            &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 40],
+            &[0, 0, 0, 0, 0, 0x0f, 0x44, 0x34, 0, 0, 0, 0, 0, 0x0f, 0x44, 0x34], //Milliseconds since the epoch
+            &[10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0, 0, 53, 17, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 56],
+            &[0, 0, 0, 0, 0, 0x0f, 0x42, 0x40, 0, 0, 0, 0, 0, 0x0f, 0x43, 0x3a],
+        ].concat();
+        assert_eq!(export("ipfix.flows", FlowFormat::Ipfix), expected);
+    }
+}
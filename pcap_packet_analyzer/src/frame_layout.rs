@@ -0,0 +1,135 @@
+/// Where the network and transport headers of an IPv4 or IPv6 packet sit in a frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct IpLayout {
+    pub(crate) version: u8,
+    pub(crate) start: usize,
+    pub(crate) transport: usize, //Where the transport header starts, after any IPv6 extension headers
+    pub(crate) end: usize, //The end given by the length field, past the captured bytes when the packet was cut short
+    pub(crate) protocol: u8,
+    pub(crate) fragment: bool, //Part of a fragmented datagram, so the transport checksum covers more than this packet
+}
+
+/// The layout of an Ethernet frame, found from the raw bytes so it can be used while rewriting
+/// them. VLAN tags are skipped over.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct FrameLayout {
+    pub(crate) ether_type: u16,
+    pub(crate) network: usize, //Where the payload of the Ethernet frame starts
+    pub(crate) ip: Option<IpLayout>,
+}
+
+impl FrameLayout {
+    pub(crate) fn parse(data: &[u8]) -> FrameLayout {
+        let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        if data.len() < 14 {
+            return FrameLayout { ether_type: 0, network: data.len(), ip: None };
+        }
+        let mut ether_type = word(12);
+        let mut network = 14;
+        while (ether_type == 0x8100 || ether_type == 0x88a8) && data.len() >= network + 4 {
+            ether_type = word(network + 2);
+            network += 4;
+        }
+
+        let ip = match ether_type {
+            0x0800 if data.len() >= network + 20 && data[network] >> 4 == 4 => {
+                let transport = network + (data[network] & 0x0f) as usize * 4;
+                let flags = word(network + 6);
+                Some(IpLayout {
+                    version: 4,
+                    start: network,
+                    transport: transport.max(network + 20),
+                    end: network + word(network + 2) as usize,
+                    protocol: data[network + 9],
+                    fragment: flags & 0x3fff != 0, //More fragments or an offset
+                })
+            }
+            0x86dd if data.len() >= network + 40 => {
+                let mut protocol = data[network + 6];
+                let mut transport = network + 40;
+                let mut fragment = false;
+                //Hop-by-hop, routing, fragment and destination options headers come before the transport
+                while matches!(protocol, 0 | 43 | 44 | 60) && data.len() >= transport + 8 {
+                    fragment |= protocol == 44;
+                    let length = if protocol == 44 { 8 } else { (data[transport + 1] as usize + 1) * 8 };
+                    protocol = data[transport];
+                    transport += length;
+                }
+                Some(IpLayout {
+                    version: 6,
+                    start: network,
+                    transport,
+                    end: network + 40 + word(network + 4) as usize,
+                    protocol,
+                    fragment,
+                })
+            }
+            _ => None,
+        };
+        FrameLayout { ether_type, network, ip }
+    }
+}
+
+/// The one's complement sum of RFC 1071 over `data`, added to `initial`, not yet inverted.
+fn checksum_sum(data: &[u8], initial: u32) -> u32 {
+    let mut sum = initial;
+    for pair in data.chunks(2) {
+        sum += u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The internet checksum of RFC 1071.
+pub(crate) fn internet_checksum(data: &[u8]) -> u16 {
+    fold(checksum_sum(data, 0))
+}
+
+/// Fills in the IPv4 header checksum and the TCP, UDP, ICMP or ICMPv6 checksum of a frame
+/// after its bytes were changed. Transport checksums are left alone when the packet was cut
+/// short or is a fragment, as the bytes they cover are not all there.
+pub(crate) fn recompute_checksums(data: &mut [u8]) {
+    let Some(ip) = FrameLayout::parse(data).ip else { return };
+    if ip.version == 4 && ip.transport <= data.len() {
+        data[ip.start + 10..ip.start + 12].fill(0);
+        let checksum = internet_checksum(&data[ip.start..ip.transport]);
+        data[ip.start + 10..ip.start + 12].copy_from_slice(&checksum.to_be_bytes());
+    }
+    if ip.fragment || ip.end > data.len() || ip.transport > ip.end {
+        return;
+    }
+    let offset = match (ip.version, ip.protocol) {
+        (_, 6) => 16,
+        (_, 17) => 6,
+        (4, 1) | (6, 58) => 2,
+        _ => return,
+    };
+    let length = ip.end - ip.transport;
+    if length < offset + 2 {
+        return;
+    }
+    let field = ip.transport + offset;
+    //A zero UDP checksum over IPv4 means none was computed, and stays that way
+    if ip.version == 4 && ip.protocol == 17 && data[field..field + 2] == [0, 0] {
+        return;
+    }
+    data[field..field + 2].fill(0);
+    let mut sum = 0;
+    if ip.protocol != 1 {
+        //The pseudo header with the addresses, the protocol and the length
+        let addresses = if ip.version == 4 { ip.start + 12..ip.start + 20 } else { ip.start + 8..ip.start + 40 };
+        sum = checksum_sum(&data[addresses], 0);
+        sum += ip.protocol as u32 + (length as u32 >> 16) + (length as u32 & 0xffff);
+    }
+    let mut checksum = fold(checksum_sum(&data[ip.transport..ip.end], sum));
+    if ip.protocol == 17 && checksum == 0 {
+        checksum = 0xffff;
+    }
+    data[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+}
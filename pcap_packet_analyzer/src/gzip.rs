@@ -0,0 +1,474 @@
+use std::io::{self, BufRead, Read};
+use crate::compressed::{corrupt, ends_early, History};
+
+//Deflate refers back at most 32 KiB
+const WINDOW: usize = 32 * 1024;
+
+//Codes up to this long are decoded with one table lookup, longer ones a bit at a time
+const FAST_BITS: u32 = 10;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+    1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12,
+    12, 13, 13];
+
+//The order the lengths of the code length code are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// A canonical Huffman code as deflate sends it, by the length of the code of every symbol.
+struct Huffman {
+    counts: [u16; 16], //Codes of every length
+    symbols: Vec<u16>, //Ordered by code
+    fast: Vec<u16>, //By the next FAST_BITS bits: the symbol and the code length, 0 when longer
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0_u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        //Where the symbols of each length start among the symbols, and the first code of that length
+        let mut offsets = [0_u16; 16];
+        let mut first_code = [0_u32; 16];
+        let mut left = 1_i32;
+        for length in 1..16 {
+            left = (left << 1) - counts[length] as i32;
+            if left < 0 {
+                return Err(corrupt("a Huffman code has too many codes"));
+            }
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+            first_code[length] = (first_code[length - 1] + counts[length - 1] as u32) << 1;
+        }
+        let mut symbols = vec![0; lengths.iter().filter(|length| **length > 0).count()];
+        let mut fast = vec![0; 1 << FAST_BITS];
+        let mut next = offsets;
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            //Codes of each length are consecutive, in the order of the symbols
+            let length = length as usize;
+            symbols[next[length] as usize] = symbol as u16;
+            let code = first_code[length] + (next[length] - offsets[length]) as u32;
+            next[length] += 1;
+            if length as u32 <= FAST_BITS {
+                //The code is sent from its highest bit, the stream is read from the lowest
+                let reversed = code.reverse_bits() >> (32 - length);
+                for high in 0..1 << (FAST_BITS - length as u32) {
+                    fast[(reversed | high << length) as usize] = (symbol as u16) << 4 | length as u16;
+                }
+            }
+        }
+        Ok(Huffman { counts, symbols, fast })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0_u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+    }
+}
+
+/// Reads deflate's bit stream, lowest bit first, without reading past what it needs.
+struct BitInput<R: BufRead> {
+    reader: R,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: BufRead> BitInput<R> {
+    /// Tops the bits up to at least `wanted`, or as many as are left.
+    fn fill(&mut self, wanted: u32) -> io::Result<()> {
+        while self.count < wanted {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            //Whole bytes are taken from the buffer until the bits are full
+            let take = (((64 - self.count) / 8) as usize).min(buffer.len());
+            for &byte in &buffer[..take] {
+                self.bits |= (byte as u64) << self.count;
+                self.count += 8;
+            }
+            self.reader.consume(take);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        if count == 0 {
+            return Ok(0);
+        }
+        self.fill(count)?;
+        if self.count < count {
+            return Err(ends_early());
+        }
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.bits >>= skip;
+        self.count -= skip;
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> io::Result<u16> {
+        self.fill(FAST_BITS)?;
+        let entry = huffman.fast[(self.bits & ((1 << FAST_BITS) - 1)) as usize];
+        let length = (entry & 0x0f) as u32;
+        if entry != 0 && length <= self.count {
+            self.bits >>= length;
+            self.count -= length;
+            return Ok(entry >> 4);
+        }
+        //A longer code, one bit at a time
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for length in 1..16 {
+            code |= self.read(1)? as i32;
+            let count = huffman.counts[length] as i32;
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("an invalid Huffman code"))
+    }
+
+    /// Whether the input ended, at a byte boundary.
+    fn at_end(&mut self) -> io::Result<bool> {
+        self.fill(8)?;
+        Ok(self.count == 0)
+    }
+}
+
+enum State {
+    Header,
+    Block,
+    Trailer,
+    Done,
+}
+
+/// Decompresses a gzip stream as it is read, one deflate block at a time. Members written one
+/// after the other are read as one stream, as gzip does.
+pub(crate) struct GzipReader<R: BufRead> {
+    input: BitInput<R>,
+    state: State,
+    history: History,
+    checked: usize, //Where the bytes not yet in the checksum start in the history
+    crc: u32,
+    size: u32,
+}
+
+impl<R: BufRead> GzipReader<R> {
+    pub(crate) fn new(reader: R) -> GzipReader<R> {
+        GzipReader {
+            input: BitInput { reader, bits: 0, count: 0 },
+            state: State::Header,
+            history: History::new(WINDOW),
+            checked: 0,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let input = &mut self.input;
+        if input.read(8)? != 0x1f || input.read(8)? != 0x8b || input.read(8)? != 8 {
+            return Err(corrupt("not a gzip stream"));
+        }
+        let flags = input.read(8)?;
+        input.read(32)?; //Modification time
+        input.read(16)?; //Extra flags and operating system
+        if flags & 0x04 != 0 {
+            let length = input.read(16)?;
+            for _ in 0..length {
+                input.read(8)?;
+            }
+        }
+        //The file name and the comment end with a zero
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while input.read(8)? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            input.read(16)?;
+        }
+        self.crc = 0;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Decodes one block, returning whether it was the last of the member.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let last = self.input.read(1)? == 1;
+        match self.input.read(2)? {
+            0 => {
+                self.input.align();
+                let length = self.input.read(16)?;
+                if self.input.read(16)? != !length & 0xffff {
+                    return Err(corrupt("a stored block has the wrong length"));
+                }
+                for _ in 0..length {
+                    let byte = self.input.read(8)? as u8;
+                    self.history.buffer.push(byte);
+                }
+            }
+            1 => {
+                let (literals, distances) = Huffman::fixed();
+                self.inflate(&literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = self.read_codes()?;
+                self.inflate(&literals, &distances)?;
+            }
+            _ => return Err(corrupt("a block of an unknown type")),
+        }
+        Ok(last)
+    }
+
+    fn read_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let input = &mut self.input;
+        let literal_count = input.read(5)? as usize + 257;
+        let distance_count = input.read(5)? as usize + 1;
+        let length_count = input.read(4)? as usize + 4;
+        let mut code_lengths = [0_u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..length_count] {
+            code_lengths[symbol] = input.read(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0_u8; literal_count + distance_count];
+        let mut index = 0;
+        while index < lengths.len() {
+            let (value, repeat) = match input.decode(&code_lengths)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 if index > 0 => (lengths[index - 1], 3 + input.read(2)? as usize),
+                17 => (0, 3 + input.read(3)? as usize),
+                18 => (0, 11 + input.read(7)? as usize),
+                _ => return Err(corrupt("a code length repeats nothing")),
+            };
+            if index + repeat > lengths.len() {
+                return Err(corrupt("too many code lengths"));
+            }
+            lengths[index..index + repeat].fill(value);
+            index += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(corrupt("a block without an end"));
+        }
+        Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+    }
+
+    fn inflate(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+        loop {
+            let symbol = self.input.decode(literals)? as usize;
+            match symbol {
+                0..=255 => self.history.buffer.push(symbol as u8),
+                256 => return Ok(()),
+                257..=285 => {
+                    let code = symbol - 257;
+                    let length = LENGTH_BASE[code] as usize + self.input.read(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = self.input.decode(distances)? as usize;
+                    if code >= 30 {
+                        return Err(corrupt("an invalid distance code"));
+                    }
+                    let distance = DISTANCE_BASE[code] as usize + self.input.read(DISTANCE_EXTRA[code] as u32)? as usize;
+                    self.history.copy_match(distance, length)?;
+                }
+                _ => return Err(corrupt("an invalid length code")),
+            }
+        }
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        self.input.align();
+        let crc = self.input.read(32)?;
+        let size = self.input.read(32)?;
+        if crc != self.crc || size != self.size {
+            return Err(corrupt("the gzip checksum does not match, the file is damaged"));
+        }
+        Ok(())
+    }
+
+    /// Adds what was decoded since the last time to the checksum and the size.
+    fn update_checksum(&mut self) {
+        let new = &self.history.buffer[self.checked..];
+        self.crc = crc32(self.crc, new);
+        self.size = self.size.wrapping_add(new.len() as u32);
+        self.checked = self.history.buffer.len();
+    }
+}
+
+impl<R: BufRead> Read for GzipReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.history.is_empty() {
+            let before = self.history.buffer.len();
+            self.history.trim();
+            self.checked -= before - self.history.buffer.len();
+            match self.state {
+                State::Header => {
+                    self.read_header()?;
+                    self.state = State::Block;
+                }
+                State::Block => {
+                    let last = self.read_block()?;
+                    self.update_checksum();
+                    if last {
+                        self.state = State::Trailer;
+                    }
+                }
+                State::Trailer => {
+                    self.read_trailer()?;
+                    self.state = if self.input.at_end()? { State::Done } else { State::Header };
+                }
+                State::Done => return Ok(0),
+            }
+        }
+        Ok(self.history.read(buffer))
+    }
+}
+
+/// The CRC-32 of IEEE 802.3 that gzip uses, continued from `crc` over `data`.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0_u32; 256];
+        for (byte, entry) in table.iter_mut().enumerate() {
+            let mut value = byte as u32;
+            for _ in 0..8 {
+                value = if value & 1 != 0 { 0xedb88320 ^ (value >> 1) } else { value >> 1 };
+            }
+            *entry = value;
+        }
+        table
+    });
+    let mut crc = !crc;
+    for &byte in data {
+        crc = table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::tests::{noise, sample};
+
+    //Made with gzip -n, the first block of each being of the kind in its name
+    const STORED: &[u8] = include_bytes!("../testdata/stored.gz"); //noise(300)
+    const FIXED: &[u8] = include_bytes!("../testdata/fixed.gz");
+    const DYNAMIC: &[u8] = include_bytes!("../testdata/dynamic.gz"); //sample(4000), -9
+    const FIXED_TEXT: &[u8] = b"hello hello hello hello, packet analyzer\n";
+
+    fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        GzipReader::new(data).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn reads_stored_blocks() {
+        assert_eq!(STORED[10] >> 1 & 3, 0);
+        assert_eq!(inflate(STORED).unwrap(), noise(300));
+    }
+
+    #[test]
+    fn reads_fixed_blocks() {
+        assert_eq!(FIXED[10] >> 1 & 3, 1);
+        assert_eq!(inflate(FIXED).unwrap(), FIXED_TEXT);
+    }
+
+    #[test]
+    fn reads_dynamic_blocks() {
+        assert_eq!(DYNAMIC[10] >> 1 & 3, 2);
+        assert_eq!(inflate(DYNAMIC).unwrap(), sample(4000));
+    }
+
+    #[test]
+    fn reads_in_small_pieces() {
+        let mut reader = GzipReader::new(DYNAMIC);
+        let mut output = Vec::new();
+        let mut buffer = [0; 7];
+        loop {
+            let length = reader.read(&mut buffer).unwrap();
+            if length == 0 {
+                break;
+            }
+            output.extend_from_slice(&buffer[..length]);
+        }
+        assert_eq!(output, sample(4000));
+    }
+
+    #[test]
+    fn reads_members_one_after_the_other() {
+        let stream = [STORED, FIXED, DYNAMIC].concat();
+        assert_eq!(inflate(&stream).unwrap(), [noise(300), FIXED_TEXT.to_vec(), sample(4000)].concat());
+    }
+
+    #[test]
+    fn reads_the_optional_header_fields() {
+        //The name, the comment and the extra field are skipped
+        let mut stream = FIXED[..10].to_vec();
+        stream[3] = 0x04 | 0x08 | 0x10;
+        stream.extend_from_slice(&[3, 0, b'x', b'y', b'z']);
+        stream.extend_from_slice(b"capture.pcap\0a comment\0");
+        stream.extend_from_slice(&FIXED[10..]);
+        assert_eq!(inflate(&stream).unwrap(), FIXED_TEXT);
+    }
+
+    #[test]
+    fn rejects_a_wrong_checksum() {
+        for position in [DYNAMIC.len() - 8, DYNAMIC.len() - 1, DYNAMIC.len() / 2] {
+            let mut stream = DYNAMIC.to_vec();
+            stream[position] ^= 0x10;
+            let error = inflate(&stream).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "byte {} changed", position);
+        }
+        let mut stream = DYNAMIC.to_vec();
+        stream[DYNAMIC.len() - 8] ^= 1;
+        assert_eq!(inflate(&stream).unwrap_err().to_string(), "the gzip checksum does not match, the file is damaged");
+    }
+
+    #[test]
+    fn reports_a_stream_cut_short_as_damaged() {
+        for stream in [STORED, FIXED, DYNAMIC] {
+            for length in [1, 5, 12, stream.len() / 2, stream.len() - 8, stream.len() - 1] {
+                let error = inflate(&stream[..length]).unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::InvalidData, "cut at {}", length);
+            }
+        }
+        assert_eq!(inflate(&DYNAMIC[..100]).unwrap_err().to_string(), "the compressed stream ends early");
+    }
+
+    #[test]
+    fn rejects_what_is_not_deflate() {
+        let mut stream = FIXED.to_vec();
+        stream[2] = 7;
+        assert_eq!(inflate(&stream).unwrap_err().to_string(), "not a gzip stream");
+        //A final block of the reserved type 3
+        let stream = [&FIXED[..10], &[0x07]].concat();
+        assert_eq!(inflate(&stream).unwrap_err().to_string(), "a block of an unknown type");
+    }
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(0, b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xcbf43926);
+    }
+}
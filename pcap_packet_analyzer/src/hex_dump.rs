@@ -0,0 +1,124 @@
+use std::fmt::Write;
+use crate::{PcapBlock, ProtocolDatagram};
+
+/// What `-x`, `-X`, `--payload` and `--annotate` asked to be dumped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct HexDumpOptions {
+    pub(crate) ascii: bool,
+    pub(crate) payload_only: bool,
+    pub(crate) annotate: bool,
+}
+
+impl HexDumpOptions {
+    pub(crate) fn new() -> HexDumpOptions {
+        HexDumpOptions {
+            ascii: false,
+            payload_only: false,
+            annotate: false,
+        }
+    }
+}
+
+/// The bytes of one decoded layer, as offsets into the captured frame.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LayerSpan {
+    pub(crate) name: &'static str,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+/// Splits the captured bytes into the layers the decoder used. Bytes after the IP packet,
+/// such as Ethernet padding, are reported as the trailer.
+pub(crate) fn layer_spans(block: &PcapBlock) -> Vec<LayerSpan> {
+    let frame = &block.ether_frame;
+    let length = block.data.len();
+    let mut spans = Vec::new();
+    if length < 14 {
+        spans.push(LayerSpan { name: "DATA", start: 0, end: length });
+        return spans;
+    }
+    spans.push(LayerSpan { name: "ETHER", start: 0, end: 14 });
+    if !frame.has_ipv4() {
+        if length > 14 {
+            spans.push(LayerSpan { name: "PAYLOAD", start: 14, end: length });
+        }
+        return spans;
+    }
+
+    let packet = &frame.packet;
+    let ip_start = 14;
+    let transport_start = (ip_start + packet.ihl as usize * 4).min(length);
+    let ip_end = (ip_start + u16::from_be_bytes(packet.total_length) as usize).clamp(transport_start, length);
+    spans.push(LayerSpan { name: "IP", start: ip_start, end: transport_start });
+
+    let (name, header_length) = match &packet.datagram {
+        ProtocolDatagram::TCP(tcp) => ("TCP", tcp.data_offset as usize),
+        ProtocolDatagram::UDP(_) => ("UDP", 8),
+        ProtocolDatagram::ICMP(_) => ("ICMP", 8),
+        ProtocolDatagram::Default(_) => ("", 0),
+    };
+    let payload_start = (transport_start + header_length).min(ip_end);
+    if header_length > 0 {
+        spans.push(LayerSpan { name, start: transport_start, end: payload_start });
+    }
+    if ip_end > payload_start {
+        spans.push(LayerSpan { name: "PAYLOAD", start: payload_start, end: ip_end });
+    }
+    if length > ip_end {
+        spans.push(LayerSpan { name: "TRAILER", start: ip_end, end: length });
+    }
+    spans
+}
+
+/// The bytes as tcpdump `-x` prints them, 16 bytes per line in groups of two with the offset first.
+/// With `ascii` the printable characters follow each line, as with `-X`.
+pub(crate) fn hex_dump(data: &[u8], ascii: bool) -> String {
+    dump_lines(data, 0, ascii)
+}
+
+/// Dumps a block according to the options: the whole frame, only its payload, or every layer
+/// under a heading giving the offsets where it starts and ends.
+pub(crate) fn dump_block(block: &PcapBlock, options: &HexDumpOptions) -> String {
+    let spans = layer_spans(block);
+    if options.payload_only {
+        return match spans.iter().find(|span| span.name == "PAYLOAD") {
+            Some(span) => dump_lines(&block.data[span.start..span.end], span.start, options.ascii),
+            None => String::new(),
+        };
+    }
+    if !options.annotate {
+        return hex_dump(&block.data, options.ascii);
+    }
+
+    let mut out = String::new();
+    for span in spans {
+        writeln!(out, "{:<8}0x{:04x}-0x{:04x} ({} bytes)", span.name, span.start,
+                 span.end.saturating_sub(1), span.end - span.start).unwrap();
+        out.push_str(&dump_lines(&block.data[span.start..span.end], span.start, options.ascii));
+    }
+    out
+}
+
+fn dump_lines(data: &[u8], first_offset: usize, ascii: bool) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        write!(out, "\t0x{:04x}: ", first_offset + line * 16).unwrap();
+        let mut hex = String::new();
+        for pair in chunk.chunks(2) {
+            hex.push(' ');
+            for byte in pair {
+                write!(hex, "{:02x}", byte).unwrap();
+            }
+        }
+        if ascii {
+            let text: String = chunk.iter()
+                .map(|byte| if (0x20..0x7f).contains(byte) { *byte as char } else { '.' })
+                .collect();
+            write!(out, "{:<40}  {}", hex, text).unwrap();
+        } else {
+            out.push_str(&hex);
+        }
+        out.push('\n');
+    }
+    out
+}
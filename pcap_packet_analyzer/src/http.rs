@@ -0,0 +1,50 @@
+/// The start line and headers of an HTTP/1.x message, taken from the first segment that carries
+/// it. Messages whose head is split over several segments are not recognised.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HttpHead {
+    pub(crate) start_line: (String, String, String), //Method, URI and version, or version, status code and reason
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) head_length: usize, //Bytes up to and including the empty line, where the body starts
+}
+
+const METHODS: &[&str] = &["GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+
+impl HttpHead {
+    /// A request head, if the payload starts with one.
+    pub(crate) fn request(payload: &[u8]) -> Option<HttpHead> {
+        let head = HttpHead::parse(payload)?;
+        let (method, _, version) = &head.start_line;
+        (METHODS.contains(&method.as_str()) && version.starts_with("HTTP/")).then_some(head)
+    }
+
+    /// A response head, if the payload starts with one.
+    pub(crate) fn response(payload: &[u8]) -> Option<HttpHead> {
+        let head = HttpHead::parse(payload)?;
+        let (version, status, _) = &head.start_line;
+        (version.starts_with("HTTP/") && status.len() == 3 && status.bytes().all(|byte| byte.is_ascii_digit())).then_some(head)
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The length of the body announced by `Content-Length`.
+    pub(crate) fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+
+    fn parse(payload: &[u8]) -> Option<HttpHead> {
+        let head_length = payload.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+        let text = std::str::from_utf8(&payload[..head_length - 4]).ok()?;
+        let mut lines = text.split("\r\n");
+        let mut start = lines.next()?.splitn(3, ' ');
+        let start_line = (start.next()?.to_string(), start.next()?.to_string(), start.next().unwrap_or("").to_string());
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Some(HttpHead { start_line, headers, head_length })
+    }
+}
@@ -1,9 +1,11 @@
 use std::fmt;
+use std::net::Ipv4Addr;
 use crate::{IPProtocol, IPVersion, ProtocolDatagram};
+use crate::json::{hex_bytes, hex_word, JsonValue};
 
 
 
-pub(crate) struct IPacket {
+pub struct IPacket {
     pub(crate) version: IPVersion,
     pub(crate) ihl: u8,
     //pub(crate) header length
@@ -52,6 +54,92 @@ impl IPacket {
             last_fragment_flag: 0,
         }
     }
+
+    pub fn source(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.source_add)
+    }
+
+    pub fn destination(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.destination_add)
+    }
+
+    pub fn protocol(&self) -> IPProtocol {
+        self.protocol
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    /// The length of the header in bytes, options included.
+    pub fn header_length(&self) -> u8 {
+        self.ihl * 4
+    }
+
+    pub fn total_length(&self) -> u16 {
+        u16::from_be_bytes(self.total_length)
+    }
+
+    pub fn identification(&self) -> u16 {
+        u16::from_be_bytes(self.identification)
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.do_not_fragment_flag == 1
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.last_fragment_flag == 1
+    }
+
+    pub fn fragment_offset(&self) -> u16 {
+        self.fragment_offset
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(self.header_checksum)
+    }
+
+    /// The TCP, UDP or ICMP packet carried, `ProtocolDatagram::Default` for any other protocol.
+    pub fn datagram(&self) -> &ProtocolDatagram {
+        &self.datagram
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let mut fields = vec![
+            ("version", JsonValue::from(if self.version == IPVersion::V4 { 4_u8 } else { 6 })),
+            ("header_length", JsonValue::from(self.ihl * 4)),
+            ("type_of_service", JsonValue::object(vec![
+                ("value", JsonValue::String(format!("0x{:02x}", self.tos))),
+                ("precedence", JsonValue::from(self.precedence)),
+                ("low_delay", JsonValue::from(self.delay == 1)),
+                ("high_throughput", JsonValue::from(self.throughput == 1)),
+                ("high_reliability", JsonValue::from(self.reliability == 1)),
+            ])),
+            ("total_length", JsonValue::from(u16::from_be_bytes(self.total_length))),
+            ("identification", JsonValue::from(u16::from_be_bytes(self.identification))),
+            ("flags", JsonValue::object(vec![
+                ("reserved", JsonValue::from(self.reserved_flag == 1)),
+                ("do_not_fragment", JsonValue::from(self.do_not_fragment_flag == 1)),
+                ("more_fragments", JsonValue::from(self.last_fragment_flag == 1)),
+            ])),
+            ("fragment_offset", JsonValue::from(self.fragment_offset)),
+            ("ttl", JsonValue::from(self.ttl)),
+            ("protocol", JsonValue::from(self.protocol.number())),
+            ("header_checksum", hex_word(self.header_checksum)),
+            ("source", JsonValue::String(format_ipv4(&self.source_add))),
+            ("destination", JsonValue::String(format_ipv4(&self.destination_add))),
+            ("options", self.options.as_ref().map_or(JsonValue::Null, |options| hex_bytes(options))),
+        ];
+        if let Some(layer) = self.datagram.layer_name() {
+            fields.push((layer, self.datagram.to_json()));
+        }
+        JsonValue::object(fields)
+    }
+}
+
+pub(crate) fn format_ipv4(address: &[u8; 4]) -> String {
+    format!("{}.{}.{}.{}", address[0], address[1], address[2], address[3])
 }
 
 impl fmt::Display for IPacket {
@@ -75,12 +163,14 @@ impl fmt::Display for IPacket {
         write!(f, "IP: Time to live    = {} seconds/hops\n", self.ttl)?;
         write!(f, "IP: Protocol        = {}\n", self.protocol)?;
         write!(f, "IP: Header checksum = 0x{:x}{:x}\n", self.header_checksum[0], self.header_checksum[1])?;
-        write!(f, "IP: Source address  = {}\n", format!("{}.{}.{}.{}", self.source_add[0], self.source_add[1], self.source_add[2], self.source_add[3]))?;
-        write!(f, "IP: Destination address= {}\n", format!("{}.{}.{}.{}", self.destination_add[0], self.destination_add[1], self.destination_add[2], self.destination_add[3]))?;
-        match self.options.clone() {
+        write!(f, "IP: Source address  = {}.{}.{}.{}\n", self.source_add[0], self.source_add[1], self.source_add[2], self.source_add[3])?;
+        write!(f, "IP: Destination address= {}.{}.{}.{}\n", self.destination_add[0], self.destination_add[1], self.destination_add[2], self.destination_add[3])?;
+        match &self.options {
             None => write!(f, "No options\n")?,
+            Some(op) if f.alternate() => write!(f, "Options: {} ({})\n", op.len(),
+                                                op.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())?,
             Some(op) => write!(f, "Options: {}\n", op.len())?
         };
-        write!(f, "{}", self.datagram)
+        self.datagram.fmt(f)
     }
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use std::fmt;
+use crate::json::{hex_bytes, hex_word, JsonValue};
+use crate::tcp_analysis::SegmentAnalysis;
 
-pub(crate) enum ProtocolDatagram {
+pub enum ProtocolDatagram {
     TCP(TCPPacket),
     UDP(UDPPacket),
     ICMP(ICMPPacket),
@@ -11,21 +13,40 @@ impl ProtocolDatagram {
     pub(crate) fn new() -> ProtocolDatagram {
         ProtocolDatagram::Default("This is the default value".parse().unwrap())
     }
+
+    /// The key the datagram is written under in the JSON output.
+    pub(crate) fn layer_name(&self) -> Option<&'static str> {
+        match self {
+            ProtocolDatagram::TCP(_) => Some("tcp"),
+            ProtocolDatagram::UDP(_) => Some("udp"),
+            ProtocolDatagram::ICMP(_) => Some("icmp"),
+            ProtocolDatagram::Default(_) => None,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        match self {
+            ProtocolDatagram::TCP(packet) => packet.to_json(),
+            ProtocolDatagram::UDP(packet) => packet.to_json(),
+            ProtocolDatagram::ICMP(packet) => packet.to_json(),
+            ProtocolDatagram::Default(_) => JsonValue::Null,
+        }
+    }
 }
 
 impl fmt::Display for ProtocolDatagram {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ProtocolDatagram::TCP(packet) => write!(f, "{}", packet),
-            ProtocolDatagram::UDP(packet) => write!(f, "{}", packet),
-            ProtocolDatagram::ICMP(packet) => write!(f, "{}", packet),
+            ProtocolDatagram::TCP(packet) => packet.fmt(f),
+            ProtocolDatagram::UDP(packet) => packet.fmt(f),
+            ProtocolDatagram::ICMP(packet) => packet.fmt(f),
             ProtocolDatagram::Default(_) => write!(f, "This is a placeholder")
         }
     }
 }
 
 
-pub(crate) struct ICMPPacket {
+pub struct ICMPPacket {
     pub(crate) packet_type: u8,
     pub(crate) code: u8,
     pub(crate) checksum: [u8; 2],
@@ -51,6 +72,54 @@ impl ICMPPacket {
             data: vec![],
         }
     }
+
+    pub fn packet_type(&self) -> u8 {
+        self.packet_type
+    }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn identifier(&self) -> u16 {
+        u16::from_be_bytes(self.identifier_be)
+    }
+
+    pub fn sequence(&self) -> u16 {
+        u16::from_be_bytes(self.sequence_be)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("type", JsonValue::from(self.packet_type)),
+            ("code", JsonValue::from(self.code)),
+            ("checksum", hex_word(self.checksum)),
+            ("identifier", JsonValue::from(u16::from_be_bytes(self.identifier_be))),
+            ("sequence", JsonValue::from(u16::from_be_bytes(self.sequence_be))),
+            ("data_length", JsonValue::from(self.data.len() as u64)),
+        ])
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self.packet_type {
+            0 => "echo reply",
+            3 => "destination unreachable",
+            4 => "source quench",
+            5 => "redirect",
+            8 => "echo request",
+            9 => "router advertisement",
+            10 => "router solicitation",
+            11 => "time exceeded",
+            12 => "parameter problem",
+            13 => "timestamp request",
+            14 => "timestamp reply",
+            _ => "unknown type",
+        }
+    }
 }
 
 impl fmt::Display for ICMPPacket {
@@ -60,12 +129,18 @@ impl fmt::Display for ICMPPacket {
         write!(f, "ICMP: type= {}\n", self.packet_type)?;
         write!(f, "ICMP: Code= {}\n", self.code)?;
         write!(f, "ICMP: checksum= 0x{:x}{:x}\n", self.checksum[0], self.checksum[1])?;
+        if f.alternate() {
+            write!(f, "ICMP: Message     = {}\n", self.type_name())?;
+            write!(f, "ICMP: Identifier  = {}\n", u16::from_be_bytes(self.identifier_be))?;
+            write!(f, "ICMP: Sequence    = {}\n", u16::from_be_bytes(self.sequence_be))?;
+            write!(f, "ICMP: Data length = {} bytes\n", self.data.len())?;
+        }
         write!(f, "ICMP:")
     }
 }
 
 
-pub(crate) struct UDPPacket {
+pub struct UDPPacket {
     pub(crate) source_port: [u8; 2],
     pub(crate) destination_port: [u8; 2],
     pub(crate) length: [u8; 2],
@@ -83,6 +158,33 @@ impl UDPPacket {
             data: vec![],
         }
     }
+
+    pub fn source_port(&self) -> u16 {
+        u16::from_be_bytes(self.source_port)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        u16::from_be_bytes(self.destination_port)
+    }
+
+    /// The length from the UDP header, header included.
+    pub fn length(&self) -> u16 {
+        u16::from_be_bytes(self.length)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("source_port", JsonValue::from(u16::from_be_bytes(self.source_port))),
+            ("destination_port", JsonValue::from(u16::from_be_bytes(self.destination_port))),
+            ("length", JsonValue::from(u16::from_be_bytes(self.length))),
+            ("checksum", hex_word(self.checksum)),
+            ("payload_length", JsonValue::from(self.data.len() as u64)),
+        ])
+    }
 }
 
 impl fmt::Display for UDPPacket {
@@ -93,12 +195,15 @@ impl fmt::Display for UDPPacket {
         write!(f, "UDP: Destination port = {}\n", u16::from_be_bytes(self.destination_port))?;
         write!(f, "UDP: Length           = {}\n", u16::from_be_bytes(self.length))?;
         write!(f, "UDP: Checksum         = 0x{:x}{:x}\n", self.checksum[0], self.checksum[1])?;
+        if f.alternate() {
+            write!(f, "UDP: Payload length   = {} bytes\n", self.data.len())?;
+        }
         write!(f, "UDP:")
     }
 }
 
 
-pub(crate) struct TCPPacket {
+pub struct TCPPacket {
     pub(crate) source_port: [u8; 2],
     pub(crate) destination_port: [u8; 2],
     pub(crate) sequence_number: [u8; 4],
@@ -109,7 +214,8 @@ pub(crate) struct TCPPacket {
     pub(crate) checksum: [u8; 2],
     pub(crate) urgent_pointer: [u8; 2],
     pub(crate) options: Option<Vec<u8>>, //Can range from 0 to 40 bytes
-
+    pub(crate) data: Vec<u8>,
+    pub(crate) analysis: Option<SegmentAnalysis>, //Filled in by the connection tracking when reading a file
 }
 
 impl TCPPacket {
@@ -124,8 +230,91 @@ impl TCPPacket {
             window: [0, 0],
             checksum: [0, 0],
             urgent_pointer: [0, 0],
-            options: None
+            options: None,
+            data: vec![],
+            analysis: None,
+        }
+    }
+
+    /// The port the segment was sent from.
+    pub fn source_port(&self) -> u16 {
+        u16::from_be_bytes(self.source_port)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        u16::from_be_bytes(self.destination_port)
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        u32::from_be_bytes(self.sequence_number)
+    }
+
+    pub fn acknowledgement_number(&self) -> u32 {
+        u32::from_be_bytes(self.acknowledgement_number)
+    }
+
+    /// The flag bits, FIN being the lowest.
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// The window as sent, before any window scaling.
+    pub fn window(&self) -> u16 {
+        u16::from_be_bytes(self.window)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let flag = |mask: u8| JsonValue::from(self.flags & mask != 0);
+        let mut json = JsonValue::object(vec![
+            ("source_port", JsonValue::from(u16::from_be_bytes(self.source_port))),
+            ("destination_port", JsonValue::from(u16::from_be_bytes(self.destination_port))),
+            ("sequence_number", JsonValue::from(u32::from_be_bytes(self.sequence_number))),
+            ("acknowledgement_number", JsonValue::from(u32::from_be_bytes(self.acknowledgement_number))),
+            ("header_length", JsonValue::from(self.data_offset)),
+            ("flags", JsonValue::object(vec![
+                ("value", JsonValue::String(format!("0x{:02x}", self.flags))),
+                ("cwr", flag(0x80)),
+                ("ece", flag(0x40)),
+                ("urg", flag(0x20)),
+                ("ack", flag(0x10)),
+                ("psh", flag(0x08)),
+                ("rst", flag(0x04)),
+                ("syn", flag(0x02)),
+                ("fin", flag(0x01)),
+            ])),
+            ("window", JsonValue::from(u16::from_be_bytes(self.window))),
+            ("checksum", hex_word(self.checksum)),
+            ("urgent_pointer", JsonValue::from(u16::from_be_bytes(self.urgent_pointer))),
+            ("options", self.options.as_ref().map_or(JsonValue::Null, |options| hex_bytes(options))),
+            ("payload_length", JsonValue::from(self.data.len() as u64)),
+        ]);
+        if let (JsonValue::Object(fields), Some(analysis)) = (&mut json, &self.analysis) {
+            fields.push(("stream".to_string(), JsonValue::from(analysis.stream as u64)));
+            fields.push(("state".to_string(), JsonValue::string(analysis.state.name())));
+            fields.push(("relative_sequence_number".to_string(), JsonValue::from(analysis.relative_seq)));
+            fields.push(("relative_acknowledgement_number".to_string(),
+                         analysis.relative_ack.map_or(JsonValue::Null, JsonValue::from)));
+            fields.push(("window_size".to_string(), JsonValue::from(analysis.window_size)));
         }
+        json
+    }
+
+    pub(crate) fn flag_names(&self) -> Vec<&'static str> {
+        [(0x80, "CWR"), (0x40, "ECE"), (0x20, "URG"), (0x10, "ACK"),
+            (0x08, "PSH"), (0x04, "RST"), (0x02, "SYN"), (0x01, "FIN")]
+            .iter()
+            .filter(|(mask, _)| self.flags & mask != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The flags the way tcpdump writes them, `S.` for SYN+ACK or `P.` for PSH+ACK.
+    pub(crate) fn flag_string(&self) -> String {
+        flag_string(self.flags)
     }
 }
 
@@ -135,16 +324,65 @@ impl fmt::Display for TCPPacket {
         write!(f, "TCP:\n")?;
         write!(f, "TCP: Source Port       = {}\n", u16::from_be_bytes(self.source_port))?;
         write!(f, "TCP: Destination Port  = {}\n", u16::from_be_bytes(self.destination_port))?;
-        write!(f, "TCP: Sequence number   = {}\n", u32::from_be_bytes(self.sequence_number))?;
-        write!(f, "TCP: Acknowledgement number     = {}\n", u32::from_be_bytes(self.acknowledgement_number))?;
+        match &self.analysis {
+            Some(analysis) => {
+                write!(f, "TCP: Stream index      = {}\n", analysis.stream)?;
+                write!(f, "TCP: Connection state  = {}\n", analysis.state.name())?;
+                write!(f, "TCP: Sequence number   = {} (relative, raw {})\n", analysis.relative_seq,
+                       u32::from_be_bytes(self.sequence_number))?;
+                match analysis.relative_ack {
+                    Some(ack) => write!(f, "TCP: Acknowledgement number     = {} (relative, raw {})\n", ack,
+                                        u32::from_be_bytes(self.acknowledgement_number))?,
+                    None => write!(f, "TCP: Acknowledgement number     = {}\n", u32::from_be_bytes(self.acknowledgement_number))?,
+                }
+            }
+            None => {
+                write!(f, "TCP: Sequence number   = {}\n", u32::from_be_bytes(self.sequence_number))?;
+                write!(f, "TCP: Acknowledgement number     = {}\n", u32::from_be_bytes(self.acknowledgement_number))?;
+            }
+        }
         write!(f, "TCP: Data offset(header length) = {} bytes\n", self.data_offset)?;
         write!(f, "TCP: Flags             = {}\n", self.flags)?;
-        write!(f, "TCP: Window            = {}\n", u16::from_be_bytes(self.window))?;
+        if f.alternate() {
+            write!(f, "TCP:     [{}]\n", self.flag_names().join(", "))?;
+        }
+        match self.analysis.as_ref().map(|analysis| (analysis.window_size, analysis.window_shift)) {
+            Some((size, Some(shift))) if shift > 0 => write!(f, "TCP: Window            = {} (raw {}, scaled by {})\n",
+                                                             size, u16::from_be_bytes(self.window), 1u32 << shift)?,
+            Some((_, None)) => write!(f, "TCP: Window            = {} (scale unknown)\n", u16::from_be_bytes(self.window))?,
+            _ => write!(f, "TCP: Window            = {}\n", u16::from_be_bytes(self.window))?,
+        }
         write!(f, "TCP: Checksum          = 0x{:x}{:x}\n", self.checksum[0], self.checksum[1])?;
         write!(f, "TCP: Urgent pointer    = {}\n", u16::from_be_bytes(self.urgent_pointer))?;
-        match self.options.clone() {
-            None => write!(f, "No options\n"),
-            Some(op) => write!(f, "Options: {}\n", op.len())
+        match &self.options {
+            None => write!(f, "No options\n")?,
+            Some(op) if f.alternate() => write!(f, "Options: {} ({})\n", op.len(),
+                                                op.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())?,
+            Some(op) => write!(f, "Options: {}\n", op.len())?
         }
+        if f.alternate() {
+            write!(f, "TCP: Payload length    = {} bytes\n", self.data.len())?;
+            if let Some(analysis) = self.analysis.as_ref().filter(|analysis| !analysis.anomalies.is_empty()) {
+                let names: Vec<&str> = analysis.anomalies.iter().map(|anomaly| anomaly.name()).collect();
+                write!(f, "TCP: Analysis          = {}\n", names.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// TCP flags the way tcpdump writes them, `S.` for SYN+ACK or `P.` for PSH+ACK.
+pub(crate) fn flag_string(flags: u8) -> String {
+    let mut letters: String = [(0x02, 'S'), (0x01, 'F'), (0x08, 'P'), (0x04, 'R'), (0x20, 'U'), (0x40, 'E'), (0x80, 'W')]
+        .iter()
+        .filter(|(mask, _)| flags & mask != 0)
+        .map(|(_, letter)| *letter)
+        .collect();
+    if flags & 0x10 != 0 {
+        letters.push('.');
     }
-}
\ No newline at end of file
+    if letters.is_empty() {
+        letters.push_str("none");
+    }
+    letters
+}
@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use crate::DisplayFilter;
+use crate::json::JsonValue;
+use crate::statistics::Statistics;
+use crate::view::Packet;
+
+//Characters of the sparkline from an empty interval up to the busiest one
+const SPARK_LEVELS: &[u8] = b" .:-=+*#%@";
+const SPARK_WIDTH: usize = 64;
+
+//Rows the CSV and JSON output may have, an interval far shorter than the capture would make
+//more than anything can plot
+const MAX_ROWS: u64 = 10_000_000;
+
+/// How the I/O graph is printed: sparklines for the terminal, or every interval as CSV or JSON.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum IoGraphFormat {
+    Sparkline,
+    Csv,
+    Json,
+}
+
+/// Parses an interval such as `1ms`, `100ms`, `1s` or `250us` into microseconds. A number
+/// without a unit is in seconds.
+pub(crate) fn parse_interval(text: &str) -> Result<u64, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let scale = match unit {
+        "us" => 1.0,
+        "ms" => 1_000.0,
+        "s" | "" => 1_000_000.0,
+        _ => return Err(format!("unknown interval unit '{}', expected us, ms or s", unit)),
+    };
+    let value: f64 = number.parse().map_err(|_| format!("'{}' is not an interval", text))?;
+    let micros = (value * scale).round();
+    if micros < 1.0 {
+        return Err(format!("interval '{}' is shorter than a microsecond", text));
+    }
+    Ok(micros as u64)
+}
+
+fn format_interval(micros: u64) -> String {
+    if micros.is_multiple_of(1_000_000) {
+        format!("{}s", micros / 1_000_000)
+    } else if micros.is_multiple_of(1_000) {
+        format!("{}ms", micros / 1_000)
+    } else {
+        format!("{}us", micros)
+    }
+}
+
+struct Series {
+    name: String,
+    filter: Option<DisplayFilter>,
+    //Packets and bytes per interval, only for the intervals that saw any
+    buckets: BTreeMap<u64, (u64, u64)>,
+}
+
+impl Series {
+    fn count(&self, bucket: u64) -> (u64, u64) {
+        self.buckets.get(&bucket).copied().unwrap_or((0, 0))
+    }
+}
+
+/// Packets and bytes per time interval, for all packets and for every `--io-filter`, to spot
+/// bursts and stalls. Intervals are counted from the first packet.
+pub(crate) struct IoGraph {
+    interval: u64,
+    format: IoGraphFormat,
+    start: Option<u64>,
+    series: Vec<Series>,
+}
+
+impl IoGraph {
+    pub(crate) fn new(interval: u64, format: IoGraphFormat, filters: &[(String, DisplayFilter)]) -> IoGraph {
+        let mut series = vec![Series { name: "all".to_string(), filter: None, buckets: BTreeMap::new() }];
+        for (name, filter) in filters {
+            series.push(Series { name: name.clone(), filter: Some(filter.clone()), buckets: BTreeMap::new() });
+        }
+        IoGraph {
+            interval,
+            format,
+            start: None,
+            series,
+        }
+    }
+
+    /// Every interval from the first packet to the last, those without packets included so the
+    /// time axis is even. Fails when there would be more than `MAX_ROWS` of them.
+    fn buckets(&self) -> io::Result<std::ops::Range<u64>> {
+        //Every packet is counted in the first series, so its last interval is the last of all
+        let count = self.series[0].buckets.keys().next_back().map_or(0, |last| last + 1);
+        if count > MAX_ROWS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "the I/O graph would have {} intervals of {}, more than {}, give a longer interval",
+                count, format_interval(self.interval), MAX_ROWS)));
+        }
+        Ok(0..count)
+    }
+
+    fn interval_start(&self, bucket: u64) -> f64 {
+        (bucket * self.interval) as f64 / 1_000_000.0
+    }
+
+    fn write_csv(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut header = vec!["interval_start".to_string()];
+        for series in &self.series {
+            let name = series.name.replace('"', "\"\"");
+            header.push(format!("\"{} packets\"", name));
+            header.push(format!("\"{} bytes\"", name));
+        }
+        let buckets = self.buckets()?;
+        writeln!(out, "{}", header.join(","))?;
+        for bucket in buckets {
+            write!(out, "{:.6}", self.interval_start(bucket))?;
+            for series in &self.series {
+                let (packets, bytes) = series.count(bucket);
+                write!(out, ",{},{}", packets, bytes)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut dyn Write) -> io::Result<()> {
+        let buckets = self.buckets()?;
+        let start = self.start.map_or(JsonValue::Null, |start| JsonValue::Float(start as f64 / 1_000_000.0));
+        write!(out, "{{\"interval_us\":{},\"start\":{},\"series\":[", self.interval, start.to_compact())?;
+        for (i, series) in self.series.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(out, "{}{{\"name\":{},\"packets\":", separator, JsonValue::string(&series.name).to_compact())?;
+            write_list(out, buckets.clone().map(|bucket| series.count(bucket).0))?;
+            write!(out, ",\"bytes\":")?;
+            write_list(out, buckets.clone().map(|bucket| series.count(bucket).1))?;
+            write!(out, "}}")?;
+        }
+        writeln!(out, "]}}")
+    }
+
+    fn write_sparklines(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "IO Graph, {} intervals", format_interval(self.interval))?;
+        writeln!(out, "{:<4} {:<32} {:>9} {:>11} {:>10} {:>12}", "", "Series", "Packets", "Bytes",
+                 "Max Pkts", "Max Bytes")?;
+        for (i, series) in self.series.iter().enumerate() {
+            let packets: u64 = series.buckets.values().map(|count| count.0).sum();
+            let bytes: u64 = series.buckets.values().map(|count| count.1).sum();
+            let max_packets = series.buckets.values().map(|count| count.0).max().unwrap_or(0);
+            let max_bytes = series.buckets.values().map(|count| count.1).max().unwrap_or(0);
+            writeln!(out, "{:<4} {:<32} {:>9} {:>11} {:>10} {:>12}", format!("[{}]", i), series.name,
+                     packets, bytes, max_packets, max_bytes)?;
+        }
+
+        //Long captures wrap, with the time of the first interval on each line. Lines without a
+        //single packet are left out, so a long stall costs one line however many intervals it spans
+        let mut lines: Vec<u64> = self.series[0].buckets.keys().map(|bucket| bucket / SPARK_WIDTH as u64).collect();
+        lines.dedup();
+        let last = self.series[0].buckets.keys().next_back().copied().unwrap_or(0);
+        for (title, pick) in [("Packets", 0), ("Bytes", 1)] {
+            writeln!(out, "\n{} per interval", title)?;
+            let maxima: Vec<u64> = self.series.iter()
+                .map(|series| series.buckets.values().map(|count| if pick == 0 { count.0 } else { count.1 }).max().unwrap_or(0))
+                .collect();
+            let mut previous = None;
+            for &line in &lines {
+                if let Some(previous) = previous.filter(|previous| line > previous + 1) {
+                    writeln!(out, "{:>14}      ({} intervals without packets)", "", (line - previous - 1) * SPARK_WIDTH as u64)?;
+                }
+                previous = Some(line);
+                let line_start = line * SPARK_WIDTH as u64;
+                for (i, series) in self.series.iter().enumerate() {
+                    let sparks: String = (line_start..line_start + SPARK_WIDTH as u64)
+                        .map(|bucket| {
+                            let count = series.count(bucket);
+                            spark(if pick == 0 { count.0 } else { count.1 }, maxima[i])
+                        })
+                        .collect();
+                    //The last line stops at the last interval with packets
+                    let width = (last + 1 - line_start).min(SPARK_WIDTH as u64) as usize;
+                    let time = if i == 0 { format!("{:.6}", self.interval_start(line_start)) } else { String::new() };
+                    writeln!(out, "{:>14} {:<4} |{}|", time, format!("[{}]", i), &sparks[..width])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_list(out: &mut dyn Write, values: impl Iterator<Item = u64>) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, value) in values.enumerate() {
+        write!(out, "{}{}", if i == 0 { "" } else { "," }, value)?;
+    }
+    write!(out, "]")
+}
+
+//Any interval with traffic shows at least the lowest mark, so only empty intervals are blank
+fn spark(value: u64, max: u64) -> char {
+    if value == 0 || max == 0 {
+        return SPARK_LEVELS[0] as char;
+    }
+    let top = (SPARK_LEVELS.len() - 1) as u64;
+    let level = (value * top).div_ceil(max).clamp(1, top);
+    SPARK_LEVELS[level as usize] as char
+}
+
+impl Statistics for IoGraph {
+    fn add(&mut self, packet: &Packet) {
+        let time = packet.microseconds();
+        let start = *self.start.get_or_insert(time);
+        //Packets stamped before the first one are counted in the first interval
+        let bucket = time.saturating_sub(start) / self.interval;
+        let bytes = packet.data().len() as u64;
+        for series in &mut self.series {
+            if series.filter.as_ref().is_none_or(|filter| filter.matches_packet(packet)) {
+                let count = series.buckets.entry(bucket).or_insert((0, 0));
+                count.0 += 1;
+                count.1 += bytes;
+            }
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut out = Vec::new();
+        self.write_report(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    //Short intervals over a long capture make a lot of output, written as it is made
+    fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self.format {
+            IoGraphFormat::Sparkline => {
+                self.write_sparklines(out)?;
+                writeln!(out)
+            }
+            //Machine readable output holds nothing but the data, without the blank line after reports
+            IoGraphFormat::Csv => self.write_csv(out),
+            IoGraphFormat::Json => self.write_json(out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PcapBlock;
+
+    fn graph(interval: u64, format: IoGraphFormat, times: &[(u32, u32)]) -> IoGraph {
+        let filters = [("udp".to_string(), DisplayFilter::parse("udp").unwrap())];
+        let mut graph = IoGraph::new(interval, format, &filters);
+        for &(seconds, microseconds) in times {
+            graph.add(&PcapBlock::from_frame(vec![0; 60], seconds, microseconds).view());
+        }
+        graph
+    }
+
+    fn report(graph: &IoGraph) -> io::Result<String> {
+        let mut out = Vec::new();
+        graph.write_report(&mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn writes_the_intervals_without_packets() {
+        let times = [(100, 0), (100, 500_000), (103, 250_000)];
+        assert_eq!(report(&graph(1_000_000, IoGraphFormat::Csv, &times)).unwrap(), "\
+            interval_start,\"all packets\",\"all bytes\",\"udp packets\",\"udp bytes\"\n\
+            0.000000,2,120,0,0\n1.000000,0,0,0,0\n2.000000,0,0,0,0\n3.000000,1,60,0,0\n");
+        assert_eq!(report(&graph(1_000_000, IoGraphFormat::Json, &times)).unwrap(),
+            "{\"interval_us\":1000000,\"start\":100,\"series\":[{\"name\":\"all\",\"packets\":[2,0,0,1],\"bytes\":[120,0,0,60]},\
+            {\"name\":\"udp\",\"packets\":[0,0,0,0],\"bytes\":[0,0,0,0]}]}\n");
+        assert_eq!(report(&graph(1_000_000, IoGraphFormat::Csv, &[])).unwrap(),
+            "interval_start,\"all packets\",\"all bytes\",\"udp packets\",\"udp bytes\"\n");
+    }
+
+    #[test]
+    fn refuses_more_rows_than_can_be_plotted() {
+        let times = [(100, 0), (120, 0)];
+        for format in [IoGraphFormat::Csv, IoGraphFormat::Json] {
+            let error = report(&graph(1, format, &times)).unwrap_err();
+            assert_eq!(error.to_string(), "the I/O graph would have 20000001 intervals of 1us, more than 10000000, give a longer interval");
+        }
+        //The sparklines leave the stretches without packets out, so they are printed still
+        assert!(report(&graph(1, IoGraphFormat::Sparkline, &times)).unwrap().contains("(19999936 intervals without packets)"));
+    }
+}
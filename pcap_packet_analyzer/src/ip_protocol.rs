@@ -1,13 +1,24 @@
 use std::fmt;
 
-#[derive(PartialOrd, PartialEq)]
-pub(crate) enum IPProtocol {
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
+pub enum IPProtocol {
     ICMP,
     TCP,
     UDP,
     Default,
 }
 
+impl IPProtocol {
+    pub fn number(&self) -> u8 {
+        match self {
+            IPProtocol::ICMP => 1,
+            IPProtocol::TCP => 6,
+            IPProtocol::UDP => 17,
+            IPProtocol::Default => 0,
+        }
+    }
+}
+
 impl fmt::Display for IPProtocol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
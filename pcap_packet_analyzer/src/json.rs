@@ -0,0 +1,165 @@
+use std::fmt;
+use std::fmt::Write;
+
+/// A JSON document built from the decoded structs. Objects keep their keys in insertion order so
+/// the output reads in the same order as the `Display` output.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonValue {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub(crate) fn object(fields: Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub(crate) fn string<T: ToString>(value: T) -> JsonValue {
+        JsonValue::String(value.to_string())
+    }
+
+    /// Everything on a single line, for the one object per line output.
+    pub(crate) fn to_compact(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, None, 0).unwrap();
+        out
+    }
+
+    pub(crate) fn to_pretty(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, Some(2), 0).unwrap();
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: Option<usize>, depth: usize) -> fmt::Result {
+        let newline = |out: &mut String, depth: usize| {
+            if let Some(width) = indent {
+                out.push('\n');
+                out.push_str(&" ".repeat(width * depth));
+            }
+        };
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(value) => write!(out, "{}", value)?,
+            JsonValue::Unsigned(value) => write!(out, "{}", value)?,
+            //JSON has no NaN or infinity, a rate over no time is written as unknown
+            JsonValue::Float(value) if !value.is_finite() => out.push_str("null"),
+            JsonValue::Float(value) => write!(out, "{}", value)?,
+            JsonValue::String(value) => write_string(out, value)?,
+            JsonValue::Array(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, depth + 1);
+                    value.write(out, indent, depth + 1)?;
+                }
+                if !values.is_empty() {
+                    newline(out, depth);
+                }
+                out.push(']');
+            }
+            JsonValue::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, depth + 1);
+                    write_string(out, key)?;
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    value.write(out, indent, depth + 1)?;
+                }
+                if !fields.is_empty() {
+                    newline(out, depth);
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_string(out: &mut String, value: &str) -> fmt::Result {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+impl From<u8> for JsonValue {
+    fn from(value: u8) -> JsonValue {
+        JsonValue::Unsigned(value as u64)
+    }
+}
+
+impl From<u16> for JsonValue {
+    fn from(value: u16) -> JsonValue {
+        JsonValue::Unsigned(value as u64)
+    }
+}
+
+impl From<u32> for JsonValue {
+    fn from(value: u32) -> JsonValue {
+        JsonValue::Unsigned(value as u64)
+    }
+}
+
+impl From<u64> for JsonValue {
+    fn from(value: u64) -> JsonValue {
+        JsonValue::Unsigned(value)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> JsonValue {
+        JsonValue::Bool(value)
+    }
+}
+
+/// Checksums and other header words are written in hex, as in the text output.
+pub(crate) fn hex_word(bytes: [u8; 2]) -> JsonValue {
+    JsonValue::String(format!("0x{:04x}", u16::from_be_bytes(bytes)))
+}
+
+pub(crate) fn hex_bytes(bytes: &[u8]) -> JsonValue {
+    JsonValue::String(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_compact_and_pretty() {
+        let value = JsonValue::object(vec![
+            ("name", JsonValue::string("a \"b\"\n\u{1}")),
+            ("values", JsonValue::Array(vec![JsonValue::from(7_u16), JsonValue::Float(0.5), JsonValue::Null])),
+            ("empty", JsonValue::Object(Vec::new())),
+        ]);
+        assert_eq!(value.to_compact(), r#"{"name":"a \"b\"\n\u0001","values":[7,0.5,null],"empty":{}}"#);
+        assert_eq!(value.to_pretty(), "{\n  \"name\": \"a \\\"b\\\"\\n\\u0001\",\n  \"values\": [\n    7,\n    0.5,\n    null\n  ],\n  \"empty\": {}\n}");
+    }
+
+    #[test]
+    fn writes_numbers_json_can_not_hold_as_null() {
+        let values = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0_f64.ln(), 2.5].map(JsonValue::Float);
+        assert_eq!(JsonValue::Array(values.to_vec()).to_compact(), "[null,null,null,null,2.5]");
+    }
+}
@@ -0,0 +1,344 @@
+//! Decodes Ethernet frames carrying IPv4 and their TCP, UDP or ICMP payload, reads and writes
+//! pcap files and evaluates display filters. It has no dependencies beyond the standard library.
+//! The `packet_analyzer` program is a command line front end to this library.
+//!
+//! ```no_run
+//! use packet_analyzer::{DisplayFilter, PcapReader, ProtocolDatagram};
+//!
+//! let filter = DisplayFilter::parse("tcp.port == 80").unwrap();
+//! for packet in PcapReader::open("capture.pcap").unwrap() {
+//!     if !filter.matches(&packet) {
+//!         continue;
+//!     }
+//!     if let Some(ip) = packet.frame().ipv4() {
+//!         if let ProtocolDatagram::TCP(tcp) = ip.datagram() {
+//!             println!("{}:{} > {}:{}", ip.source(), tcp.source_port(), ip.destination(), tcp.destination_port());
+//!         }
+//!     }
+//!     println!("{}", packet.to_json_string());
+//! }
+//! ```
+
+#![allow(unused_imports)]
+#![allow(unused_variables)]
+#![allow(dead_code)]
+#![allow(unused_mut)]
+#![allow(unused_assignments)]
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::write_with_newline)]
+
+
+mod ip_protocol;
+mod pcap_file_header;
+mod pcap_block;
+mod ethernet_frame;
+mod internet_packet;
+// mod pcap_file;
+mod internet_protocol_types;
+mod pcap_reader;
+mod packet_fields;
+mod display_filter;
+mod json;
+mod cli_options;
+mod field_export;
+mod summary;
+mod hex_dump;
+mod statistics;
+mod protocol_hierarchy;
+mod conversations;
+mod io_graph;
+mod tcp_analysis;
+mod alerts;
+mod flow_export;
+mod dns;
+mod http;
+mod zeek;
+mod tui;
+mod aes;
+mod pcap_writer;
+mod frame_layout;
+mod anonymize;
+mod rewrite;
+mod capture_edit;
+mod cli;
+mod mapped_file;
+mod compressed;
+mod gzip;
+mod zstd;
+mod parallel;
+pub mod view;
+mod pipeline;
+mod listener;
+mod encapsulation;
+mod pcap_over_ip;
+mod bpf;
+#[cfg(target_os = "linux")]
+mod af_packet;
+
+use std::fmt;
+use std::str::FromStr;
+use pcap_file_header::PcapFileHeader;
+use cli_options::CliOptions;
+
+pub use ip_protocol::IPProtocol;
+pub use ethernet_frame::{EthernetFrame, MacAddr};
+pub use pcap_block::PcapBlock;
+pub use internet_protocol_types::{ProtocolDatagram, ICMPPacket, UDPPacket, TCPPacket};
+pub use internet_packet::IPacket;
+pub use pcap_reader::PcapReader;
+pub use pcap_writer::PcapWriter;
+pub use display_filter::DisplayFilter;
+pub use mapped_file::MappedFile;
+pub use cli::run_cli;
+// use pcap_file::PcapFile;
+
+#[derive(Copy, Clone, PartialOrd, PartialEq)]
+enum IPVersion {
+    V4,
+    V6,
+}
+
+impl fmt::Display for IPVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IPVersion::V4 => write!(f, "4"),
+            IPVersion::V6 => write!(f, "6")
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum Filter {
+    Host([u8; 4]),
+    Port([u8; 2]),
+    Ip,
+    Tcp,
+    Udp,
+    Icmp,
+    Net([u8; 4]),
+    Count(i32),
+    Default(String),
+}
+
+impl Filter {
+    fn from_str(str: Vec<String>) -> Result<Filter, String> {
+        if str.len() ==1{
+            Ok(match str[0].as_str(){
+                "tcp" => {Filter::Tcp},
+                "udp" =>{Filter::Udp},
+                "icmp" => {Filter::Icmp},
+                "ip" => {Filter::Ip}
+                _ => {Filter::Default("Default".to_string())}
+            })
+        }
+        else {
+            match str[0].as_str() {
+                "host" => Ok(Filter::Host(parse_ipv4(&str[1])?)),
+                "port" => {
+                    let num = u16::from_str(&str[1]).map_err(|_| format!("'{}' is not a port number", str[1]))?;
+                    Ok(Filter::Port(num.to_be_bytes()))
+                }
+                "net" => Ok(Filter::Net(parse_ipv4(&str[1])?)),
+                "-c" => match str[1].parse::<i32>() {
+                    Ok(count) if count > 0 => Ok(Filter::Count(count)),
+                    _ => Err(format!("'{}' is not a number of packets", str[1])),
+                },
+                &_ => { Ok(Filter::Default("default".to_string())) }
+            }
+        }
+    }
+}
+
+//Exactly four decimal octets, as in 192.168.1.10
+fn parse_ipv4(address: &str) -> Result<[u8; 4], String> {
+    let octets: Vec<u8> = address.split('.').map(u8::from_str).collect::<Result<_, _>>()
+        .map_err(|_| format!("'{}' is not an IPv4 address", address))?;
+    octets.try_into().map_err(|_| format!("'{}' is not an IPv4 address", address))
+}
+
+fn create_and_return_ether(data: &[u8]) -> EthernetFrame {
+    let packet_size = data.len() as u32;
+    let mut frame = EthernetFrame::new();
+    frame.packet_size = packet_size;
+    if data.len() < 14 {
+        return frame;
+    }
+    let destination_address: [u8; 6] = data[0..6].try_into().unwrap();
+    let source_address: [u8; 6] = data[6..12].try_into().unwrap();
+    let ether_type: [u8; 2] = data[12..14].try_into().unwrap();
+
+    //Only IPv4 payloads are decoded, everything else keeps the placeholder packet
+    if ether_type != [0x08, 0x00] || data.len() < 34 {
+        frame.destination_address = destination_address;
+        frame.source_address = source_address;
+        frame.ether_type = ether_type;
+        if ether_type == [0x86, 0xdd] {
+            frame.version = IPVersion::V6;
+        }
+        return frame;
+    }
+
+    let ipv = data[14] >> 4;
+    let ihl = data[14] & 0x0f;
+
+    let type_of_service = data[15];
+    let precedence = type_of_service >> 5;
+    let delay = (type_of_service >> 4) & 1;
+    let throughput = (type_of_service >> 3) & 1;
+    let reliability = (type_of_service >> 2) & 1;
+    let tos = type_of_service & 0x03;
+
+    let total_length: [u8; 2] = data[16..18].try_into().unwrap();
+    let identification: [u8; 2] = data[18..20].try_into().unwrap();
+
+    //Here first 3 bits are flags and rest 13 are Fragment offset
+    let reserved_flag = data[20] >> 7;
+    let do_not_fragment_flag = (data[20] >> 6) & 1;
+    let last_fragment_flag = (data[20] >> 5) & 1;
+    let fragment_offset = (u16::from_be_bytes([data[20], data[21]]) & 0x1fff) * 8;
+
+    let ttl = data[22];
+    let temp = data[23];
+    let mut protocol = IPProtocol::Default;
+    match temp {
+        1 => { protocol = IPProtocol::ICMP }
+        6 => { protocol = IPProtocol::TCP }
+        17 => { protocol = IPProtocol::UDP }
+        _ => {}
+    }
+    let header_checksum: [u8; 2] = data[24..26].try_into().unwrap();
+
+    let source_add: [u8; 4] = data[26..30].try_into().unwrap();
+    let destination_add: [u8; 4] = data[30..34].try_into().unwrap();
+
+    let mut options = None;
+    let mut current: usize = 34;
+    if ihl > 5 {
+        current = (34 + ((ihl * 4) - 20) as usize).min(data.len());
+        options = Some(data[34..(current)].to_vec());
+    }
+    //Ethernet pads short frames, so the IP total length marks where the payload really ends
+    let ip_end = (14 + u16::from_be_bytes(total_length) as usize).clamp(current, data.len());
+
+
+    let mut datagram: ProtocolDatagram = ProtocolDatagram::new();
+    match protocol {
+        IPProtocol::ICMP if ip_end >= current + 8 => {
+            let mut icmp = ICMPPacket::new();
+            icmp.packet_type = data[current];
+            icmp.code = data[current + 1];
+            icmp.checksum = data[current + 2..current + 4].try_into().unwrap();
+            icmp.identifier_be = data[(current + 4)..(current + 6)].try_into().unwrap();
+            icmp.identifier_le = data[(current + 4)..(current + 6)].try_into().unwrap();
+            icmp.sequence_be = data[(current + 6)..(current + 8)].try_into().unwrap();
+            icmp.sequence_le = data[(current + 6)..(current + 8)].try_into().unwrap();
+            if ip_end >= current + 16 {
+                icmp.timestamp = data[(current + 8)..(current + 16)].try_into().unwrap();
+            }
+            icmp.data = data[(current + 16).min(ip_end)..ip_end].to_vec();
+            datagram = ProtocolDatagram::ICMP(icmp);
+        }
+        IPProtocol::UDP if ip_end >= current + 8 => {
+            let mut udp = UDPPacket::new();
+            udp.source_port = data[current..(current + 2)].try_into().unwrap();
+            udp.destination_port = data[(current + 2)..(current + 4)].try_into().unwrap();
+            udp.length = data[(current + 4)..(current + 6)].try_into().unwrap();
+            udp.checksum = data[(current + 6)..(current + 8)].try_into().unwrap();
+            udp.data = data[(current + 8)..ip_end].to_vec();
+            datagram = ProtocolDatagram::UDP(udp);
+        }
+        IPProtocol::TCP if ip_end >= current + 20 => {
+            let mut tcp = TCPPacket::new();
+            tcp.source_port = data[current..(current + 2)].try_into().unwrap();
+            tcp.destination_port = data[(current + 2)..(current + 4)].try_into().unwrap();
+            tcp.sequence_number = data[(current + 4)..(current + 8)].try_into().unwrap();
+            tcp.acknowledgement_number = data[(current + 8)..(current + 12)].try_into().unwrap();
+
+            let data_offset = (data[current + 12] >> 4) & 0xF;
+            let flags = data[current + 13];
+
+            tcp.data_offset = data_offset * 4;
+            tcp.flags = flags;
+            tcp.window = data[(current + 14)..(current + 16)].try_into().unwrap();
+            tcp.checksum = data[(current + 16)..(current + 18)].try_into().unwrap();
+            tcp.urgent_pointer = data[(current + 18)..(current + 20)].try_into().unwrap();
+            let header_end = (current + tcp.data_offset as usize).clamp(current + 20, ip_end);
+            if header_end > current + 20 {
+                tcp.options = Some(data[(current + 20)..header_end].to_vec());
+            }
+            tcp.data = data[header_end..ip_end].to_vec();
+            datagram = ProtocolDatagram::TCP(tcp);
+        }
+        _ => {}
+    }
+
+
+    let version = match ipv {
+        4 => { IPVersion::V4 }
+        _ => { IPVersion::V6 }
+    };
+
+    let packet = IPacket {
+        version,
+        ihl,
+        tos,
+        precedence,
+        delay,
+        throughput,
+        reliability,
+        total_length,
+        identification,
+        reserved_flag,
+        do_not_fragment_flag,
+        last_fragment_flag,
+        fragment_offset,
+        ttl,
+        protocol,
+        header_checksum,
+        source_add,
+        destination_add,
+        options,
+        datagram,
+
+    };
+
+    EthernetFrame {
+        packet_size,
+        destination_address,
+        source_address,
+        ether_type,
+        version,
+        packet,
+    }
+}
+
+//Reads the frame in place, so records can be filtered before they are copied into blocks
+fn matches_filter(data: &[u8], filter: &Filter) -> bool {
+    let ip = view::EthernetFrame::new(data).and_then(|frame| frame.ipv4());
+    let protocol = ip.map(|ip| ip.protocol());
+    match *filter {
+        Filter::Host(address) | Filter::Net(address) => {
+            ip.is_some_and(|ip| ip.source().octets() == address || ip.destination().octets() == address)
+        }
+        Filter::Port(port) => {
+            let port = u16::from_be_bytes(port);
+            match ip.map(|ip| ip.transport()) {
+                Some(view::Transport::Tcp(tcp)) => [tcp.source_port(), tcp.destination_port()].contains(&port),
+                Some(view::Transport::Udp(udp)) => [udp.source_port(), udp.destination_port()].contains(&port),
+                _ => false,
+            }
+        }
+        Filter::Ip => ip.is_some(),
+        Filter::Tcp => protocol == Some(6),
+        Filter::Udp => protocol == Some(17),
+        Filter::Icmp => protocol == Some(1),
+        Filter::Count(count) => count > 0,
+        Filter::Default(_) => true,
+    }
+}
+
+fn passes_filters(block: &PcapBlock, filter: &Filter, options: &CliOptions) -> bool {
+    matches_filter(&block.data, filter) &&
+        options.display_filter.as_ref().is_none_or(|display_filter| display_filter.matches(block))
+}
@@ -0,0 +1,124 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{create_and_return_ether, PcapBlock};
+use crate::cli_options::CliOptions;
+use crate::encapsulation::Decapsulator;
+use crate::pipeline::Pipeline;
+
+/// Where frames are received when neither a capture file nor `--listen` is given.
+pub(crate) const DEFAULT_ADDRESS: &str = "0.0.0.0:4331";
+
+//Large enough for any UDP datagram, so no frame is cut short
+const BUFFER_SIZE: usize = 64 * 1024;
+
+//How often the receiving loops look for an interrupt while no packets come in
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signal: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+extern "C" fn on_interrupt(_signal: i32) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C and SIGTERM stop the packets from coming in rather than the program, so the
+/// reports can still be printed and the files finished.
+pub(crate) fn catch_interrupts() {
+    unsafe {
+        signal(SIGINT, on_interrupt);
+        signal(SIGTERM, on_interrupt);
+    }
+}
+
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The address `--listen` binds to: a port alone means every IPv4 interface.
+pub(crate) fn bind_address(text: &str) -> String {
+    match text.parse::<u16>() {
+        Ok(port) => format!("0.0.0.0:{}", port),
+        Err(_) => text.to_string(),
+    }
+}
+
+/// One frame as it came off the socket.
+struct Datagram {
+    data: Vec<u8>,
+    sender: SocketAddr,
+    received: Duration, //Since the epoch
+}
+
+/// Receives Ethernet frames sent as UDP datagrams, from any number of senders, until
+/// interrupted or until `-c` packets were seen. Each frame goes through the same filters and
+/// outputs as the packets of a capture file, the reports being printed when it stops.
+pub(crate) fn listen(address: &str, options: &CliOptions) -> Result<(), String> {
+    let socket = UdpSocket::bind(address).map_err(|error| format!("could not listen on {}: {}", address, error))?;
+    let local_address = socket.local_addr().map_err(|error| format!("could not listen on {}: {}", address, error))?;
+    eprintln!("Listening on: {}", local_address);
+    let mut pipeline = Pipeline::new(options, BUFFER_SIZE as u32, 1, false)?;
+
+    //Frames are received on their own thread so slow output does not make the socket drop them
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || receive(socket, sender));
+    catch_interrupts();
+
+    let mut decapsulator = Decapsulator::new(options.encapsulation);
+    let mut number = 0;
+    while !interrupted() && !pipeline.done() {
+        let datagram = match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(datagram)) => datagram,
+            Ok(Err(error)) => return Err(format!("could not receive on {}: {}", local_address, error)),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let Some(frame) = decapsulator.unwrap(&datagram.data, datagram.sender) else { continue };
+        number += 1;
+        let length = frame.data.len() as u32;
+        //The time the envelope gives is when the frame was captured, not when it got here
+        let (seconds, microseconds) = frame.timestamp
+            .unwrap_or((datagram.received.as_secs() as u32, datagram.received.subsec_micros()));
+        let mut block = PcapBlock::new();
+        block.number = number;
+        block.timestamp_seconds = seconds.to_ne_bytes();
+        block.timestamp_microseconds = microseconds.to_ne_bytes();
+        block.captured_length = length.to_ne_bytes();
+        block.original_length = frame.original_length.unwrap_or(length).max(length).to_ne_bytes();
+        block.ether_frame = create_and_return_ether(&frame.data);
+        block.data = frame.data;
+        block.sender = Some(datagram.sender);
+        block.envelope = frame.envelope;
+        pipeline.process(block)?;
+    }
+
+    if decapsulator.dropped > 0 {
+        eprintln!("Dropped {} datagrams without a frame in the expected encapsulation", decapsulator.dropped);
+    }
+    pipeline.finish(|count| format!("Received {} packets", count))?;
+    Ok(())
+}
+
+fn receive(socket: UdpSocket, channel: Sender<io::Result<Datagram>>) {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let result = socket.recv_from(&mut buffer).map(|(length, sender)| Datagram {
+            data: buffer[..length].to_vec(),
+            sender,
+            received: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),
+        });
+        let failed = result.is_err();
+        if channel.send(result).is_err() || failed {
+            return;
+        }
+    }
+}
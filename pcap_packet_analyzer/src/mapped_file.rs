@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+
+#[cfg(unix)]
+use std::ffi::c_void;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+//From sys/mman.h
+#[cfg(unix)]
+const PROT_READ: i32 = 1;
+#[cfg(unix)]
+const MAP_PRIVATE: i32 = 2;
+#[cfg(unix)]
+const MADV_SEQUENTIAL: i32 = 2;
+
+//off_t, which is as wide as a long on Linux and 64 bits on the other systems
+#[cfg(target_os = "linux")]
+type Offset = std::ffi::c_long;
+#[cfg(all(unix, not(target_os = "linux")))]
+type Offset = i64;
+
+#[cfg(unix)]
+extern "C" {
+    fn mmap(address: *mut c_void, length: usize, protection: i32, flags: i32, fd: i32, offset: Offset) -> *mut c_void;
+    fn munmap(address: *mut c_void, length: usize) -> i32;
+    fn madvise(address: *mut c_void, length: usize, advice: i32) -> i32;
+}
+
+/// A file mapped into memory read only, so a capture is decoded straight from the page cache
+/// instead of being copied into buffers first. Where there is no `mmap` the file is read whole.
+pub struct MappedFile {
+    #[cfg(unix)]
+    memory: *const u8,
+    #[cfg(not(unix))]
+    memory: Vec<u8>,
+    length: usize,
+}
+
+//The mapping is read only and private, nothing changes it while it is shared between threads
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    #[cfg(unix)]
+    pub fn open(path: &str) -> io::Result<MappedFile> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len() as usize;
+        //An empty file cannot be mapped, and there is nothing to read from it anyway
+        if length == 0 {
+            return Ok(MappedFile { memory: std::ptr::NonNull::dangling().as_ptr(), length });
+        }
+        let memory = unsafe { mmap(std::ptr::null_mut(), length, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if memory as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        //The records are read front to back, so the kernel can read ahead and drop what was read
+        unsafe { madvise(memory, length, MADV_SEQUENTIAL) };
+        Ok(MappedFile { memory: memory as *const u8, length })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(path: &str) -> io::Result<MappedFile> {
+        let memory = std::fs::read(path)?;
+        let length = memory.len();
+        Ok(MappedFile { memory, length })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.memory, self.length) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.memory[..self.length]
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.length > 0 {
+            unsafe { munmap(self.memory as *mut c_void, self.length) };
+        }
+    }
+}
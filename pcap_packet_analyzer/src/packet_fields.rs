@@ -0,0 +1,313 @@
+use crate::pcap_block::format_utc;
+use crate::view::{Packet, Transport};
+
+/// How the value of a field is written and compared.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum FieldKind {
+    Protocol,
+    Unsigned,
+    Ipv4,
+    Ether,
+    Bytes,
+    Text,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum FieldValue {
+    Unsigned(u64),
+    Bytes(Vec<u8>),
+}
+
+//Every field that can be named in a filter, with the kind of value it holds
+const FIELDS: &[(&str, FieldKind)] = &[
+    ("frame.number", FieldKind::Unsigned),
+    ("frame.time", FieldKind::Text),
+    ("frame.time_epoch", FieldKind::Text),
+    ("frame.len", FieldKind::Unsigned),
+    ("frame.cap_len", FieldKind::Unsigned),
+    ("frame.sender", FieldKind::Text),
+    ("frame.encapsulation", FieldKind::Text),
+    ("frame.interface_id", FieldKind::Unsigned),
+    ("frame.vlan_id", FieldKind::Unsigned),
+    ("eth", FieldKind::Protocol),
+    ("eth.dst", FieldKind::Ether),
+    ("eth.src", FieldKind::Ether),
+    ("eth.addr", FieldKind::Ether),
+    ("eth.type", FieldKind::Unsigned),
+    ("ip", FieldKind::Protocol),
+    ("ip.version", FieldKind::Unsigned),
+    ("ip.hdr_len", FieldKind::Unsigned),
+    ("ip.tos", FieldKind::Unsigned),
+    ("ip.precedence", FieldKind::Unsigned),
+    ("ip.len", FieldKind::Unsigned),
+    ("ip.id", FieldKind::Unsigned),
+    ("ip.flags.rb", FieldKind::Unsigned),
+    ("ip.flags.df", FieldKind::Unsigned),
+    ("ip.flags.mf", FieldKind::Unsigned),
+    ("ip.frag_offset", FieldKind::Unsigned),
+    ("ip.ttl", FieldKind::Unsigned),
+    ("ip.proto", FieldKind::Unsigned),
+    ("ip.checksum", FieldKind::Unsigned),
+    ("ip.src", FieldKind::Ipv4),
+    ("ip.dst", FieldKind::Ipv4),
+    ("ip.addr", FieldKind::Ipv4),
+    ("tcp", FieldKind::Protocol),
+    ("tcp.srcport", FieldKind::Unsigned),
+    ("tcp.dstport", FieldKind::Unsigned),
+    ("tcp.port", FieldKind::Unsigned),
+    ("tcp.seq", FieldKind::Unsigned),
+    ("tcp.ack", FieldKind::Unsigned),
+    ("tcp.hdr_len", FieldKind::Unsigned),
+    ("tcp.flags", FieldKind::Unsigned),
+    ("tcp.flags.fin", FieldKind::Unsigned),
+    ("tcp.flags.syn", FieldKind::Unsigned),
+    ("tcp.flags.reset", FieldKind::Unsigned),
+    ("tcp.flags.push", FieldKind::Unsigned),
+    ("tcp.flags.ack", FieldKind::Unsigned),
+    ("tcp.flags.urg", FieldKind::Unsigned),
+    ("tcp.window_size", FieldKind::Unsigned),
+    ("tcp.checksum", FieldKind::Unsigned),
+    ("tcp.urgent_pointer", FieldKind::Unsigned),
+    ("tcp.len", FieldKind::Unsigned),
+    ("tcp.payload", FieldKind::Bytes),
+    ("tcp.stream", FieldKind::Unsigned),
+    ("tcp.state", FieldKind::Text),
+    ("tcp.seq_relative", FieldKind::Unsigned),
+    ("tcp.ack_relative", FieldKind::Unsigned),
+    ("tcp.window_size_scaled", FieldKind::Unsigned),
+    ("udp", FieldKind::Protocol),
+    ("udp.srcport", FieldKind::Unsigned),
+    ("udp.dstport", FieldKind::Unsigned),
+    ("udp.port", FieldKind::Unsigned),
+    ("udp.length", FieldKind::Unsigned),
+    ("udp.checksum", FieldKind::Unsigned),
+    ("udp.payload", FieldKind::Bytes),
+    ("icmp", FieldKind::Protocol),
+    ("icmp.type", FieldKind::Unsigned),
+    ("icmp.code", FieldKind::Unsigned),
+    ("icmp.checksum", FieldKind::Unsigned),
+    ("icmp.ident", FieldKind::Unsigned),
+    ("icmp.seq", FieldKind::Unsigned),
+    ("icmp.data", FieldKind::Bytes),
+];
+
+pub(crate) fn field_kind(name: &str) -> Option<FieldKind> {
+    FIELDS.iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, kind)| *kind)
+}
+
+fn unsigned(value: u64) -> Vec<FieldValue> {
+    vec![FieldValue::Unsigned(value)]
+}
+
+fn bytes(value: &[u8]) -> Vec<FieldValue> {
+    vec![FieldValue::Bytes(value.to_vec())]
+}
+
+fn flag(flags: u8, mask: u8) -> Vec<FieldValue> {
+    unsigned(if flags & mask != 0 { 1 } else { 0 })
+}
+
+/// Returns every value the field has in the packet. Fields such as `ip.addr` or `tcp.port`
+/// match both directions and so return two values, fields of layers missing from the packet
+/// return none.
+pub(crate) fn field_values(packet: &Packet, name: &str) -> Vec<FieldValue> {
+    let record = &packet.record;
+    let frame = record.frame();
+    let ip = packet.ipv4();
+    let transport = packet.transport();
+    let (layer, _) = name.split_once('.').unwrap_or((name, ""));
+
+    match (layer, frame, ip, transport) {
+        ("frame", ..) => match name {
+            "frame.number" => unsigned(record.number() as u64),
+            "frame.time" => bytes(format_utc(record.seconds(), record.microseconds()).as_bytes()),
+            "frame.time_epoch" => bytes(format!("{}.{:06}", record.seconds(), record.microseconds()).as_bytes()),
+            "frame.len" => unsigned(record.original_length() as u64),
+            "frame.cap_len" => unsigned(record.data().len() as u64),
+            "frame.sender" => packet.sender.map(|sender| bytes(sender.to_string().as_bytes())).unwrap_or_default(),
+            "frame.encapsulation" => packet.envelope.map(|envelope| bytes(envelope.kind.name().as_bytes())).unwrap_or_default(),
+            "frame.interface_id" => packet.envelope.and_then(|envelope| envelope.interface)
+                .map(|interface| unsigned(interface as u64)).unwrap_or_default(),
+            "frame.vlan_id" => packet.envelope.and_then(|envelope| envelope.vlan)
+                .map(|vlan| unsigned(vlan as u64)).unwrap_or_default(),
+            _ => vec![],
+        },
+        ("eth", Some(frame), ..) => match name {
+            "eth" => unsigned(1),
+            "eth.dst" => bytes(&frame.destination().octets()),
+            "eth.src" => bytes(&frame.source().octets()),
+            "eth.addr" => [bytes(&frame.source().octets()), bytes(&frame.destination().octets())].concat(),
+            "eth.type" => unsigned(frame.ether_type() as u64),
+            _ => vec![],
+        },
+        ("ip", _, Some(ip), _) => match name {
+            "ip" => unsigned(1),
+            "ip.version" => unsigned(if ip.version() == 4 { 4 } else { 6 }),
+            "ip.hdr_len" => unsigned(ip.header_length() as u64),
+            "ip.tos" => unsigned((ip.type_of_service() & 0x03) as u64),
+            "ip.precedence" => unsigned((ip.type_of_service() >> 5) as u64),
+            "ip.len" => unsigned(ip.total_length() as u64),
+            "ip.id" => unsigned(ip.identification() as u64),
+            "ip.flags.rb" => unsigned(ip.reserved_flag() as u64),
+            "ip.flags.df" => unsigned(ip.dont_fragment() as u64),
+            "ip.flags.mf" => unsigned(ip.more_fragments() as u64),
+            "ip.frag_offset" => unsigned(ip.fragment_offset() as u64),
+            "ip.ttl" => unsigned(ip.ttl() as u64),
+            "ip.proto" => unsigned(ip.protocol() as u64),
+            "ip.checksum" => unsigned(ip.checksum() as u64),
+            "ip.src" => bytes(&ip.source().octets()),
+            "ip.dst" => bytes(&ip.destination().octets()),
+            "ip.addr" => [bytes(&ip.source().octets()), bytes(&ip.destination().octets())].concat(),
+            _ => vec![],
+        },
+        ("tcp", _, _, Transport::Tcp(tcp)) => match name {
+            "tcp" => unsigned(1),
+            "tcp.srcport" => unsigned(tcp.source_port() as u64),
+            "tcp.dstport" => unsigned(tcp.destination_port() as u64),
+            "tcp.port" => [unsigned(tcp.source_port() as u64), unsigned(tcp.destination_port() as u64)].concat(),
+            "tcp.seq" => unsigned(tcp.sequence_number() as u64),
+            "tcp.ack" => unsigned(tcp.acknowledgement_number() as u64),
+            "tcp.hdr_len" => unsigned(tcp.header_length() as u64),
+            "tcp.flags" => unsigned(tcp.flags() as u64),
+            "tcp.flags.fin" => flag(tcp.flags(), 0x01),
+            "tcp.flags.syn" => flag(tcp.flags(), 0x02),
+            "tcp.flags.reset" => flag(tcp.flags(), 0x04),
+            "tcp.flags.push" => flag(tcp.flags(), 0x08),
+            "tcp.flags.ack" => flag(tcp.flags(), 0x10),
+            "tcp.flags.urg" => flag(tcp.flags(), 0x20),
+            "tcp.window_size" => unsigned(tcp.window() as u64),
+            "tcp.checksum" => unsigned(tcp.checksum() as u64),
+            "tcp.urgent_pointer" => unsigned(tcp.urgent_pointer() as u64),
+            "tcp.len" => unsigned(tcp.payload().len() as u64),
+            "tcp.payload" => bytes(tcp.payload()),
+            _ => match packet.analysis {
+                Some(analysis) => match name {
+                    "tcp.stream" => unsigned(analysis.stream as u64),
+                    "tcp.state" => bytes(analysis.state.name().as_bytes()),
+                    "tcp.seq_relative" => unsigned(analysis.relative_seq as u64),
+                    "tcp.ack_relative" => analysis.relative_ack.map_or(vec![], |ack| unsigned(ack as u64)),
+                    "tcp.window_size_scaled" => unsigned(analysis.window_size as u64),
+                    _ => vec![],
+                },
+                None => vec![],
+            },
+        },
+        ("udp", _, _, Transport::Udp(udp)) => match name {
+            "udp" => unsigned(1),
+            "udp.srcport" => unsigned(udp.source_port() as u64),
+            "udp.dstport" => unsigned(udp.destination_port() as u64),
+            "udp.port" => [unsigned(udp.source_port() as u64), unsigned(udp.destination_port() as u64)].concat(),
+            "udp.length" => unsigned(udp.length() as u64),
+            "udp.checksum" => unsigned(udp.checksum() as u64),
+            "udp.payload" => bytes(udp.payload()),
+            _ => vec![],
+        },
+        ("icmp", _, _, Transport::Icmp(icmp)) => match name {
+            "icmp" => unsigned(1),
+            "icmp.type" => unsigned(icmp.message_type() as u64),
+            "icmp.code" => unsigned(icmp.code() as u64),
+            "icmp.checksum" => unsigned(icmp.checksum() as u64),
+            "icmp.ident" => unsigned(icmp.identifier() as u64),
+            "icmp.seq" => unsigned(icmp.sequence() as u64),
+            //The data starts after the timestamp that follows the header
+            "icmp.data" => bytes(icmp.payload().get(8..).unwrap_or_default()),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// Writes a value the way it is usually shown for its kind of field.
+pub(crate) fn format_value(kind: FieldKind, value: &FieldValue) -> String {
+    match value {
+        FieldValue::Unsigned(number) => number.to_string(),
+        FieldValue::Bytes(bytes) => match kind {
+            FieldKind::Ipv4 => bytes.iter().map(|byte| byte.to_string()).collect::<Vec<String>>().join("."),
+            FieldKind::Text => String::from_utf8_lossy(bytes).into_owned(),
+            _ => bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(":"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PcapWriter, ProtocolDatagram};
+    use crate::tcp_analysis::TcpAnalyzer;
+    use crate::view::Records;
+
+    //From 10.0.0.1 to 10.0.0.2, or back for a reply
+    fn ipv4(reply: bool, header: &[u8], protocol: u8, transport: &[u8], padding: usize) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+        let length = (header.len() + 20 + transport.len()) as u16;
+        frame.extend_from_slice(&[0x40 | (5 + header.len() as u8 / 4), 0xa4]);
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&[0x12, 0x34, 0xa0, 0x02, 64, protocol, 0xbe, 0xef]);
+        frame.extend_from_slice(if reply { &[10, 0, 0, 2, 10, 0, 0, 1] } else { &[10, 0, 0, 1, 10, 0, 0, 2] });
+        frame.extend_from_slice(header);
+        frame.extend_from_slice(transport);
+        frame.resize(frame.len() + padding, 0);
+        frame
+    }
+
+    //A TCP handshake with options and payload, UDP, ICMP and frames that stop short of a layer
+    fn capture() -> Vec<u8> {
+        let syn = [&[0x9c, 0x40, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x70, 0x02, 0xff, 0xff, 0, 0, 0, 0][..],
+            &[2, 4, 5, 0xb4, 1, 3, 3, 7]].concat();
+        let syn_ack = [&[0, 80, 0x9c, 0x40, 0, 0, 0, 9, 0, 0, 0, 2, 0x70, 0x12, 0x20, 0, 0, 0, 0, 0][..],
+            &[2, 4, 5, 0xb4, 1, 3, 3, 2]].concat();
+        let data = [&[0x9c, 0x40, 0, 80, 0, 0, 0, 2, 0, 0, 0, 10, 0x50, 0x18, 0x01, 0, 0, 0, 0, 0][..], b"GET / HTTP/1.1\r\n"].concat();
+        let udp = [&[0x30, 0x39, 0, 53, 0, 13, 0, 0][..], b"hello"].concat();
+        let echo = [&[8, 0, 0xf7, 0xff, 0, 1, 0, 2][..], &[0xee; 8], b"ping"].concat();
+        let frames = [
+            ipv4(false, &[], 6, &syn, 0),
+            ipv4(true, &[1, 1, 1, 0], 6, &syn_ack, 0),
+            ipv4(false, &[], 6, &data, 6),
+            ipv4(false, &[], 17, &udp, 10),
+            ipv4(false, &[], 1, &echo, 0),
+            ipv4(false, &[], 1, &echo[..12], 0),
+            ipv4(false, &[], 6, &data[..12], 0),
+            ipv4(false, &[], 47, b"gre", 0),
+            [&[0xff; 12][..], &[0x08, 0x06], &[0; 28]].concat(),
+            vec![0x02; 10],
+        ];
+        let mut capture = Vec::new();
+        let mut writer = PcapWriter::new(&mut capture, 65535, 1).unwrap();
+        for (number, frame) in frames.iter().enumerate() {
+            writer.write_packet(1_700_000_000, number as u32 * 1000, frame.len() as u32 + 4, frame).unwrap();
+        }
+        writer.flush().unwrap();
+        capture
+    }
+
+    #[test]
+    fn reads_a_record_like_the_block_decoded_from_it() {
+        let capture = capture();
+        let (mut record_analyzer, mut block_analyzer) = (TcpAnalyzer::new(), TcpAnalyzer::new());
+        for record in Records::new(&capture).unwrap() {
+            let analysis = record_analyzer.analyze_frame(record.data(), record.timestamp());
+            let packet = Packet::new(record, analysis.as_ref());
+            let mut block = record.to_block();
+            block_analyzer.annotate(&mut block);
+            for (field, _) in FIELDS {
+                let values = field_values(&packet, field);
+                assert_eq!(values, field_values(&block.view(), field), "{} of packet {}", field, record.number());
+                assert_eq!(values, field_values(&packet.to_block().view(), field), "{} of packet {}", field, record.number());
+            }
+            if record.number() == 3 {
+                //Scaled by the shift the client offered in its SYN
+                assert_eq!(field_values(&packet, "tcp.window_size_scaled"), unsigned(256 << 7));
+            }
+            //The payloads end where the decoder ends them
+            let payload = |name| field_values(&packet, name).pop();
+            match &block.ether_frame.packet.datagram {
+                ProtocolDatagram::TCP(tcp) => assert_eq!(payload("tcp.payload"), Some(FieldValue::Bytes(tcp.data.clone()))),
+                ProtocolDatagram::UDP(udp) => assert_eq!(payload("udp.payload"), Some(FieldValue::Bytes(udp.data.clone()))),
+                ProtocolDatagram::ICMP(icmp) => assert_eq!(payload("icmp.data"), Some(FieldValue::Bytes(icmp.data.clone()))),
+                ProtocolDatagram::Default(_) => assert!(["tcp", "udp", "icmp"].iter().all(|name| payload(name).is_none())),
+            }
+        }
+    }
+}
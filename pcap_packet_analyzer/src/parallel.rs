@@ -0,0 +1,355 @@
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use crate::DisplayFilter;
+use crate::cli_options::CliOptions;
+use crate::flow_export::Flow;
+use crate::pipeline::Shard;
+use crate::tcp_analysis::{SegmentAnalysis, TcpAnalyzer};
+use crate::view::{Packet, Record, Records, Transport};
+
+//Records the reader splits between the workers at a time
+const BATCH_SIZE: usize = 1024;
+
+//Batches waiting for each worker and for the merge, which keeps a reader that runs ahead from
+//holding the whole capture
+const QUEUE_LENGTH: usize = 16;
+
+/// What a worker made of its part of a batch: the records with the analysis of their segment
+/// and whether they match the display filter when the worker could tell, and the flows its
+/// shard finished.
+#[derive(Default)]
+struct Decoded<'a> {
+    packets: Vec<(Record<'a>, Option<SegmentAnalysis>, Option<bool>)>,
+    flows: Vec<Flow>,
+}
+
+/// Where the merge puts back together what the workers made of the capture.
+pub(crate) trait Merge {
+    /// The outputs a worker runs itself on the packets of its flows, if any.
+    fn shard(&mut self) -> Option<Shard>;
+
+    /// Takes the next packet of the capture, with whether it matches the display filter when
+    /// its worker could tell. Returns whether more packets are wanted.
+    fn packet(&mut self, packet: &Packet, displayed: Option<bool>) -> Result<bool, String>;
+
+    /// Takes the flows the shards finished with the packets of a batch, once they were given.
+    fn flows(&mut self, flows: Vec<Flow>) -> Result<(), String>;
+
+    /// Takes in a shard once the capture was read.
+    fn merge(&mut self, shard: Shard) -> Result<(), String>;
+}
+
+/// Reads the records of a capture file with `--threads` workers. A reader thread splits them
+/// into batches and gives every worker the records of the flows it owns, by a hash of their
+/// 5-tuple, so each worker tracks its connections over all of their packets in order. The
+/// workers track the connections, evaluate the display filter and, when they can tell which
+/// packets pass the filters, run the shards of `merge` on them. The packets are given to
+/// `merge` in capture order until it wants no more, and the shards once every packet was read.
+/// The records are read in place throughout, only the outputs that keep packets copy them.
+pub(crate) fn process(records: &mut Records, options: &CliOptions, merge: &mut impl Merge) -> Result<(), String> {
+    let workers = options.threads;
+    //A filter on the stream numbers needs the numbers of the whole capture, only known here
+    let display_filter = options.display_filter.as_ref().filter(|filter| !filter.uses_field("tcp.stream"));
+    let decided = display_filter.is_some() || options.display_filter.is_none();
+
+    thread::scope(|scope| {
+        let mut queues = Vec::new();
+        let mut results = Vec::new();
+        let mut returns = Vec::new();
+        let mut threads = Vec::new();
+        let (spare_parts, parts_returned) = mpsc::channel();
+        for _ in 0..workers {
+            let (queue, batches) = mpsc::sync_channel(QUEUE_LENGTH);
+            let (sender, result) = mpsc::sync_channel(QUEUE_LENGTH);
+            let (spare_decoded, decoded_returned) = mpsc::channel();
+            let spare_parts = spare_parts.clone();
+            let shard = if decided { merge.shard() } else { None };
+            threads.push(scope.spawn(move || decode(batches, sender, (spare_parts, decoded_returned), display_filter, shard)));
+            queues.push(queue);
+            results.push(result);
+            returns.push(spare_decoded);
+        }
+        let (sender, orders) = mpsc::sync_channel(QUEUE_LENGTH);
+        let (spare_orders, orders_returned) = mpsc::channel();
+        scope.spawn(move || split(records, queues, sender, (parts_returned, orders_returned)));
+
+        //Every worker numbers its streams from 0, they are numbered again in the order they start
+        let mut streams = vec![Vec::new(); workers];
+        let mut stream_count = 0;
+        let mut parts: Vec<Decoded> = Vec::with_capacity(workers);
+        let mut next = vec![0; workers];
+        for order in orders {
+            for result in &results {
+                parts.push(result.recv().map_err(|_| "a decoding thread stopped".to_string())?);
+            }
+            next.fill(0);
+            for &worker in &order {
+                let Some((record, analysis, displayed)) = parts[worker].packets.get_mut(next[worker]) else { continue };
+                next[worker] += 1;
+                renumber(analysis, &mut streams[worker], &mut stream_count);
+                if !merge.packet(&Packet::new(*record, analysis.as_ref()), *displayed)? {
+                    //Dropping the channels stops the reader and the workers
+                    return Ok(());
+                }
+            }
+            for part in &mut parts {
+                merge.flows(std::mem::take(&mut part.flows))?;
+            }
+            //The threads may have stopped already, then nothing needs the vectors any more
+            for (part, spare) in parts.drain(..).zip(&returns) {
+                let _ = spare.send(part);
+            }
+            let _ = spare_orders.send(order);
+        }
+        //Every batch was merged, so the workers are done too
+        for thread in threads {
+            if let Some(shard) = thread.join().map_err(|_| "a decoding thread stopped".to_string())? {
+                merge.merge(shard)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Hands out the records in batches, sending the merge the worker of every record in order.
+fn split<'a>(records: &mut Records<'a>, queues: Vec<SyncSender<Vec<Record<'a>>>>, orders: SyncSender<Vec<usize>>,
+             (parts_returned, orders_returned): (Receiver<Vec<Record<'a>>>, Receiver<Vec<usize>>)) {
+    let mut records = records.peekable();
+    while records.peek().is_some() {
+        let mut parts: Vec<_> = (0..queues.len())
+            .map(|_| parts_returned.try_recv().unwrap_or_default())
+            .collect();
+        let mut order = orders_returned.try_recv().unwrap_or_else(|_| Vec::with_capacity(BATCH_SIZE));
+        order.clear();
+        for record in records.by_ref().take(BATCH_SIZE) {
+            let worker = worker_for(&record, queues.len());
+            parts[worker].push(record);
+            order.push(worker);
+        }
+        //Every worker gets its part, even an empty one, so the merge takes one part from each
+        for (queue, part) in queues.iter().zip(parts) {
+            if queue.send(part).is_err() {
+                return;
+            }
+        }
+        if orders.send(order).is_err() {
+            return;
+        }
+    }
+}
+
+/// The worker that owns the flow of a record. Both directions hash the same, and packets
+/// without ports are spread by their addresses alone.
+fn worker_for(record: &Record, workers: usize) -> usize {
+    let Some(ip) = record.frame().and_then(|frame| frame.ipv4()) else {
+        return record.number() as usize % workers;
+    };
+    let (source_port, destination_port) = match ip.transport() {
+        Transport::Tcp(tcp) => (tcp.source_port(), tcp.destination_port()),
+        Transport::Udp(udp) => (udp.source_port(), udp.destination_port()),
+        _ => (0, 0),
+    };
+    let source = (u32::from(ip.source()) as u64) << 16 | source_port as u64;
+    let destination = (u32::from(ip.destination()) as u64) << 16 | destination_port as u64;
+    //A multiplicative hash spreads the flows well enough, for much less than SipHash
+    let mut hash = ip.protocol() as u64;
+    for endpoint in [source.min(destination), source.max(destination)] {
+        hash = (hash.rotate_left(5) ^ endpoint).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+    (hash >> 32) as usize % workers
+}
+
+fn decode<'a>(batches: Receiver<Vec<Record<'a>>>, results: SyncSender<Decoded<'a>>,
+              (spare_parts, decoded_returned): (Sender<Vec<Record<'a>>>, Receiver<Decoded<'a>>),
+              display_filter: Option<&DisplayFilter>, mut shard: Option<Shard>) -> Option<Shard> {
+    let mut tcp_analyzer = TcpAnalyzer::new();
+    for mut batch in batches {
+        //The analyses of a batch the merge is done with are dropped here, where they were made
+        let mut decoded = decoded_returned.try_recv().unwrap_or_default();
+        decoded.packets.clear();
+        for record in batch.drain(..) {
+            let analysis = tcp_analyzer.analyze_frame(record.data(), record.timestamp());
+            let packet = Packet::new(record, analysis.as_ref());
+            let displayed = display_filter.map(|filter| filter.matches_packet(&packet));
+            if let Some(shard) = &mut shard {
+                shard.add(&packet, displayed);
+            }
+            decoded.packets.push((record, analysis, displayed));
+        }
+        if let Some(shard) = &mut shard {
+            decoded.flows = shard.take_flows();
+        }
+        let _ = spare_parts.send(batch);
+        if results.send(decoded).is_err() {
+            break;
+        }
+    }
+    shard
+}
+
+/// Gives a segment the number its stream has in the whole capture. A worker numbers its
+/// streams as they start, so a number it has not used before is always the next one.
+fn renumber(analysis: &mut Option<SegmentAnalysis>, streams: &mut Vec<usize>, stream_count: &mut usize) {
+    let Some(analysis) = analysis else { return };
+    if analysis.stream == streams.len() {
+        streams.push(*stream_count);
+        *stream_count += 1;
+    }
+    analysis.stream = streams[analysis.stream];
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::pcap_writer::PcapWriter;
+
+    /// TCP connections between a few hosts, their segments interleaved, with ports reused by
+    /// new connections, a few resets and retransmissions, and ARP and DNS packets in between.
+    /// Most segments with data start with an HTTP head.
+    pub(crate) fn capture() -> Vec<u8> {
+        let mut state: u64 = 3;
+        let mut random = |bound: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        let mut capture = Vec::new();
+        let mut writer = PcapWriter::new(&mut capture, 65535, 1).unwrap();
+        //Next sequence number of each side and whether the handshake was sent
+        let mut connections = [(0_u32, 0_u32, false); 40];
+        for number in 0..6000_u32 {
+            let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+            let flow = random(40) as usize;
+            let (client, server) = ([10, 0, 0, flow as u8 % 7], [10, 0, 1, flow as u8 % 3]);
+            let client_port = 40000 + (flow as u16 / 7) * 7;
+            match random(20) {
+                0 => frame[12..14].copy_from_slice(&[0x08, 0x06]),
+                1 => {
+                    //A query for x, or the response to it
+                    let response = number % 2 == 1;
+                    let message = [&(flow as u16).to_be_bytes()[..], if response { &[0x81, 0x80] } else { &[0x01, 0x00] },
+                                   &[0, 1, 0, 0, 0, 0, 0, 0, 1, b'x', 0, 0, 1, 0, 1]].concat();
+                    frame.extend_from_slice(&[0x45, 0, 0, 28 + message.len() as u8, 0, 0, 0, 0, 64, 17, 0, 0]);
+                    frame.extend_from_slice(&if response { [server, client] } else { [client, server] }.concat());
+                    frame.extend_from_slice(&[0, 53, 0, 53, 0, 8 + message.len() as u8, 0, 0]);
+                    frame.extend_from_slice(&message);
+                }
+                kind => {
+                    let connection = &mut connections[flow];
+                    let from_client = random(2) == 0;
+                    let (flags, length) = match kind {
+                        _ if !connection.2 || kind == 2 => {
+                            //A new connection, perhaps on ports an earlier one used
+                            *connection = (random(1 << 32) as u32, random(1 << 32) as u32, true);
+                            (0x02, 0)
+                        }
+                        3 => (0x14, 0),
+                        _ => (0x18, random(300) as u32),
+                    };
+                    let side = if from_client || flags == 0x02 { &mut connection.0 } else { &mut connection.1 };
+                    let seq = if kind == 4 { side.wrapping_sub(length) } else { *side };
+                    *side = side.wrapping_add(length + (flags == 0x02) as u32);
+                    let ack = if from_client { connection.1 } else { connection.0 };
+                    let (source, destination) = if from_client || flags == 0x02 { (client, server) } else { (server, client) };
+                    let (source_port, destination_port) = if from_client || flags == 0x02 { (client_port, 443) } else { (443, client_port) };
+                    frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0]);
+                    frame[16..18].copy_from_slice(&(40 + length as u16).to_be_bytes());
+                    frame.extend_from_slice(&[source, destination].concat());
+                    frame.extend_from_slice(&source_port.to_be_bytes());
+                    frame.extend_from_slice(&destination_port.to_be_bytes());
+                    frame.extend_from_slice(&seq.to_be_bytes());
+                    frame.extend_from_slice(&ack.to_be_bytes());
+                    frame.extend_from_slice(&[0x50, flags | if flags == 0x02 { 0 } else { 0x10 }, 0xff, 0xff, 0, 0, 0, 0]);
+                    frame.resize(frame.len() + length as usize, 0x61);
+                    let head: &[u8] = if from_client { b"GET / HTTP/1.1\r\n\r\n" } else { b"HTTP/1.1 200 OK\r\n\r\n" };
+                    if length as usize >= head.len() {
+                        let start = frame.len() - length as usize;
+                        frame[start..start + head.len()].copy_from_slice(head);
+                    }
+                }
+            }
+            //Packets come in pairs with the same time
+            writer.write_packet(number / 200, number / 2 % 100 * 10_000, frame.len() as u32, &frame).unwrap();
+        }
+        writer.flush().unwrap();
+        capture
+    }
+
+    /// Keeps the number and analysis of every packet it is given with whether it was displayed,
+    /// up to `limit` packets.
+    struct Collected {
+        packets: Vec<(u32, Option<SegmentAnalysis>, Option<bool>)>,
+        limit: usize,
+    }
+
+    impl Collected {
+        fn new(limit: usize) -> Collected {
+            Collected { packets: Vec::new(), limit }
+        }
+    }
+
+    impl Merge for Collected {
+        fn shard(&mut self) -> Option<Shard> {
+            None
+        }
+
+        fn packet(&mut self, packet: &Packet, displayed: Option<bool>) -> Result<bool, String> {
+            self.packets.push((packet.record.number(), packet.analysis.cloned(), displayed));
+            Ok(self.packets.len() < self.limit)
+        }
+
+        fn flows(&mut self, _flows: Vec<Flow>) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn merge(&mut self, _shard: Shard) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decodes_like_a_single_thread() {
+        let capture = capture();
+        let filter = DisplayFilter::parse("tcp.len > 100 || tcp.flags.reset == 1").unwrap();
+        let mut tcp_analyzer = TcpAnalyzer::new();
+        let expected: Vec<_> = Records::new(&capture).unwrap()
+            .map(|record| {
+                let analysis = tcp_analyzer.analyze_frame(record.data(), record.timestamp());
+                let displayed = filter.matches_packet(&Packet::new(record, analysis.as_ref()));
+                (record.number(), analysis, Some(displayed))
+            })
+            .collect();
+        assert!(expected.iter().filter_map(|(_, analysis, _)| analysis.as_ref()).any(|analysis| analysis.stream > 200));
+
+        for threads in [2, 3, 8] {
+            let mut options = CliOptions::new();
+            options.threads = threads;
+            options.display_filter = Some(filter.clone());
+            let mut collected = Collected::new(usize::MAX);
+            process(&mut Records::new(&capture).unwrap(), &options, &mut collected).unwrap();
+            assert_eq!(collected.packets, expected, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn leaves_filters_on_stream_numbers_to_the_merge() {
+        let capture = capture();
+        let mut options = CliOptions::new();
+        options.threads = 4;
+        options.display_filter = Some(DisplayFilter::parse("tcp.stream == 3").unwrap());
+        let mut collected = Collected::new(usize::MAX);
+        process(&mut Records::new(&capture).unwrap(), &options, &mut collected).unwrap();
+        assert_eq!(collected.packets.len(), 6000);
+        assert!(collected.packets.iter().all(|(_, _, displayed)| displayed.is_none()));
+    }
+
+    #[test]
+    fn stops_when_the_output_wants_no_more() {
+        let capture = capture();
+        let mut options = CliOptions::new();
+        options.threads = 3;
+        let mut collected = Collected::new(10);
+        process(&mut Records::new(&capture).unwrap(), &options, &mut collected).unwrap();
+        let numbers: Vec<u32> = collected.packets.iter().map(|(number, _, _)| *number).collect();
+        assert_eq!(numbers, (1..=10).collect::<Vec<_>>());
+    }
+}
@@ -1,21 +1,167 @@
-use crate::EthernetFrame;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{create_and_return_ether, EthernetFrame};
+use crate::encapsulation::Envelope;
+use crate::json::JsonValue;
+use crate::summary::summary_line;
+use crate::view::Packet;
 
-pub(crate) struct PcapBlock {
+/// One captured packet: when it was captured, its bytes and the frame decoded from them.
+pub struct PcapBlock {
+    pub(crate) number: u32, //Position of the block in the capture, starting at 1
     pub(crate) timestamp_seconds: [u8; 4],
     pub(crate) timestamp_microseconds: [u8; 4],
+    pub(crate) timestamp_nanoseconds: Option<u32>, //The fraction to the nanosecond, when the capture had it
     pub(crate) captured_length: [u8; 4],
     pub(crate) original_length: [u8; 4],
     pub(crate) ether_frame: EthernetFrame,
+    pub(crate) data: Vec<u8>, //The captured bytes the frame was decoded from
+    pub(crate) sender: Option<SocketAddr>, //Who sent the frame, when it was received over the network
+    pub(crate) envelope: Option<Envelope>, //What the frame was wrapped in when it was received
 }
 
 impl PcapBlock {
     pub(crate) fn new() -> PcapBlock {
         PcapBlock {
+            number: 0,
             timestamp_seconds: [0, 0, 0, 0],
             timestamp_microseconds: [0, 0, 0, 0],
+            timestamp_nanoseconds: None,
             captured_length: [0, 0, 0, 0],
             original_length: [0, 0, 0, 0],
             ether_frame: EthernetFrame::new(),
+            data: vec![],
+            sender: None,
+            envelope: None,
         }
     }
-}
\ No newline at end of file
+
+    /// Decodes a frame captured at `seconds` and `microseconds` since the epoch, as if it were
+    /// read whole from a capture. It is not numbered.
+    pub fn from_frame(data: Vec<u8>, seconds: u32, microseconds: u32) -> PcapBlock {
+        let length = data.len() as u32;
+        let mut block = PcapBlock::new();
+        block.timestamp_seconds = seconds.to_ne_bytes();
+        block.timestamp_microseconds = microseconds.to_ne_bytes();
+        block.captured_length = length.to_ne_bytes();
+        block.original_length = length.to_ne_bytes();
+        block.ether_frame = create_and_return_ether(&data);
+        block.data = data;
+        block
+    }
+
+    /// Position of the block in the capture, starting at 1.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(u32::from_ne_bytes(self.timestamp_seconds) as u64, self.nanoseconds())
+    }
+
+    /// The fraction of the timestamp in nanoseconds, scaled up from the microseconds of a
+    /// capture that only had those.
+    pub(crate) fn nanoseconds(&self) -> u32 {
+        self.timestamp_nanoseconds.unwrap_or(u32::from_ne_bytes(self.timestamp_microseconds).saturating_mul(1000))
+    }
+
+    pub fn captured_length(&self) -> u32 {
+        u32::from_ne_bytes(self.captured_length)
+    }
+
+    /// The length of the packet on the wire, more than was captured when it was cut short.
+    pub fn original_length(&self) -> u32 {
+        u32::from_ne_bytes(self.original_length)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The block read in place, for the outputs that look at a packet without keeping it.
+    pub(crate) fn view(&self) -> Packet<'_> {
+        Packet::of_block(self)
+    }
+
+    pub fn frame(&self) -> &EthernetFrame {
+        &self.ether_frame
+    }
+
+    /// Who sent the frame, when it was received over the network.
+    pub fn sender(&self) -> Option<SocketAddr> {
+        self.sender
+    }
+
+    /// The one line summary printed for every packet by default.
+    pub fn summary(&self) -> String {
+        summary_line(self)
+    }
+
+    /// The block as the JSON object `--json` prints, on one line.
+    pub fn to_json_string(&self) -> String {
+        self.to_json().to_compact()
+    }
+
+    pub fn to_json_pretty(&self) -> String {
+        self.to_json().to_pretty()
+    }
+
+    /// Seconds since the epoch, with the microseconds as the fraction.
+    pub fn timestamp(&self) -> f64 {
+        u32::from_ne_bytes(self.timestamp_seconds) as f64 +
+            u32::from_ne_bytes(self.timestamp_microseconds) as f64 / 1_000_000.0
+    }
+
+    /// The timestamp as `seconds.microseconds` since the epoch, without rounding.
+    pub(crate) fn format_epoch(&self) -> String {
+        format!("{}.{:06}", u32::from_ne_bytes(self.timestamp_seconds), u32::from_ne_bytes(self.timestamp_microseconds))
+    }
+
+    /// The timestamp as a UTC date and time, `2024-01-31 13:45:10.123456`.
+    pub(crate) fn format_time(&self) -> String {
+        format_utc(u32::from_ne_bytes(self.timestamp_seconds), u32::from_ne_bytes(self.timestamp_microseconds))
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let mut frame = vec![
+            ("number", JsonValue::from(self.number)),
+            ("time", JsonValue::String(self.format_epoch())),
+            ("length", JsonValue::from(u32::from_ne_bytes(self.original_length))),
+            ("captured_length", JsonValue::from(u32::from_ne_bytes(self.captured_length))),
+        ];
+        if let Some(sender) = self.sender {
+            frame.push(("sender", JsonValue::String(sender.to_string())));
+        }
+        if let Some(envelope) = &self.envelope {
+            let mut fields = vec![("type", JsonValue::String(envelope.kind.name().to_string()))];
+            fields.extend(envelope.session.map(|session| ("session", JsonValue::from(session))));
+            fields.extend(envelope.interface.map(|interface| ("interface", JsonValue::from(interface))));
+            fields.extend(envelope.vlan.map(|vlan| ("vlan", JsonValue::from(vlan))));
+            frame.push(("encapsulation", JsonValue::object(fields)));
+        }
+        JsonValue::object(vec![
+            ("frame", JsonValue::object(frame)),
+            ("eth", self.ether_frame.to_json()),
+        ])
+    }
+}
+
+/// Seconds and microseconds since the epoch as a UTC date and time, `2024-01-31 13:45:10.123456`.
+pub(crate) fn format_utc(seconds: u32, microseconds: u32) -> String {
+    let seconds = seconds as i64;
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    //Converts days since 1970-01-01 to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}", year, month, day, time_of_day / 3600,
+            time_of_day % 3600 / 60, time_of_day % 60, microseconds)
+}
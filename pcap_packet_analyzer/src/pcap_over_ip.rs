@@ -0,0 +1,174 @@
+use std::io::{BufReader, BufWriter};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use crate::PcapBlock;
+use crate::cli_options::CliOptions;
+use crate::listener::{catch_interrupts, interrupted, POLL_INTERVAL};
+use crate::pcap_reader::PcapReader;
+use crate::pcap_writer::PcapWriter;
+use crate::pipeline::Pipeline;
+
+//Packets waiting to be sent to one client. A live capture drops what does not fit rather than
+//wait for a slow client
+const CLIENT_QUEUE: usize = 4096;
+
+//Packets read from the network ahead of the decoding
+const READ_AHEAD: usize = 1024;
+
+/// One packet as it is sent to every client.
+struct Record {
+    seconds: u32,
+    microseconds: u32,
+    original_length: u32,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Clients {
+    queues: Vec<SyncSender<Arc<Record>>>,
+    threads: Vec<JoinHandle<()>>,
+    served: u64,
+}
+
+/// Streams the packets as pcap over TCP, the way Wireshark and NetworkMiner read it: every
+/// client that connects gets a pcap file header followed by the packets from then on.
+pub(crate) struct PcapServer {
+    clients: Arc<Mutex<Clients>>,
+    live: bool, //Packets that do not fit in a client queue are dropped instead of waited for
+    pub(crate) packet_count: u64,
+    pub(crate) dropped: u64,
+}
+
+impl PcapServer {
+    /// Listens on `address`. Unless the packets are `live`, waits for the first client so it
+    /// gets the whole capture.
+    pub(crate) fn new(address: &str, snap_length: u32, link_type: u32, live: bool) -> Result<PcapServer, String> {
+        let listener = TcpListener::bind(address).map_err(|error| format!("could not serve on {}: {}", address, error))?;
+        let local_address = listener.local_addr().map_err(|error| format!("could not serve on {}: {}", address, error))?;
+        let clients = Arc::new(Mutex::new(Clients::default()));
+        if !live {
+            eprintln!("Waiting for a client on {}", local_address);
+            let (stream, peer) = listener.accept().map_err(|error| format!("could not serve on {}: {}", address, error))?;
+            add_client(&clients, stream, peer, snap_length, link_type);
+        } else {
+            eprintln!("Serving pcap over IP on {}", local_address);
+        }
+
+        let accepting = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(peer) = stream.peer_addr() {
+                    add_client(&accepting, stream, peer, snap_length, link_type);
+                }
+            }
+        });
+        Ok(PcapServer { clients, live, packet_count: 0, dropped: 0 })
+    }
+
+    pub(crate) fn add(&mut self, block: &PcapBlock) {
+        let record = Arc::new(Record {
+            seconds: u32::from_ne_bytes(block.timestamp_seconds),
+            microseconds: u32::from_ne_bytes(block.timestamp_microseconds),
+            original_length: u32::from_ne_bytes(block.original_length),
+            data: block.data.clone(),
+        });
+        let mut clients = self.clients.lock().unwrap();
+        let mut dropped = 0;
+        //Clients that went away have dropped their end of the queue
+        clients.queues.retain(|queue| if self.live {
+            match queue.try_send(Arc::clone(&record)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        } else {
+            queue.send(Arc::clone(&record)).is_ok()
+        });
+        self.dropped += dropped;
+        self.packet_count += 1;
+    }
+
+    /// Sends what is still queued, closes every connection and returns the number of clients
+    /// that were served.
+    pub(crate) fn finish(&mut self) -> u64 {
+        let (threads, served) = {
+            let mut clients = self.clients.lock().unwrap();
+            clients.queues.clear();
+            (std::mem::take(&mut clients.threads), clients.served)
+        };
+        for thread in threads {
+            let _ = thread.join();
+        }
+        served
+    }
+}
+
+fn add_client(clients: &Mutex<Clients>, stream: TcpStream, peer: SocketAddr, snap_length: u32, link_type: u32) {
+    eprintln!("Streaming to {}", peer);
+    let (queue, records) = mpsc::sync_channel(CLIENT_QUEUE);
+    let thread = thread::spawn(move || serve_client(stream, records, snap_length, link_type));
+    let mut clients = clients.lock().unwrap();
+    clients.queues.push(queue);
+    clients.threads.push(thread);
+    clients.served += 1;
+}
+
+fn serve_client(stream: TcpStream, records: Receiver<Arc<Record>>, snap_length: u32, link_type: u32) {
+    let Ok(mut writer) = PcapWriter::new(BufWriter::new(stream), snap_length, link_type) else { return };
+    loop {
+        //Whatever was written goes out as soon as the queue is empty, so live packets are not held back
+        let record = match records.try_recv() {
+            Ok(record) => record,
+            Err(TryRecvError::Empty) => {
+                if writer.flush().is_err() {
+                    return;
+                }
+                match records.recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        if writer.write_packet(record.seconds, record.microseconds, record.original_length, &record.data).is_err() {
+            return;
+        }
+    }
+    let _ = writer.flush();
+}
+
+/// Reads the pcap stream a pcap over IP server sends, as if it were a local file, until the
+/// server closes it, the program is interrupted or `-c` packets were seen.
+pub(crate) fn connect(address: &str, options: &CliOptions) -> Result<(), String> {
+    let stream = TcpStream::connect(address).map_err(|error| format!("could not connect to {}: {}", address, error))?;
+    eprintln!("Connected to {}", address);
+    let pcap_reader = PcapReader::new(BufReader::new(stream))
+        .map_err(|error| format!("{} did not send a pcap stream: {}", address, error))?;
+    let mut pipeline = Pipeline::new(options, pcap_reader.snap_length(), pcap_reader.link_type(), pcap_reader.nanosecond())?;
+
+    //The packets are read on their own thread so an interrupt is seen while waiting for them
+    let (sender, receiver) = mpsc::sync_channel(READ_AHEAD);
+    thread::spawn(move || {
+        for pcap_block in pcap_reader {
+            if sender.send(pcap_block).is_err() {
+                return;
+            }
+        }
+    });
+    catch_interrupts();
+
+    while !interrupted() && !pipeline.done() {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(pcap_block) => pipeline.process(pcap_block)?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    pipeline.finish(|count| format!("Received {} packets from {}", count, address))?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use crate::{create_and_return_ether, PcapBlock, PcapFileHeader};
+
+//Largest record we accept before assuming the file is corrupt
+pub(crate) const MAX_RECORD_LENGTH: u32 = 256 * 1024 * 1024;
+
+/// Whether a pcap file with this magic number is big endian and has nanosecond timestamps,
+/// None when it is not a pcap file.
+pub(crate) fn byte_order(magic_number: [u8; 4]) -> Option<(bool, bool)> {
+    match magic_number {
+        [0xd4, 0xc3, 0xb2, 0xa1] => Some((false, false)),
+        [0xa1, 0xb2, 0xc3, 0xd4] => Some((true, false)),
+        [0x4d, 0x3c, 0xb2, 0xa1] => Some((false, true)),
+        [0xa1, 0xb2, 0x3c, 0x4d] => Some((true, true)),
+        _ => None,
+    }
+}
+
+/// The error of a record longer than any capture would hold, from a damaged file.
+pub(crate) fn too_long(number: u32, captured_length: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("record {} is {} bytes long, the capture is damaged", number, captured_length))
+}
+
+/// Reads the blocks of a pcap stream one after the other. The timestamps and lengths of every
+/// block are stored in native byte order, whatever the byte order of the file was.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    pub(crate) header: PcapFileHeader,
+    big_endian: bool,
+    nanosecond: bool,
+    block_count: u32,
+    error: Option<io::Error>, //Why reading stopped, when it was not the end of the stream
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<PcapReader<R>> {
+        let mut header = PcapFileHeader::new();
+        reader.read_exact(&mut header.magic_number)?;
+        reader.read_exact(&mut header.version_major)?;
+        reader.read_exact(&mut header.version_minor)?;
+        reader.read_exact(&mut header.time_zone)?;
+        reader.read_exact(&mut header.timestamp_accuracy)?;
+        reader.read_exact(&mut header.snap_length)?;
+        reader.read_exact(&mut header.link_layer_type)?;
+
+        let (big_endian, nanosecond) = byte_order(header.magic_number)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a pcap file"))?;
+
+        Ok(PcapReader {
+            reader,
+            header,
+            big_endian,
+            nanosecond,
+            block_count: 0,
+            error: None,
+        })
+    }
+
+    pub fn snap_length(&self) -> u32 {
+        self.read_u32(&self.header.snap_length)
+    }
+
+    pub fn link_type(&self) -> u32 {
+        self.read_u32(&self.header.link_layer_type)
+    }
+
+    /// Whether the timestamps of the capture are in nanoseconds rather than microseconds.
+    pub fn nanosecond(&self) -> bool {
+        self.nanosecond
+    }
+
+    /// Why reading stopped before the end of the capture, if it did. A capture that ends in the
+    /// middle of its last record is not an error, as it is usually one that was still being written.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// A capture that ends in the middle of a record is read up to that record, as it is
+    /// usually one that was still being written. Anything else is kept in `error`.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Option<()> {
+        match self.reader.read_exact(buffer) {
+            Ok(()) => Some(()),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::UnexpectedEof {
+                    self.error = Some(error);
+                }
+                None
+            }
+        }
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: &str) -> io::Result<PcapReader<BufReader<File>>> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = PcapBlock;
+
+    fn next(&mut self) -> Option<PcapBlock> {
+        let mut record_header = [0_u8; 16];
+        self.read_exact(&mut record_header)?;
+
+        let timestamp_seconds = self.read_u32(&record_header[0..4]);
+        let timestamp_fraction = self.read_u32(&record_header[4..8]);
+        let captured_length = self.read_u32(&record_header[8..12]);
+        let original_length = self.read_u32(&record_header[12..16]);
+        if captured_length > MAX_RECORD_LENGTH {
+            self.error = Some(too_long(self.block_count + 1, captured_length));
+            return None;
+        }
+
+        let mut pcap_block_data = vec![0_u8; captured_length as usize];
+        self.read_exact(&mut pcap_block_data)?;
+
+        self.block_count += 1;
+        let mut pcap_block = PcapBlock::new();
+        pcap_block.number = self.block_count;
+        pcap_block.timestamp_seconds = timestamp_seconds.to_ne_bytes();
+        if self.nanosecond {
+            pcap_block.timestamp_microseconds = (timestamp_fraction / 1000).to_ne_bytes();
+            pcap_block.timestamp_nanoseconds = Some(timestamp_fraction);
+        } else {
+            pcap_block.timestamp_microseconds = timestamp_fraction.to_ne_bytes();
+        }
+        pcap_block.captured_length = captured_length.to_ne_bytes();
+        pcap_block.original_length = original_length.to_ne_bytes();
+        pcap_block.ether_frame = create_and_return_ether(&pcap_block_data);
+        pcap_block.data = pcap_block_data;
+        Some(pcap_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use crate::gzip::GzipReader;
+    use crate::pcap_writer::tests::{capture, MICROSECOND};
+    use crate::view::Records;
+
+    fn read<R: Read>(mut reader: PcapReader<R>) -> (usize, Option<io::ErrorKind>) {
+        let count = reader.by_ref().count();
+        (count, reader.error().map(|error| error.kind()))
+    }
+
+    fn read_records(capture: &[u8]) -> (usize, Option<io::ErrorKind>) {
+        let mut records = Records::new(capture).unwrap();
+        let count = records.by_ref().count();
+        (count, records.error().map(|error| error.kind()))
+    }
+
+    #[test]
+    fn a_capture_cut_short_in_its_last_record_ends_cleanly() {
+        let whole = capture(MICROSECOND, &[(1, 0), (2, 0), (3, 0)]);
+        for cut in [1, 10, 17] {
+            let cut_short = &whole[..whole.len() - cut];
+            assert_eq!(read(PcapReader::new(cut_short).unwrap()), (2, None));
+            assert_eq!(read_records(cut_short), (2, None));
+        }
+    }
+
+    #[test]
+    fn a_record_too_long_is_damage() {
+        let mut damaged = capture(MICROSECOND, &[(1, 0), (2, 0), (3, 0)]);
+        damaged[24 + 17 + 8..24 + 17 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read(PcapReader::new(&damaged[..]).unwrap()), (1, Some(io::ErrorKind::InvalidData)));
+        assert_eq!(read_records(&damaged), (1, Some(io::ErrorKind::InvalidData)));
+        let mut records = Records::new(&damaged).unwrap();
+        assert_eq!(records.by_ref().count(), 1);
+        assert_eq!(records.next().map(|record| record.number()), None);
+        assert_eq!(records.error().unwrap().to_string(), "record 2 is 4294967295 bytes long, the capture is damaged");
+    }
+
+    #[test]
+    fn a_compressed_stream_cut_short_is_damage() {
+        //Two stored blocks, the first with the first two records and the second cut short
+        let whole = capture(MICROSECOND, &[(1, 0), (2, 0), (3, 0)]);
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        for (last, block) in [(0, &whole[..58]), (1, &whole[58..])] {
+            gzip.push(last);
+            gzip.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            gzip.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            gzip.extend_from_slice(block);
+        }
+        let cut_short = Cursor::new(gzip[..gzip.len() - 10].to_vec());
+        assert_eq!(read(PcapReader::new(GzipReader::new(cut_short)).unwrap()), (2, Some(io::ErrorKind::InvalidData)));
+    }
+}
+
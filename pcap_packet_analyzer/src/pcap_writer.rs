@@ -0,0 +1,236 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use crate::PcapBlock;
+
+/// Writes a classic pcap file, little endian whatever the byte order of the capture the packets
+/// came from, with microsecond timestamps unless made with `with_nanoseconds`.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    nanosecond: bool,
+    pub(crate) packet_count: u64,
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(writer: W, snap_length: u32, link_type: u32) -> io::Result<PcapWriter<W>> {
+        PcapWriter::with_resolution(writer, snap_length, link_type, false)
+    }
+
+    /// Writes a pcap file with nanosecond timestamps, for packets from a capture that had them.
+    /// The timestamps of blocks that only have microseconds are scaled up.
+    pub fn with_nanoseconds(writer: W, snap_length: u32, link_type: u32) -> io::Result<PcapWriter<W>> {
+        PcapWriter::with_resolution(writer, snap_length, link_type, true)
+    }
+
+    pub(crate) fn with_resolution(mut writer: W, snap_length: u32, link_type: u32, nanosecond: bool) -> io::Result<PcapWriter<W>> {
+        let magic = if nanosecond { [0x4d, 0x3c, 0xb2, 0xa1] } else { [0xd4, 0xc3, 0xb2, 0xa1] };
+        writer.write_all(&magic)?;
+        writer.write_all(&2_u16.to_le_bytes())?;
+        writer.write_all(&4_u16.to_le_bytes())?;
+        writer.write_all(&0_i32.to_le_bytes())?; //Time zone, always UTC
+        writer.write_all(&0_u32.to_le_bytes())?; //Timestamp accuracy, unused
+        writer.write_all(&snap_length.to_le_bytes())?;
+        writer.write_all(&link_type.to_le_bytes())?;
+        Ok(PcapWriter { writer, nanosecond, packet_count: 0 })
+    }
+
+    /// Writes one record. `original_length` is the length of the packet on the wire, which is
+    /// more than `data` holds when it was cut short.
+    pub fn write_packet(&mut self, seconds: u32, microseconds: u32, original_length: u32, data: &[u8]) -> io::Result<()> {
+        let fraction = if self.nanosecond { microseconds.saturating_mul(1000) } else { microseconds };
+        self.write_record(seconds, fraction, original_length, data)
+    }
+
+    /// Writes a block as it was read, its timestamp as precise as the file allows.
+    pub fn write_block(&mut self, block: &PcapBlock) -> io::Result<()> {
+        let fraction = if self.nanosecond { block.nanoseconds() } else { u32::from_ne_bytes(block.timestamp_microseconds) };
+        self.write_record(u32::from_ne_bytes(block.timestamp_seconds), fraction, u32::from_ne_bytes(block.original_length),
+                          &block.data)
+    }
+
+    fn write_record(&mut self, seconds: u32, fraction: u32, original_length: u32, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&seconds.to_le_bytes())?;
+        self.writer.write_all(&fraction.to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&original_length.max(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.packet_count += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// What `-w` was asked to do.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WriteOptions {
+    pub(crate) path: String,
+    pub(crate) file_size: Option<u64>, //-C, in bytes
+    pub(crate) seconds: Option<u32>, //-G
+    pub(crate) packets: Option<u64>, //-c of the split command
+    pub(crate) files: Option<u32>, //-W, the number of files kept before the first is written again
+}
+
+impl WriteOptions {
+    pub(crate) fn new(path: String) -> WriteOptions {
+        WriteOptions { path, file_size: None, seconds: None, packets: None, files: None }
+    }
+
+    fn rotates(&self) -> bool {
+        self.file_size.is_some() || self.seconds.is_some() || self.packets.is_some()
+    }
+
+    /// The name of the file with the given index. A rotated capture numbers its files,
+    /// `capture.pcap` becoming `capture-0.pcap`, `capture-1.pcap` and so on.
+    fn file_name(&self, index: u64) -> String {
+        if !self.rotates() {
+            return self.path.clone();
+        }
+        match self.path.rsplit_once('.') {
+            Some((stem, extension)) if !extension.contains('/') => format!("{}-{}.{}", stem, index, extension),
+            _ => format!("{}-{}", self.path, index),
+        }
+    }
+}
+
+/// Writes the packets to a pcap file, starting a new file once the current one reaches a size,
+/// a number of packets or covers a time span, and writing over the oldest file once a number of them were written.
+pub(crate) struct RotatingPcapWriter {
+    options: WriteOptions,
+    snap_length: u32,
+    link_type: u32,
+    nanosecond: bool,
+    writer: Option<PcapWriter<BufWriter<File>>>,
+    file_start: u32, //The timestamp of the first packet of the current file
+    bytes: u64, //Written to the current file
+    file_packets: u64, //Written to the current file
+    pub(crate) file_count: u64, //Files opened so far
+    pub(crate) packet_count: u64,
+}
+
+impl RotatingPcapWriter {
+    pub(crate) fn new(options: &WriteOptions, snap_length: u32, link_type: u32, nanosecond: bool) -> Result<RotatingPcapWriter, String> {
+        let mut writer = RotatingPcapWriter {
+            options: options.clone(),
+            snap_length,
+            link_type,
+            nanosecond,
+            writer: None,
+            file_start: 0,
+            bytes: 0,
+            file_packets: 0,
+            file_count: 0,
+            packet_count: 0,
+        };
+        //Opened now so a path that cannot be written is reported before any packet comes
+        writer.open_next()?;
+        Ok(writer)
+    }
+
+    pub(crate) fn add(&mut self, block: &PcapBlock) -> Result<(), String> {
+        let seconds = u32::from_ne_bytes(block.timestamp_seconds);
+        if self.packet_count == 0 {
+            self.file_start = seconds;
+        }
+        let full = self.options.file_size.is_some_and(|size| self.bytes >= size);
+        let expired = self.options.seconds.is_some_and(|span| seconds >= self.file_start.saturating_add(span));
+        let counted = self.options.packets.is_some_and(|packets| self.file_packets >= packets);
+        if full || expired || counted {
+            self.open_next()?;
+            self.file_start = seconds;
+        }
+        if let Err(error) = self.writer.as_mut().unwrap().write_block(block) {
+            return Err(format!("could not write {}: {}", self.current_name(), error));
+        }
+        self.bytes += 16 + block.data.len() as u64;
+        self.file_packets += 1;
+        self.packet_count += 1;
+        Ok(())
+    }
+
+    /// Finishes the last file.
+    pub(crate) fn finish(&mut self) -> Result<(), String> {
+        let name = self.current_name();
+        match &mut self.writer {
+            Some(writer) => writer.flush().map_err(|error| format!("could not write {}: {}", name, error)),
+            None => Ok(()),
+        }
+    }
+
+    fn current_name(&self) -> String {
+        self.options.file_name(self.index(self.file_count.saturating_sub(1)))
+    }
+
+    fn index(&self, count: u64) -> u64 {
+        match self.options.files {
+            Some(files) => count % files as u64,
+            None => count,
+        }
+    }
+
+    fn open_next(&mut self) -> Result<(), String> {
+        self.finish()?;
+        let name = self.options.file_name(self.index(self.file_count));
+        let file = File::create(&name).map_err(|error| format!("could not create {}: {}", name, error))?;
+        let writer = PcapWriter::with_resolution(BufWriter::new(file), self.snap_length, self.link_type, self.nanosecond)
+            .map_err(|error| format!("could not write {}: {}", name, error))?;
+        self.writer = Some(writer);
+        self.bytes = 24;
+        self.file_packets = 0;
+        self.file_count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::PcapReader;
+
+    pub(crate) const MICROSECOND: [u8; 4] = [0xd4, 0xc3, 0xb2, 0xa1];
+    pub(crate) const NANOSECOND: [u8; 4] = [0x4d, 0x3c, 0xb2, 0xa1];
+
+    /// A little endian Ethernet capture with a record of one byte for every timestamp.
+    pub(crate) fn capture(magic: [u8; 4], timestamps: &[(u32, u32)]) -> Vec<u8> {
+        let mut capture = magic.to_vec();
+        for field in [2_u16.to_le_bytes(), 4_u16.to_le_bytes()] {
+            capture.extend_from_slice(&field);
+        }
+        for field in [0, 0, 65535, 1_u32] {
+            capture.extend_from_slice(&field.to_le_bytes());
+        }
+        for (number, (seconds, fraction)) in timestamps.iter().enumerate() {
+            for field in [*seconds, *fraction, 1, 1] {
+                capture.extend_from_slice(&field.to_le_bytes());
+            }
+            capture.push(number as u8);
+        }
+        capture
+    }
+
+    fn rewrite(input: &[u8], nanosecond: bool) -> Vec<u8> {
+        let mut writer = PcapWriter::with_resolution(Vec::new(), 65535, 1, nanosecond).unwrap();
+        for block in PcapReader::new(input).unwrap() {
+            writer.write_block(&block).unwrap();
+        }
+        writer.writer
+    }
+
+    #[test]
+    fn keeps_the_resolution_of_the_capture() {
+        let nanoseconds = capture(NANOSECOND, &[(10, 10_000_123), (11, 999_999_999)]);
+        assert_eq!(rewrite(&nanoseconds, true), nanoseconds);
+        let microseconds = capture(MICROSECOND, &[(10, 10_000), (11, 999_999)]);
+        assert_eq!(rewrite(&microseconds, false), microseconds);
+    }
+
+    #[test]
+    fn scales_timestamps_to_the_resolution_written() {
+        let microseconds = capture(MICROSECOND, &[(10, 123_456)]);
+        assert_eq!(rewrite(&microseconds, true), capture(NANOSECOND, &[(10, 123_456_000)]));
+        let nanoseconds = capture(NANOSECOND, &[(10, 10_000_123)]);
+        assert_eq!(rewrite(&nanoseconds, false), capture(MICROSECOND, &[(10, 10_000)]));
+    }
+}
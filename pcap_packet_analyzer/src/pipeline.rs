@@ -0,0 +1,417 @@
+use std::io::{self, BufWriter, ErrorKind, StdoutLock, Write};
+use crate::{matches_filter, Filter, PcapBlock};
+use crate::cli::print_pcap;
+use crate::anonymize::Anonymizer;
+use crate::cli_options::{CliOptions, OutputFormat};
+use crate::flow_export::{Flow, FlowExporter, FlowTable};
+use crate::parallel::Merge;
+use crate::pcap_over_ip::PcapServer;
+use crate::pcap_writer::RotatingPcapWriter;
+use crate::rewrite::Rewriter;
+use crate::statistics::Statistics;
+use crate::tcp_analysis::TcpAnalyzer;
+use crate::view::{Packet, Record};
+use crate::zeek::ZeekLogger;
+
+/// Everything a packet goes through after it was read or received: the connection tracking,
+/// the filters and whichever outputs were asked for.
+pub(crate) struct Pipeline<'a> {
+    options: &'a CliOptions,
+    filter: Filter,
+    statistics: Vec<Box<dyn Statistics>>,
+    tcp_analyzer: TcpAnalyzer,
+    flow_exporter: Option<FlowExporter>,
+    zeek_logger: Option<ZeekLogger>,
+    anonymizer: Option<Anonymizer>,
+    rewriter: Option<Rewriter>,
+    server: Option<PcapServer>,
+    pcap_output: Option<RotatingPcapWriter>,
+    print_packets: bool,
+    keeps_packets: bool, //Whether an output prints or keeps the packets, which needs them copied into blocks
+    sharded: bool, //Whether worker threads run the outputs that go by flow, to be merged at the end
+    live: bool,
+    out: BufWriter<StdoutLock<'static>>,
+    closed: bool, //Whoever read the output closed it, so there is no use going on
+    pub(crate) packet_count: u64, //Every packet seen, whether it passed the filters or not
+}
+
+impl<'a> Pipeline<'a> {
+    /// Opens every output. The snap length, link type and timestamp resolution are those of the
+    /// packets that will come, for the outputs that write a new capture.
+    pub(crate) fn new(options: &'a CliOptions, snap_length: u32, link_type: u32, nanosecond: bool) -> Result<Pipeline<'a>, String> {
+        let statistics: Vec<Box<dyn Statistics>> = options.statistics.iter().map(|kind| kind.create(options)).collect();
+        let flow_exporter = options.flow_export.as_ref().map(FlowExporter::new).transpose()?;
+        let zeek_logger = options.zeek.as_ref().map(ZeekLogger::new).transpose()?;
+        let anonymizer = options.anonymize.as_ref()
+            .map(|anonymize| Anonymizer::new(anonymize, snap_length, link_type))
+            .transpose()?;
+        let rewriter = options.rewrite.as_ref()
+            .map(|rewrite| Rewriter::new(rewrite, snap_length, link_type))
+            .transpose()?;
+        //Packets from a file wait for a client, live ones are not held back for anybody
+        let live = options.file_name.is_none();
+        let server = options.serve.as_ref()
+            .map(|address| PcapServer::new(address, snap_length, link_type, live))
+            .transpose()?;
+        let pcap_output = options.write.as_ref()
+            .map(|write| RotatingPcapWriter::new(write, snap_length, link_type, nanosecond))
+            .transpose()?;
+        //Every other output replaces the packets
+        let print_packets = statistics.is_empty() && flow_exporter.is_none() && zeek_logger.is_none() && anonymizer.is_none()
+            && rewriter.is_none() && server.is_none() && pcap_output.is_none();
+        let keeps_packets = print_packets || anonymizer.is_some() || rewriter.is_some() || server.is_some() || pcap_output.is_some();
+
+        let mut pipeline = Pipeline {
+            options,
+            filter: options.filter.clone(),
+            statistics,
+            tcp_analyzer: if live { TcpAnalyzer::retiring() } else { TcpAnalyzer::new() },
+            flow_exporter,
+            zeek_logger,
+            anonymizer,
+            rewriter,
+            server,
+            pcap_output,
+            print_packets,
+            keeps_packets,
+            sharded: false,
+            live,
+            out: BufWriter::new(io::stdout().lock()),
+            closed: false,
+            packet_count: 0,
+        };
+        if let Some(exporter) = &options.field_exporter {
+            let result = writeln!(pipeline.out, "{}", exporter.header());
+            pipeline.printed(result, "the packets")?;
+        }
+        Ok(pipeline)
+    }
+
+    pub(crate) fn process(&mut self, mut block: PcapBlock) -> Result<(), String> {
+        //Connections are tracked over every packet so stream numbers do not depend on the filters
+        self.tcp_analyzer.annotate(&mut block);
+        let packet = block.view();
+        if self.passes(&packet, None) {
+            self.output(&packet, Some(&block))?;
+        }
+        Ok(())
+    }
+
+    /// Goes on with a record of a capture file, read in place. It is only copied into a block
+    /// when it passed the filters and goes to an output that prints or keeps it.
+    pub(crate) fn process_record(&mut self, record: Record) -> Result<(), String> {
+        //Connections are tracked over every packet so stream numbers do not depend on the filters
+        let analysis = self.tcp_analyzer.analyze_frame(record.data(), record.timestamp());
+        let packet = Packet::new(record, analysis.as_ref());
+        if self.passes(&packet, None) {
+            self.output(&packet, None)?;
+        }
+        Ok(())
+    }
+
+    //Counts the packet and tells whether it passes the filters
+    fn passes(&mut self, packet: &Packet, displayed: Option<bool>) -> bool {
+        let captured = matches_filter(packet.data(), &self.filter);
+        if let Filter::Count(count) = &mut self.filter {
+            *count -= 1;
+        }
+        self.packet_count += 1;
+        captured && displayed.unwrap_or_else(|| {
+            self.options.display_filter.as_ref().is_none_or(|display_filter| display_filter.matches_packet(packet))
+        })
+    }
+
+    //The block is made from the packet when an output needs one and none was given
+    fn output(&mut self, packet: &Packet, block: Option<&PcapBlock>) -> Result<(), String> {
+        if let Some(exporter) = &mut self.flow_exporter {
+            if self.sharded {
+                exporter.note(packet.microseconds());
+            } else {
+                exporter.add(packet)?;
+            }
+        }
+        if let Some(logger) = self.zeek_logger.as_mut().filter(|_| !self.sharded) {
+            logger.add(packet);
+        }
+        for (report, kind) in self.statistics.iter_mut().zip(&self.options.statistics) {
+            if !(self.sharded && kind.per_flow()) {
+                report.add(packet);
+            }
+        }
+        if !self.keeps_packets {
+            return Ok(());
+        }
+        let copy;
+        let block = match block {
+            Some(block) => block,
+            None => {
+                copy = packet.to_block();
+                &copy
+            }
+        };
+        if let Some(anonymizer) = &mut self.anonymizer {
+            anonymizer.add(block)?;
+        }
+        if let Some(rewriter) = &mut self.rewriter {
+            rewriter.add(block)?;
+        }
+        if let Some(server) = &mut self.server {
+            server.add(block);
+        }
+        if let Some(output) = &mut self.pcap_output {
+            output.add(block)?;
+        }
+        if self.print_packets {
+            let mut result = print_pcap(&mut self.out, block, self.options);
+            //Live packets are shown as they come, not when a buffer is full
+            if self.live {
+                result = result.and_then(|_| self.out.flush());
+            }
+            self.printed(result, "the packets")?;
+        }
+        Ok(())
+    }
+
+    /// Whether no more packets are wanted: `-c` was given and that many were seen, or whoever
+    /// read the output closed it.
+    pub(crate) fn done(&self) -> bool {
+        self.closed || matches!(self.filter, Filter::Count(count) if count <= 0)
+    }
+
+    //A closed output is how `| head` says it has seen enough, not an error
+    fn printed(&mut self, result: io::Result<()>, what: &str) -> Result<(), String> {
+        match result {
+            Err(error) if error.kind() == ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(())
+            }
+            result => result.map_err(|error| format!("could not print {}: {}", what, error)),
+        }
+    }
+
+    /// Prints the reports, finishes the files that were written and closes with the line
+    /// `total` gives for the number of packets seen, when the output is text.
+    pub(crate) fn finish(&mut self, total: impl FnOnce(u64) -> String) -> Result<(), String> {
+        let options = self.options;
+        for report in &self.statistics {
+            if self.closed {
+                break;
+            }
+            match report.write_report(&mut self.out) {
+                Err(error) if error.kind() == ErrorKind::BrokenPipe => self.closed = true,
+                result => result.map_err(|error| format!("could not print the report: {}", error))?,
+            }
+        }
+        //The lines are gathered first, the outputs they are about being fields of the pipeline too
+        let mut lines = Vec::new();
+        if let Some(exporter) = &mut self.flow_exporter {
+            let (records, datagrams) = exporter.finish()?;
+            lines.push(format!("Exported {} flow records in {} datagrams", records, datagrams));
+        }
+        if let (Some(anonymizer), Some(anonymize)) = (&mut self.anonymizer, &options.anonymize) {
+            lines.push(format!("Wrote {} anonymized packets to {}", anonymizer.finish()?, anonymize.output));
+            if anonymizer.zeroed > 0 {
+                lines.push(format!("{} of them could not be parsed and were zeroed after the Ethernet header", anonymizer.zeroed));
+            }
+        }
+        if let (Some(rewriter), Some(rewrite)) = (&mut self.rewriter, &options.rewrite) {
+            let written = rewriter.finish()?;
+            lines.push(format!("Wrote {} packets to {}, {} of them rewritten", written, rewrite.output, rewriter.rewritten));
+        }
+        if let (Some(output), Some(write)) = (&mut self.pcap_output, &options.write) {
+            output.finish()?;
+            lines.push(match output.file_count {
+                1 => format!("Wrote {} packets to {}", output.packet_count, write.path),
+                files => format!("Wrote {} packets to {} files", output.packet_count, files),
+            });
+        }
+        if let Some(server) = &mut self.server {
+            let clients = server.finish();
+            lines.push(format!("Streamed {} packets to {} clients", server.packet_count, clients));
+            if server.dropped > 0 {
+                lines.push(format!("Dropped {} packets for clients that fell behind", server.dropped));
+            }
+        }
+        if let (Some(logger), Some(zeek)) = (&self.zeek_logger, &options.zeek) {
+            for (path, entries) in logger.finish()? {
+                lines.push(format!("Wrote {} entries to {}/{}.log", entries, zeek.directory, path));
+            }
+        }
+        if self.text_output() {
+            lines.push(total(self.packet_count));
+        }
+        for line in lines {
+            if self.closed {
+                break;
+            }
+            let result = writeln!(self.out, "{}", line);
+            self.printed(result, "the summary")?;
+        }
+        if !self.closed {
+            let result = self.out.flush();
+            self.printed(result, "the summary")?;
+        }
+        Ok(())
+    }
+
+    /// Whether a closing line about the packets may be printed. The JSON and field output is
+    /// meant for other programs, so it holds nothing but packets.
+    fn text_output(&self) -> bool {
+        self.options.output_format == OutputFormat::Text && !self.options.statistics.iter().any(|kind| kind.machine_readable())
+    }
+}
+
+impl Merge for Pipeline<'_> {
+    /// The outputs that go by flow run in the workers, unless `-c` was given: whether a packet is
+    /// among the first ones depends on every packet before it.
+    fn shard(&mut self) -> Option<Shard> {
+        let statistics: Vec<Box<dyn Statistics>> = self.options.statistics.iter()
+            .filter(|kind| kind.per_flow())
+            .map(|kind| kind.create(self.options))
+            .collect();
+        if matches!(self.filter, Filter::Count(_)) || (statistics.is_empty() && self.flow_exporter.is_none() && self.zeek_logger.is_none()) {
+            return None;
+        }
+        self.sharded = true;
+        Some(Shard {
+            filter: self.filter.clone(),
+            statistics,
+            flow_table: self.options.flow_export.as_ref().map(FlowTable::new),
+            zeek_logger: self.zeek_logger.as_ref().map(ZeekLogger::shard),
+        })
+    }
+
+    fn packet(&mut self, packet: &Packet, displayed: Option<bool>) -> Result<bool, String> {
+        if self.passes(packet, displayed) {
+            self.output(packet, None)?;
+        }
+        Ok(!self.done())
+    }
+
+    fn flows(&mut self, flows: Vec<Flow>) -> Result<(), String> {
+        match &mut self.flow_exporter {
+            Some(exporter) => exporter.export(flows),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes in what a worker thread's shard made of the flows it owns.
+    fn merge(&mut self, shard: Shard) -> Result<(), String> {
+        let reports = self.statistics.iter_mut().zip(&self.options.statistics).filter(|(_, kind)| kind.per_flow());
+        for ((report, _), other) in reports.zip(shard.statistics) {
+            report.merge(other);
+        }
+        if let (Some(exporter), Some(table)) = (&mut self.flow_exporter, shard.flow_table) {
+            exporter.merge(table)?;
+        }
+        if let (Some(logger), Some(other)) = (&mut self.zeek_logger, shard.zeek_logger) {
+            logger.merge(other);
+        }
+        Ok(())
+    }
+}
+
+/// The outputs a worker thread of `--threads` runs on the packets of the flows it owns: the
+/// reports that go by flow, the flow table of the flow export and the Zeek logs.
+pub(crate) struct Shard {
+    filter: Filter,
+    statistics: Vec<Box<dyn Statistics>>,
+    flow_table: Option<FlowTable>,
+    zeek_logger: Option<ZeekLogger>,
+}
+
+impl Shard {
+    /// Adds a packet if it passes the filters. `displayed` is whether it matches the display
+    /// filter, when there is one.
+    pub(crate) fn add(&mut self, packet: &Packet, displayed: Option<bool>) {
+        if displayed == Some(false) || !matches_filter(packet.data(), &self.filter) {
+            return;
+        }
+        if let Some(table) = &mut self.flow_table {
+            table.expire(packet.microseconds());
+            table.add(packet);
+        }
+        if let Some(logger) = &mut self.zeek_logger {
+            logger.add(packet);
+        }
+        self.statistics.iter_mut().for_each(|report| report.add(packet));
+    }
+
+    /// Takes the flows that timed out or ended since the last time, for the merge to export.
+    pub(crate) fn take_flows(&mut self) -> Vec<Flow> {
+        self.flow_table.as_mut().map(FlowTable::take_finished).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::parallel;
+    use crate::flow_export::{FlowDestination, FlowExportOptions, FlowFormat};
+    use crate::statistics::StatisticsKind;
+    use crate::view::Records;
+    use crate::zeek::ZeekOptions;
+
+    /// The reports, the Zeek logs and the sorted flow records of a capture read with `threads`.
+    fn outputs(capture: &[u8], threads: usize) -> (Vec<String>, Vec<String>, Vec<Vec<u8>>) {
+        let directory = std::env::temp_dir().join(format!("packet_analyzer-{}-threads-{}", std::process::id(), threads));
+        let flows = directory.join("flows");
+        fs::create_dir_all(&directory).unwrap();
+        let mut options = CliOptions::new();
+        options.threads = threads;
+        options.statistics = ["proto", "conv,ip", "endpoints,tcp", "tcp", "io,5s"].into_iter()
+            .map(|name| StatisticsKind::from_str(name).unwrap())
+            .collect();
+        options.zeek = Some(ZeekOptions::new(directory.display().to_string()));
+        let mut flow_export = FlowExportOptions::new(FlowFormat::NetflowV5, FlowDestination::File(flows.display().to_string()));
+        (flow_export.active_timeout, flow_export.inactive_timeout) = (20, 2);
+        options.flow_export = Some(flow_export);
+
+        let mut records = Records::new(capture).unwrap();
+        let mut pipeline = Pipeline::new(&options, 65535, 1, false).unwrap();
+        if threads > 1 {
+            parallel::process(&mut records, &options, &mut pipeline).unwrap();
+        } else {
+            for record in &mut records {
+                pipeline.process_record(record).unwrap();
+            }
+        }
+        let reports = pipeline.statistics.iter().map(|report| report.report()).collect();
+        pipeline.zeek_logger.as_ref().unwrap().finish().unwrap();
+        pipeline.flow_exporter.as_mut().unwrap().finish().unwrap();
+        let logs = ["conn", "dns", "http"].iter()
+            .map(|log| fs::read_to_string(directory.join(format!("{}.log", log))).unwrap())
+            .collect();
+        //The records of the version 5 datagrams, which may be packed otherwise with more threads
+        let datagrams = fs::read(&flows).unwrap();
+        let mut records = Vec::new();
+        let mut position = 0;
+        while position < datagrams.len() {
+            let count = u16::from_be_bytes([datagrams[position + 2], datagrams[position + 3]]) as usize;
+            records.extend(datagrams[position + 24..position + 24 + count * 48].chunks(48).map(<[u8]>::to_vec));
+            position += 24 + count * 48;
+        }
+        records.sort();
+        fs::remove_dir_all(directory).unwrap();
+        (reports, logs, records)
+    }
+
+    #[test]
+    fn merges_what_the_workers_made_into_what_one_thread_makes() {
+        let capture = parallel::tests::capture();
+        let (reports, logs, flows) = outputs(&capture, 1);
+        assert!(logs.iter().all(|log| log.lines().count() > 100));
+        assert!(flows.len() > 500);
+        for threads in [2, 5] {
+            let (merged_reports, merged_logs, merged_flows) = outputs(&capture, threads);
+            for (merged, report) in merged_reports.iter().zip(&reports) {
+                assert_eq!(merged, report, "{} threads", threads);
+            }
+            for (merged, log) in merged_logs.iter().zip(&logs) {
+                assert_eq!(merged, log, "{} threads", threads);
+            }
+            assert!(merged_flows == flows, "{} threads", threads);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::Write;
+use crate::statistics::{same_kind, Statistics};
+use crate::view::{Packet, Transport};
+
+/// The application protocol usually found on a port. The lower of the two ports is tried
+/// first since that is normally the server side.
+pub(crate) fn application_protocol(transport: &str, source_port: u16, destination_port: u16) -> Option<&'static str> {
+    let lookup = |port: u16| match (transport, port) {
+        (_, 53) => Some("dns"),
+        ("udp", 67) | ("udp", 68) => Some("dhcp"),
+        ("udp", 123) => Some("ntp"),
+        ("udp", 161) | ("udp", 162) => Some("snmp"),
+        ("udp", 514) => Some("syslog"),
+        ("udp", 5353) => Some("mdns"),
+        ("tcp", 20) | ("tcp", 21) => Some("ftp"),
+        ("tcp", 22) => Some("ssh"),
+        ("tcp", 23) => Some("telnet"),
+        ("tcp", 25) | ("tcp", 587) => Some("smtp"),
+        ("tcp", 80) | ("tcp", 8080) => Some("http"),
+        ("tcp", 110) => Some("pop"),
+        ("tcp", 143) => Some("imap"),
+        ("tcp", 443) | ("tcp", 8443) => Some("tls"),
+        ("tcp", 3306) => Some("mysql"),
+        ("tcp", 5432) => Some("pgsql"),
+        _ => None,
+    };
+    let (low, high) = if source_port <= destination_port {
+        (source_port, destination_port)
+    } else {
+        (destination_port, source_port)
+    };
+    lookup(low).or_else(|| lookup(high))
+}
+
+/// The names of the layers a packet was decoded into, outermost first.
+pub(crate) fn protocol_path(packet: &Packet) -> Vec<&'static str> {
+    let mut path = vec!["eth"];
+    let Some(frame) = packet.frame() else { return path };
+    match frame.ether_type() {
+        0x0800 if frame.ipv4().is_some() => path.push("ipv4"),
+        0x86dd => {
+            path.push("ipv6");
+            return path;
+        }
+        0x0806 => {
+            path.push("arp");
+            return path;
+        }
+        _ => {
+            path.push("other");
+            return path;
+        }
+    }
+
+    let (transport, source_port, destination_port, payload_length) = match packet.transport() {
+        Transport::Tcp(tcp) => ("tcp", tcp.source_port(), tcp.destination_port(), tcp.payload().len()),
+        Transport::Udp(udp) => ("udp", udp.source_port(), udp.destination_port(), udp.payload().len()),
+        Transport::Icmp(_) => {
+            path.push("icmp");
+            return path;
+        }
+        Transport::Other => {
+            path.push("other");
+            return path;
+        }
+    };
+    path.push(transport);
+    //Segments without payload, such as bare ACKs, stop at the transport layer as in Wireshark
+    if payload_length > 0 {
+        path.push(application_protocol(transport, source_port, destination_port).unwrap_or("data"));
+    }
+    path
+}
+
+struct ProtocolNode {
+    name: &'static str,
+    packets: u64,
+    bytes: u64,
+    children: Vec<ProtocolNode>,
+}
+
+impl ProtocolNode {
+    fn new(name: &'static str) -> ProtocolNode {
+        ProtocolNode {
+            name,
+            packets: 0,
+            bytes: 0,
+            children: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, path: &[&'static str], bytes: u64) {
+        let Some((first, rest)) = path.split_first() else { return };
+        let position = match self.children.iter().position(|child| child.name == *first) {
+            Some(position) => position,
+            None => {
+                self.children.push(ProtocolNode::new(first));
+                self.children.len() - 1
+            }
+        };
+        let child = &mut self.children[position];
+        child.packets += 1;
+        child.bytes += bytes;
+        child.add(rest, bytes);
+    }
+
+    fn merge(&mut self, other: ProtocolNode) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+        for other_child in other.children {
+            match self.children.iter_mut().find(|child| child.name == other_child.name) {
+                Some(child) => child.merge(other_child),
+                None => self.children.push(other_child),
+            }
+        }
+    }
+}
+
+/// Counts the packets and bytes seen at every layer of the capture, like
+/// Wireshark's Protocol Hierarchy window. Bytes are whole frame bytes at every level.
+pub(crate) struct ProtocolHierarchy {
+    root: ProtocolNode,
+}
+
+impl ProtocolHierarchy {
+    pub(crate) fn new() -> ProtocolHierarchy {
+        ProtocolHierarchy {
+            root: ProtocolNode::new("frame"),
+        }
+    }
+
+    fn write_node(&self, out: &mut String, node: &ProtocolNode, depth: usize) {
+        let percent = |part: u64, total: u64| if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 };
+        let label = format!("{}{}", "  ".repeat(depth), node.name);
+        writeln!(out, "{:<24} {:>10} {:>12} {:>9.2}% {:>9.2}%", label, node.packets, node.bytes,
+                 percent(node.packets, self.root.packets), percent(node.bytes, self.root.bytes)).unwrap();
+        let mut children: Vec<&ProtocolNode> = node.children.iter().collect();
+        children.sort_by(|a, b| b.packets.cmp(&a.packets).then(a.name.cmp(b.name)));
+        for child in children {
+            self.write_node(out, child, depth + 1);
+        }
+    }
+}
+
+impl Statistics for ProtocolHierarchy {
+    fn add(&mut self, packet: &Packet) {
+        let bytes = packet.data().len() as u64;
+        self.root.packets += 1;
+        self.root.bytes += bytes;
+        self.root.add(&protocol_path(packet), bytes);
+    }
+
+    fn merge(&mut self, other: Box<dyn Statistics>) {
+        self.root.merge(same_kind::<ProtocolHierarchy>(other).root);
+    }
+
+    fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Protocol Hierarchy Statistics").unwrap();
+        writeln!(out, "{:<24} {:>10} {:>12} {:>10} {:>10}", "Protocol", "Packets", "Bytes", "% Packets", "% Bytes").unwrap();
+        self.write_node(&mut out, &self.root, 0);
+        out
+    }
+}
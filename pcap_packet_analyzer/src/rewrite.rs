@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::Ipv4Addr;
+use crate::{DisplayFilter, PcapBlock};
+use crate::frame_layout::{recompute_checksums, FrameLayout};
+use crate::pcap_writer::PcapWriter;
+
+/// Maps the addresses of one IPv4 network onto another, keeping the host part, like the
+/// pseudo NAT of tcprewrite. A /32 maps a single address.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Ipv4Map {
+    from: u32,
+    to: u32,
+    mask: u32,
+}
+
+impl Ipv4Map {
+    /// Parses `<network>[/<prefix>]:<network>[/<prefix>]`, where the prefix of the first side is
+    /// used for the second when it has none.
+    fn from_str(text: &str) -> Result<Ipv4Map, String> {
+        let (from, to) = text.split_once(':').ok_or_else(|| format!("'{}' is not a mapping, expected <from>:<to>", text))?;
+        let (from, from_prefix) = parse_network(from)?;
+        let (to, to_prefix) = parse_network(to)?;
+        let prefix = match (from_prefix, to_prefix) {
+            (Some(from), Some(to)) if from != to => {
+                return Err(format!("'{}' maps networks of different sizes", text));
+            }
+            (prefix, other) => prefix.or(other).unwrap_or(32),
+        };
+        let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+        Ok(Ipv4Map { from: from & mask, to: to & mask, mask })
+    }
+
+    fn apply(&self, address: [u8; 4]) -> Option<[u8; 4]> {
+        let address = u32::from_be_bytes(address);
+        (address & self.mask == self.from).then(|| (self.to | (address & !self.mask)).to_be_bytes())
+    }
+}
+
+fn parse_network(text: &str) -> Result<(u32, Option<u32>), String> {
+    let (address, prefix) = match text.split_once('/') {
+        Some((address, prefix)) => {
+            let prefix = prefix.parse::<u32>().ok().filter(|prefix| *prefix <= 32)
+                .ok_or_else(|| format!("'{}' is not a prefix length", prefix))?;
+            (address, Some(prefix))
+        }
+        None => (text, None),
+    };
+    let address: Ipv4Addr = address.parse().map_err(|_| format!("'{}' is not an IPv4 address", address))?;
+    Ok((u32::from(address), prefix))
+}
+
+//Six bytes of one or two hexadecimal digits each, separated by colons
+fn parse_mac(text: &str) -> Result<[u8; 6], String> {
+    let error = || format!("'{}' is not a MAC address", text);
+    let bytes = text.split(':')
+        .map(|part| match part.len() {
+            1 | 2 if part.bytes().all(|digit| digit.is_ascii_hexdigit()) => u8::from_str_radix(part, 16).map_err(|_| error()),
+            _ => Err(error()),
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    bytes.try_into().map_err(|_| error())
+}
+
+fn parse_port_map(text: &str) -> Result<(u16, u16), String> {
+    let error = || format!("'{}' is not a port mapping, expected <from>:<to>", text);
+    let (from, to) = text.split_once(':').ok_or_else(error)?;
+    Ok((from.parse().map_err(|_| error())?, to.parse().map_err(|_| error())?))
+}
+
+/// How a rule changes the TTL or hop limit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum TtlChange {
+    Set(u8),
+    Add(u8),
+    Subtract(u8),
+}
+
+/// One change a rule makes to a packet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum RewriteAction {
+    SourceIp(Ipv4Map),
+    DestinationIp(Ipv4Map),
+    Ip(Ipv4Map), //Either address
+    SourcePort(u16, u16),
+    DestinationPort(u16, u16),
+    Port(u16, u16),
+    SourceMac([u8; 6]),
+    DestinationMac([u8; 6]),
+    Ttl(TtlChange),
+    Dscp(u8),
+    Vlan(u16, u8), //The VLAN id and the priority of the tag to add or change
+    StripVlan,
+}
+
+/// A change and the packets it applies to, written `<name>=<value>[ if <display filter>]`.
+#[derive(Clone, Debug)]
+pub(crate) struct RewriteRule {
+    pub(crate) action: RewriteAction,
+    pub(crate) condition: Option<DisplayFilter>,
+}
+
+impl RewriteRule {
+    pub(crate) fn from_str(text: &str) -> Result<RewriteRule, String> {
+        let (change, condition) = match text.split_once(" if ") {
+            Some((change, condition)) => (change.trim(), Some(DisplayFilter::parse(condition)
+                .map_err(|error| format!("Invalid display filter in rule '{}': {}", text, error))?)),
+            None => (text.trim(), None),
+        };
+        let (name, value) = change.split_once('=').ok_or_else(|| format!("'{}' is not a rule, expected <name>=<value>", change))?;
+        let action = match name {
+            "srcip" => RewriteAction::SourceIp(Ipv4Map::from_str(value)?),
+            "dstip" => RewriteAction::DestinationIp(Ipv4Map::from_str(value)?),
+            "ip" => RewriteAction::Ip(Ipv4Map::from_str(value)?),
+            "srcport" => {
+                let (from, to) = parse_port_map(value)?;
+                RewriteAction::SourcePort(from, to)
+            }
+            "dstport" => {
+                let (from, to) = parse_port_map(value)?;
+                RewriteAction::DestinationPort(from, to)
+            }
+            "port" => {
+                let (from, to) = parse_port_map(value)?;
+                RewriteAction::Port(from, to)
+            }
+            "srcmac" => RewriteAction::SourceMac(parse_mac(value)?),
+            "dstmac" => RewriteAction::DestinationMac(parse_mac(value)?),
+            "ttl" => {
+                let error = || format!("'{}' is not a TTL, expected <n>, +<n> or -<n>", value);
+                RewriteAction::Ttl(match value.as_bytes().first() {
+                    Some(b'+') => TtlChange::Add(value[1..].parse().map_err(|_| error())?),
+                    Some(b'-') => TtlChange::Subtract(value[1..].parse().map_err(|_| error())?),
+                    _ => TtlChange::Set(value.parse().map_err(|_| error())?),
+                })
+            }
+            "dscp" => RewriteAction::Dscp(value.parse().ok().filter(|dscp| *dscp < 64)
+                .ok_or_else(|| format!("'{}' is not a DSCP value from 0 to 63", value))?),
+            "vlan" if value == "strip" => RewriteAction::StripVlan,
+            "vlan" => {
+                let (id, priority) = value.split_once(',').unwrap_or((value, "0"));
+                let id = id.parse().ok().filter(|id| *id < 4096).ok_or_else(|| format!("'{}' is not a VLAN id", id))?;
+                let priority = priority.parse().ok().filter(|priority| *priority < 8)
+                    .ok_or_else(|| format!("'{}' is not a VLAN priority from 0 to 7", priority))?;
+                RewriteAction::Vlan(id, priority)
+            }
+            _ => return Err(format!("unknown rule '{}', expected srcip, dstip, ip, srcport, dstport, port, srcmac, dstmac, ttl, dscp or vlan", name)),
+        };
+        Ok(RewriteRule { action, condition })
+    }
+}
+
+/// Where `--rewrite` writes and the rules it applies, in the order given.
+#[derive(Clone, Debug)]
+pub(crate) struct RewriteOptions {
+    pub(crate) output: String,
+    pub(crate) rules: Vec<RewriteRule>,
+}
+
+impl RewriteOptions {
+    pub(crate) fn new(output: String) -> RewriteOptions {
+        RewriteOptions {
+            output,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Applies the rules to every packet and writes the result to a new capture, with the
+/// checksums computed again.
+pub(crate) struct Rewriter {
+    options: RewriteOptions,
+    writer: PcapWriter<BufWriter<File>>,
+    pub(crate) rewritten: u64, //Packets at least one rule applied to
+}
+
+impl Rewriter {
+    pub(crate) fn new(options: &RewriteOptions, snap_length: u32, link_type: u32) -> Result<Rewriter, String> {
+        if link_type != 1 {
+            return Err(format!("only Ethernet captures can be rewritten, this one has link type {}", link_type));
+        }
+        let file = File::create(&options.output).map_err(|error| format!("could not create {}: {}", options.output, error))?;
+        //A tag added to a packet of the full snap length makes it four bytes longer
+        let writer = PcapWriter::new(BufWriter::new(file), snap_length.saturating_add(4), link_type)
+            .map_err(|error| format!("could not write {}: {}", options.output, error))?;
+        Ok(Rewriter { options: options.clone(), writer, rewritten: 0 })
+    }
+
+    pub(crate) fn add(&mut self, block: &PcapBlock) -> Result<(), String> {
+        //The conditions are all decided on the packet as it was read, so one rule cannot hide another
+        let actions: Vec<RewriteAction> = self.options.rules.iter()
+            .filter(|rule| rule.condition.as_ref().is_none_or(|condition| condition.matches(block)))
+            .map(|rule| rule.action)
+            .collect();
+        let mut data = block.data.clone();
+        let mut original_length = u32::from_ne_bytes(block.original_length);
+        for action in &actions {
+            let before = data.len();
+            apply(action, &mut data);
+            original_length = (original_length as i64 + data.len() as i64 - before as i64) as u32;
+        }
+        if data != block.data {
+            recompute_checksums(&mut data);
+            self.rewritten += 1;
+        }
+        self.writer.write_packet(u32::from_ne_bytes(block.timestamp_seconds), u32::from_ne_bytes(block.timestamp_microseconds),
+                                 original_length, &data)
+            .map_err(|error| format!("could not write {}: {}", self.options.output, error))
+    }
+
+    /// Finishes the file and returns the number of packets written.
+    pub(crate) fn finish(&mut self) -> Result<u64, String> {
+        self.writer.flush().map_err(|error| format!("could not write {}: {}", self.options.output, error))?;
+        Ok(self.writer.packet_count)
+    }
+}
+
+fn apply(action: &RewriteAction, data: &mut Vec<u8>) {
+    let layout = FrameLayout::parse(data);
+    let network = layout.network;
+    let ip = layout.ip;
+    //ARP for IPv4 over Ethernet has the sender address at 14 and the target address at 24
+    let arp = layout.ether_type == 0x0806 && data.len() >= network + 28 && data[network + 4] == 6 && data[network + 5] == 4;
+    let ipv4 = ip.filter(|ip| ip.version == 4);
+    let addresses = match (ipv4, arp) {
+        (Some(ip), _) => Some((ip.start + 12, ip.start + 16)),
+        (None, true) => Some((network + 14, network + 24)),
+        _ => None,
+    };
+    //Ports are only in the first fragment, and only TCP and UDP have them
+    let ports = ip.filter(|ip| matches!(ip.protocol, 6 | 17) && !ip.fragment && data.len() >= ip.transport + 4)
+        .map(|ip| (ip.transport, ip.transport + 2));
+
+    let map_address = |data: &mut Vec<u8>, offset: usize, map: &Ipv4Map| {
+        if let Some(mapped) = map.apply(data[offset..offset + 4].try_into().unwrap()) {
+            data[offset..offset + 4].copy_from_slice(&mapped);
+        }
+    };
+    let map_port = |data: &mut Vec<u8>, offset: usize, from: u16, to: u16| {
+        if data[offset..offset + 2] == from.to_be_bytes() {
+            data[offset..offset + 2].copy_from_slice(&to.to_be_bytes());
+        }
+    };
+    match *action {
+        RewriteAction::SourceIp(map) => if let Some((source, _)) = addresses { map_address(data, source, &map) },
+        RewriteAction::DestinationIp(map) => if let Some((_, destination)) = addresses { map_address(data, destination, &map) },
+        RewriteAction::Ip(map) => if let Some((source, destination)) = addresses {
+            map_address(data, source, &map);
+            map_address(data, destination, &map);
+        },
+        RewriteAction::SourcePort(from, to) => if let Some((source, _)) = ports { map_port(data, source, from, to) },
+        RewriteAction::DestinationPort(from, to) => if let Some((_, destination)) = ports { map_port(data, destination, from, to) },
+        RewriteAction::Port(from, to) => if let Some((source, destination)) = ports {
+            map_port(data, source, from, to);
+            map_port(data, destination, from, to);
+        },
+        RewriteAction::SourceMac(mac) if data.len() >= 12 => data[6..12].copy_from_slice(&mac),
+        RewriteAction::DestinationMac(mac) if data.len() >= 6 => data[0..6].copy_from_slice(&mac),
+        RewriteAction::Ttl(change) => if let Some(ip) = ip {
+            //The hop limit of IPv6 is where the TTL would be in IPv4
+            let offset = if ip.version == 4 { ip.start + 8 } else { ip.start + 7 };
+            data[offset] = match change {
+                TtlChange::Set(ttl) => ttl,
+                TtlChange::Add(add) => data[offset].saturating_add(add),
+                TtlChange::Subtract(subtract) => data[offset].saturating_sub(subtract),
+            };
+        },
+        RewriteAction::Dscp(dscp) => if let Some(ip) = ip {
+            //The ECN bits stay as they were
+            if ip.version == 4 {
+                data[ip.start + 1] = dscp << 2 | (data[ip.start + 1] & 0x03);
+            } else {
+                let traffic_class = ((data[ip.start] & 0x0f) << 4 | data[ip.start + 1] >> 4) & 0x03 | dscp << 2;
+                data[ip.start] = (data[ip.start] & 0xf0) | traffic_class >> 4;
+                data[ip.start + 1] = (data[ip.start + 1] & 0x0f) | traffic_class << 4;
+            }
+        },
+        RewriteAction::Vlan(id, priority) if data.len() >= 14 => {
+            let tag = ((priority as u16) << 13 | id).to_be_bytes();
+            if data[12..14] == [0x81, 0x00] && data.len() >= 18 {
+                //Already tagged, the tag is changed and keeps its drop eligible bit
+                data[14] = tag[0] | (data[14] & 0x10);
+                data[15] = tag[1];
+            } else {
+                data.splice(12..12, [0x81, 0x00, tag[0], tag[1]]);
+            }
+        }
+        RewriteAction::StripVlan if data.len() >= 18 && data[12..14] == [0x81, 0x00] => {
+            data.drain(12..16);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_only_whole_mac_addresses() {
+        assert_eq!(parse_mac("00:1a:2B:3c:4d:5e"), Ok([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]));
+        assert_eq!(parse_mac("0:1:2:a:b:c"), Ok([0, 1, 2, 0xa, 0xb, 0xc]));
+        for text in ["00:11:22:33:44", "00:11:22:33:44:55:66", "00:11:zz:33:44:55:66", "00:11:22:33:44:zz", "00::22:33:44:55",
+                     "000:11:22:33:44:55", "+0:11:22:33:44:55", "00-11-22-33-44-55", ""] {
+            assert_eq!(parse_mac(text), Err(format!("'{}' is not a MAC address", text)));
+        }
+    }
+}
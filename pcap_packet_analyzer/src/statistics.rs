@@ -0,0 +1,93 @@
+use std::any::Any;
+use std::io::{self, Write};
+use crate::alerts::Alerts;
+use crate::cli_options::CliOptions;
+use crate::conversations::{ConversationLevel, Conversations, Endpoints};
+use crate::io_graph::{parse_interval, IoGraph, IoGraphFormat};
+use crate::protocol_hierarchy::ProtocolHierarchy;
+use crate::tcp_analysis::TcpAnalysis;
+use crate::view::Packet;
+
+/// A report built from every packet that passes the filters and printed once the whole
+/// capture has been read.
+pub(crate) trait Statistics: Any + Send {
+    fn add(&mut self, packet: &Packet);
+    fn report(&self) -> String;
+
+    /// Prints the report, which long reports do as they go rather than build it all first.
+    fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", self.report())
+    }
+
+    /// Adds in the report a worker thread built of the flows it owns. Only the reports that go
+    /// by flow are built in the workers.
+    fn merge(&mut self, _other: Box<dyn Statistics>) {
+        unreachable!("only the reports that go by flow are merged")
+    }
+}
+
+/// The report of a worker thread as the type it was made as, the type of the report it is
+/// merged into.
+pub(crate) fn same_kind<T: Statistics>(other: Box<dyn Statistics>) -> Box<T> {
+    let other: Box<dyn Any> = other;
+    other.downcast().expect("reports are merged into reports of their own kind")
+}
+
+/// The reports that can be asked for with `--stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum StatisticsKind {
+    ProtocolHierarchy,
+    Conversations(ConversationLevel),
+    Endpoints(ConversationLevel),
+    IoGraph(u64, IoGraphFormat), //Interval in microseconds
+    TcpAnalysis,
+    Alerts,
+}
+
+impl StatisticsKind {
+    pub(crate) fn from_str(name: &str) -> Result<StatisticsKind, String> {
+        let (report, argument) = name.split_once(',').unwrap_or((name, ""));
+        match report {
+            "proto" => Ok(StatisticsKind::ProtocolHierarchy),
+            "conv" => Ok(StatisticsKind::Conversations(ConversationLevel::from_str(argument)?)),
+            "endpoints" => Ok(StatisticsKind::Endpoints(ConversationLevel::from_str(argument)?)),
+            "io" => {
+                let (interval, format) = argument.split_once(',').unwrap_or((argument, ""));
+                let format = match format {
+                    "" => IoGraphFormat::Sparkline,
+                    "csv" => IoGraphFormat::Csv,
+                    "json" => IoGraphFormat::Json,
+                    _ => return Err(format!("unknown I/O graph format '{}', expected csv or json", format)),
+                };
+                Ok(StatisticsKind::IoGraph(parse_interval(interval)?, format))
+            }
+            "tcp" => Ok(StatisticsKind::TcpAnalysis),
+            "alerts" => Ok(StatisticsKind::Alerts),
+            _ => Err(format!("unknown statistics '{}', expected proto, conv,<level>, endpoints,<level>, io,<interval>, tcp or alerts", name)),
+        }
+    }
+
+    pub(crate) fn create(&self, options: &CliOptions) -> Box<dyn Statistics> {
+        match self {
+            StatisticsKind::ProtocolHierarchy => Box::new(ProtocolHierarchy::new()),
+            StatisticsKind::Conversations(level) => Box::new(Conversations::new(*level, options.top)),
+            StatisticsKind::Endpoints(level) => Box::new(Endpoints::new(*level, options.top)),
+            StatisticsKind::IoGraph(interval, format) => Box::new(IoGraph::new(*interval, *format, &options.io_filters)),
+            StatisticsKind::TcpAnalysis => Box::new(TcpAnalysis::new()),
+            StatisticsKind::Alerts => Box::new(Alerts::new(options.thresholds, options.output_format)),
+        }
+    }
+
+    /// Whether the report can be built by the worker threads of `--threads`, each from the flows
+    /// it owns, and merged once the capture was read. The I/O graph starts its intervals at the
+    /// first packet of the whole capture and the alerts look for scans over many flows, so they
+    /// see every packet where the workers' packets are put back in order.
+    pub(crate) fn per_flow(&self) -> bool {
+        !matches!(self, StatisticsKind::IoGraph(..) | StatisticsKind::Alerts)
+    }
+
+    /// Whether the report is meant for other programs, so nothing else may be printed with it.
+    pub(crate) fn machine_readable(&self) -> bool {
+        matches!(self, StatisticsKind::IoGraph(_, IoGraphFormat::Csv | IoGraphFormat::Json))
+    }
+}
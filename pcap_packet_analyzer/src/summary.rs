@@ -0,0 +1,66 @@
+use crate::{PcapBlock, ProtocolDatagram};
+use crate::ethernet_frame::format_mac;
+use crate::internet_packet::format_ipv4;
+
+/// Name of the protocols carried directly over Ethernet that we know about.
+pub(crate) fn ether_type_name(ether_type: [u8; 2]) -> Option<&'static str> {
+    match ether_type {
+        [0x08, 0x00] => Some("IPv4"),
+        [0x08, 0x06] => Some("ARP"),
+        [0x86, 0xdd] => Some("IPv6"),
+        [0x81, 0x00] => Some("VLAN"),
+        [0x88, 0xcc] => Some("LLDP"),
+        _ => None,
+    }
+}
+
+/// One line per packet in the style of tcpdump: number, time, addresses and ports, protocol,
+/// flags and the length of the payload.
+pub(crate) fn summary_line(block: &PcapBlock) -> String {
+    let frame = &block.ether_frame;
+    let packet = &frame.packet;
+    let time = block.format_time();
+    let head = match (block.sender, &block.envelope) {
+        (Some(sender), Some(envelope)) => format!("{:>5} {} [{} {}]", block.number, &time[11..], sender, envelope),
+        (Some(sender), None) => format!("{:>5} {} [{}]", block.number, &time[11..], sender),
+        _ => format!("{:>5} {}", block.number, &time[11..]),
+    };
+
+    if !frame.has_ipv4() {
+        let protocol = match ether_type_name(frame.ether_type) {
+            Some(name) => name.to_string(),
+            None => format!("ethertype 0x{:04x}", u16::from_be_bytes(frame.ether_type)),
+        };
+        return format!("{} {} > {} {}, length {}", head, format_mac(&frame.source_address),
+                       format_mac(&frame.destination_address), protocol, frame.packet_size);
+    }
+
+    let source = format_ipv4(&packet.source_add);
+    let destination = format_ipv4(&packet.destination_add);
+    let ip_payload_length = (u16::from_be_bytes(packet.total_length) as usize).saturating_sub(packet.ihl as usize * 4);
+    match &packet.datagram {
+        //Tracked connections show relative numbers and the scaled window, with the stream and its state
+        ProtocolDatagram::TCP(tcp) => match &tcp.analysis {
+            Some(analysis) => format!("{} {}:{} > {}:{} TCP [{}] seq {} ack {} win {}, length {}, stream {} {}", head,
+                                      source, u16::from_be_bytes(tcp.source_port),
+                                      destination, u16::from_be_bytes(tcp.destination_port),
+                                      tcp.flag_string(), analysis.relative_seq,
+                                      analysis.relative_ack.unwrap_or(0), analysis.window_size, tcp.data.len(),
+                                      analysis.stream, analysis.state.name()),
+            None => format!("{} {}:{} > {}:{} TCP [{}] seq {} ack {} win {}, length {}", head,
+                            source, u16::from_be_bytes(tcp.source_port),
+                            destination, u16::from_be_bytes(tcp.destination_port),
+                            tcp.flag_string(), u32::from_be_bytes(tcp.sequence_number),
+                            u32::from_be_bytes(tcp.acknowledgement_number),
+                            u16::from_be_bytes(tcp.window), tcp.data.len()),
+        },
+        ProtocolDatagram::UDP(udp) => format!("{} {}:{} > {}:{} UDP, length {}", head,
+                                              source, u16::from_be_bytes(udp.source_port),
+                                              destination, u16::from_be_bytes(udp.destination_port), udp.data.len()),
+        ProtocolDatagram::ICMP(icmp) => format!("{} {} > {} ICMP {} id {} seq {}, length {}", head, source, destination,
+                                                icmp.type_name(), u16::from_be_bytes(icmp.identifier_be),
+                                                u16::from_be_bytes(icmp.sequence_be), ip_payload_length),
+        ProtocolDatagram::Default(_) => format!("{} {} > {} IP proto {}, length {}", head, source, destination,
+                                                block.data.get(23).copied().unwrap_or(0), ip_payload_length),
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use crate::{PcapBlock, ProtocolDatagram};
+use crate::conversations::Endpoint;
+use crate::statistics::{same_kind, Statistics};
+use crate::internet_protocol_types::flag_string;
+use crate::view::{EthernetFrame, Packet, Transport};
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+
+//A segment resent sooner than this after the previous one was most likely reordered on the way
+const OUT_OF_ORDER_THRESHOLD: f64 = 0.003;
+
+//When live captures forget a connection: a while after it closed, as long as Linux keeps TIME_WAIT,
+//or once it was quiet for much longer. They are looked for every few seconds of capture
+const CLOSED_TIMEOUT: f64 = 60.0;
+const IDLE_TIMEOUT: f64 = 600.0;
+const RETIRE_INTERVAL: f64 = 10.0;
+
+/// Something unusual about a segment, as in Wireshark's `tcp.analysis` flags.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum TcpAnomaly {
+    Retransmission,
+    FastRetransmission,
+    OutOfOrder,
+    DuplicateAck,
+    ZeroWindow,
+    ZeroWindowProbe,
+    WindowFull,
+    KeepAlive,
+    Reset,
+}
+
+impl TcpAnomaly {
+    const ALL: [TcpAnomaly; 9] = [TcpAnomaly::Retransmission, TcpAnomaly::FastRetransmission, TcpAnomaly::OutOfOrder,
+        TcpAnomaly::DuplicateAck, TcpAnomaly::ZeroWindow, TcpAnomaly::ZeroWindowProbe, TcpAnomaly::WindowFull,
+        TcpAnomaly::KeepAlive, TcpAnomaly::Reset];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TcpAnomaly::Retransmission => "Retransmission",
+            TcpAnomaly::FastRetransmission => "Fast retransmission",
+            TcpAnomaly::OutOfOrder => "Out-of-order",
+            TcpAnomaly::DuplicateAck => "Duplicate ACK",
+            TcpAnomaly::ZeroWindow => "Zero window",
+            TcpAnomaly::ZeroWindowProbe => "Zero window probe",
+            TcpAnomaly::WindowFull => "Window full",
+            TcpAnomaly::KeepAlive => "Keep-alive",
+            TcpAnomaly::Reset => "Reset",
+        }
+    }
+}
+
+/// Where a connection is in the TCP state machine once a segment has been seen. Both sides
+/// share one state, the way a firewall in the middle tracks them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait, //One side sent a FIN that was not acknowledged yet
+    CloseWait, //The first FIN was acknowledged, the other side may still send
+    LastAck, //Both sides sent a FIN, the last one waits for its ACK
+    TimeWait,
+    Closed,
+}
+
+impl TcpState {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynReceived => "SYN_RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::FinWait => "FIN_WAIT",
+            TcpState::CloseWait => "CLOSE_WAIT",
+            TcpState::LastAck => "LAST_ACK",
+            TcpState::TimeWait => "TIME_WAIT",
+            TcpState::Closed => "CLOSED",
+        }
+    }
+}
+
+/// What the analyzer found out about one segment.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SegmentAnalysis {
+    pub(crate) stream: usize,
+    pub(crate) state: TcpState,
+    pub(crate) relative_seq: u32, //Relative to the initial sequence number of the sender
+    pub(crate) relative_ack: Option<u32>, //Relative to the initial sequence number of the receiver, with ACK set
+    pub(crate) window_size: u32, //The window scaled by the negotiated shift
+    pub(crate) window_shift: Option<u8>, //None when the handshake was not seen, so the scale is unknown
+    pub(crate) anomalies: Vec<TcpAnomaly>,
+    pub(crate) ack_rtt: Option<f64>, //Time since the oldest segment this one acknowledges was sent
+}
+
+//Sequence numbers wrap, so they are compared by their distance instead
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// The state of one side of a connection, as seen from the segments it sent.
+#[derive(Default)]
+struct Direction {
+    base_seq: Option<u32>,
+    next_seq: Option<u32>, //One past the highest sequence number sent
+    last_ack: Option<u32>,
+    window: Option<u32>,
+    syn_seen: bool,
+    window_shift: Option<u8>, //From the window scale option of the SYN
+    fin_end: Option<u32>, //Sequence number after the FIN
+    fin_acked: bool,
+    dup_acks: u32,
+    last_segment_time: f64,
+    unacked: Vec<(u32, f64)>, //End sequence number and send time of segments waiting for their ACK
+}
+
+/// The ACK RTTs of a connection, summed up as they come rather than all kept.
+struct RttSummary {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RttSummary {
+    fn new() -> RttSummary {
+        RttSummary { count: 0, sum: 0.0, min: f64::INFINITY, max: 0.0 }
+    }
+
+    fn add(&mut self, rtt: f64) {
+        self.count += 1;
+        self.sum += rtt;
+        self.min = self.min.min(rtt);
+        self.max = self.max.max(rtt);
+    }
+}
+
+struct Connection {
+    endpoints: (Endpoint, Endpoint), //The side that was seen first, normally the client, comes first
+    directions: [Direction; 2],
+    state: Option<TcpState>,
+    packets: u64,
+    bytes: u64,
+    first_seen: f64,
+    last_seen: f64,
+    syn_time: Option<f64>,
+    syn_ack_seq: Option<u32>,
+    handshake_rtt: Option<f64>,
+    ack_rtts: RttSummary,
+    anomalies: [u64; TcpAnomaly::ALL.len()],
+}
+
+impl Connection {
+    fn new(endpoints: (Endpoint, Endpoint), time: f64) -> Connection {
+        Connection {
+            endpoints,
+            directions: [Direction::default(), Direction::default()],
+            state: None,
+            packets: 0,
+            bytes: 0,
+            first_seen: time,
+            last_seen: time,
+            syn_time: None,
+            syn_ack_seq: None,
+            handshake_rtt: None,
+            ack_rtts: RttSummary::new(),
+            anomalies: [0; TcpAnomaly::ALL.len()],
+        }
+    }
+}
+
+/// Follows every TCP connection in the capture and checks each segment against what was seen
+/// before it in both directions.
+pub(crate) struct TcpAnalyzer {
+    streams: HashMap<(Endpoint, Endpoint), usize>,
+    connections: BTreeMap<usize, Connection>, //By stream number
+    stream_count: usize,
+    retire: bool,
+    last_retired: f64,
+}
+
+impl TcpAnalyzer {
+    pub(crate) fn new() -> TcpAnalyzer {
+        TcpAnalyzer {
+            streams: HashMap::new(),
+            connections: BTreeMap::new(),
+            stream_count: 0,
+            retire: false,
+            last_retired: 0.0,
+        }
+    }
+
+    /// An analyzer for live captures, which could go on forever: it forgets the connections that
+    /// closed or went quiet. A segment of a connection it forgot starts a new stream.
+    pub(crate) fn retiring() -> TcpAnalyzer {
+        TcpAnalyzer { retire: true, ..TcpAnalyzer::new() }
+    }
+
+    fn retire_connections(&mut self, time: f64) {
+        self.last_retired = time;
+        let streams = &mut self.streams;
+        self.connections.retain(|stream, connection| {
+            let timeout = match connection.state {
+                Some(TcpState::TimeWait | TcpState::Closed) => CLOSED_TIMEOUT,
+                _ => IDLE_TIMEOUT,
+            };
+            if time - connection.last_seen < timeout {
+                return true;
+            }
+            let (first, second) = connection.endpoints;
+            let key = if first <= second { (first, second) } else { (second, first) };
+            //The ports may have been reused by a newer stream already
+            if streams.get(&key) == Some(stream) {
+                streams.remove(&key);
+            }
+            false
+        });
+    }
+
+    /// Analyzes a TCP block and keeps the result with the segment, for the packet output.
+    pub(crate) fn annotate(&mut self, block: &mut PcapBlock) {
+        let analysis = self.analyze(block);
+        if let ProtocolDatagram::TCP(tcp) = &mut block.ether_frame.packet.datagram {
+            tcp.analysis = analysis;
+        }
+    }
+
+    pub(crate) fn analyze(&mut self, block: &PcapBlock) -> Option<SegmentAnalysis> {
+        self.analyze_frame(&block.data, block.timestamp())
+    }
+
+    /// Analyzes the segment in a frame captured at `time`, read in place so the packets that are
+    /// filtered out are never copied.
+    pub(crate) fn analyze_frame(&mut self, data: &[u8], time: f64) -> Option<SegmentAnalysis> {
+        let ip = EthernetFrame::new(data)?.ipv4()?;
+        let Transport::Tcp(tcp) = ip.transport() else { return None };
+        let source = Endpoint::Transport(ip.source().octets(), tcp.source_port());
+        let destination = Endpoint::Transport(ip.destination().octets(), tcp.destination_port());
+        let seq = tcp.sequence_number();
+        let ack = tcp.acknowledgement_number();
+        let raw_window = tcp.window();
+        let flags = tcp.flags();
+        let length = tcp.payload().len() as u32;
+        let sequence_length = length + (flags & SYN != 0) as u32 + (flags & FIN != 0) as u32;
+        let control = flags & (SYN | FIN | RST) != 0;
+        if self.retire && time - self.last_retired >= RETIRE_INTERVAL {
+            self.retire_connections(time);
+        }
+
+        let key = if source <= destination { (source, destination) } else { (destination, source) };
+        //A new SYN with another initial sequence number reuses the ports for a new connection
+        let reused = |connection: &Connection| {
+            let sender = &connection.directions[if connection.endpoints.0 == source { 0 } else { 1 }];
+            flags & (SYN | ACK) == SYN && sender.base_seq.is_some_and(|base| base != seq)
+        };
+        let stream = match self.streams.get(&key) {
+            Some(&stream) if !reused(&self.connections[&stream]) => stream,
+            _ => {
+                let stream = self.stream_count;
+                self.stream_count += 1;
+                self.connections.insert(stream, Connection::new((source, destination), time));
+                self.streams.insert(key, stream);
+                stream
+            }
+        };
+        let connection = self.connections.get_mut(&stream).unwrap();
+        connection.packets += 1;
+        connection.bytes += data.len() as u64;
+        connection.last_seen = time;
+
+        let forward = connection.endpoints.0 == source;
+        let [first, second] = &mut connection.directions;
+        let (sender, receiver) = if forward { (first, second) } else { (second, first) };
+
+        //Windows are scaled once both SYNs were seen, and only if both offered the option
+        if flags & SYN != 0 {
+            sender.syn_seen = true;
+            sender.window_shift = tcp.window_scale();
+        }
+        let window_shift = if flags & SYN != 0 {
+            Some(0)
+        } else if sender.syn_seen && receiver.syn_seen {
+            Some(match (sender.window_shift, receiver.window_shift) {
+                (Some(shift), Some(_)) => shift.min(14),
+                _ => 0,
+            })
+        } else {
+            None
+        };
+        let window = (raw_window as u32) << window_shift.unwrap_or(0);
+
+        //Without the SYN, numbers are relative to the first one seen so the first segment is 1
+        if flags & SYN != 0 {
+            sender.base_seq.get_or_insert(seq);
+        }
+        let sender_base = *sender.base_seq.get_or_insert(seq.wrapping_sub(1));
+        if flags & ACK != 0 {
+            receiver.base_seq.get_or_insert(ack.wrapping_sub(1));
+        }
+        let relative_seq = seq.wrapping_sub(sender_base);
+        let relative_ack = receiver.base_seq.filter(|_| flags & ACK != 0).map(|base| ack.wrapping_sub(base));
+
+        let mut anomalies = Vec::new();
+
+        if sender.next_seq == Some(seq) && length == 1 && receiver.window == Some(0) {
+            anomalies.push(TcpAnomaly::ZeroWindowProbe);
+        }
+        if window == 0 && !control {
+            anomalies.push(TcpAnomaly::ZeroWindow);
+        }
+        if length <= 1 && !control && sender.next_seq == Some(seq.wrapping_add(1)) {
+            anomalies.push(TcpAnomaly::KeepAlive);
+        }
+        if let (true, Some(acked), Some(receive_window)) = (length > 0, receiver.last_ack, receiver.window) {
+            if seq.wrapping_add(length) == acked.wrapping_add(receive_window) {
+                anomalies.push(TcpAnomaly::WindowFull);
+            }
+        }
+        if flags & ACK != 0 && sequence_length == 0 && !control && !anomalies.contains(&TcpAnomaly::KeepAlive)
+            && sender.last_ack == Some(ack) && sender.window == Some(window) {
+            sender.dup_acks += 1;
+            anomalies.push(TcpAnomaly::DuplicateAck);
+        } else if flags & ACK != 0 && sender.last_ack != Some(ack) {
+            sender.dup_acks = 0;
+        }
+        let probe = anomalies.iter().any(|anomaly| matches!(anomaly, TcpAnomaly::KeepAlive | TcpAnomaly::ZeroWindowProbe));
+        let resent = match sender.next_seq {
+            Some(next) if sequence_length > 0 && !probe && seq_lt(seq, next) => {
+                if receiver.dup_acks >= 2 && receiver.last_ack == Some(seq) {
+                    anomalies.push(TcpAnomaly::FastRetransmission);
+                } else if time - sender.last_segment_time < OUT_OF_ORDER_THRESHOLD {
+                    anomalies.push(TcpAnomaly::OutOfOrder);
+                } else {
+                    anomalies.push(TcpAnomaly::Retransmission);
+                }
+                true
+            }
+            _ => false,
+        };
+        if flags & RST != 0 {
+            anomalies.push(TcpAnomaly::Reset);
+        }
+
+        //Update what the sender has sent
+        //A probe only asks whether the window opened again, its byte is sent for real later
+        if sequence_length > 0 && !probe {
+            let end = seq.wrapping_add(sequence_length);
+            if resent {
+                //The ACK could be for either copy, so neither is used to measure the RTT
+                sender.unacked.retain(|(unacked_end, _)| !(seq_lt(seq, *unacked_end) && seq_le(*unacked_end, end)));
+            } else {
+                sender.unacked.push((end, time));
+            }
+            if sender.next_seq.is_none_or(|next| seq_lt(next, end)) {
+                sender.next_seq = Some(end);
+            }
+            sender.last_segment_time = time;
+        }
+
+        //And what it acknowledged of the other side
+        let mut ack_rtt = None;
+        if flags & ACK != 0 {
+            if let Some(&(_, sent)) = receiver.unacked.iter().find(|(end, _)| seq_le(*end, ack)) {
+                ack_rtt = Some(time - sent);
+            }
+            receiver.unacked.retain(|(end, _)| !seq_le(*end, ack));
+            sender.last_ack = Some(ack);
+            if receiver.fin_end.is_some_and(|end| seq_le(end, ack)) {
+                receiver.fin_acked = true;
+            }
+        }
+        sender.window = Some(window);
+        if flags & FIN != 0 {
+            sender.fin_end.get_or_insert(seq.wrapping_add(sequence_length));
+        }
+
+        let fins_sent = connection.directions.iter().filter(|direction| direction.fin_end.is_some()).count();
+        let fins_acked = connection.directions.iter().filter(|direction| direction.fin_acked).count();
+        let state = match (connection.state, flags & (SYN | ACK | RST)) {
+            (Some(TcpState::Closed), _) => TcpState::Closed,
+            (_, flag) if flag & RST != 0 => TcpState::Closed,
+            (_, SYN) => TcpState::SynSent,
+            (_, flag) if flag == SYN | ACK => TcpState::SynReceived,
+            (Some(handshake @ (TcpState::SynSent | TcpState::SynReceived)), flag) if flag & ACK == 0 => handshake,
+            _ => match (fins_sent, fins_acked) {
+                (0, _) => TcpState::Established,
+                (1, 0) => TcpState::FinWait,
+                (1, _) => TcpState::CloseWait,
+                (_, 2) => TcpState::TimeWait,
+                _ => TcpState::LastAck,
+            },
+        };
+        connection.state = Some(state);
+
+        //The initial RTT runs from the SYN to the ACK completing the handshake
+        match flags & (SYN | ACK) {
+            SYN => { connection.syn_time.get_or_insert(time); }
+            flag if flag == SYN | ACK => { connection.syn_ack_seq.get_or_insert(seq); }
+            ACK if connection.handshake_rtt.is_none() => {
+                if let (Some(syn_time), Some(syn_ack_seq)) = (connection.syn_time, connection.syn_ack_seq) {
+                    if ack == syn_ack_seq.wrapping_add(1) {
+                        connection.handshake_rtt = Some(time - syn_time);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(rtt) = ack_rtt {
+            connection.ack_rtts.add(rtt);
+        }
+        for anomaly in &anomalies {
+            connection.anomalies[TcpAnomaly::ALL.iter().position(|known| known == anomaly).unwrap()] += 1;
+        }
+        Some(SegmentAnalysis {
+            stream,
+            state,
+            relative_seq,
+            relative_ack,
+            window_size: window,
+            window_shift,
+            anomalies,
+            ack_rtt,
+        })
+    }
+}
+
+/// Per connection summary of the TCP analysis, followed by every segment that was flagged.
+pub(crate) struct TcpAnalysis {
+    analyzer: TcpAnalyzer,
+    starts: Vec<u32>, //The number of the first packet of every stream
+    findings: Vec<(u32, usize, String, Vec<TcpAnomaly>)>,
+}
+
+impl TcpAnalysis {
+    pub(crate) fn new() -> TcpAnalysis {
+        TcpAnalysis {
+            analyzer: TcpAnalyzer::new(),
+            starts: Vec::new(),
+            findings: Vec::new(),
+        }
+    }
+}
+
+impl Statistics for TcpAnalysis {
+    fn add(&mut self, packet: &Packet) {
+        let Some(analysis) = self.analyzer.analyze_frame(packet.data(), packet.timestamp()) else { return };
+        if analysis.stream == self.starts.len() {
+            self.starts.push(packet.record.number());
+        }
+        if analysis.anomalies.is_empty() {
+            return;
+        }
+        let (Some(ip), Transport::Tcp(tcp)) = (packet.ipv4(), packet.transport()) else { return };
+        let segment = format!("{} > {} [{}] seq {} ack {} win {} len {}",
+                              Endpoint::Transport(ip.source().octets(), tcp.source_port()),
+                              Endpoint::Transport(ip.destination().octets(), tcp.destination_port()),
+                              flag_string(tcp.flags()), tcp.sequence_number(), tcp.acknowledgement_number(),
+                              tcp.window(), tcp.payload().len());
+        self.findings.push((packet.record.number(), analysis.stream, segment, analysis.anomalies));
+    }
+
+    fn merge(&mut self, other: Box<dyn Statistics>) {
+        let other = same_kind::<TcpAnalysis>(other);
+        //Both reports numbered their streams as they started, so together they are numbered by their first packet
+        let mut starts: Vec<(u32, usize, usize)> = [&self.starts, &other.starts].into_iter().enumerate()
+            .flat_map(|(report, starts)| starts.iter().enumerate().map(move |(stream, &number)| (number, report, stream)))
+            .collect();
+        starts.sort_unstable();
+        let mut numbers = [vec![0; self.starts.len()], vec![0; other.starts.len()]];
+        for (number, &(_, report, stream)) in starts.iter().enumerate() {
+            numbers[report][stream] = number;
+        }
+        let analyzer = &mut self.analyzer;
+        let [ours, theirs] = &numbers;
+        analyzer.connections = std::mem::take(&mut analyzer.connections).into_iter()
+            .map(|(stream, connection)| (ours[stream], connection))
+            .chain(other.analyzer.connections.into_iter().map(|(stream, connection)| (theirs[stream], connection)))
+            .collect();
+        analyzer.streams = std::mem::take(&mut analyzer.streams).into_iter()
+            .map(|(key, stream)| (key, ours[stream]))
+            .chain(other.analyzer.streams.into_iter().map(|(key, stream)| (key, theirs[stream])))
+            .collect();
+        analyzer.stream_count = starts.len();
+        self.starts = starts.into_iter().map(|(number, _, _)| number).collect();
+        for finding in &mut self.findings {
+            finding.1 = ours[finding.1];
+        }
+        self.findings.extend(other.findings.into_iter().map(|mut finding| {
+            finding.1 = theirs[finding.1];
+            finding
+        }));
+        self.findings.sort_by_key(|finding| finding.0);
+    }
+
+    fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "TCP Analysis").unwrap();
+        for (stream, connection) in &self.analyzer.connections {
+            writeln!(out, "Stream {}: {} <-> {}", stream, connection.endpoints.0, connection.endpoints.1).unwrap();
+            writeln!(out, "  Packets {}, bytes {}, duration {:.6}", connection.packets, connection.bytes,
+                     connection.last_seen - connection.first_seen).unwrap();
+            match connection.handshake_rtt {
+                Some(rtt) => writeln!(out, "  Handshake RTT {:.6}", rtt).unwrap(),
+                None => writeln!(out, "  Handshake not seen").unwrap(),
+            }
+            let rtts = &connection.ack_rtts;
+            if rtts.count > 0 {
+                writeln!(out, "  ACK RTT min {:.6}, avg {:.6}, max {:.6} over {} segments", rtts.min,
+                         rtts.sum / rtts.count as f64, rtts.max, rtts.count).unwrap();
+            }
+            let counts: Vec<String> = TcpAnomaly::ALL.iter().zip(connection.anomalies)
+                .filter(|(_, count)| *count > 0)
+                .map(|(anomaly, count)| format!("{} {}", anomaly.name(), count))
+                .collect();
+            if counts.is_empty() {
+                writeln!(out, "  No problems found").unwrap();
+            } else {
+                writeln!(out, "  {}", counts.join(", ")).unwrap();
+            }
+        }
+
+        if !self.findings.is_empty() {
+            writeln!(out, "\nExpert Info").unwrap();
+            writeln!(out, "{:>7} {:>6}  {:<40} Segment", "Frame", "Stream", "Finding").unwrap();
+            for (number, stream, segment, anomalies) in &self.findings {
+                let names: Vec<&str> = anomalies.iter().map(|anomaly| anomaly.name()).collect();
+                writeln!(out, "{:>7} {:>6}  {:<40} {}", number, stream, names.join(", "), segment).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(client_port: u16, flags: u8, seconds: u32) -> PcapBlock {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&client_port.to_be_bytes());
+        frame.extend_from_slice(&[0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        PcapBlock::from_frame(frame, seconds, 0)
+    }
+
+    fn streams(mut analyzer: TcpAnalyzer, segments: &[(u16, u8, u32)]) -> Vec<usize> {
+        segments.iter()
+            .map(|&(port, flags, seconds)| analyzer.analyze(&segment(port, flags, seconds)).unwrap().stream)
+            .collect()
+    }
+
+    #[test]
+    fn live_captures_forget_connections_that_are_over() {
+        let segments = [
+            (1000, SYN, 0), (2000, SYN, 1), (3000, SYN, 1), (1000, RST, 2), (2000, ACK, 5),
+            //Past the closed timeout of the first connection but not the idle one of the others
+            (1000, ACK, 70), (2000, ACK, 70),
+            //The third one went quiet
+            (3000, ACK, 650), (2000, ACK, 650),
+        ];
+        assert_eq!(streams(TcpAnalyzer::retiring(), &segments), [0, 1, 2, 0, 1, 3, 1, 4, 1]);
+        assert_eq!(streams(TcpAnalyzer::new(), &segments), [0, 1, 2, 0, 1, 0, 1, 2, 1]);
+    }
+}
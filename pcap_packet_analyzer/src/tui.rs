@@ -0,0 +1,777 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::process::Command;
+use crate::{passes_filters, DisplayFilter, Filter, PcapBlock, ProtocolDatagram};
+use crate::cli_options::CliOptions;
+use crate::compressed::{self, CaptureInput};
+use crate::dns::{type_name, DnsMessage};
+use crate::ethernet_frame::format_mac;
+use crate::hex_dump::layer_spans;
+use crate::http::HttpHead;
+use crate::internet_packet::format_ipv4;
+use crate::pcap_reader::PcapReader;
+use crate::summary::{ether_type_name, summary_line};
+use crate::tcp_analysis::TcpAnalyzer;
+
+const REVERSE: &str = "\x1b[7m";
+const BOLD: &str = "\x1b[1m";
+const HIGHLIGHT: &str = "\x1b[30;46m"; //Black on cyan, for the bytes of the selected field
+const RESET: &str = "\x1b[0m";
+
+const HELP: &str = "q quit  Tab switch pane  Enter/\u{2190}\u{2192} expand  / filter  g go to packet";
+
+/// One line of the packet details. Layers and fields with parts of their own can be expanded,
+/// and every node knows which bytes of the frame it was decoded from.
+struct TreeNode {
+    key: String, //Names the node across packets so it stays expanded, empty for leaves
+    label: String,
+    bytes: (usize, usize), //Start and end offset in the frame, equal when the value is derived
+    children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn leaf(label: String, bytes: (usize, usize)) -> TreeNode {
+        TreeNode { key: String::new(), label, bytes, children: Vec::new() }
+    }
+
+    fn branch(key: &str, label: String, bytes: (usize, usize), children: Vec<TreeNode>) -> TreeNode {
+        TreeNode { key: key.to_string(), label, bytes, children }
+    }
+}
+
+/// The layer tree of a block, from the frame through Ethernet and IPv4 to the transport and
+/// the application protocols we decode.
+fn layer_tree(block: &PcapBlock) -> Vec<TreeNode> {
+    let length = block.data.len();
+    let at = |start: usize, end: usize| (start.min(length), end.min(length));
+    let span = |name: &str| layer_spans(block).into_iter().find(|span| span.name == name).map(|span| (span.start, span.end));
+    let frame = &block.ether_frame;
+    let mut tree = vec![TreeNode::branch("frame", format!("Frame {}: {} bytes on wire, {} bytes captured", block.number,
+                                                          u32::from_ne_bytes(block.original_length), length), (0, length), vec![
+        TreeNode::leaf(format!("Arrival time: {}", block.format_time()), (0, 0)),
+        TreeNode::leaf(format!("Epoch time: {}", block.format_epoch()), (0, 0)),
+        TreeNode::leaf(format!("Frame length: {} bytes", u32::from_ne_bytes(block.original_length)), (0, 0)),
+        TreeNode::leaf(format!("Capture length: {} bytes", length), (0, 0)),
+    ])];
+    if length < 14 {
+        return tree;
+    }
+    let ether_type = match ether_type_name(frame.ether_type) {
+        Some(name) => format!("{} (0x{:04x})", name, u16::from_be_bytes(frame.ether_type)),
+        None => format!("0x{:04x}", u16::from_be_bytes(frame.ether_type)),
+    };
+    tree.push(TreeNode::branch("eth", format!("Ethernet II, Src: {}, Dst: {}", format_mac(&frame.source_address),
+                                              format_mac(&frame.destination_address)), (0, 14), vec![
+        TreeNode::leaf(format!("Destination: {}", format_mac(&frame.destination_address)), (0, 6)),
+        TreeNode::leaf(format!("Source: {}", format_mac(&frame.source_address)), (6, 12)),
+        TreeNode::leaf(format!("Type: {}", ether_type), (12, 14)),
+    ]));
+    if !frame.has_ipv4() {
+        if let Some(payload) = span("PAYLOAD") {
+            let name = ether_type_name(frame.ether_type).unwrap_or("Data");
+            tree.push(TreeNode::leaf(format!("{} ({} bytes)", name, payload.1 - payload.0), payload));
+        }
+        return tree;
+    }
+
+    let packet = &frame.packet;
+    let ip = span("IP").unwrap_or((14, 14));
+    let mut ip_fields = vec![
+        TreeNode::leaf("Version: 4".to_string(), at(14, 15)),
+        TreeNode::leaf(format!("Header length: {} bytes", packet.ihl as usize * 4), at(14, 15)),
+        TreeNode::leaf(format!("Type of service: 0x{:02x}", packet.tos), at(15, 16)),
+        TreeNode::leaf(format!("Total length: {}", u16::from_be_bytes(packet.total_length)), at(16, 18)),
+        TreeNode::leaf(format!("Identification: 0x{:04x} ({})", u16::from_be_bytes(packet.identification),
+                               u16::from_be_bytes(packet.identification)), at(18, 20)),
+        TreeNode::branch("ip.flags", format!("Flags: 0x{:x}", block.data.get(20).map_or(0, |byte| byte >> 5)), at(20, 21), vec![
+            TreeNode::leaf(format!("Reserved bit: {}", set_name(packet.reserved_flag != 0)), at(20, 21)),
+            TreeNode::leaf(format!("Don't fragment: {}", set_name(packet.do_not_fragment_flag != 0)), at(20, 21)),
+            TreeNode::leaf(format!("More fragments: {}", set_name(packet.last_fragment_flag != 0)), at(20, 21)),
+        ]),
+        TreeNode::leaf(format!("Fragment offset: {}", packet.fragment_offset), at(20, 22)),
+        TreeNode::leaf(format!("Time to live: {}", packet.ttl), at(22, 23)),
+        TreeNode::leaf(format!("Protocol: {}", packet.protocol), at(23, 24)),
+        TreeNode::leaf(format!("Header checksum: 0x{:04x}", u16::from_be_bytes(packet.header_checksum)), at(24, 26)),
+        TreeNode::leaf(format!("Source address: {}", format_ipv4(&packet.source_add)), at(26, 30)),
+        TreeNode::leaf(format!("Destination address: {}", format_ipv4(&packet.destination_add)), at(30, 34)),
+    ];
+    if ip.1 > 34 {
+        ip_fields.push(TreeNode::leaf(format!("Options: {} bytes", ip.1 - 34), at(34, ip.1)));
+    }
+    tree.push(TreeNode::branch("ip", format!("Internet Protocol Version 4, Src: {}, Dst: {}", format_ipv4(&packet.source_add),
+                                             format_ipv4(&packet.destination_add)), ip, ip_fields));
+
+    let t = ip.1;
+    let payload = span("PAYLOAD");
+    let port_fields = |source: [u8; 2], destination: [u8; 2]| vec![
+        TreeNode::leaf(format!("Source port: {}", u16::from_be_bytes(source)), at(t, t + 2)),
+        TreeNode::leaf(format!("Destination port: {}", u16::from_be_bytes(destination)), at(t + 2, t + 4)),
+    ];
+    let mut ports = None;
+    match &packet.datagram {
+        ProtocolDatagram::TCP(tcp) => {
+            let header_end = t + tcp.data_offset as usize;
+            let mut fields = port_fields(tcp.source_port, tcp.destination_port);
+            let analysis = tcp.analysis.as_ref();
+            if let Some(analysis) = analysis {
+                fields.push(TreeNode::leaf(format!("Stream index: {}", analysis.stream), (0, 0)));
+            }
+            let raw_seq = u32::from_be_bytes(tcp.sequence_number);
+            let raw_ack = u32::from_be_bytes(tcp.acknowledgement_number);
+            fields.push(TreeNode::leaf(match analysis {
+                Some(analysis) => format!("Sequence number: {} (relative, raw {})", analysis.relative_seq, raw_seq),
+                None => format!("Sequence number: {}", raw_seq),
+            }, at(t + 4, t + 8)));
+            fields.push(TreeNode::leaf(match analysis.and_then(|analysis| analysis.relative_ack) {
+                Some(ack) => format!("Acknowledgment number: {} (relative, raw {})", ack, raw_ack),
+                None => format!("Acknowledgment number: {}", raw_ack),
+            }, at(t + 8, t + 12)));
+            fields.push(TreeNode::leaf(format!("Header length: {} bytes", tcp.data_offset), at(t + 12, t + 13)));
+            let flag_names = tcp.flag_names();
+            fields.push(TreeNode::branch("tcp.flags", format!("Flags: 0x{:03x} ({})", tcp.flags, flag_names.join(", ")),
+                                         at(t + 13, t + 14),
+                [(0x80, "Congestion window reduced"), (0x40, "ECN-Echo"), (0x20, "Urgent"), (0x10, "Acknowledgment"),
+                    (0x08, "Push"), (0x04, "Reset"), (0x02, "Syn"), (0x01, "Fin")].iter()
+                    .map(|(mask, name)| TreeNode::leaf(format!("{}: {}", name, set_name(tcp.flags & mask != 0)), at(t + 13, t + 14)))
+                    .collect()));
+            fields.push(TreeNode::leaf(format!("Window: {}", u16::from_be_bytes(tcp.window)), at(t + 14, t + 16)));
+            if let Some(analysis) = analysis {
+                fields.push(TreeNode::leaf(format!("Calculated window size: {}", analysis.window_size), (0, 0)));
+            }
+            fields.push(TreeNode::leaf(format!("Checksum: 0x{:04x}", u16::from_be_bytes(tcp.checksum)), at(t + 16, t + 18)));
+            fields.push(TreeNode::leaf(format!("Urgent pointer: {}", u16::from_be_bytes(tcp.urgent_pointer)), at(t + 18, t + 20)));
+            if header_end > t + 20 {
+                fields.push(TreeNode::leaf(format!("Options: {} bytes", header_end - t - 20), at(t + 20, header_end)));
+            }
+            if let Some(analysis) = analysis {
+                fields.push(TreeNode::leaf(format!("Connection state: {}", analysis.state.name()), (0, 0)));
+                if !analysis.anomalies.is_empty() {
+                    let names: Vec<&str> = analysis.anomalies.iter().map(|anomaly| anomaly.name()).collect();
+                    fields.push(TreeNode::leaf(format!("Expert info: {}", names.join(", ")), (0, 0)));
+                }
+            }
+            let (seq, ack) = match analysis {
+                Some(analysis) => (analysis.relative_seq, analysis.relative_ack.unwrap_or(0)),
+                None => (raw_seq, raw_ack),
+            };
+            tree.push(TreeNode::branch("tcp", format!("Transmission Control Protocol, Src Port: {}, Dst Port: {}, Seq: {}, Ack: {}, Len: {}",
+                                                      u16::from_be_bytes(tcp.source_port), u16::from_be_bytes(tcp.destination_port),
+                                                      seq, ack, tcp.data.len()), at(t, header_end), fields));
+            ports = Some((true, u16::from_be_bytes(tcp.source_port), u16::from_be_bytes(tcp.destination_port)));
+        }
+        ProtocolDatagram::UDP(udp) => {
+            let mut fields = port_fields(udp.source_port, udp.destination_port);
+            fields.push(TreeNode::leaf(format!("Length: {}", u16::from_be_bytes(udp.length)), at(t + 4, t + 6)));
+            fields.push(TreeNode::leaf(format!("Checksum: 0x{:04x}", u16::from_be_bytes(udp.checksum)), at(t + 6, t + 8)));
+            tree.push(TreeNode::branch("udp", format!("User Datagram Protocol, Src Port: {}, Dst Port: {}",
+                                                      u16::from_be_bytes(udp.source_port), u16::from_be_bytes(udp.destination_port)),
+                                       at(t, t + 8), fields));
+            ports = Some((false, u16::from_be_bytes(udp.source_port), u16::from_be_bytes(udp.destination_port)));
+        }
+        ProtocolDatagram::ICMP(icmp) => {
+            tree.push(TreeNode::branch("icmp", format!("Internet Control Message Protocol, {}", icmp.type_name()), at(t, t + 8), vec![
+                TreeNode::leaf(format!("Type: {} ({})", icmp.packet_type, icmp.type_name()), at(t, t + 1)),
+                TreeNode::leaf(format!("Code: {}", icmp.code), at(t + 1, t + 2)),
+                TreeNode::leaf(format!("Checksum: 0x{:04x}", u16::from_be_bytes(icmp.checksum)), at(t + 2, t + 4)),
+                TreeNode::leaf(format!("Identifier: {}", u16::from_be_bytes(icmp.identifier_be)), at(t + 4, t + 6)),
+                TreeNode::leaf(format!("Sequence number: {}", u16::from_be_bytes(icmp.sequence_be)), at(t + 6, t + 8)),
+            ]));
+        }
+        ProtocolDatagram::Default(_) => {}
+    }
+
+    if let Some(payload) = payload {
+        let application = ports.and_then(|(tcp, source, destination)| {
+            application_tree(&block.data[payload.0..payload.1], payload.0, tcp, source, destination)
+        });
+        tree.push(application.unwrap_or_else(|| TreeNode::leaf(format!("Data ({} bytes)", payload.1 - payload.0), payload)));
+    }
+    if let Some(trailer) = span("TRAILER") {
+        tree.push(TreeNode::leaf(format!("Trailer ({} bytes)", trailer.1 - trailer.0), trailer));
+    }
+    tree
+}
+
+//The DNS or HTTP message in a payload starting at `offset` in the frame, if it holds one
+fn application_tree(payload: &[u8], offset: usize, tcp: bool, source: u16, destination: u16) -> Option<TreeNode> {
+    if source == 53 || destination == 53 {
+        //DNS over TCP starts with the length of the message
+        let start = if tcp { 2 } else { 0 };
+        let message = DnsMessage::parse(payload.get(start..)?).ok()?;
+        let p = offset + start;
+        let record_type = |rtype: u16| type_name(rtype).map_or(rtype.to_string(), str::to_string);
+        let mut fields = vec![
+            TreeNode::leaf(format!("Transaction ID: 0x{:04x}", message.id), (p, p + 2)),
+            TreeNode::leaf(format!("Flags: 0x{:04x}", message.flags), (p + 2, p + 4)),
+            TreeNode::leaf(format!("Questions: {}", message.questions.len()), (p + 4, p + 6)),
+            TreeNode::leaf(format!("Answer RRs: {}", message.answers.len()), (p + 6, p + 8)),
+        ];
+        for question in &message.questions {
+            fields.push(TreeNode::leaf(format!("Query: {} type {}", question.name, record_type(question.qtype)), (0, 0)));
+        }
+        for answer in &message.answers {
+            fields.push(TreeNode::leaf(format!("Answer: {} type {} TTL {} {}", answer.name, record_type(answer.rtype),
+                                               answer.ttl, answer.data), (0, 0)));
+        }
+        let kind = if message.is_response() { "response" } else { "query" };
+        return Some(TreeNode::branch("dns", format!("Domain Name System ({})", kind), (offset, offset + payload.len()), fields));
+    }
+    if !tcp {
+        return None;
+    }
+    let head = HttpHead::request(payload).or_else(|| HttpHead::response(payload))?;
+    let mut fields = Vec::new();
+    let mut line_start = 0;
+    //Every line of the head, the empty one that ends it excluded
+    while line_start + 2 < head.head_length {
+        let line_end = payload[line_start..].windows(2).position(|window| window == b"\r\n")? + line_start;
+        fields.push(TreeNode::leaf(String::from_utf8_lossy(&payload[line_start..line_end]).into_owned(),
+                                   (offset + line_start, offset + line_end + 2)));
+        line_start = line_end + 2;
+    }
+    if payload.len() > head.head_length {
+        fields.push(TreeNode::leaf(format!("Body: {} bytes", payload.len() - head.head_length),
+                                   (offset + head.head_length, offset + payload.len())));
+    }
+    Some(TreeNode::branch("http", "Hypertext Transfer Protocol".to_string(), (offset, offset + payload.len()), fields))
+}
+
+fn set_name(set: bool) -> &'static str {
+    if set { "Set" } else { "Not set" }
+}
+
+//Collects the rows of the tree that can be seen with the expanded nodes open
+fn visible_rows<'a>(nodes: &'a [TreeNode], expanded: &HashSet<String>, depth: usize, rows: &mut Vec<(usize, &'a TreeNode)>) {
+    for node in nodes {
+        rows.push((depth, node));
+        if !node.children.is_empty() && expanded.contains(&node.key) {
+            visible_rows(&node.children, expanded, depth + 1, rows);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    Interrupt,
+    Char(char),
+}
+
+//Turns what one read from the terminal returned into keys
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    const SEQUENCES: &[(&[u8], Key)] = &[(b"\x1b[A", Key::Up), (b"\x1bOA", Key::Up), (b"\x1b[B", Key::Down),
+        (b"\x1bOB", Key::Down), (b"\x1b[C", Key::Right), (b"\x1bOC", Key::Right), (b"\x1b[D", Key::Left),
+        (b"\x1bOD", Key::Left), (b"\x1b[5~", Key::PageUp), (b"\x1b[6~", Key::PageDown), (b"\x1b[H", Key::Home),
+        (b"\x1bOH", Key::Home), (b"\x1b[1~", Key::Home), (b"\x1b[F", Key::End), (b"\x1bOF", Key::End),
+        (b"\x1b[4~", Key::End)];
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest[0] == 0x1b {
+            if let Some((sequence, key)) = SEQUENCES.iter().find(|(sequence, _)| rest.starts_with(sequence)) {
+                keys.push(*key);
+                i += sequence.len();
+            } else if rest.len() > 1 && (rest[1] == b'[' || rest[1] == b'O') {
+                //A sequence we do not use, skipped up to its final byte
+                i += 2;
+                while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                    i += 1;
+                }
+                i += 1;
+            } else {
+                keys.push(Key::Escape);
+                i += 1;
+            }
+            continue;
+        }
+        let length = match rest[0] {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1,
+        };
+        keys.push(match rest[0] {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x03 => Key::Interrupt,
+            _ => match std::str::from_utf8(&rest[..length.min(rest.len())]).ok().and_then(|text| text.chars().next()) {
+                Some(c) if !c.is_control() => Key::Char(c),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            },
+        });
+        i += length;
+    }
+    keys
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Pane {
+    List,
+    Tree,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Mode {
+    Browse,
+    Filter(String), //The filter to go back to when the edit is cancelled
+    Jump(String),
+}
+
+//Heights of the packet list, the details and the bytes for a terminal of `rows` lines
+fn layout(rows: usize) -> (usize, usize, usize) {
+    //The title, the two pane headings and the status line take a row each
+    let usable = rows.saturating_sub(4);
+    let list = usable * 2 / 5;
+    let tree = (usable - list).div_ceil(2);
+    (list, tree, usable - list - tree)
+}
+
+//Pads or cuts text to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut out: String = text.chars().take(width).collect();
+    let length = out.chars().count();
+    out.extend(std::iter::repeat_n(' ', width - length));
+    out
+}
+
+struct Browser {
+    file_name: String,
+    packets: Vec<PcapBlock>,
+    shown: Vec<usize>, //Positions in `packets` of those passing the live filter
+    filter_text: String,
+    filter_error: Option<String>,
+    selected: usize, //Position in `shown`
+    list_top: usize,
+    tree: Vec<TreeNode>,
+    expanded: HashSet<String>,
+    tree_selected: usize,
+    tree_top: usize,
+    focus: Pane,
+    mode: Mode,
+    status: String,
+}
+
+impl Browser {
+    fn new(file_name: &str, packets: Vec<PcapBlock>) -> Browser {
+        let mut browser = Browser {
+            file_name: file_name.to_string(),
+            shown: (0..packets.len()).collect(),
+            packets,
+            filter_text: String::new(),
+            filter_error: None,
+            selected: 0,
+            list_top: 0,
+            tree: Vec::new(),
+            expanded: HashSet::new(),
+            tree_selected: 0,
+            tree_top: 0,
+            focus: Pane::List,
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        browser.select(0);
+        browser
+    }
+
+    fn select(&mut self, position: usize) {
+        self.selected = position.min(self.shown.len().saturating_sub(1));
+        self.tree = match self.shown.get(self.selected) {
+            Some(&index) => layer_tree(&self.packets[index]),
+            None => Vec::new(),
+        };
+        let rows = self.tree_rows().len();
+        self.tree_selected = self.tree_selected.min(rows.saturating_sub(1));
+    }
+
+    fn tree_rows(&self) -> Vec<(usize, &TreeNode)> {
+        let mut rows = Vec::new();
+        visible_rows(&self.tree, &self.expanded, 0, &mut rows);
+        rows
+    }
+
+    //Filters the packets again while the expression is typed, keeping the last one that parsed
+    fn apply_filter(&mut self) {
+        let filter = if self.filter_text.trim().is_empty() {
+            None
+        } else {
+            match DisplayFilter::parse(&self.filter_text) {
+                Ok(filter) => Some(filter),
+                Err(error) => {
+                    self.filter_error = Some(error);
+                    return;
+                }
+            }
+        };
+        self.filter_error = None;
+        let current = self.shown.get(self.selected).copied().unwrap_or(0);
+        self.shown = (0..self.packets.len())
+            .filter(|&index| filter.as_ref().is_none_or(|filter| filter.matches(&self.packets[index])))
+            .collect();
+        //The selection stays on the same packet, or moves to the next one shown
+        let position = self.shown.iter().position(|&index| index >= current).unwrap_or(self.shown.len());
+        self.select(position);
+    }
+
+    fn jump(&mut self, text: &str) {
+        let Ok(number) = text.trim().parse::<u32>() else {
+            self.status = format!("'{}' is not a packet number", text);
+            return;
+        };
+        match self.packets.iter().position(|block| block.number == number) {
+            Some(index) => match self.shown.iter().position(|&shown| shown == index) {
+                Some(position) => self.select(position),
+                None => self.status = format!("Packet {} is hidden by the filter", number),
+            },
+            None => self.status = format!("There is no packet {}", number),
+        }
+    }
+
+    /// Handles a key, returns false when the browser should close.
+    fn handle(&mut self, key: Key, rows: usize) -> bool {
+        let (list_height, tree_height, _) = layout(rows);
+        match self.mode.clone() {
+            Mode::Filter(previous) => {
+                match key {
+                    Key::Char(c) => self.filter_text.push(c),
+                    Key::Backspace => { self.filter_text.pop(); }
+                    Key::Enter if self.filter_error.is_none() => self.mode = Mode::Browse,
+                    Key::Escape | Key::Interrupt => {
+                        self.filter_text = previous;
+                        self.mode = Mode::Browse;
+                    }
+                    _ => return true,
+                }
+                self.apply_filter();
+                return true;
+            }
+            Mode::Jump(mut text) => {
+                match key {
+                    Key::Char(c) if c.is_ascii_digit() => {
+                        text.push(c);
+                        self.mode = Mode::Jump(text);
+                    }
+                    Key::Backspace => {
+                        text.pop();
+                        self.mode = Mode::Jump(text);
+                    }
+                    Key::Enter => {
+                        self.mode = Mode::Browse;
+                        self.jump(&text);
+                    }
+                    Key::Escape | Key::Interrupt => self.mode = Mode::Browse,
+                    _ => {}
+                }
+                return true;
+            }
+            Mode::Browse => {}
+        }
+
+        self.status.clear();
+        let (current, count, page) = match self.focus {
+            Pane::List => (self.selected, self.shown.len(), list_height.max(1)),
+            Pane::Tree => (self.tree_selected, self.tree_rows().len(), tree_height.max(1)),
+        };
+        let moved = match key {
+            Key::Char('q') | Key::Interrupt => return false,
+            Key::Up | Key::Char('k') => Some(current.saturating_sub(1)),
+            Key::Down | Key::Char('j') => Some(current + 1),
+            Key::PageUp => Some(current.saturating_sub(page)),
+            Key::PageDown => Some(current + page),
+            Key::Home => Some(0),
+            Key::End => Some(count.saturating_sub(1)),
+            Key::Tab => {
+                self.focus = if self.focus == Pane::List { Pane::Tree } else { Pane::List };
+                None
+            }
+            Key::Char('/') => {
+                self.mode = Mode::Filter(self.filter_text.clone());
+                None
+            }
+            Key::Char('g') | Key::Char(':') => {
+                self.mode = Mode::Jump(String::new());
+                None
+            }
+            Key::Enter | Key::Right if self.focus == Pane::List => {
+                self.focus = Pane::Tree;
+                None
+            }
+            Key::Enter | Key::Char(' ') | Key::Right | Key::Left if self.focus == Pane::Tree => {
+                self.toggle(key);
+                None
+            }
+            _ => None,
+        };
+        if let Some(position) = moved {
+            let position = position.min(count.saturating_sub(1));
+            match self.focus {
+                Pane::List => self.select(position),
+                Pane::Tree => self.tree_selected = position,
+            }
+        }
+        true
+    }
+
+    //Opens or closes the selected node of the tree; left on a closed node goes to its parent
+    fn toggle(&mut self, key: Key) {
+        let rows = self.tree_rows();
+        let Some(&(depth, node)) = rows.get(self.tree_selected) else { return };
+        let open = self.expanded.contains(&node.key);
+        let key_name = node.key.clone();
+        let branch = !node.children.is_empty();
+        let parent = rows[..self.tree_selected].iter().rposition(|(row_depth, _)| *row_depth < depth);
+        match key {
+            Key::Left if branch && open => { self.expanded.remove(&key_name); }
+            Key::Left => self.tree_selected = parent.unwrap_or(self.tree_selected),
+            Key::Right if branch => { self.expanded.insert(key_name); }
+            Key::Enter | Key::Char(' ') if branch && open => { self.expanded.remove(&key_name); }
+            Key::Enter | Key::Char(' ') if branch => { self.expanded.insert(key_name); }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, rows: usize, columns: usize) -> String {
+        let mut screen = String::from("\x1b[H");
+        if rows < 10 || columns < 40 {
+            screen.push_str("\x1b[2JThe terminal is too small");
+            return screen;
+        }
+        let (list_height, tree_height, hex_height) = layout(rows);
+        let mut lines: Vec<String> = Vec::new();
+
+        let mut title = format!(" {}  {} of {} packets shown", self.file_name, self.shown.len(), self.packets.len());
+        if !self.filter_text.is_empty() {
+            title.push_str(&format!("  filter: {}", self.filter_text));
+        }
+        lines.push(format!("{}{}{}", REVERSE, fit(&title, columns), RESET));
+
+        //Packet list
+        if self.selected < self.list_top {
+            self.list_top = self.selected;
+        } else if self.selected >= self.list_top + list_height {
+            self.list_top = self.selected + 1 - list_height;
+        }
+        for row in 0..list_height {
+            let position = self.list_top + row;
+            let line = match self.shown.get(position) {
+                Some(&index) => fit(&summary_line(&self.packets[index]), columns),
+                None => fit("", columns),
+            };
+            lines.push(self.selection_style(position == self.selected && !self.shown.is_empty(), Pane::List, line));
+        }
+
+        //Packet details
+        lines.push(heading(" Packet details", self.focus == Pane::Tree, columns));
+        if self.tree_selected < self.tree_top {
+            self.tree_top = self.tree_selected;
+        } else if self.tree_selected >= self.tree_top + tree_height {
+            self.tree_top = self.tree_selected + 1 - tree_height;
+        }
+        let tree_rows = self.tree_rows();
+        let mut highlight = (0, 0);
+        for row in 0..tree_height {
+            let position = self.tree_top + row;
+            let line = match tree_rows.get(position) {
+                Some((depth, node)) => {
+                    let marker = match (node.children.is_empty(), self.expanded.contains(&node.key)) {
+                        (true, _) => "  ",
+                        (false, true) => "\u{25be} ",
+                        (false, false) => "\u{25b8} ",
+                    };
+                    fit(&format!("{}{}{}", "  ".repeat(*depth), marker, node.label), columns)
+                }
+                None => fit("", columns),
+            };
+            let selected = position == self.tree_selected && !tree_rows.is_empty();
+            lines.push(self.selection_style(selected, Pane::Tree, line));
+        }
+        if let Some((_, node)) = tree_rows.get(self.tree_selected) {
+            highlight = node.bytes;
+        }
+
+        //Bytes, scrolled so the start of the highlighted field can be seen
+        lines.push(heading(" Bytes", false, columns));
+        let data = self.shown.get(self.selected).map_or(&[][..], |&index| &self.packets[index].data[..]);
+        let highlight_line = highlight.0 / 16;
+        let hex_top = if highlight.1 > highlight.0 && highlight_line >= hex_height { highlight_line + 1 - hex_height } else { 0 };
+        let ascii = columns >= 72;
+        for row in 0..hex_height {
+            let line_start = (hex_top + row) * 16;
+            let Some(chunk) = data.get(line_start..(line_start + 16).min(data.len())).filter(|chunk| !chunk.is_empty()) else {
+                lines.push(fit("", columns));
+                continue;
+            };
+            let marked = |offset: usize| offset >= highlight.0 && offset < highlight.1;
+            let mut line = format!("{:04x}  ", line_start);
+            let mut text = String::new();
+            for (i, byte) in chunk.iter().enumerate() {
+                let printable = if (0x20..0x7f).contains(byte) { *byte as char } else { '.' };
+                if marked(line_start + i) {
+                    line.push_str(&format!("{}{:02x}{} ", HIGHLIGHT, byte, RESET));
+                    text.push_str(&format!("{}{}{}", HIGHLIGHT, printable, RESET));
+                } else {
+                    line.push_str(&format!("{:02x} ", byte));
+                    text.push(printable);
+                }
+            }
+            line.push_str(&"   ".repeat(16 - chunk.len()));
+            if ascii {
+                line.push(' ');
+                line.push_str(&text);
+            }
+            lines.push(line);
+        }
+
+        let status = match &self.mode {
+            Mode::Filter(_) => match &self.filter_error {
+                Some(error) => format!("Filter: {}_   {}", self.filter_text, error),
+                None => format!("Filter: {}_   {} packets", self.filter_text, self.shown.len()),
+            },
+            Mode::Jump(text) => format!("Go to packet: {}_", text),
+            Mode::Browse if !self.status.is_empty() => self.status.clone(),
+            Mode::Browse => HELP.to_string(),
+        };
+        lines.push(fit(&status, columns));
+
+        for (row, line) in lines.iter().enumerate() {
+            screen.push_str(&format!("\x1b[{};1H{}\x1b[K", row + 1, line));
+        }
+        screen
+    }
+
+    fn selection_style(&self, selected: bool, pane: Pane, line: String) -> String {
+        match (selected, self.focus == pane) {
+            (true, true) => format!("{}{}{}", REVERSE, line, RESET),
+            (true, false) => format!("{}{}{}", BOLD, line, RESET),
+            (false, _) => line,
+        }
+    }
+}
+
+//The line above a pane, in bold when the pane has the focus
+fn heading(title: &str, focused: bool, columns: usize) -> String {
+    let style = if focused { BOLD } else { "" };
+    format!("{}{}{}", style, fit(&format!("{} {}", title, "\u{2500}".repeat(columns)), columns), RESET)
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and puts it back when dropped.
+struct Terminal {
+    tty: File,
+    saved: String,
+}
+
+impl Terminal {
+    fn open() -> Result<Terminal, String> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")
+            .map_err(|_| "the terminal UI needs a terminal".to_string())?;
+        let saved = stty(&tty, &["-g"])?;
+        stty(&tty, &["raw", "-echo", "min", "1", "time", "0"])?;
+        let mut terminal = Terminal { tty, saved };
+        terminal.write("\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        Ok(terminal)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let size = stty(&self.tty, &["size"]).unwrap_or_default();
+        let mut numbers = size.split_whitespace().filter_map(|number| number.parse().ok());
+        match (numbers.next(), numbers.next()) {
+            (Some(rows), Some(columns)) if rows > 0 && columns > 0 => (rows, columns),
+            _ => (24, 80),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        self.tty.write_all(text.as_bytes())
+            .and_then(|_| self.tty.flush())
+            .map_err(|error| format!("could not write to the terminal: {}", error))
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, String> {
+        self.tty.read(buffer).map_err(|error| format!("could not read from the terminal: {}", error))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stty(&self.tty, &[self.saved.as_str()]);
+    }
+}
+
+//Runs stty on the terminal, the only way to reach its settings without a library
+fn stty(tty: &File, args: &[&str]) -> Result<String, String> {
+    let input = tty.try_clone().map_err(|error| format!("could not use the terminal: {}", error))?;
+    let output = Command::new("stty").args(args).stdin(input).output()
+        .map_err(|error| format!("could not run stty: {}", error))?;
+    if !output.status.success() {
+        return Err(format!("stty failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+//Reads every packet that passes the capture filter and -Y, with the TCP connections tracked
+fn load(file_name: &str, options: &CliOptions) -> Result<Vec<PcapBlock>, String> {
+    let could_not_read = |error| format!("could not read {}: {}", file_name, error);
+    let capture;
+    let input: Box<dyn Read> = match compressed::open(file_name).map_err(|error| format!("could not open {}: {}", file_name, error))? {
+        CaptureInput::Mapped(mapped) => {
+            capture = mapped;
+            Box::new(&capture[..])
+        }
+        CaptureInput::Stream(stream) => stream,
+    };
+    let mut reader = PcapReader::new(input).map_err(could_not_read)?;
+    let mut filter = options.filter.clone();
+    let mut tcp_analyzer = TcpAnalyzer::new();
+    let mut packets = Vec::new();
+    for mut block in &mut reader {
+        tcp_analyzer.annotate(&mut block);
+        let my_filter = filter.clone();
+        if let Filter::Count(count) = filter {
+            filter = Filter::Count(count - 1)
+        }
+        if passes_filters(&block, &my_filter, options) {
+            packets.push(block);
+        }
+    }
+    match reader.take_error() {
+        Some(error) => Err(could_not_read(error)),
+        None => Ok(packets),
+    }
+}
+
+/// Browses a capture in the terminal: the packet list, the layers of the selected packet and
+/// its bytes, with a display filter applied as it is typed.
+pub(crate) fn run(file_name: &str, options: &CliOptions) -> Result<(), String> {
+    let packets = load(file_name, options)?;
+    let mut browser = Browser::new(file_name, packets);
+    let mut terminal = Terminal::open()?;
+    let mut buffer = [0; 64];
+    loop {
+        let (rows, columns) = terminal.size();
+        let screen = browser.draw(rows, columns);
+        terminal.write(&screen)?;
+        let length = terminal.read(&mut buffer)?;
+        for key in parse_keys(&buffer[..length]) {
+            if !browser.handle(key, rows) {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! Borrowed views over captured bytes. A view checks the lengths it needs when it is made and
+//! reads every field from the buffer only when asked, so walking a capture allocates nothing.
+//! [`EthernetFrame::decode`] and [`Record::to_block`] turn a view into the owned types when
+//! the packet has to outlive the buffer.
+//!
+//! ```no_run
+//! use packet_analyzer::MappedFile;
+//! use packet_analyzer::view::{Records, Transport};
+//!
+//! let capture = MappedFile::open("capture.pcap").unwrap();
+//! let mut syns = 0;
+//! for record in Records::new(&capture).unwrap() {
+//!     let Some(ip) = record.frame().and_then(|frame| frame.ipv4()) else { continue };
+//!     if let Transport::Tcp(tcp) = ip.transport() {
+//!         if tcp.flags() & 0x12 == 0x02 {
+//!             syns += 1;
+//!         }
+//!     }
+//! }
+//! println!("{} connection attempts", syns);
+//! ```
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use crate::{create_and_return_ether, MacAddr, PcapBlock, ProtocolDatagram};
+use crate::encapsulation::Envelope;
+use crate::tcp_analysis::SegmentAnalysis;
+use crate::pcap_reader::{byte_order, too_long, MAX_RECORD_LENGTH};
+
+const ETHERNET_HEADER: usize = 14;
+const IPV4_HEADER: usize = 20;
+const TCP_HEADER: usize = 20;
+const UDP_HEADER: usize = 8;
+const ICMP_HEADER: usize = 8;
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// An Ethernet frame, at least as long as its header.
+#[derive(Copy, Clone, Debug)]
+pub struct EthernetFrame<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    /// None when `data` is too short for an Ethernet header.
+    pub fn new(data: &'a [u8]) -> Option<EthernetFrame<'a>> {
+        (data.len() >= ETHERNET_HEADER).then_some(EthernetFrame { data })
+    }
+
+    pub fn destination(&self) -> MacAddr {
+        MacAddr(self.data[0..6].try_into().unwrap())
+    }
+
+    pub fn source(&self) -> MacAddr {
+        MacAddr(self.data[6..12].try_into().unwrap())
+    }
+
+    pub fn ether_type(&self) -> u16 {
+        read_u16(self.data, 12)
+    }
+
+    /// Everything after the Ethernet header.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[ETHERNET_HEADER..]
+    }
+
+    /// The whole frame.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The IPv4 packet the frame carries, when it carries one with a whole header.
+    pub fn ipv4(&self) -> Option<Ipv4Header<'a>> {
+        if self.ether_type() != 0x0800 {
+            return None;
+        }
+        Ipv4Header::new(self.payload())
+    }
+
+    /// Decodes every field of the frame into the owned types.
+    pub fn decode(&self) -> crate::EthernetFrame {
+        create_and_return_ether(self.data)
+    }
+}
+
+/// An IPv4 header and the payload it announces. Ethernet pads short frames, so the payload
+/// ends where the total length says rather than where the frame does.
+#[derive(Copy, Clone, Debug)]
+pub struct Ipv4Header<'a> {
+    data: &'a [u8], //The header and the payload, without the padding
+    header_end: usize,
+}
+
+impl<'a> Ipv4Header<'a> {
+    /// None when `data` is too short for the fixed header or the header length is below 20.
+    pub fn new(data: &'a [u8]) -> Option<Ipv4Header<'a>> {
+        if data.len() < IPV4_HEADER || data[0] & 0x0f < 5 {
+            return None;
+        }
+        let header_end = ((data[0] & 0x0f) as usize * 4).min(data.len());
+        let end = (read_u16(data, 2) as usize).clamp(header_end, data.len());
+        Some(Ipv4Header { data: &data[..end], header_end })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.data[0] >> 4
+    }
+
+    /// The length of the header in bytes, options included.
+    pub fn header_length(&self) -> u8 {
+        (self.data[0] & 0x0f) * 4
+    }
+
+    pub fn type_of_service(&self) -> u8 {
+        self.data[1]
+    }
+
+    pub fn total_length(&self) -> u16 {
+        read_u16(self.data, 2)
+    }
+
+    pub fn identification(&self) -> u16 {
+        read_u16(self.data, 4)
+    }
+
+    /// The flag bit no one is meant to set.
+    pub fn reserved_flag(&self) -> bool {
+        self.data[6] & 0x80 != 0
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.data[6] & 0x40 != 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.data[6] & 0x20 != 0
+    }
+
+    /// The offset of the fragment in bytes.
+    pub fn fragment_offset(&self) -> u16 {
+        (read_u16(self.data, 6) & 0x1fff) * 8
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.data[8]
+    }
+
+    /// The protocol number, 6 for TCP, 17 for UDP and 1 for ICMP.
+    pub fn protocol(&self) -> u8 {
+        self.data[9]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.data, 10)
+    }
+
+    pub fn source(&self) -> Ipv4Addr {
+        Ipv4Addr::from(read_u32(self.data, 12))
+    }
+
+    pub fn destination(&self) -> Ipv4Addr {
+        Ipv4Addr::from(read_u32(self.data, 16))
+    }
+
+    pub fn options(&self) -> &'a [u8] {
+        &self.data[IPV4_HEADER.min(self.header_end)..self.header_end]
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[self.header_end..]
+    }
+
+    /// The TCP, UDP or ICMP header that starts the payload, when the payload is long enough
+    /// for it.
+    pub fn transport(&self) -> Transport<'a> {
+        let payload = self.payload();
+        match self.protocol() {
+            6 if payload.len() >= TCP_HEADER => Transport::Tcp(TcpSegment { data: payload }),
+            17 if payload.len() >= UDP_HEADER => Transport::Udp(UdpDatagram { data: payload }),
+            1 if payload.len() >= ICMP_HEADER => Transport::Icmp(IcmpMessage { data: payload }),
+            _ => Transport::Other,
+        }
+    }
+}
+
+/// What an IPv4 packet carries.
+#[derive(Copy, Clone, Debug)]
+pub enum Transport<'a> {
+    Tcp(TcpSegment<'a>),
+    Udp(UdpDatagram<'a>),
+    Icmp(IcmpMessage<'a>),
+    Other, //Another protocol, or a payload cut too short for its header
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TcpSegment<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn source_port(&self) -> u16 {
+        read_u16(self.data, 0)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        read_u16(self.data, 2)
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        read_u32(self.data, 4)
+    }
+
+    pub fn acknowledgement_number(&self) -> u32 {
+        read_u32(self.data, 8)
+    }
+
+    /// The length of the header in bytes, options included.
+    pub fn header_length(&self) -> u8 {
+        (self.data[12] >> 4) * 4
+    }
+
+    /// The flag bits, FIN being the lowest.
+    pub fn flags(&self) -> u8 {
+        self.data[13]
+    }
+
+    /// The window as sent, before any window scaling.
+    pub fn window(&self) -> u16 {
+        read_u16(self.data, 14)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.data, 16)
+    }
+
+    pub fn urgent_pointer(&self) -> u16 {
+        read_u16(self.data, 18)
+    }
+
+    pub fn options(&self) -> &'a [u8] {
+        &self.data[TCP_HEADER..self.header_end()]
+    }
+
+    /// The shift count of the window scale option, which is only sent with a SYN.
+    pub fn window_scale(&self) -> Option<u8> {
+        let options = self.options();
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                0 => return None, //End of options
+                1 => i += 1, //No operation
+                3 if i + 2 < options.len() && options[i + 1] == 3 => return Some(options[i + 2]),
+                _ => match options.get(i + 1) {
+                    Some(&length) if length >= 2 => i += length as usize,
+                    _ => return None,
+                },
+            }
+        }
+        None
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[self.header_end()..]
+    }
+
+    fn header_end(&self) -> usize {
+        (self.header_length() as usize).clamp(TCP_HEADER, self.data.len())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct UdpDatagram<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub fn source_port(&self) -> u16 {
+        read_u16(self.data, 0)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        read_u16(self.data, 2)
+    }
+
+    /// The length from the UDP header, header included.
+    pub fn length(&self) -> u16 {
+        read_u16(self.data, 4)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.data, 6)
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[UDP_HEADER..]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct IcmpMessage<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> IcmpMessage<'a> {
+    pub fn message_type(&self) -> u8 {
+        self.data[0]
+    }
+
+    pub fn code(&self) -> u8 {
+        self.data[1]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.data, 2)
+    }
+
+    /// The identifier of an echo request or reply.
+    pub fn identifier(&self) -> u16 {
+        read_u16(self.data, 4)
+    }
+
+    /// The sequence number of an echo request or reply.
+    pub fn sequence(&self) -> u16 {
+        read_u16(self.data, 6)
+    }
+
+    /// Everything after the 8 byte header.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[ICMP_HEADER..]
+    }
+}
+
+/// One record of a pcap file, borrowed from the buffer the file is in.
+#[derive(Copy, Clone, Debug)]
+pub struct Record<'a> {
+    number: u32,
+    seconds: u32,
+    microseconds: u32,
+    nanoseconds: Option<u32>,
+    original_length: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Position of the record in the capture, starting at 1.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn seconds(&self) -> u32 {
+        self.seconds
+    }
+
+    /// The fraction of the timestamp, in microseconds even for a nanosecond capture.
+    pub fn microseconds(&self) -> u32 {
+        self.microseconds
+    }
+
+    /// The fraction of the timestamp in nanoseconds, for a nanosecond capture.
+    pub fn nanoseconds(&self) -> Option<u32> {
+        self.nanoseconds
+    }
+
+    /// The length of the packet on the wire, more than was captured when it was cut short.
+    pub fn original_length(&self) -> u32 {
+        self.original_length
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn frame(&self) -> Option<EthernetFrame<'a>> {
+        EthernetFrame::new(self.data)
+    }
+
+    /// Seconds since the epoch, with the microseconds as the fraction.
+    pub fn timestamp(&self) -> f64 {
+        self.seconds as f64 + self.microseconds as f64 / 1_000_000.0
+    }
+
+    /// Copies the record into an owned block, decoded the way `PcapReader` decodes it.
+    pub fn to_block(&self) -> PcapBlock {
+        let mut block = PcapBlock::new();
+        block.number = self.number;
+        block.timestamp_seconds = self.seconds.to_ne_bytes();
+        block.timestamp_microseconds = self.microseconds.to_ne_bytes();
+        block.timestamp_nanoseconds = self.nanoseconds;
+        block.captured_length = (self.data.len() as u32).to_ne_bytes();
+        block.original_length = self.original_length.to_ne_bytes();
+        block.ether_frame = create_and_return_ether(self.data);
+        block.data = self.data.to_vec();
+        block
+    }
+}
+
+/// A packet read in place with what is known about it besides its bytes, for the outputs
+/// that look at a packet without keeping it.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Packet<'a> {
+    pub(crate) record: Record<'a>,
+    pub(crate) sender: Option<SocketAddr>, //Who sent the frame, when it was received over the network
+    pub(crate) envelope: Option<&'a Envelope>, //What the frame was wrapped in when it was received
+    pub(crate) analysis: Option<&'a SegmentAnalysis>, //What the TCP analysis found out about the segment
+}
+
+impl<'a> Packet<'a> {
+    /// A record of a capture file, with what the TCP analysis found out about it.
+    pub(crate) fn new(record: Record<'a>, analysis: Option<&'a SegmentAnalysis>) -> Packet<'a> {
+        Packet { record, sender: None, envelope: None, analysis }
+    }
+
+    pub(crate) fn of_block(block: &'a PcapBlock) -> Packet<'a> {
+        let analysis = match &block.ether_frame.packet.datagram {
+            ProtocolDatagram::TCP(tcp) => tcp.analysis.as_ref(),
+            _ => None,
+        };
+        let record = Record {
+            number: block.number,
+            seconds: u32::from_ne_bytes(block.timestamp_seconds),
+            microseconds: u32::from_ne_bytes(block.timestamp_microseconds),
+            nanoseconds: block.timestamp_nanoseconds,
+            original_length: block.original_length(),
+            data: &block.data,
+        };
+        Packet { record, sender: block.sender, envelope: block.envelope.as_ref(), analysis }
+    }
+
+    /// Copies the packet into an owned block, for the outputs that keep it.
+    pub(crate) fn to_block(self) -> PcapBlock {
+        let mut block = self.record.to_block();
+        block.sender = self.sender;
+        block.envelope = self.envelope.cloned();
+        if let ProtocolDatagram::TCP(tcp) = &mut block.ether_frame.packet.datagram {
+            tcp.analysis = self.analysis.cloned();
+        }
+        block
+    }
+
+    pub(crate) fn data(&self) -> &'a [u8] {
+        self.record.data
+    }
+
+    pub(crate) fn frame(&self) -> Option<EthernetFrame<'a>> {
+        self.record.frame()
+    }
+
+    /// The IPv4 packet in the frame, when it carries one.
+    pub(crate) fn ipv4(&self) -> Option<Ipv4Header<'a>> {
+        self.frame().and_then(|frame| frame.ipv4())
+    }
+
+    /// What the IPv4 packet carries, `Other` when there is none.
+    pub(crate) fn transport(&self) -> Transport<'a> {
+        self.ipv4().map_or(Transport::Other, |ip| ip.transport())
+    }
+
+    pub(crate) fn timestamp(&self) -> f64 {
+        self.record.timestamp()
+    }
+
+    /// The timestamp in microseconds since the epoch.
+    pub(crate) fn microseconds(&self) -> u64 {
+        self.record.seconds as u64 * 1_000_000 + self.record.microseconds as u64
+    }
+}
+
+/// The records of a pcap file held in memory, usually a [`MappedFile`](crate::MappedFile).
+/// Like `PcapReader` it stops at the first record that is cut short.
+pub struct Records<'a> {
+    capture: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+    nanosecond: bool,
+    number: u32,
+    error: Option<io::Error>, //Why reading stopped, when it was not the end of the capture
+}
+
+impl<'a> Records<'a> {
+    pub fn new(capture: &'a [u8]) -> io::Result<Records<'a>> {
+        let not_pcap = || io::Error::new(io::ErrorKind::InvalidData, "not a pcap file");
+        if capture.len() < 24 {
+            return Err(not_pcap());
+        }
+        let (big_endian, nanosecond) = byte_order(capture[0..4].try_into().unwrap()).ok_or_else(not_pcap)?;
+        Ok(Records { capture, offset: 24, big_endian, nanosecond, number: 0, error: None })
+    }
+
+    pub fn snap_length(&self) -> u32 {
+        self.read_u32(16)
+    }
+
+    pub fn link_type(&self) -> u32 {
+        self.read_u32(20)
+    }
+
+    /// Whether the timestamps of the capture are in nanoseconds rather than microseconds.
+    pub fn nanosecond(&self) -> bool {
+        self.nanosecond
+    }
+
+    /// Why reading stopped before the end of the capture, if it did. A capture that ends in the
+    /// middle of its last record is not an error, as it is usually one that was still being written.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let bytes: [u8; 4] = self.capture[offset..offset + 4].try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        let start = self.offset + 16;
+        if start > self.capture.len() {
+            return None;
+        }
+        let captured_length = self.read_u32(self.offset + 8);
+        if captured_length > MAX_RECORD_LENGTH {
+            //Nothing after it can be found, so later calls end at once
+            self.error = Some(too_long(self.number + 1, captured_length));
+            self.offset = self.capture.len();
+            return None;
+        }
+        if start + captured_length as usize > self.capture.len() {
+            return None;
+        }
+        let fraction = self.read_u32(self.offset + 4);
+        let (microseconds, nanoseconds) = if self.nanosecond { (fraction / 1000, Some(fraction)) } else { (fraction, None) };
+        self.number += 1;
+        let record = Record {
+            number: self.number,
+            seconds: self.read_u32(self.offset),
+            microseconds,
+            nanoseconds,
+            original_length: self.read_u32(self.offset + 12),
+            data: &self.capture[start..start + captured_length as usize],
+        };
+        self.offset = start + captured_length as usize;
+        Some(record)
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::dns::{rcode_name, type_name, DnsMessage};
+use crate::http::HttpHead;
+use crate::internet_packet::format_ipv4;
+use crate::json::JsonValue;
+use crate::pcap_block::format_utc;
+use crate::tcp_analysis::TcpAnomaly;
+use crate::view::{Packet, Transport};
+
+//UDP and ICMP have no handshake, so their connections end after this long without packets
+const UDP_INACTIVITY_TIMEOUT: f64 = 60.0;
+
+const CONN_FIELDS: &[(&str, &str)] = &[("ts", "time"), ("uid", "string"), ("id.orig_h", "addr"), ("id.orig_p", "port"),
+    ("id.resp_h", "addr"), ("id.resp_p", "port"), ("proto", "enum"), ("service", "string"), ("duration", "interval"),
+    ("orig_bytes", "count"), ("resp_bytes", "count"), ("conn_state", "string"), ("local_orig", "bool"),
+    ("local_resp", "bool"), ("missed_bytes", "count"), ("history", "string"), ("orig_pkts", "count"),
+    ("orig_ip_bytes", "count"), ("resp_pkts", "count"), ("resp_ip_bytes", "count"), ("tunnel_parents", "set[string]")];
+
+const DNS_FIELDS: &[(&str, &str)] = &[("ts", "time"), ("uid", "string"), ("id.orig_h", "addr"), ("id.orig_p", "port"),
+    ("id.resp_h", "addr"), ("id.resp_p", "port"), ("proto", "enum"), ("trans_id", "count"), ("rtt", "interval"),
+    ("query", "string"), ("qclass", "count"), ("qclass_name", "string"), ("qtype", "count"), ("qtype_name", "string"),
+    ("rcode", "count"), ("rcode_name", "string"), ("AA", "bool"), ("TC", "bool"), ("RD", "bool"), ("RA", "bool"),
+    ("Z", "count"), ("answers", "vector[string]"), ("TTLs", "vector[interval]"), ("rejected", "bool")];
+
+const HTTP_FIELDS: &[(&str, &str)] = &[("ts", "time"), ("uid", "string"), ("id.orig_h", "addr"), ("id.orig_p", "port"),
+    ("id.resp_h", "addr"), ("id.resp_p", "port"), ("trans_depth", "count"), ("method", "string"), ("host", "string"),
+    ("uri", "string"), ("referrer", "string"), ("version", "string"), ("user_agent", "string"), ("origin", "string"),
+    ("request_body_len", "count"), ("response_body_len", "count"), ("status_code", "count"), ("status_msg", "string")];
+
+/// Where `--zeek` writes its logs, and whether as TSV or JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ZeekOptions {
+    pub(crate) directory: String,
+    pub(crate) json: bool,
+}
+
+impl ZeekOptions {
+    pub(crate) fn new(directory: String) -> ZeekOptions {
+        ZeekOptions {
+            directory,
+            json: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum ConnectionKey {
+    Tcp(usize), //The stream index of the connection tracking
+    Other(u8, ([u8; 4], u16), ([u8; 4], u16)), //Protocol and both endpoints, the lower one first
+}
+
+#[derive(Default)]
+struct Side {
+    packets: u64,
+    ip_bytes: u64,
+    payload_bytes: u64,
+    sequence_end: Option<u32>, //Highest relative sequence number after the data sent, for TCP
+    syn: bool,
+    syn_ack: bool,
+    fin: bool,
+    rst: bool,
+}
+
+impl Side {
+    //TCP counts the sequence space covered so retransmissions are not counted twice
+    fn bytes(&self) -> u64 {
+        match self.sequence_end {
+            Some(end) => end.saturating_sub(1) as u64,
+            None => self.payload_bytes,
+        }
+    }
+}
+
+struct Connection {
+    uid: String,
+    number: u32, //The packet that opened it
+    protocol: u8,
+    orig: ([u8; 4], u16),
+    resp: ([u8; 4], u16),
+    first: f64,
+    last: f64,
+    service: Option<&'static str>,
+    sides: [Side; 2], //The originator first
+    history: String,
+    http_request: Option<usize>, //The HTTP transactions whose bodies are being sent
+    http_response: Option<usize>,
+    http_depth: u64, //HTTP requests seen so far
+    http_waiting: VecDeque<usize>, //HTTP requests without a response yet, the oldest first
+}
+
+impl Connection {
+    fn add_history(&mut self, letter: char, from_orig: bool) {
+        let letter = if from_orig { letter } else { letter.to_ascii_lowercase() };
+        if !self.history.contains(letter) {
+            self.history.push(letter);
+        }
+    }
+
+    fn conn_state(&self) -> &'static str {
+        let [orig, resp] = &self.sides;
+        if self.protocol != 6 {
+            return if resp.packets > 0 { "SF" } else { "S0" };
+        }
+        match (orig.syn, resp.syn_ack) {
+            (true, false) if resp.rst => "REJ",
+            (true, false) if orig.rst => "RSTOS0",
+            (true, false) if orig.fin => "SH",
+            (true, false) if resp.packets == 0 => "S0",
+            (false, true) if resp.rst => "RSTRH",
+            (false, true) if resp.fin => "SHR",
+            (true, true) if orig.rst => "RSTO",
+            (true, true) if resp.rst => "RSTR",
+            (true, true) if orig.fin && resp.fin => "SF",
+            (true, true) if orig.fin => "S2",
+            (true, true) if resp.fin => "S3",
+            (true, true) => "S1",
+            _ => "OTH",
+        }
+    }
+}
+
+struct DnsTransaction {
+    number: u32, //The packet it was logged at
+    connection: usize,
+    query: DnsMessage,
+    query_time: f64,
+    response: Option<(DnsMessage, f64)>,
+}
+
+struct HttpTransaction {
+    number: u32, //The packet it was logged at
+    connection: usize,
+    time: f64,
+    depth: u64,
+    request: HttpHead,
+    request_body: u64,
+    response: Option<HttpHead>,
+    response_body: u64,
+}
+
+/// Runs the packets through connection tracking and the DNS and HTTP decoders and writes Zeek
+/// style `conn.log`, `dns.log` and `http.log` files. The protocol logs are only written when the
+/// capture holds any of their traffic.
+pub(crate) struct ZeekLogger {
+    options: ZeekOptions,
+    connections: Vec<Connection>,
+    active: HashMap<ConnectionKey, usize>,
+    dns: Vec<DnsTransaction>,
+    dns_pending: HashMap<(usize, u16), usize>, //Queries waiting for their response, by connection and id
+    http: Vec<HttpTransaction>,
+    first_time: Option<(u32, (u32, u32))>, //The number and time of the first packet
+    last_time: (u32, u32),
+}
+
+impl ZeekLogger {
+    pub(crate) fn new(options: &ZeekOptions) -> Result<ZeekLogger, String> {
+        create_dir_all(&options.directory).map_err(|error| format!("could not create {}: {}", options.directory, error))?;
+        Ok(ZeekLogger::empty(options))
+    }
+
+    fn empty(options: &ZeekOptions) -> ZeekLogger {
+        ZeekLogger {
+            options: options.clone(),
+            connections: Vec::new(),
+            active: HashMap::new(),
+            dns: Vec::new(),
+            dns_pending: HashMap::new(),
+            http: Vec::new(),
+            first_time: None,
+            last_time: (0, 0),
+        }
+    }
+
+    /// A logger for a worker thread of `--threads`, for the connections it owns. Its logs are
+    /// merged into this one's before they are written.
+    pub(crate) fn shard(&self) -> ZeekLogger {
+        ZeekLogger::empty(&self.options)
+    }
+
+    /// Takes in the connections a worker thread logged. Connections, and the DNS and HTTP entries
+    /// with the same time, come in the order of their first packet as if one logger had seen
+    /// every packet, and the connection ids are made again from that order. Once merged a
+    /// logger only writes its logs, the connections it tracked are gone.
+    pub(crate) fn merge(&mut self, other: ZeekLogger) {
+        let offset = self.connections.len();
+        let mut connections: Vec<(usize, Connection)> = std::mem::take(&mut self.connections).into_iter()
+            .chain(other.connections)
+            .enumerate()
+            .collect();
+        connections.sort_by_key(|(_, connection)| connection.number);
+        let mut positions = vec![0; connections.len()];
+        for (position, (index, _)) in connections.iter().enumerate() {
+            positions[*index] = position;
+        }
+        self.connections = connections.into_iter().enumerate().map(|(position, (_, mut connection))| {
+            connection.uid = make_uid(position as u64, connection.first, connection.orig, connection.resp);
+            connection
+        }).collect();
+
+        self.dns.extend(other.dns.into_iter().map(|mut transaction| {
+            transaction.connection += offset;
+            transaction
+        }));
+        self.dns.iter_mut().for_each(|transaction| transaction.connection = positions[transaction.connection]);
+        self.dns.sort_by_key(|transaction| transaction.number);
+        self.http.extend(other.http.into_iter().map(|mut transaction| {
+            transaction.connection += offset;
+            transaction
+        }));
+        self.http.iter_mut().for_each(|transaction| transaction.connection = positions[transaction.connection]);
+        self.http.sort_by_key(|transaction| transaction.number);
+
+        self.active.clear();
+        self.dns_pending.clear();
+        self.first_time = self.first_time.into_iter().chain(other.first_time).min_by_key(|(number, _)| *number);
+        self.last_time = self.last_time.max(other.last_time);
+    }
+
+    pub(crate) fn add(&mut self, packet: &Packet) {
+        let number = packet.record.number();
+        let stamp = (packet.record.seconds(), packet.record.microseconds());
+        self.first_time.get_or_insert((number, stamp));
+        self.last_time = self.last_time.max(stamp);
+        let Some(ip) = packet.ipv4() else { return };
+        let time = packet.timestamp();
+        let (source_port, destination_port, payload, tcp_flags) = match ip.transport() {
+            Transport::Tcp(tcp) => (tcp.source_port(), tcp.destination_port(), tcp.payload(), tcp.flags()),
+            Transport::Udp(udp) => (udp.source_port(), udp.destination_port(), udp.payload(), 0),
+            //Zeek keeps the ICMP type and code where the ports would be, the data follows a timestamp
+            Transport::Icmp(icmp) => (icmp.message_type() as u16, icmp.code() as u16, icmp.payload().get(8..).unwrap_or_default(), 0),
+            Transport::Other => return,
+        };
+        let (source_address, destination_address) = (ip.source().octets(), ip.destination().octets());
+        let source = (source_address, source_port);
+        let destination = (destination_address, destination_port);
+        let protocol = ip.protocol();
+        let analysis = packet.analysis;
+        let key = match analysis {
+            Some(analysis) => ConnectionKey::Tcp(analysis.stream),
+            //ICMP replies carry another type than their request, so only the addresses pair them up
+            None if protocol == 1 => ConnectionKey::Other(protocol, (source_address.min(destination_address), 0),
+                                                         (source_address.max(destination_address), 0)),
+            None => ConnectionKey::Other(protocol, source.min(destination), source.max(destination)),
+        };
+        let index = match self.active.get(&key) {
+            Some(&index) if protocol == 6 || time - self.connections[index].last < UDP_INACTIVITY_TIMEOUT => index,
+            _ => {
+                //A SYN+ACK first means the SYN was missed, so the receiver is the originator
+                let flipped = tcp_flags & 0x12 == 0x12;
+                let (orig, resp) = if flipped { (destination, source) } else { (source, destination) };
+                let uid = make_uid(self.connections.len() as u64, time, orig, resp);
+                self.connections.push(Connection {
+                    uid,
+                    number,
+                    protocol,
+                    orig,
+                    resp,
+                    first: time,
+                    last: time,
+                    service: None,
+                    sides: [Side::default(), Side::default()],
+                    history: if flipped { "^".to_string() } else { String::new() },
+                    http_request: None,
+                    http_response: None,
+                    http_depth: 0,
+                    http_waiting: VecDeque::new(),
+                });
+                self.active.insert(key, self.connections.len() - 1);
+                self.connections.len() - 1
+            }
+        };
+
+        let connection = &mut self.connections[index];
+        connection.last = connection.last.max(time);
+        let from_orig = if protocol == 1 { source_address == connection.orig.0 } else { source == connection.orig };
+        let side = &mut connection.sides[if from_orig { 0 } else { 1 }];
+        side.packets += 1;
+        side.ip_bytes += ip.total_length() as u64;
+        side.payload_bytes += payload.len() as u64;
+
+        let resent = analysis.is_some_and(|analysis| analysis.anomalies.iter()
+            .any(|anomaly| matches!(anomaly, TcpAnomaly::Retransmission | TcpAnomaly::FastRetransmission)));
+        if protocol == 6 {
+            side.syn |= tcp_flags & 0x12 == 0x02;
+            side.syn_ack |= tcp_flags & 0x12 == 0x12;
+            side.fin |= tcp_flags & 0x01 != 0;
+            side.rst |= tcp_flags & 0x04 != 0;
+            if let Some(analysis) = analysis.filter(|_| !payload.is_empty()) {
+                let end = analysis.relative_seq.wrapping_add(payload.len() as u32);
+                side.sequence_end = Some(side.sequence_end.map_or(end, |known| known.max(end)));
+            }
+            let letters = [
+                (tcp_flags & 0x12 == 0x02, 'S'),
+                (tcp_flags & 0x12 == 0x12, 'H'),
+                (tcp_flags & 0x17 == 0x10 && payload.is_empty(), 'A'),
+                (!payload.is_empty(), 'D'),
+                (tcp_flags & 0x01 != 0, 'F'),
+                (tcp_flags & 0x04 != 0, 'R'),
+                (resent, 'T'),
+                (analysis.is_some_and(|analysis| analysis.anomalies.contains(&TcpAnomaly::ZeroWindow)), 'W'),
+            ];
+            for (seen, letter) in letters {
+                if seen {
+                    connection.add_history(letter, from_orig);
+                }
+            }
+        } else if protocol == 17 && !payload.is_empty() {
+            connection.add_history('D', from_orig);
+        }
+
+        if payload.is_empty() || resent || protocol == 1 {
+            return;
+        }
+        if source_port == 53 || destination_port == 53 {
+            //DNS over TCP starts with the length of the message
+            let message = if protocol == 6 { payload.get(2..).unwrap_or(&[]) } else { payload };
+            if let Ok(message) = DnsMessage::parse(message) {
+                self.connections[index].service = Some("dns");
+                self.add_dns(number, index, message, time);
+            }
+        } else if protocol == 6 {
+            self.add_http(number, index, from_orig, payload, time);
+        }
+    }
+
+    fn add_dns(&mut self, number: u32, connection: usize, message: DnsMessage, time: f64) {
+        let key = (connection, message.id);
+        if !message.is_response() {
+            self.dns_pending.insert(key, self.dns.len());
+            self.dns.push(DnsTransaction { number, connection, query: message, query_time: time, response: None });
+            return;
+        }
+        match self.dns_pending.remove(&key) {
+            Some(pending) => self.dns[pending].response = Some((message, time)),
+            //The query was not captured, so the response stands in for it
+            None => self.dns.push(DnsTransaction { number, connection, query: message.clone(), query_time: time, response: Some((message, time)) }),
+        }
+    }
+
+    fn add_http(&mut self, number: u32, index: usize, from_orig: bool, payload: &[u8], time: f64) {
+        let connection = &mut self.connections[index];
+        if from_orig {
+            if let Some(request) = HttpHead::request(payload) {
+                connection.service = Some("http");
+                connection.http_request = Some(self.http.len());
+                connection.http_waiting.push_back(self.http.len());
+                connection.http_depth += 1;
+                self.http.push(HttpTransaction {
+                    number,
+                    connection: index,
+                    time,
+                    depth: connection.http_depth,
+                    request_body: (payload.len() - request.head_length) as u64,
+                    request,
+                    response: None,
+                    response_body: 0,
+                });
+            } else if let Some(transaction) = connection.http_request {
+                self.http[transaction].request_body += payload.len() as u64;
+            }
+            return;
+        }
+        match HttpHead::response(payload) {
+            //Interim 1xx responses come before the real one and are not logged
+            Some(response) if response.start_line.1.starts_with('1') => {}
+            Some(response) => {
+                if let Some(transaction) = connection.http_waiting.pop_front() {
+                    connection.http_response = Some(transaction);
+                    self.http[transaction].response_body = (payload.len() - response.head_length) as u64;
+                    self.http[transaction].response = Some(response);
+                }
+            }
+            None => {
+                if let Some(transaction) = connection.http_response {
+                    self.http[transaction].response_body += payload.len() as u64;
+                }
+            }
+        }
+    }
+
+    /// Writes the logs and returns the name and number of entries of every log written.
+    pub(crate) fn finish(&self) -> Result<Vec<(&'static str, usize)>, String> {
+        let mut written = Vec::new();
+        let conn_rows: Vec<Vec<JsonValue>> = self.connections.iter().map(|connection| {
+            let [orig, resp] = &connection.sides;
+            let mut row = id_values(connection);
+            row.extend([
+                JsonValue::string(match connection.protocol { 6 => "tcp", 17 => "udp", _ => "icmp" }),
+                connection.service.map_or(JsonValue::Null, JsonValue::string),
+                if connection.last > connection.first { seconds(connection.last - connection.first) } else { JsonValue::Null },
+                orig.bytes().into(),
+                resp.bytes().into(),
+                JsonValue::string(connection.conn_state()),
+                JsonValue::Null,
+                JsonValue::Null,
+                0u64.into(),
+                if connection.history.is_empty() { JsonValue::Null } else { JsonValue::string(&connection.history) },
+                orig.packets.into(),
+                orig.ip_bytes.into(),
+                resp.packets.into(),
+                resp.ip_bytes.into(),
+                JsonValue::Null,
+            ]);
+            row
+        }).collect();
+        self.write_log("conn", CONN_FIELDS, conn_rows)?;
+        written.push(("conn", self.connections.len()));
+
+        if !self.dns.is_empty() {
+            let rows = self.dns.iter().map(|transaction| self.dns_row(transaction)).collect();
+            self.write_log("dns", DNS_FIELDS, rows)?;
+            written.push(("dns", self.dns.len()));
+        }
+        if !self.http.is_empty() {
+            let rows = self.http.iter().map(|transaction| self.http_row(transaction)).collect();
+            self.write_log("http", HTTP_FIELDS, rows)?;
+            written.push(("http", self.http.len()));
+        }
+        Ok(written)
+    }
+
+    fn dns_row(&self, transaction: &DnsTransaction) -> Vec<JsonValue> {
+        let connection = &self.connections[transaction.connection];
+        let query = &transaction.query;
+        let question = query.questions.first();
+        let response = transaction.response.as_ref().map(|(response, _)| response);
+        //The header bits are the response's when there is one
+        let flags = response.unwrap_or(query).flags;
+        let bit = |mask: u16| JsonValue::from(flags & mask != 0);
+        let mut row = id_values(connection);
+        row[0] = seconds(transaction.query_time);
+        row.extend([
+            JsonValue::string(if connection.protocol == 6 { "tcp" } else { "udp" }),
+            query.id.into(),
+            //A response without its query has no round trip to measure
+            transaction.response.as_ref().filter(|_| !query.is_response())
+                .map_or(JsonValue::Null, |(_, time)| seconds(time - transaction.query_time)),
+            question.map_or(JsonValue::Null, |question| JsonValue::string(&question.name)),
+            question.map_or(JsonValue::Null, |question| question.qclass.into()),
+            question.map_or(JsonValue::Null, |question| match question.qclass {
+                1 => JsonValue::string("C_INTERNET"),
+                class => JsonValue::String(format!("qclass-{}", class)),
+            }),
+            question.map_or(JsonValue::Null, |question| question.qtype.into()),
+            question.map_or(JsonValue::Null, |question| match type_name(question.qtype) {
+                Some(name) => JsonValue::string(name),
+                None => JsonValue::String(format!("query-{}", question.qtype)),
+            }),
+            response.map_or(JsonValue::Null, |response| response.rcode().into()),
+            response.map_or(JsonValue::Null, |response| match rcode_name(response.rcode()) {
+                Some(name) => JsonValue::string(name),
+                None => JsonValue::String(format!("rcode-{}", response.rcode())),
+            }),
+            bit(0x0400),
+            bit(0x0200),
+            bit(0x0100),
+            bit(0x0080),
+            ((flags >> 4) & 0x7).into(),
+            response.filter(|response| !response.answers.is_empty()).map_or(JsonValue::Null, |response| {
+                JsonValue::Array(response.answers.iter().map(|answer| JsonValue::string(&answer.data)).collect())
+            }),
+            response.filter(|response| !response.answers.is_empty()).map_or(JsonValue::Null, |response| {
+                JsonValue::Array(response.answers.iter().map(|answer| seconds(answer.ttl as f64)).collect())
+            }),
+            JsonValue::from(response.is_some_and(|response| response.rcode() == 5)),
+        ]);
+        row
+    }
+
+    fn http_row(&self, transaction: &HttpTransaction) -> Vec<JsonValue> {
+        let request = &transaction.request;
+        let header = |name: &str| request.header(name).map_or(JsonValue::Null, JsonValue::string);
+        let response = transaction.response.as_ref();
+        let mut row = id_values(&self.connections[transaction.connection]);
+        row[0] = seconds(transaction.time);
+        row.extend([
+            transaction.depth.into(),
+            JsonValue::string(&request.start_line.0),
+            header("Host"),
+            JsonValue::string(&request.start_line.1),
+            header("Referer"),
+            JsonValue::string(request.start_line.2.trim_start_matches("HTTP/")),
+            header("User-Agent"),
+            header("Origin"),
+            transaction.request_body.into(),
+            transaction.response_body.into(),
+            response.and_then(|response| response.start_line.1.parse::<u64>().ok()).map_or(JsonValue::Null, JsonValue::from),
+            response.map_or(JsonValue::Null, |response| JsonValue::string(&response.start_line.2)),
+        ]);
+        row
+    }
+
+    fn write_log(&self, path: &str, fields: &[(&str, &str)], mut rows: Vec<Vec<JsonValue>>) -> Result<(), String> {
+        let file_name = Path::new(&self.options.directory).join(format!("{}.log", path));
+        let file = File::create(&file_name).map_err(|error| format!("could not create {}: {}", file_name.display(), error))?;
+        let mut out = BufWriter::new(file);
+        //Entries are written in time order, as Zeek does
+        rows.sort_by(|a, b| match (&a[0], &b[0]) {
+            (JsonValue::Float(a), JsonValue::Float(b)) => a.total_cmp(b),
+            _ => std::cmp::Ordering::Equal,
+        });
+
+        let (seconds, microseconds) = self.first_time.map_or((0, 0), |(_, stamp)| stamp);
+        let mut text = String::new();
+        if !self.options.json {
+            text.push_str("#separator \\x09\n#set_separator\t,\n#empty_field\t(empty)\n#unset_field\t-\n");
+            text.push_str(&format!("#path\t{}\n#open\t{}\n", path, zeek_date(seconds, microseconds)));
+            text.push_str(&format!("#fields\t{}\n", fields.iter().map(|(name, _)| *name).collect::<Vec<_>>().join("\t")));
+            text.push_str(&format!("#types\t{}\n", fields.iter().map(|(_, kind)| *kind).collect::<Vec<_>>().join("\t")));
+        }
+        for row in rows {
+            if self.options.json {
+                //Unset fields are left out of JSON logs
+                let object = fields.iter().zip(row)
+                    .filter(|(_, value)| *value != JsonValue::Null)
+                    .map(|((name, _), value)| (name.to_string(), value))
+                    .collect();
+                text.push_str(&JsonValue::Object(object).to_compact());
+            } else {
+                let cells: Vec<String> = row.iter().map(tsv_value).collect();
+                text.push_str(&cells.join("\t"));
+            }
+            text.push('\n');
+        }
+        if !self.options.json {
+            text.push_str(&format!("#close\t{}\n", zeek_date(self.last_time.0, self.last_time.1)));
+        }
+        out.write_all(text.as_bytes())
+            .and_then(|_| out.flush())
+            .map_err(|error| format!("could not write {}: {}", file_name.display(), error))
+    }
+}
+
+//Times are kept to the microsecond of the capture, without the noise of the float arithmetic
+fn seconds(value: f64) -> JsonValue {
+    JsonValue::Float((value * 1e6).round() / 1e6)
+}
+
+//The fields every log starts with: time, uid and the endpoints of the connection
+fn id_values(connection: &Connection) -> Vec<JsonValue> {
+    vec![
+        seconds(connection.first),
+        JsonValue::string(&connection.uid),
+        JsonValue::String(format_ipv4(&connection.orig.0)),
+        connection.orig.1.into(),
+        JsonValue::String(format_ipv4(&connection.resp.0)),
+        connection.resp.1.into(),
+    ]
+}
+
+fn tsv_value(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Null => "-".to_string(),
+        JsonValue::Bool(value) => if *value { "T" } else { "F" }.to_string(),
+        JsonValue::Unsigned(value) => value.to_string(),
+        JsonValue::Float(value) => format!("{:.6}", value),
+        JsonValue::String(text) if text.is_empty() => "(empty)".to_string(),
+        JsonValue::String(text) => escape(text),
+        JsonValue::Array(values) if values.is_empty() => "(empty)".to_string(),
+        JsonValue::Array(values) => values.iter().map(tsv_value).collect::<Vec<String>>().join(","),
+        JsonValue::Object(_) => "-".to_string(),
+    }
+}
+
+//Separators and anything unprintable are written as \xNN so every row stays on one line
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for byte in text.bytes() {
+        if !(0x20..0x7f).contains(&byte) || byte == b'\\' || byte == b',' {
+            out.push_str(&format!("\\x{:02x}", byte));
+        } else {
+            out.push(byte as char);
+        }
+    }
+    out
+}
+
+//The time format of the #open and #close lines, 2024-01-31-13-45-10
+fn zeek_date(seconds: u32, microseconds: u32) -> String {
+    format_utc(seconds, microseconds)[..19].replace([' ', ':'], "-")
+}
+
+/// A Zeek style connection id, a `C` followed by 17 base62 characters. It is derived from the
+/// connection itself so running the same capture twice gives the same ids.
+fn make_uid(counter: u64, time: f64, orig: ([u8; 4], u16), resp: ([u8; 4], u16)) -> String {
+    const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    //FNV-1a over everything that tells connections apart
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    feed(&counter.to_be_bytes());
+    feed(&time.to_bits().to_be_bytes());
+    feed(&orig.0);
+    feed(&orig.1.to_be_bytes());
+    feed(&resp.0);
+    feed(&resp.1.to_be_bytes());
+    let mut uid = String::from("C");
+    let mut value = hash as u128 | ((hash.rotate_left(29) ^ counter) as u128) << 64;
+    for _ in 0..17 {
+        uid.push(ALPHABET[(value % 62) as usize] as char);
+        value /= 62;
+    }
+    uid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PcapBlock;
+    use crate::tcp_analysis::TcpAnalyzer;
+
+    //A segment of the connection from 10.0.0.1 on the client port to 10.0.0.2:80
+    fn segment(client_port: u16, from_client: bool, seq: u32, payload: &[u8]) -> PcapBlock {
+        let (source, destination) = if from_client { ([10, 0, 0, 1], [10, 0, 0, 2]) } else { ([10, 0, 0, 2], [10, 0, 0, 1]) };
+        let (source_port, destination_port) = if from_client { (client_port, 80) } else { (80, client_port) };
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0]);
+        frame[16..18].copy_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[source, destination].concat());
+        frame.extend_from_slice(&[source_port.to_be_bytes(), destination_port.to_be_bytes()].concat());
+        frame.extend_from_slice(&seq.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 1, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(payload);
+        PcapBlock::from_frame(frame, 1, 0)
+    }
+
+    #[test]
+    fn pairs_pipelined_requests_with_their_responses() {
+        let directory = std::env::temp_dir().join(format!("packet_analyzer-{}-zeek", std::process::id()));
+        let mut logger = ZeekLogger::new(&ZeekOptions::new(directory.display().to_string())).unwrap();
+        let mut tcp_analyzer = TcpAnalyzer::new();
+        let first = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n";
+        let second = b"GET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let other = b"GET /c HTTP/1.1\r\nHost: x\r\n\r\n";
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi";
+        let missing = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        let segments = [
+            segment(40000, true, 1, first),
+            segment(40000, true, 1 + first.len() as u32, second),
+            segment(40001, true, 1, other),
+            segment(40000, false, 1, ok),
+            segment(40001, false, 1, missing),
+            segment(40000, false, 1 + ok.len() as u32, missing),
+        ];
+        for mut block in segments {
+            tcp_analyzer.annotate(&mut block);
+            logger.add(&block.view());
+        }
+        let transactions: Vec<_> = logger.http.iter().map(|transaction| {
+            (transaction.connection, transaction.depth, transaction.request.start_line.1.as_str(),
+             transaction.response.as_ref().map(|response| response.start_line.1.as_str()), transaction.response_body)
+        }).collect();
+        assert_eq!(transactions, [
+            (0, 1, "/a", Some("200"), 2),
+            (0, 2, "/b", Some("404"), 0),
+            (1, 1, "/c", Some("404"), 0),
+        ]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
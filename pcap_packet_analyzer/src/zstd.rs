@@ -0,0 +1,886 @@
+use std::io::{self, BufRead, Read};
+use crate::compressed::{corrupt, ends_early, read_input, History};
+
+const MAGIC: u32 = 0xfd2fb528;
+
+//Frames that only carry metadata, with any magic number from 0x184d2a50 to 0x184d2a5f
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+
+//Windows larger than this are refused rather than allocated
+const MAX_WINDOW: u64 = 1 << 31;
+
+const MAX_BLOCK: usize = 128 * 1024;
+
+const LITERAL_LENGTH_BASE: [u32; 36] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32,
+    40, 48, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+const LITERAL_LENGTH_EXTRA: [u8; 36] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7,
+    8, 9, 10, 11, 12, 13, 14, 15, 16];
+const MATCH_LENGTH_BASE: [u32; 53] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+    25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099,
+    8195, 16387, 32771, 65539];
+const MATCH_LENGTH_EXTRA: [u8; 53] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+//The distributions the sequences use unless a block sends its own, from RFC 8878
+const LITERAL_LENGTH_DEFAULT: [i16; 36] = [4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3,
+    2, 1, 1, 1, 1, 1, -1, -1, -1, -1];
+const MATCH_LENGTH_DEFAULT: [i16; 53] = [1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1];
+const OFFSET_DEFAULT: [i16; 29] = [1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1,
+    -1, -1];
+
+/// Reads a bit stream from its first byte, lowest bit first, as the FSE table descriptions are sent.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    position: usize, //In bits
+}
+
+impl ForwardBits<'_> {
+    fn peek(&self, count: u32) -> u32 {
+        let mut value = 0_u64;
+        for (shift, byte) in self.data.iter().skip(self.position / 8).take(5).enumerate() {
+            value |= (*byte as u64) << (8 * shift);
+        }
+        ((value >> (self.position % 8)) & ((1 << count) - 1)) as u32
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.position += count as usize;
+        value
+    }
+}
+
+/// Reads a bit stream from its last byte back, the way the compressed literals and sequences
+/// are sent. The highest set bit of the last byte marks where the stream starts.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    position: isize, //The bits not read yet, negative once more were read than there are
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> io::Result<BackwardBits<'a>> {
+        match data.last() {
+            Some(&last) if last != 0 => {
+                Ok(BackwardBits { data, position: (data.len() * 8 - 8) as isize + 7 - last.leading_zeros() as isize })
+            }
+            _ => Err(corrupt("a bit stream without its start marker")),
+        }
+    }
+
+    /// The next `count` bits, zeros standing in for the bits before the start of the data.
+    fn peek(&self, count: u32) -> u64 {
+        self.bits_at(self.position - count as isize, count)
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.position -= count as isize;
+        value
+    }
+
+    fn bits_at(&self, start: isize, count: u32) -> u64 {
+        if count == 0 {
+            return 0;
+        }
+        if start < 0 {
+            let missing = (-start) as u32;
+            return if missing >= count { 0 } else { self.bits_at(0, count - missing) << missing };
+        }
+        let start = start as usize;
+        let byte = start / 8;
+        let end = (byte + 8).min(self.data.len());
+        let mut word = [0_u8; 8];
+        word[..end - byte].copy_from_slice(&self.data[byte..end]);
+        (u64::from_le_bytes(word) >> (start % 8)) & ((1 << count) - 1)
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8, //Read to find the next state
+    base: u16, //Added to those bits
+}
+
+/// A finite state entropy decoding table.
+#[derive(Clone)]
+struct Fse {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl Fse {
+    /// Builds the table from the probability of every symbol, -1 standing for less than one.
+    fn new(probabilities: &[i16], log: u32) -> io::Result<Fse> {
+        let size = 1_usize << log;
+        let mut entries = vec![FseEntry::default(); size];
+        let mut next = vec![0_u32; probabilities.len()];
+        //Symbols of less than one go at the end of the table, one entry each
+        let mut high = size;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            if probability == -1 {
+                high = high.checked_sub(1).ok_or_else(|| corrupt("an FSE table with too many symbols"))?;
+                entries[high].symbol = symbol as u8;
+                next[symbol] = 1;
+            } else {
+                next[symbol] = probability.max(0) as u32;
+            }
+        }
+        //The others are spread over the rest
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            for _ in 0..probability.max(0) {
+                entries[position].symbol = symbol as u8;
+                position = (position + step) & (size - 1);
+                while position >= high {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+        if position != 0 {
+            return Err(corrupt("an FSE table whose probabilities do not add up"));
+        }
+        for entry in &mut entries {
+            let state = next[entry.symbol as usize];
+            next[entry.symbol as usize] += 1;
+            let bits = log - (31 - state.leading_zeros());
+            entry.bits = bits as u8;
+            entry.base = ((state << bits) - size as u32) as u16;
+        }
+        Ok(Fse { log, entries })
+    }
+
+    /// Reads the description of a table, returning the table and the number of bytes it took.
+    fn read(data: &[u8], max_log: u32, max_symbol: usize) -> io::Result<(Fse, usize)> {
+        let mut bits = ForwardBits { data, position: 0 };
+        let log = bits.read(4) + 5;
+        if log > max_log {
+            return Err(corrupt("an FSE table too large"));
+        }
+        let mut remaining = (1_i32 << log) + 1;
+        let mut threshold = 1_i32 << log;
+        let mut width = log + 1;
+        let mut probabilities = Vec::new();
+        while remaining > 1 {
+            if probabilities.len() > max_symbol {
+                return Err(corrupt("an FSE table with too many symbols"));
+            }
+            //Small values are sent with one bit less
+            let max = 2 * threshold - 1 - remaining;
+            let value = bits.peek(width) as i32;
+            let mut count = if value & (threshold - 1) < max {
+                bits.position += width as usize - 1;
+                value & (threshold - 1)
+            } else {
+                bits.position += width as usize;
+                let count = value & (2 * threshold - 1);
+                if count >= threshold { count - max } else { count }
+            };
+            count -= 1;
+            remaining -= count.abs();
+            probabilities.push(count as i16);
+            //A zero is followed by the number of zeros after it, 3 meaning there are more
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2);
+                    probabilities.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            while remaining < threshold {
+                width -= 1;
+                threshold >>= 1;
+            }
+        }
+        let length = bits.position.div_ceil(8);
+        if remaining != 1 || probabilities.len() > max_symbol + 1 || length > data.len() {
+            return Err(corrupt("a damaged FSE table"));
+        }
+        Ok((Fse::new(&probabilities, log)?, length))
+    }
+
+    /// A table of a single symbol, that reads no bits.
+    fn single(symbol: u8) -> Fse {
+        Fse { log: 0, entries: vec![FseEntry { symbol, bits: 0, base: 0 }] }
+    }
+}
+
+/// The Huffman table of the literals, indexed by the next `max_bits` bits.
+#[derive(Clone)]
+struct HuffmanTable {
+    max_bits: u32,
+    entries: Vec<(u8, u8)>, //The symbol and the length of its code
+}
+
+impl HuffmanTable {
+    /// Reads the description of a table, returning the table and the number of bytes it took.
+    fn read(data: &[u8]) -> io::Result<(HuffmanTable, usize)> {
+        let header = *data.first().ok_or_else(|| corrupt("a Huffman table is missing"))? as usize;
+        let mut weights = Vec::new();
+        let length = if header >= 128 {
+            //Four bits for each weight
+            let count = header - 127;
+            let bytes = data.get(1..1 + count.div_ceil(2)).ok_or_else(|| corrupt("a Huffman table is cut short"))?;
+            for i in 0..count {
+                weights.push(if i % 2 == 0 { bytes[i / 2] >> 4 } else { bytes[i / 2] & 0x0f });
+            }
+            1 + bytes.len()
+        } else {
+            //The weights compressed with FSE, decoded with two states in turn
+            let compressed = data.get(1..1 + header).ok_or_else(|| corrupt("a Huffman table is cut short"))?;
+            let (fse, used) = Fse::read(compressed, 6, 12)?;
+            let mut bits = BackwardBits::new(&compressed[used..])?;
+            let mut states = [bits.read(fse.log) as usize, bits.read(fse.log) as usize];
+            'weights: loop {
+                for turn in 0..2 {
+                    let entry = fse.entries[states[turn]];
+                    weights.push(entry.symbol);
+                    states[turn] = entry.base as usize + bits.read(entry.bits as u32) as usize;
+                    if bits.position < 0 {
+                        weights.push(fse.entries[states[1 - turn]].symbol);
+                        break 'weights;
+                    }
+                }
+                if weights.len() > 255 {
+                    return Err(corrupt("a Huffman table with too many weights"));
+                }
+            }
+            1 + header
+        };
+
+        //The weight of the last symbol is what makes the total a power of two
+        let total: u32 = weights.iter().filter(|weight| **weight > 0).map(|weight| 1 << (weight - 1)).sum();
+        if total == 0 || weights.len() > 255 || weights.iter().any(|weight| *weight > 12) {
+            return Err(corrupt("a damaged Huffman table"));
+        }
+        let max_bits = 32 - total.leading_zeros();
+        let rest = (1 << max_bits) - total;
+        if max_bits > 11 || !rest.is_power_of_two() {
+            return Err(corrupt("a damaged Huffman table"));
+        }
+        weights.push((rest.trailing_zeros() + 1) as u8);
+
+        //Each weight gets its part of the table, the lowest weights and longest codes first
+        let mut starts = [0_usize; 13];
+        let mut next = 0;
+        for (weight, start) in starts.iter_mut().enumerate().take(max_bits as usize + 1).skip(1) {
+            *start = next;
+            next += weights.iter().filter(|w| **w as usize == weight).count() << (weight - 1);
+        }
+        let mut entries = vec![(0, 0); 1 << max_bits];
+        for (symbol, &weight) in weights.iter().enumerate() {
+            if weight == 0 {
+                continue;
+            }
+            let span = 1 << (weight - 1);
+            let start = starts[weight as usize];
+            entries[start..start + span].fill((symbol as u8, (max_bits + 1 - weight as u32) as u8));
+            starts[weight as usize] += span;
+        }
+        Ok((HuffmanTable { max_bits, entries }, length))
+    }
+
+    fn decode_stream(&self, data: &[u8], count: usize, literals: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.position -= length as isize;
+            literals.push(symbol);
+        }
+        if bits.position != 0 {
+            return Err(corrupt("a literals stream of the wrong length"));
+        }
+        Ok(())
+    }
+}
+
+/// The tables a block may reuse from the block before it.
+#[derive(Clone)]
+struct Tables {
+    huffman: Option<HuffmanTable>,
+    literal_lengths: Option<Fse>,
+    offsets: Option<Fse>,
+    match_lengths: Option<Fse>,
+}
+
+enum State {
+    Frame,
+    Block,
+    Done,
+}
+
+/// Decompresses a Zstandard stream as it is read, one block at a time. Frames written one after
+/// the other are read as one stream and skippable frames are passed over. Dictionaries are not
+/// supported.
+pub(crate) struct ZstdReader<R: BufRead> {
+    reader: R,
+    state: State,
+    history: History,
+    tables: Tables,
+    repeat_offsets: [usize; 3],
+    literals: Vec<u8>,
+    block: Vec<u8>,
+    checksum: Option<Xxh64>, //When the frame ends with one
+    checked: usize, //Where the bytes not yet in the checksum start in the history
+}
+
+impl<R: BufRead> ZstdReader<R> {
+    pub(crate) fn new(reader: R) -> ZstdReader<R> {
+        ZstdReader {
+            reader,
+            state: State::Frame,
+            history: History::new(0),
+            tables: Tables { huffman: None, literal_lengths: None, offsets: None, match_lengths: None },
+            repeat_offsets: [1, 4, 8],
+            literals: Vec::new(),
+            block: Vec::new(),
+            checksum: None,
+            checked: 0,
+        }
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        read_input(&mut self.reader, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_number(&mut self, length: usize) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        read_input(&mut self.reader, &mut bytes[..length])?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads the header of the next frame, returning false at the end of the stream.
+    fn read_frame_header(&mut self) -> io::Result<bool> {
+        loop {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+            let magic = u32::from_le_bytes(self.read_bytes()?);
+            if magic & 0xfffffff0 == SKIPPABLE_MAGIC {
+                let length = u32::from_le_bytes(self.read_bytes()?) as u64;
+                if io::copy(&mut (&mut self.reader).take(length), &mut io::sink())? < length {
+                    return Err(ends_early());
+                }
+                continue;
+            }
+            if magic != MAGIC {
+                return Err(corrupt("not a Zstandard stream"));
+            }
+            break;
+        }
+        let [descriptor] = self.read_bytes()?;
+        let single_segment = descriptor & 0x20 != 0;
+        if descriptor & 0x08 != 0 {
+            return Err(corrupt("a Zstandard frame header with a reserved bit set"));
+        }
+        let mut window = 0;
+        if !single_segment {
+            let [byte] = self.read_bytes()?;
+            let base = 1_u64 << (10 + (byte >> 3));
+            window = base + base / 8 * (byte & 0x07) as u64;
+        }
+        let dictionary = self.read_number([0, 1, 2, 4][(descriptor & 0x03) as usize])?;
+        if dictionary != 0 {
+            return Err(corrupt("the Zstandard frame needs a dictionary"));
+        }
+        let content_size = match descriptor >> 6 {
+            0 => self.read_number(if single_segment { 1 } else { 0 })?,
+            1 => self.read_number(2)? + 256,
+            2 => self.read_number(4)?,
+            _ => self.read_number(8)?,
+        };
+        if single_segment {
+            window = content_size;
+        }
+        if window > MAX_WINDOW {
+            return Err(corrupt("the Zstandard window is too large"));
+        }
+        self.history.set_window(window as usize);
+        self.tables = Tables { huffman: None, literal_lengths: None, offsets: None, match_lengths: None };
+        self.repeat_offsets = [1, 4, 8];
+        self.checksum = (descriptor & 0x04 != 0).then(Xxh64::new);
+        self.checked = self.history.buffer.len();
+        Ok(true)
+    }
+
+    /// Decodes one block, returning whether it was the last of the frame.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let header = self.read_number(3)? as usize;
+        let (last, kind, size) = (header & 1 == 1, (header >> 1) & 3, header >> 3);
+        if size > MAX_BLOCK {
+            return Err(corrupt("a Zstandard block too large"));
+        }
+        match kind {
+            0 => {
+                let start = self.history.buffer.len();
+                self.history.buffer.resize(start + size, 0);
+                read_input(&mut self.reader, &mut self.history.buffer[start..])?;
+            }
+            1 => {
+                let [byte] = self.read_bytes()?;
+                let length = self.history.buffer.len() + size;
+                self.history.buffer.resize(length, byte);
+            }
+            2 => {
+                let mut block = std::mem::take(&mut self.block);
+                block.resize(size, 0);
+                read_input(&mut self.reader, &mut block)?;
+                let result = self.decode_block(&block);
+                self.block = block;
+                result?;
+            }
+            _ => return Err(corrupt("a Zstandard block of a reserved type")),
+        }
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(&self.history.buffer[self.checked..]);
+        }
+        self.checked = self.history.buffer.len();
+        if last {
+            if let Some(checksum) = self.checksum.take() {
+                let expected = u32::from_le_bytes(self.read_bytes()?);
+                if checksum.finish() as u32 != expected {
+                    return Err(corrupt("the Zstandard checksum does not match, the file is damaged"));
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    fn decode_block(&mut self, block: &[u8]) -> io::Result<()> {
+        let used = self.read_literals(block)?;
+        let data = &block[used..];
+        let (count, mut used) = match data {
+            [0, ..] => (0, 1),
+            [byte @ 0..=127, ..] => (*byte as usize, 1),
+            [byte @ 128..=254, second, ..] => (((*byte as usize - 128) << 8) + *second as usize, 2),
+            [255, second, third, ..] => (*second as usize + ((*third as usize) << 8) + 0x7f00, 3),
+            _ => return Err(corrupt("a Zstandard block is cut short")),
+        };
+        if count == 0 {
+            self.history.buffer.extend_from_slice(&self.literals);
+            return Ok(());
+        }
+        let modes = *data.get(used).ok_or_else(|| corrupt("a Zstandard block is cut short"))?;
+        used += 1;
+        let literal_lengths = read_table(data, &mut used, modes >> 6, &mut self.tables.literal_lengths,
+                                         &LITERAL_LENGTH_DEFAULT, 6, 9, 35)?;
+        let offsets = read_table(data, &mut used, (modes >> 4) & 3, &mut self.tables.offsets,
+                                 &OFFSET_DEFAULT, 5, 8, 31)?;
+        let match_lengths = read_table(data, &mut used, (modes >> 2) & 3, &mut self.tables.match_lengths,
+                                       &MATCH_LENGTH_DEFAULT, 6, 9, 52)?;
+
+        let mut bits = BackwardBits::new(&data[used..])?;
+        let mut literal_state = bits.read(literal_lengths.log) as usize;
+        let mut offset_state = bits.read(offsets.log) as usize;
+        let mut match_state = bits.read(match_lengths.log) as usize;
+        let mut literal = 0;
+        for sequence in 0..count {
+            let literal_code = literal_lengths.entries[literal_state];
+            let offset_code = offsets.entries[offset_state];
+            let match_code = match_lengths.entries[match_state];
+            if literal_code.symbol > 35 || match_code.symbol > 52 || offset_code.symbol > 31 {
+                return Err(corrupt("a Zstandard sequence with an invalid code"));
+            }
+            let offset_value = (1_usize << offset_code.symbol) + bits.read(offset_code.symbol as u32) as usize;
+            let match_length = MATCH_LENGTH_BASE[match_code.symbol as usize] as usize
+                + bits.read(MATCH_LENGTH_EXTRA[match_code.symbol as usize] as u32) as usize;
+            let literal_length = LITERAL_LENGTH_BASE[literal_code.symbol as usize] as usize
+                + bits.read(LITERAL_LENGTH_EXTRA[literal_code.symbol as usize] as u32) as usize;
+            if sequence + 1 < count {
+                literal_state = literal_code.base as usize + bits.read(literal_code.bits as u32) as usize;
+                match_state = match_code.base as usize + bits.read(match_code.bits as u32) as usize;
+                offset_state = offset_code.base as usize + bits.read(offset_code.bits as u32) as usize;
+            }
+
+            let literals = self.literals.get(literal..literal + literal_length)
+                .ok_or_else(|| corrupt("a Zstandard sequence uses more literals than there are"))?;
+            self.history.buffer.extend_from_slice(literals);
+            literal += literal_length;
+            let offset = self.resolve_offset(offset_value, literal_length)?;
+            self.history.copy_match(offset, match_length)?;
+        }
+        if bits.position != 0 {
+            return Err(corrupt("a Zstandard sequence stream of the wrong length"));
+        }
+        self.history.buffer.extend_from_slice(&self.literals[literal..]);
+        Ok(())
+    }
+
+    /// Turns the offset a sequence sends into a distance, keeping the last three distances for
+    /// the sequences that repeat one of them.
+    fn resolve_offset(&mut self, offset_value: usize, literal_length: usize) -> io::Result<usize> {
+        let repeat = &mut self.repeat_offsets;
+        if offset_value > 3 {
+            *repeat = [offset_value - 3, repeat[0], repeat[1]];
+            return Ok(repeat[0]);
+        }
+        //Without literals before it a match does not repeat the last distance, which would have
+        //made it part of the match before
+        match offset_value - 1 + (literal_length == 0) as usize {
+            0 => {}
+            1 => *repeat = [repeat[1], repeat[0], repeat[2]],
+            2 => *repeat = [repeat[2], repeat[0], repeat[1]],
+            _ => {
+                let offset = repeat[0].checked_sub(1).filter(|offset| *offset > 0)
+                    .ok_or_else(|| corrupt("a Zstandard sequence with an invalid offset"))?;
+                *repeat = [offset, repeat[0], repeat[1]];
+            }
+        }
+        Ok(repeat[0])
+    }
+
+    /// Decodes the literals section into `literals`, returning its length.
+    fn read_literals(&mut self, block: &[u8]) -> io::Result<usize> {
+        let cut_short = || corrupt("a Zstandard literals section is cut short");
+        let header = |length: usize| -> io::Result<u64> {
+            let bytes = block.get(..length).ok_or_else(cut_short)?;
+            Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64))
+        };
+        let first = *block.first().ok_or_else(cut_short)?;
+        let (kind, format) = (first & 3, (first >> 2) & 3);
+        self.literals.clear();
+        if kind < 2 {
+            let (size, start) = match format {
+                0 | 2 => ((first >> 3) as usize, 1),
+                1 => ((header(2)? >> 4) as usize, 2),
+                _ => ((header(3)? >> 4) as usize, 3),
+            };
+            if size > MAX_BLOCK {
+                return Err(corrupt("a Zstandard literals section too large"));
+            }
+            if kind == 0 {
+                self.literals.extend_from_slice(block.get(start..start + size).ok_or_else(cut_short)?);
+                return Ok(start + size);
+            }
+            let byte = *block.get(start).ok_or_else(cut_short)?;
+            self.literals.resize(size, byte);
+            return Ok(start + 1);
+        }
+
+        let (streams, start, width) = match format {
+            0 => (1, 3, 10),
+            1 => (4, 3, 10),
+            2 => (4, 4, 14),
+            _ => (4, 5, 18),
+        };
+        let value = header(start)? >> 4;
+        let regenerated = (value & ((1 << width) - 1)) as usize;
+        let compressed = (value >> width & ((1 << width) - 1)) as usize;
+        if regenerated > MAX_BLOCK {
+            return Err(corrupt("a Zstandard literals section too large"));
+        }
+        let mut data = block.get(start..start + compressed).ok_or_else(cut_short)?;
+        if kind == 2 {
+            let (table, used) = HuffmanTable::read(data)?;
+            self.tables.huffman = Some(table);
+            data = &data[used..];
+        }
+        let table = self.tables.huffman.as_ref().ok_or_else(|| corrupt("a Zstandard block reuses a missing Huffman table"))?;
+        if streams == 1 {
+            table.decode_stream(data, regenerated, &mut self.literals)?;
+        } else {
+            //Three stream lengths come first, the fourth stream takes the rest
+            let jumps = data.get(..6).ok_or_else(cut_short)?;
+            let mut lengths = [0; 4];
+            for (i, length) in lengths.iter_mut().take(3).enumerate() {
+                *length = u16::from_le_bytes([jumps[2 * i], jumps[2 * i + 1]]) as usize;
+            }
+            lengths[3] = (data.len() - 6).checked_sub(lengths[..3].iter().sum()).ok_or_else(cut_short)?;
+            let part = regenerated.div_ceil(4);
+            if part * 3 > regenerated {
+                return Err(corrupt("a Zstandard literals section too short for four streams"));
+            }
+            let mut offset = 6;
+            for (i, length) in lengths.into_iter().enumerate() {
+                let count = if i < 3 { part } else { regenerated - 3 * part };
+                table.decode_stream(&data[offset..offset + length], count, &mut self.literals)?;
+                offset += length;
+            }
+        }
+        Ok(start + compressed)
+    }
+}
+
+/// Reads the table of one of the sequence codes as the mode says: the default distribution, a
+/// single symbol, a table sent with the block, or the table of the block before.
+#[allow(clippy::too_many_arguments)]
+fn read_table(data: &[u8], used: &mut usize, mode: u8, previous: &mut Option<Fse>, default: &[i16],
+              default_log: u32, max_log: u32, max_symbol: usize) -> io::Result<Fse> {
+    let table = match mode {
+        0 => Fse::new(default, default_log)?,
+        1 => {
+            let symbol = *data.get(*used).ok_or_else(|| corrupt("a Zstandard block is cut short"))?;
+            *used += 1;
+            Fse::single(symbol)
+        }
+        2 => {
+            let (table, length) = Fse::read(&data[(*used).min(data.len())..], max_log, max_symbol)?;
+            *used += length;
+            table
+        }
+        _ => previous.clone().ok_or_else(|| corrupt("a Zstandard block reuses a missing table"))?,
+    };
+    *previous = Some(table.clone());
+    Ok(table)
+}
+
+impl<R: BufRead> Read for ZstdReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.history.is_empty() {
+            let before = self.history.buffer.len();
+            self.history.trim();
+            self.checked -= before - self.history.buffer.len();
+            match self.state {
+                State::Frame => {
+                    self.state = if self.read_frame_header()? { State::Block } else { State::Done };
+                }
+                State::Block => {
+                    if self.read_block()? {
+                        self.state = State::Frame;
+                    }
+                }
+                State::Done => return Ok(0),
+            }
+        }
+        Ok(self.history.read(buffer))
+    }
+}
+
+const PRIME_1: u64 = 0x9e3779b185ebca87;
+const PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME_3: u64 = 0x165667b19e3779f9;
+const PRIME_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME_5: u64 = 0x27d4eb2f165667c5;
+
+/// XXH64 with a seed of 0, the checksum of a Zstandard frame being its lowest 32 bits.
+struct Xxh64 {
+    accumulators: [u64; 4],
+    buffer: [u8; 32],
+    buffered: usize,
+    length: u64,
+}
+
+fn round(accumulator: u64, input: u64) -> u64 {
+    accumulator.wrapping_add(input.wrapping_mul(PRIME_2)).rotate_left(31).wrapping_mul(PRIME_1)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+impl Xxh64 {
+    fn new() -> Xxh64 {
+        Xxh64 {
+            accumulators: [PRIME_1.wrapping_add(PRIME_2), PRIME_2, 0, 0_u64.wrapping_sub(PRIME_1)],
+            buffer: [0; 32],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.buffered > 0 {
+            let take = (32 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.buffer;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.consume(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (i, accumulator) in self.accumulators.iter_mut().enumerate() {
+            *accumulator = round(*accumulator, read_u64(&stripe[8 * i..]));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.accumulators;
+        let mut hash = if self.length >= 32 {
+            let mut hash = v1.rotate_left(1).wrapping_add(v2.rotate_left(7)).wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for accumulator in self.accumulators {
+                hash = (hash ^ round(0, accumulator)).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+            }
+            hash
+        } else {
+            PRIME_5
+        };
+        hash = hash.wrapping_add(self.length);
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            hash = (hash ^ round(0, read_u64(rest))).rotate_left(27).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            hash = (hash ^ word.wrapping_mul(PRIME_1)).rotate_left(23).wrapping_mul(PRIME_2).wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash = (hash ^ (byte as u64).wrapping_mul(PRIME_5)).rotate_left(11).wrapping_mul(PRIME_1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^ (hash >> 32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::tests::{noise, sample};
+
+    //Made with the zstd program, with its checksums
+    const RAW: &[u8] = include_bytes!("../testdata/raw.zst"); //noise(300), one raw block
+    const RLE: &[u8] = include_bytes!("../testdata/rle.zst"); //300000 x, a compressed block then RLE blocks
+    const COMPRESSED: &[u8] = include_bytes!("../testdata/compressed.zst"); //sample(4000), -19
+    //sample(8000) at --target-compressed-block-size=1340, the second block reusing the Huffman table
+    const BLOCKS: &[u8] = include_bytes!("../testdata/blocks.zst");
+
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        ZstdReader::new(data).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// The kind of every block of a frame with a single segment and no dictionary, and the
+    /// literals type of the compressed ones.
+    fn block_kinds(frame: &[u8]) -> Vec<(usize, Option<u8>)> {
+        let descriptor = frame[4];
+        let single_segment = descriptor & 0x20 != 0;
+        let content_size = [single_segment as usize, 2, 4, 8][(descriptor >> 6) as usize];
+        let mut position = 5 + !single_segment as usize + content_size;
+        let mut kinds = Vec::new();
+        loop {
+            let header = frame[position] as usize | (frame[position + 1] as usize) << 8 | (frame[position + 2] as usize) << 16;
+            position += 3;
+            let (kind, size) = ((header >> 1) & 3, header >> 3);
+            kinds.push((kind, (kind == 2).then(|| frame[position] & 0x0f)));
+            position += if kind == 1 { 1 } else { size };
+            if header & 1 == 1 {
+                return kinds;
+            }
+        }
+    }
+
+    #[test]
+    fn reads_raw_blocks() {
+        assert_eq!(block_kinds(RAW), [(0, None)]);
+        assert_eq!(decompress(RAW).unwrap(), noise(300));
+    }
+
+    #[test]
+    fn reads_rle_blocks() {
+        assert_eq!(block_kinds(RLE)[1..], [(1, None), (1, None)]);
+        assert_eq!(decompress(RLE).unwrap(), vec![b'x'; 300_000]);
+    }
+
+    #[test]
+    fn reads_four_literals_streams() {
+        //Compressed literals, 4 streams with sizes of 14 bits
+        assert_eq!(block_kinds(COMPRESSED), [(2, Some(0b1010))]);
+        assert_eq!(decompress(COMPRESSED).unwrap(), sample(4000));
+    }
+
+    #[test]
+    fn reuses_the_tables_of_the_block_before() {
+        //The second block has treeless literals in a single stream
+        assert_eq!(block_kinds(BLOCKS), [(2, Some(0b1010)), (2, Some(0b0011))]);
+        assert_eq!(decompress(BLOCKS).unwrap(), sample(8000));
+    }
+
+    #[test]
+    fn repeats_offsets() {
+        let frame = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x20, 10, //A single segment of 10 bytes
+            0x20, 0, 0, b'a', b'b', b'c', b'd', //A raw block of 4 bytes
+            0x3d, 0, 0, //The last block, compressed, of 7 bytes
+            0x00, //No literals
+            2, 0x54, 0, 0, 0, //Two sequences, each code given once: no literals, offset 1 and a match of 3
+            0x01, //No bits but the start marker
+        ];
+        //Without literals offset 1 is the second distance kept, 4 at first, which then swaps
+        //with the first one, 1
+        assert_eq!(decompress(&frame).unwrap(), b"abcdabcccc");
+    }
+
+    #[test]
+    fn reads_frames_one_after_the_other() {
+        let skippable = [0x5a, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+        let stream = [RAW, &skippable, COMPRESSED, RLE].concat();
+        assert_eq!(decompress(&stream).unwrap(), [noise(300), sample(4000), vec![b'x'; 300_000]].concat());
+    }
+
+    #[test]
+    fn rejects_a_wrong_checksum() {
+        let mut stream = COMPRESSED.to_vec();
+        *stream.last_mut().unwrap() ^= 1;
+        assert_eq!(decompress(&stream).unwrap_err().to_string(), "the Zstandard checksum does not match, the file is damaged");
+        let mut stream = RAW.to_vec();
+        stream[100] ^= 1;
+        assert_eq!(decompress(&stream).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reports_a_stream_cut_short_as_damaged() {
+        for stream in [RAW, RLE, COMPRESSED, BLOCKS] {
+            for length in [2, 5, 9, stream.len() / 2, stream.len() - 4, stream.len() - 1] {
+                let error = decompress(&stream[..length]).unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::InvalidData, "cut at {}", length);
+            }
+        }
+        assert_eq!(decompress(&COMPRESSED[..100]).unwrap_err().to_string(), "the compressed stream ends early");
+        //A skippable frame shorter than it says
+        assert_eq!(decompress(&[0x50, 0x2a, 0x4d, 0x18, 9, 0, 0, 0, 1]).unwrap_err().to_string(), "the compressed stream ends early");
+    }
+
+    #[test]
+    fn rejects_dictionaries() {
+        let frame = [0x28, 0xb5, 0x2f, 0xfd, 0x21, 1, 0];
+        assert_eq!(decompress(&frame).unwrap_err().to_string(), "the Zstandard frame needs a dictionary");
+    }
+
+    fn xxh64(data: &[u8]) -> u64 {
+        let mut hash = Xxh64::new();
+        hash.update(data);
+        hash.finish()
+    }
+
+    #[test]
+    fn computes_xxh64() {
+        assert_eq!(xxh64(b""), 0xef46db3751d8e999);
+        assert_eq!(xxh64(b"abc"), 0x44bc2cf5ad770999);
+        assert_eq!(xxh64(b"Nobody inspects the spammish repetition"), 0xfbcea83c8a378bf1);
+        //Fed in pieces of every size, across the stripes of 32 bytes
+        let data = sample(1000);
+        for piece in [1, 3, 31, 32, 33, 100] {
+            let mut hash = Xxh64::new();
+            for chunk in data.chunks(piece) {
+                hash.update(chunk);
+            }
+            assert_eq!(hash.finish(), xxh64(&data), "pieces of {}", piece);
+        }
+    }
+}